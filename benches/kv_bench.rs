@@ -5,18 +5,21 @@ mod scenarios {
     pub mod concurrent_ops;
     pub mod bulk_ops;
     pub mod json_ops;
+    pub mod ycsb;
 }
 
 use scenarios::data_size::bench_data_size;
 use scenarios::concurrent_ops::bench_concurrent_ops;
 use scenarios::bulk_ops::bench_bulk_ops;
 use scenarios::json_ops::bench_json_ops;
+use scenarios::ycsb::bench_ycsb;
 
 criterion_group!(
     benches,
     bench_data_size,
     bench_concurrent_ops,
     bench_bulk_ops,
-    bench_json_ops
+    bench_json_ops,
+    bench_ycsb
 );
 criterion_main!(benches);
\ No newline at end of file
@@ -28,7 +28,7 @@ pub fn bench_bulk_ops(c: &mut Criterion) {
                             format!("bulk_key_{}", i),
                             data.clone(),
                             None
-                        ).await;
+                        ).await.unwrap();
                     }
                 })
             })
@@ -37,16 +37,18 @@ pub fn bench_bulk_ops(c: &mut Criterion) {
         // Pre-set keys for bulk GET
         rt.block_on(async {
             for i in 0..*size {
-                cluster.set(format!("bulk_key_{}", i), data.clone(), None).await;
+                cluster.set(format!("bulk_key_{}", i), data.clone(), None).await.unwrap();
             }
         });
 
         // Sequential bulk GET
         group.bench_with_input(BenchmarkId::new("bulk_sequential_get", size), size, |b, &n| {
             b.iter(|| {
-                for i in 0..n {
-                    cluster.get(&format!("bulk_key_{}", i));
-                }
+                rt.block_on(async {
+                    for i in 0..n {
+                        cluster.get(&format!("bulk_key_{}", i)).await;
+                    }
+                })
             })
         });
     }
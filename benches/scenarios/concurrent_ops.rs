@@ -36,7 +36,7 @@ pub fn bench_concurrent_ops(c: &mut Criterion) {
                     }
 
                     for handle in handles {
-                        handle.await.unwrap();
+                        handle.await.unwrap().unwrap();
                     }
                 })
             })
@@ -49,7 +49,7 @@ pub fn bench_concurrent_ops(c: &mut Criterion) {
                     format!("concurrent_key_{}", i),
                     format!("value_{}", i).into_bytes(),
                     None
-                ).await;
+                ).await.unwrap();
             }
         });
 
@@ -63,7 +63,7 @@ pub fn bench_concurrent_ops(c: &mut Criterion) {
                     for i in 0..n {
                         let cluster = Arc::clone(&cluster);
                         handles.push(task::spawn(async move {
-                            cluster.get(&format!("concurrent_key_{}", i))
+                            cluster.get(&format!("concurrent_key_{}", i)).await
                         }));
                     }
 
@@ -89,10 +89,10 @@ pub fn bench_concurrent_ops(c: &mut Criterion) {
                                     format!("mixed_key_{}", i),
                                     format!("value_{}", i).into_bytes(),
                                     None
-                                ).await;
+                                ).await.unwrap();
                                 None
                             } else {
-                                cluster.get(&format!("mixed_key_{}", i - 1))
+                                cluster.get(&format!("mixed_key_{}", i - 1)).await
                             }
                         }));
                     }
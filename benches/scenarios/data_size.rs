@@ -1,9 +1,13 @@
 use criterion::{Criterion, BenchmarkId};
 use tokio::runtime::Runtime;
+use volt::value::INLINE_CAPACITY;
 use volt::KVCluster;
 
 const SIZES: &[usize] = &[10, 100, 1_000, 10_000, 100_000];
 
+/// Sizes around the inline capacity, where values move to the heap
+const SMALL_SIZES: &[usize] = &[8, 16, INLINE_CAPACITY, INLINE_CAPACITY + 1, 32, 62, 128];
+
 fn generate_data(size: usize) -> Vec<u8> {
     (0..size).map(|i| (i % 256) as u8).collect()
 }
@@ -29,13 +33,39 @@ pub fn bench_data_size(c: &mut Criterion) {
 
         // Pre-set the key for get operations
         rt.block_on(async {
-            cluster.set(format!("key_{}", size), data.clone(), None).await
+            cluster.set(format!("key_{}", size), data.clone(), None).await.unwrap()
         });
 
         group.bench_with_input(BenchmarkId::new("get", size), size, |b, &size| {
-            b.iter(|| cluster.get(&format!("key_{}", size)))
+            b.iter(|| rt.block_on(cluster.get(&format!("key_{}", size))))
         });
     }
     
     group.finish();
-} 
\ No newline at end of file
+
+    bench_small_values(c, &rt);
+}
+
+/// Values up to `INLINE_CAPACITY` bytes are stored inside their entry; the
+/// sizes just past it show what the heap allocation costs
+fn bench_small_values(c: &mut Criterion, rt: &Runtime) {
+    let mut group = c.benchmark_group("Small Values");
+    let mut cluster = KVCluster::new(100, 2);
+    rt.block_on(async {
+        cluster.add_node("node1".to_string());
+    });
+    for size in SMALL_SIZES {
+        let data = generate_data(*size);
+        let key = format!("small_{}", size);
+
+        group.bench_with_input(BenchmarkId::new("set", size), size, |b, _| {
+            b.iter(|| rt.block_on(cluster.set(key.as_str(), data.clone(), None)))
+        });
+
+        rt.block_on(cluster.set(key.as_str(), data.clone(), None)).unwrap();
+        group.bench_with_input(BenchmarkId::new("get", size), size, |b, _| {
+            b.iter(|| rt.block_on(cluster.get(&key)))
+        });
+    }
+    group.finish();
+}
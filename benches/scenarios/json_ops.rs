@@ -52,7 +52,7 @@ fn generate_simple_document(size_factor: usize) -> SimpleDocument {
     SimpleDocument {
         id: format!("doc-{}", size_factor),
         value: size_factor as i32,
-        active: size_factor % 2 == 0,
+        active: size_factor.is_multiple_of(2),
     }
 }
 
@@ -60,8 +60,8 @@ fn generate_simple_document(size_factor: usize) -> SimpleDocument {
 fn generate_complex_document(size_factor: usize) -> ComplexDocument {
     // Ajustar el número de elementos según el factor de tamaño
     let num_tags = size_factor.min(100);
-    let num_items = (size_factor / 10).max(1).min(1000);
-    let num_properties = (size_factor / 20).max(1).min(500);
+    let num_items = (size_factor / 10).clamp(1, 1000);
+    let num_properties = (size_factor / 20).clamp(1, 500);
     
     // Generar tags
     let tags = (0..num_tags)
@@ -164,7 +164,7 @@ pub fn bench_json_ops(c: &mut Criterion) {
         group.bench_with_input(BenchmarkId::new("simple_get", size), &size, |b, _| {
             b.iter(|| {
                 let _: Result<Option<SimpleDocument>, _> = rt.block_on(async {
-                    cluster.get_json(&format!("simple:{}", size))
+                    cluster.get_json(&format!("simple:{}", size)).await
                 });
             })
         });
@@ -195,7 +195,7 @@ pub fn bench_json_ops(c: &mut Criterion) {
         group.bench_with_input(BenchmarkId::new("complex_get", size), &size, |b, _| {
             b.iter(|| {
                 let _: Result<Option<ComplexDocument>, _> = rt.block_on(async {
-                    cluster.get_json(&format!("complex:{}", size))
+                    cluster.get_json(&format!("complex:{}", size)).await
                 });
             })
         });
@@ -226,7 +226,7 @@ pub fn bench_json_ops(c: &mut Criterion) {
         group.bench_with_input(BenchmarkId::new("generic_get", size), &size, |b, _| {
             b.iter(|| {
                 let _: Result<Option<serde_json::Value>, _> = rt.block_on(async {
-                    cluster.get_json_value(&format!("generic:{}", size))
+                    cluster.get_json_value(&format!("generic:{}", size)).await
                 });
             })
         });
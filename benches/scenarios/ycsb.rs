@@ -0,0 +1,109 @@
+use criterion::{BenchmarkId, Criterion, Throughput};
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::runtime::Runtime;
+use volt::workload::{ycsb_key, KeyChooser, KeyDistribution, YcsbOp, YcsbWorkload, MAX_SCAN_LENGTH};
+use volt::KVCluster;
+
+/// Records loaded before each workload runs; override with VOLT_YCSB_RECORDS
+const DEFAULT_RECORDS: u64 = 10_000;
+/// Operations per measured iteration; override with VOLT_YCSB_OPERATIONS
+const DEFAULT_OPERATIONS: u64 = 1_000;
+/// YCSB's default record: 10 fields of 100 bytes
+const VALUE_SIZE: usize = 1_000;
+const NODES: usize = 5;
+const REPLICATION_FACTOR: usize = 3;
+
+fn env_count(name: &str, default: u64) -> u64 {
+    std::env::var(name)
+        .ok()
+        .and_then(|v| v.trim().parse().ok())
+        .filter(|&n| n > 0)
+        .unwrap_or(default)
+}
+
+fn record(rng: &mut SmallRng) -> Vec<u8> {
+    (0..VALUE_SIZE).map(|_| rng.gen_range(b'a'..=b'z')).collect()
+}
+
+/// Runs `operations` operations of `workload`. `inserted` is the number of
+/// records stored so far; inserts append past it.
+async fn run_ops(
+    cluster: &KVCluster,
+    workload: YcsbWorkload,
+    chooser: &KeyChooser,
+    inserted: &AtomicU64,
+    operations: u64,
+    rng: &mut SmallRng,
+) {
+    for _ in 0..operations {
+        let records = inserted.load(Ordering::Relaxed);
+        // Rank 0 is the hottest key: the first record, or the newest one
+        // when the workload reads the latest records
+        let rank = chooser.next_index(rng).min(records - 1);
+        let index = if workload.reads_latest() { records - 1 - rank } else { rank };
+        match workload.next_op(rng) {
+            YcsbOp::Read => {
+                cluster.get(&ycsb_key(index)).await;
+            }
+            YcsbOp::Update => {
+                cluster.set(ycsb_key(index), record(rng), None).await.unwrap();
+            }
+            YcsbOp::Insert => {
+                let index = inserted.fetch_add(1, Ordering::Relaxed);
+                cluster.set(ycsb_key(index), record(rng), None).await.unwrap();
+            }
+            YcsbOp::Scan => {
+                let length = rng.gen_range(1..=MAX_SCAN_LENGTH);
+                cluster.scan(ycsb_key(index), length);
+            }
+            YcsbOp::ReadModifyWrite => {
+                let key = ycsb_key(index);
+                let mut value = cluster.get(&key).await.unwrap_or_else(|| record(rng));
+                let field = rng.gen_range(0..value.len().max(1));
+                if let Some(byte) = value.get_mut(field) {
+                    *byte = rng.gen_range(b'a'..=b'z');
+                }
+                cluster.set(key, value, None).await.unwrap();
+            }
+        }
+    }
+}
+
+/// YCSB core workloads A to F over a zipfian keyspace on a replicated
+/// cluster, so regressions in replication and TTL handling show up as
+/// lower throughput.
+pub fn bench_ycsb(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let records = env_count("VOLT_YCSB_RECORDS", DEFAULT_RECORDS);
+    let operations = env_count("VOLT_YCSB_OPERATIONS", DEFAULT_OPERATIONS);
+    let chooser = KeyChooser::new(records, "zipfian".parse::<KeyDistribution>().unwrap());
+
+    let mut group = c.benchmark_group("YCSB");
+    group.throughput(Throughput::Elements(operations));
+    // Scans visit the whole keyspace, so keep the sample count modest
+    group.sample_size(10);
+
+    for workload in YcsbWorkload::ALL {
+        let mut cluster = KVCluster::new(100, REPLICATION_FACTOR);
+        let mut rng = SmallRng::seed_from_u64(42);
+        rt.block_on(async {
+            for i in 0..NODES {
+                cluster.add_node(format!("node{}", i));
+            }
+            for index in 0..records {
+                cluster.set(ycsb_key(index), record(&mut rng), None).await.unwrap();
+            }
+        });
+        let inserted = AtomicU64::new(records);
+
+        group.bench_with_input(BenchmarkId::new(workload.name(), records), &workload, |b, &workload| {
+            b.iter(|| {
+                rt.block_on(run_ops(&cluster, workload, &chooser, &inserted, operations, &mut rng))
+            })
+        });
+    }
+
+    group.finish();
+}
@@ -0,0 +1,81 @@
+//! Heap memory held per key for values on both sides of the inline capacity.
+//!
+//! Run with `cargo bench --bench value_memory`. It reads the heap usage glibc
+//! reports, allocator chunk overhead included, so it only measures on Linux
+//! with glibc. It runs on its own rather than as a criterion group, so the
+//! timing benches keep the system allocator as it is.
+
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+mod measure {
+    use tokio::runtime::Runtime;
+    use volt::value::INLINE_CAPACITY;
+    use volt::KVCluster;
+
+    /// Keys stored per value size
+    const KEYS: usize = 100_000;
+
+    const SIZES: &[usize] = &[8, 16, INLINE_CAPACITY, INLINE_CAPACITY + 1, 32, 62, 128];
+
+    mod glibc {
+        #[repr(C)]
+        struct MallInfo2 {
+            arena: usize,
+            ordblks: usize,
+            smblks: usize,
+            hblks: usize,
+            hblkhd: usize,
+            usmblks: usize,
+            fsmblks: usize,
+            uordblks: usize,
+            fordblks: usize,
+            keepcost: usize,
+        }
+
+        extern "C" {
+            fn mallinfo2() -> MallInfo2;
+        }
+
+        /// Heap bytes in use across all arenas, chunk headers and rounding
+        /// included
+        pub fn heap_in_use() -> usize {
+            // SAFETY: mallinfo2 takes no arguments and returns a plain struct
+            let info = unsafe { mallinfo2() };
+            info.uordblks + info.hblkhd
+        }
+    }
+
+    /// Heap bytes held per key once `KEYS` values of `size` bytes are stored on
+    /// a single node, store and TTL bookkeeping included
+    fn bytes_per_key(rt: &Runtime, size: usize) -> usize {
+        let value: Vec<u8> = (0..size).map(|i| (i % 256) as u8).collect();
+        let keys: Vec<String> = (0..KEYS).map(|i| format!("key_{}", i)).collect();
+        let mut cluster = KVCluster::new(100, 1);
+        rt.block_on(async {
+            cluster.add_node("node1".to_string());
+        });
+        let before = glibc::heap_in_use();
+        rt.block_on(async {
+            for key in &keys {
+                cluster.set(key.as_str(), value.clone(), None).await.unwrap();
+            }
+        });
+        let after = glibc::heap_in_use();
+        after.saturating_sub(before) / KEYS
+    }
+
+    pub fn run() {
+        let rt = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        println!("Heap bytes per key, {} keys per size, inline up to {} bytes", KEYS, INLINE_CAPACITY);
+        println!("{:>10} {:>14}", "value size", "bytes per key");
+        for &size in SIZES {
+            println!("{:>10} {:>14}", size, bytes_per_key(&rt, size));
+        }
+    }
+}
+
+fn main() {
+    #[cfg(all(target_os = "linux", target_env = "gnu"))]
+    measure::run();
+    #[cfg(not(all(target_os = "linux", target_env = "gnu")))]
+    eprintln!("value_memory reads glibc's heap statistics and only runs on Linux with glibc");
+}
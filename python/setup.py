@@ -9,6 +9,9 @@ setup(
     install_requires=[
         "requests>=2.28.0",
     ],
+    extras_require={
+        "routing": ["xxhash>=3.0"],
+    },
     classifiers=[
         "Development Status :: 3 - Alpha",
         "Intended Audience :: Developers",
@@ -1,22 +1,123 @@
+import bisect
 import requests
 import json
 from typing import Any, Dict, Optional, Union
 
+try:
+    import xxhash
+except ImportError:  # only needed for client-side routing
+    xxhash = None
+
+RING_VERSION_HEADER = "X-Volt-Ring-Version"
+
+# Revision of the server HTTP API this client speaks
+API_REVISION = 1
+
+# Optional server features this client knows how to use
+CLIENT_FEATURES = {"namespaces", "client_tracking"}
+
 
 class VoltClient:
     """
     Python client for the Volt key-value database.
     """
     
-    def __init__(self, host: str = "localhost", port: int = 3000):
+    def __init__(self, host: str = "localhost", port: int = 3000, client_side_routing: bool = False):
         """
         Initialize the Volt client.
         
         Args:
             host: The hostname of the Volt server
             port: The port of the Volt server
+            client_side_routing: Route each key straight to the address advertised
+                by its owning node, using the ring from /admin/ring. Requires the
+                'xxhash' package. Falls back to routing through the server when
+                the server hashes keys differently.
         """
         self.base_url = f"http://{host}:{port}"
+        self.client_side_routing = client_side_routing
+        self.ring_version: Optional[int] = None
+        self._ring_hashes: list = []
+        self._ring_nodes: list = []
+        self._node_urls: Dict[str, str] = {}
+        self._hash_seed = 0
+        self.server_version: Optional[str] = None
+        self.api_revision: Optional[int] = None
+        self.features: set = set()
+        if client_side_routing:
+            if xxhash is None:
+                raise ImportError("client_side_routing requires the 'xxhash' package")
+            self.negotiate()
+            if self.client_side_routing:
+                self.refresh_ring()
+    
+    def negotiate(self) -> None:
+        """
+        Agree with the server on the API revision and features to use.
+        
+        Uses the lower of the client's and the server's API revisions and only
+        the features both sides have. A server older than /version is treated
+        as revision 0 without optional features. Client-side routing is turned
+        off when the server hashes keys differently than this client.
+        
+        Raises:
+            RuntimeError: If the server no longer serves this client's revision
+        """
+        response = requests.get(f"{self.base_url}/version")
+        if response.status_code == 404:
+            self.server_version = None
+            self.api_revision = 0
+            self.features = set()
+            return
+        response.raise_for_status()
+        info = response.json()
+        if API_REVISION < info.get("min_client_api_revision", 0):
+            raise RuntimeError(
+                f"Volt server {info['version']} needs clients of API revision "
+                f"{info['min_client_api_revision']} or later; this client is revision {API_REVISION}"
+            )
+        self.server_version = info["version"]
+        self.api_revision = min(API_REVISION, info["api_revision"])
+        server_features = {name for name, enabled in info.get("features", {}).items() if enabled}
+        self.features = CLIENT_FEATURES & server_features
+        if info.get("ring_hash_algorithm") != "xxh32":
+            self.client_side_routing = False
+    
+    def supports(self, feature: str) -> bool:
+        """Whether both the client and the server have an optional feature."""
+        if self.api_revision is None:
+            self.negotiate()
+        return feature in self.features
+    
+    def refresh_ring(self) -> None:
+        """Fetch the current ring topology from the server."""
+        response = requests.get(f"{self.base_url}/admin/ring")
+        response.raise_for_status()
+        ring = response.json()
+        if ring["hash_algorithm"] != "xxh32":
+            raise ValueError(f"Unsupported ring hash algorithm: {ring['hash_algorithm']}")
+        vnodes = sorted(ring["vnodes"], key=lambda v: v["hash"])
+        self._ring_hashes = [v["hash"] for v in vnodes]
+        self._ring_nodes = [v["node"] for v in vnodes]
+        self._node_urls = {n["id"]: n["addr"] for n in ring["nodes"] if n.get("addr")}
+        self._hash_seed = ring["hash_seed"]
+        self.ring_version = ring["version"]
+    
+    def _url_for(self, key: str) -> str:
+        """Base URL of the node owning the key, or the configured server."""
+        if not self.client_side_routing or not self._ring_hashes:
+            return self.base_url
+        key_hash = xxhash.xxh32_intdigest(key.encode("utf-8"), seed=self._hash_seed)
+        index = bisect.bisect_left(self._ring_hashes, key_hash) % len(self._ring_hashes)
+        return self._node_urls.get(self._ring_nodes[index], self.base_url)
+    
+    def _observe(self, response: requests.Response) -> requests.Response:
+        """Refresh the ring when a response reports a newer version."""
+        if self.client_side_routing:
+            version = response.headers.get(RING_VERSION_HEADER)
+            if version is not None and int(version) != self.ring_version:
+                self.refresh_ring()
+        return response
     
     def health(self) -> bool:
         """Check if the Volt server is healthy."""
@@ -36,7 +137,7 @@ class VoltClient:
         Returns:
             The value as a string, or None if the key doesn't exist
         """
-        response = requests.get(f"{self.base_url}/kv/{key}")
+        response = self._observe(requests.get(f"{self._url_for(key)}/kv/{key}"))
         if response.status_code == 200:
             return response.json()["value"]
         return None
@@ -57,10 +158,10 @@ class VoltClient:
         if ttl_seconds is not None:
             data["ttl_seconds"] = ttl_seconds
             
-        response = requests.post(
-            f"{self.base_url}/kv/{key}",
+        response = self._observe(requests.post(
+            f"{self._url_for(key)}/kv/{key}",
             json=data
-        )
+        ))
         return response.status_code == 200
     
     def delete(self, key: str) -> bool:
@@ -73,7 +174,7 @@ class VoltClient:
         Returns:
             True if successful, False otherwise
         """
-        response = requests.delete(f"{self.base_url}/kv/{key}")
+        response = self._observe(requests.delete(f"{self._url_for(key)}/kv/{key}"))
         return response.status_code == 200
     
     def get_json(self, key: str) -> Optional[Dict[str, Any]]:
@@ -86,7 +187,7 @@ class VoltClient:
         Returns:
             The JSON value as a dictionary, or None if the key doesn't exist
         """
-        response = requests.get(f"{self.base_url}/json/{key}")
+        response = self._observe(requests.get(f"{self._url_for(key)}/json/{key}"))
         if response.status_code == 200:
             return response.json()["value"]
         return None
@@ -107,10 +208,10 @@ class VoltClient:
         if ttl_seconds is not None:
             data["ttl_seconds"] = ttl_seconds
             
-        response = requests.post(
-            f"{self.base_url}/json/{key}",
+        response = self._observe(requests.post(
+            f"{self._url_for(key)}/json/{key}",
             json=data
-        )
+        ))
         return response.status_code == 200
 
 
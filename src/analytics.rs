@@ -0,0 +1,164 @@
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+use tokio::time::Instant;
+
+use crate::key::key_name;
+use crate::{KVCluster, ValueType};
+
+/// Upper bounds of the value-size histogram buckets, in bytes
+const SIZE_BUCKETS: &[usize] = &[64, 256, 1 << 10, 4 << 10, 16 << 10, 64 << 10, 256 << 10, 1 << 20];
+
+/// Upper bounds of the remaining-TTL buckets
+const TTL_BUCKETS: &[(&str, Duration)] = &[
+    ("1m", Duration::from_secs(60)),
+    ("10m", Duration::from_secs(600)),
+    ("1h", Duration::from_secs(3600)),
+    ("1d", Duration::from_secs(86_400)),
+];
+
+/// Number of prefixes listed in each top-prefix table
+const TOP_PREFIXES: usize = 10;
+
+/// Separator between a key's prefix and the rest of the key
+const PREFIX_SEPARATOR: u8 = b':';
+
+#[derive(Serialize, Debug, Clone)]
+pub struct SizeBucket {
+    /// Inclusive upper bound in bytes; `None` for the overflow bucket
+    pub max_bytes: Option<usize>,
+    pub count: usize,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct TtlBucket {
+    /// `none` for keys without a TTL, `<=X` for keys expiring within X, `>X` for the rest
+    pub label: String,
+    pub count: usize,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct PrefixStats {
+    pub prefix: String,
+    pub keys: usize,
+    pub bytes: usize,
+}
+
+/// Summary of a sample of the keyspace, for capacity planning
+#[derive(Serialize, Debug, Clone)]
+pub struct KeyspaceReport {
+    pub sampled_keys: usize,
+    pub sampled_bytes: usize,
+    pub value_sizes: Vec<SizeBucket>,
+    pub ttl_distribution: Vec<TtlBucket>,
+    pub types: BTreeMap<ValueType, usize>,
+    pub top_prefixes_by_count: Vec<PrefixStats>,
+    pub top_prefixes_by_bytes: Vec<PrefixStats>,
+}
+
+fn size_bucket(len: usize) -> usize {
+    SIZE_BUCKETS
+        .iter()
+        .position(|max| len <= *max)
+        .unwrap_or(SIZE_BUCKETS.len())
+}
+
+fn ttl_bucket(remaining: Option<Duration>) -> usize {
+    match remaining {
+        None => 0,
+        Some(remaining) => {
+            1 + TTL_BUCKETS
+                .iter()
+                .position(|(_, max)| remaining <= *max)
+                .unwrap_or(TTL_BUCKETS.len())
+        }
+    }
+}
+
+fn prefix_of(key: &[u8]) -> String {
+    let prefix = key.split(|&b| b == PREFIX_SEPARATOR).next().unwrap_or(key);
+    key_name(prefix)
+}
+
+fn top_prefixes(
+    prefixes: &HashMap<String, (usize, usize)>,
+    rank: impl Fn(&(usize, usize)) -> usize,
+) -> Vec<PrefixStats> {
+    let mut ranked: Vec<_> = prefixes.iter().collect();
+    ranked.sort_by(|a, b| rank(b.1).cmp(&rank(a.1)).then_with(|| a.0.cmp(b.0)));
+    ranked
+        .into_iter()
+        .take(TOP_PREFIXES)
+        .map(|(prefix, (keys, bytes))| PrefixStats {
+            prefix: prefix.clone(),
+            keys: *keys,
+            bytes: *bytes,
+        })
+        .collect()
+}
+
+impl KVCluster {
+    /// Samples up to `sample_per_node` live keys from each node and summarizes
+    /// value sizes, TTLs, value types and key prefixes. Only primary copies are
+    /// counted so replicas don't inflate the figures.
+    pub fn keyspace_report(&self, sample_per_node: usize) -> KeyspaceReport {
+        let now = Instant::now();
+        let mut sizes = vec![0; SIZE_BUCKETS.len() + 1];
+        let mut ttls = vec![0; TTL_BUCKETS.len() + 2];
+        let mut types = BTreeMap::new();
+        let mut prefixes: HashMap<String, (usize, usize)> = HashMap::new();
+        let mut sampled_keys = 0;
+        let mut sampled_bytes = 0;
+
+        for (idx, node) in self.nodes.iter().enumerate() {
+            let sampled = node
+                .store
+                .iter()
+                .filter(|entry| entry.expiry.is_none_or(|expiry| expiry > now))
+                .filter(|entry| self.primary_idx(entry.key().as_bytes()) == Some(idx))
+                .take(sample_per_node);
+            for entry in sampled {
+                let len = entry.value.len();
+                sampled_keys += 1;
+                sampled_bytes += len;
+                sizes[size_bucket(len)] += 1;
+                ttls[ttl_bucket(entry.expiry.map(|expiry| expiry - now))] += 1;
+                *types.entry(ValueType::of(&entry.value)).or_insert(0) += 1;
+                let stats = prefixes.entry(prefix_of(entry.key().as_bytes())).or_insert((0, 0));
+                stats.0 += 1;
+                stats.1 += len;
+            }
+        }
+
+        let value_sizes = sizes
+            .into_iter()
+            .enumerate()
+            .map(|(i, count)| SizeBucket {
+                max_bytes: SIZE_BUCKETS.get(i).copied(),
+                count,
+            })
+            .collect();
+        let ttl_distribution = ttls
+            .into_iter()
+            .enumerate()
+            .map(|(i, count)| {
+                let label = match i {
+                    0 => "none".to_string(),
+                    i if i <= TTL_BUCKETS.len() => format!("<={}", TTL_BUCKETS[i - 1].0),
+                    _ => format!(">{}", TTL_BUCKETS[TTL_BUCKETS.len() - 1].0),
+                };
+                TtlBucket { label, count }
+            })
+            .collect();
+
+        KeyspaceReport {
+            sampled_keys,
+            sampled_bytes,
+            value_sizes,
+            ttl_distribution,
+            types,
+            top_prefixes_by_count: top_prefixes(&prefixes, |(keys, _)| *keys),
+            top_prefixes_by_bytes: top_prefixes(&prefixes, |(_, bytes)| *bytes),
+        }
+    }
+}
@@ -1,29 +1,80 @@
 use axum::{
-    extract::{Path, State},
-    http::StatusCode,
-    response::IntoResponse,
-    routing::{get, post, delete},
-    Json, Router,
+    async_trait,
+    body::Bytes,
+    extract::ws::{close_code, CloseFrame, Message, WebSocket, WebSocketUpgrade},
+    extract::{DefaultBodyLimit, FromRequest, FromRequestParts, MatchedPath, Path, Query, Request, State},
+    http::{header, request::Parts, HeaderMap, HeaderValue, StatusCode},
+    middleware,
+    response::{IntoResponse, Response},
+    routing::{delete, get, head, post},
+    Extension, Json, Router,
 };
-use serde::{Deserialize, Serialize};
+use base64::alphabet;
+use base64::engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig};
+use base64::Engine;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::sync::Arc;
 use std::time::Duration;
 use tower_http::cors::{Any, CorsLayer};
-use tracing::info;
 
-use crate::KVCluster;
+use crate::auth::{auth_layer, AccessControl, AuditRecord, User};
+use crate::bulk::BulkDocument;
+use crate::config::VoltConfig;
+use crate::crdt::CrdtValue;
+use crate::dump::write_dump;
+use crate::failover::NodeStatus;
+use crate::health::HealthReport;
+use crate::idempotency::{idempotency_layer, IdempotencyStore, DEFAULT_IDEMPOTENCY_TTL};
+use crate::limits::{json_depth_exceeds, ApiLimits};
+use crate::projection::{project, Projection};
+use crate::quota::{NamespaceQuota, QuotaUsage};
+use crate::ratelimit::{rate_limit_layer, ClientQuotas};
+use crate::stream::{StreamEntry, StreamId, StreamLimits, DEFAULT_STREAM_MAX_LEN};
+use crate::tracking::{ClientId, TrackingMessage};
+use crate::trash::TrashedKey;
+use crate::version::VersionInfo;
+use crate::{AckMode, KVCluster, KVError, Key, ValueType, WriteOptions};
+
+/// Request header carrying a client deadline, in milliseconds, for write operations
+pub const TIMEOUT_HEADER: &str = "x-volt-timeout-ms";
+
+/// Request header overriding the replica ack mode for a write: `primary`,
+/// `all` or a replica count
+pub const ACK_HEADER: &str = "x-volt-ack";
+
+/// Response header carrying the ring version, so routing clients can spot a stale ring
+pub const RING_VERSION_HEADER: &str = "x-volt-ring-version";
+
+/// Request header naming the tracking client a read is made for, as given
+/// in the hello message of the `/tracking` WebSocket
+pub const TRACKING_HEADER: &str = "x-volt-tracking";
+
+/// Most keys sent in one invalidation message
+const MAX_INVALIDATION_BATCH: usize = 256;
+
+/// Keys sampled per node by the keyspace report when no sample size is given
+const DEFAULT_KEYSPACE_SAMPLE: usize = 1000;
+
+/// Stream entries returned by a read that does not set `count`
+const DEFAULT_STREAM_READ_COUNT: usize = 100;
 
 // Request and response types
 #[derive(Deserialize)]
 pub struct SetRequest {
     value: String,
     ttl_seconds: Option<u64>,
+    /// Restart the TTL on every read
+    #[serde(default)]
+    sliding_ttl: bool,
 }
 
 #[derive(Deserialize)]
 pub struct SetJsonRequest {
     value: serde_json::Value,
     ttl_seconds: Option<u64>,
+    #[serde(default)]
+    sliding_ttl: bool,
 }
 
 #[derive(Serialize)]
@@ -31,11 +82,252 @@ pub struct GetResponse {
     value: String,
 }
 
+#[derive(Deserialize)]
+pub struct RenameRequest {
+    /// A string, or `{"base64": "..."}` for a binary key
+    to: Key,
+}
+
+#[derive(Deserialize)]
+pub struct CopyRequest {
+    to: Key,
+    #[serde(default)]
+    replace: bool,
+}
+
+#[derive(Deserialize)]
+pub struct AcquireLockRequest {
+    ttl_ms: u64,
+}
+
+#[derive(Serialize)]
+pub struct LockResponse {
+    /// Token renewing and releasing the lock
+    token: String,
+    ttl_ms: u64,
+}
+
+#[derive(Deserialize)]
+pub struct RenewLockRequest {
+    token: String,
+    ttl_ms: u64,
+}
+
+#[derive(Deserialize)]
+pub struct ReleaseLockRequest {
+    token: String,
+}
+
+#[derive(Serialize)]
+pub struct KeyTypeResponse {
+    #[serde(rename = "type")]
+    value_type: ValueType,
+}
+
 #[derive(Serialize)]
 pub struct GetJsonResponse {
     value: serde_json::Value,
 }
 
+#[derive(Deserialize)]
+pub struct ToggleRequest {
+    enabled: bool,
+}
+
+#[derive(Serialize)]
+pub struct NodeStatusResponse {
+    node: String,
+    status: NodeStatus,
+}
+
+#[derive(Serialize)]
+pub struct WriteModesResponse {
+    read_only: bool,
+    maintenance: bool,
+}
+
+#[derive(Deserialize)]
+pub struct BulkJsonItem {
+    key: String,
+    value: serde_json::Value,
+    #[serde(alias = "ttl")]
+    ttl_seconds: Option<u64>,
+}
+
+#[derive(Serialize)]
+pub struct BulkItemResult {
+    key: String,
+    success: bool,
+    /// HTTP status the item would have received as a single write
+    status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct BulkJsonResponse {
+    succeeded: usize,
+    failed: usize,
+    results: Vec<BulkItemResult>,
+}
+
+#[derive(Serialize)]
+pub struct BlobResponse {
+    hash: String,
+    refs: u64,
+}
+
+#[derive(Deserialize)]
+pub struct ExpirePrefixRequest {
+    prefix: String,
+    /// Time left to every key under the prefix; 0 deletes them at once
+    ttl_ms: u64,
+}
+
+#[derive(Serialize)]
+pub struct ExpirePrefixResponse {
+    keys: usize,
+}
+
+#[derive(Deserialize)]
+pub struct NamespaceQuotaRequest {
+    namespace: String,
+    /// `null` removes the namespace's quota
+    quota: Option<NamespaceQuota>,
+}
+
+#[derive(Serialize)]
+pub struct NamespaceQuotasResponse {
+    namespaces: BTreeMap<String, QuotaUsage>,
+}
+
+#[derive(Deserialize)]
+pub struct SlidingNamespaceRequest {
+    namespace: String,
+    enabled: bool,
+}
+
+#[derive(Serialize)]
+pub struct SlidingNamespacesResponse {
+    namespaces: Vec<String>,
+}
+
+#[derive(Deserialize)]
+pub struct SensitiveFieldsRequest {
+    namespace: String,
+    /// JSONPaths such as `$.email`; an empty list stops encrypting the namespace
+    paths: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct SensitiveFieldsResponse {
+    namespaces: BTreeMap<String, Vec<String>>,
+}
+
+#[derive(Deserialize)]
+pub struct SoftDeleteRequest {
+    namespace: String,
+    /// How long deleted keys can be restored; `null` turns soft delete off
+    retention_seconds: Option<u64>,
+}
+
+#[derive(Serialize)]
+pub struct SoftDeleteResponse {
+    /// Retention window of each namespace, in seconds
+    namespaces: BTreeMap<String, u64>,
+}
+
+#[derive(Serialize)]
+pub struct PurgeResponse {
+    purged: usize,
+}
+
+#[derive(Deserialize)]
+pub struct GetJsonQuery {
+    #[serde(default)]
+    decrypt: bool,
+    /// Comma-separated fields to return instead of the whole document
+    fields: Option<String>,
+}
+
+/// Bearer tokens allowed to read decrypted JSON documents when no users are
+/// configured
+#[derive(Clone, Default)]
+struct DecryptTokens(Arc<Vec<String>>);
+
+impl DecryptTokens {
+    fn allows(&self, headers: &HeaderMap) -> bool {
+        let token = headers
+            .get(header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "));
+        token.is_some_and(|token| self.0.iter().any(|allowed| allowed == token.trim()))
+    }
+}
+
+#[derive(Deserialize)]
+pub struct MergeCrdtRequest {
+    value: CrdtValue,
+    ttl_seconds: Option<u64>,
+}
+
+#[derive(Deserialize)]
+pub struct IncrementRequest {
+    delta: i64,
+}
+
+#[derive(Deserialize)]
+pub struct StreamAddRequest {
+    fields: BTreeMap<String, String>,
+    /// Replaces the stream's length cap
+    max_len: Option<usize>,
+    /// Replaces the stream's maximum entry age
+    max_age_seconds: Option<u64>,
+}
+
+#[derive(Serialize)]
+pub struct StreamAddResponse {
+    id: StreamId,
+}
+
+#[derive(Deserialize)]
+pub struct StreamReadQuery {
+    /// Return entries after this id; the whole stream by default
+    after: Option<StreamId>,
+    count: Option<usize>,
+}
+
+#[derive(Serialize)]
+pub struct StreamReadResponse {
+    entries: Vec<StreamEntry>,
+}
+
+#[derive(Deserialize)]
+pub struct StreamGroupRequest {
+    group: String,
+    /// Deliver entries after this id; the whole stream by default
+    after: Option<StreamId>,
+}
+
+#[derive(Deserialize)]
+pub struct StreamGroupReadRequest {
+    group: String,
+    count: Option<usize>,
+}
+
+#[derive(Serialize)]
+pub struct StreamGroupsResponse {
+    /// Last id delivered to each group
+    groups: BTreeMap<String, StreamId>,
+}
+
+#[derive(Serialize)]
+pub struct CrdtResponse {
+    /// Plain view of the value: a number, a register's value or a set's members
+    value: serde_json::Value,
+    state: CrdtValue,
+}
+
 #[derive(Serialize)]
 pub struct ApiResponse {
     success: bool,
@@ -44,34 +336,702 @@ pub struct ApiResponse {
 
 // API handlers
 pub async fn create_api_router(cluster: Arc<KVCluster>) -> Router {
+    create_api_router_with_config(cluster, &VoltConfig::default()).await
+}
+
+/// Builds the API router with the request limits and client quotas in `config`
+pub async fn create_api_router_with_config(cluster: Arc<KVCluster>, config: &VoltConfig) -> Router {
+    let limits = config.api_limits;
     // Configure CORS
     let cors = CorsLayer::new()
         .allow_origin(Any)
         .allow_methods(Any)
         .allow_headers(Any);
 
-    Router::new()
+    let idempotency = Arc::new(IdempotencyStore::new(DEFAULT_IDEMPOTENCY_TTL));
+    IdempotencyStore::spawn_purger(&idempotency);
+
+    let router = Router::new()
         .route("/health", get(health_check))
+        .route("/health/live", get(liveness_check))
+        .route("/health/ready", get(readiness_check))
+        .route("/stats", get(get_stats))
+        .route("/version", get(get_version))
+        .route("/tracking", get(track_invalidations))
+        .route("/admin/keyspace-report", get(keyspace_report))
+        .route("/admin/ring", get(get_ring))
+        .route("/admin/consistency-probe", get(consistency_probe))
+        .route("/admin/export", get(export_dump))
+        .route("/admin/nodes/:node/down", post(mark_node_down))
+        .route("/admin/nodes/:node/up", post(mark_node_up))
+        .route("/admin/readonly", get(get_write_modes).post(set_read_only))
+        .route("/admin/maintenance", get(get_write_modes).post(set_maintenance))
+        .route("/admin/prefix/expire", post(expire_prefix))
+        .route("/admin/quotas", get(get_namespace_quotas).post(set_namespace_quota))
+        .route("/admin/sliding-ttl", get(get_sliding_namespaces).post(set_sliding_namespace))
+        .route("/admin/sensitive-fields", get(get_sensitive_fields).post(set_sensitive_fields))
+        .route("/admin/soft-delete", get(get_soft_delete).post(set_soft_delete))
+        .route("/admin/trash", get(get_trash).delete(purge_trash))
+        .route("/admin/trash/:key/restore", post(restore_key))
         .route("/kv/:key", get(get_value))
         .route("/kv/:key", post(set_value))
         .route("/kv/:key", delete(delete_value))
+        .route("/kv/:key", head(key_exists))
+        .route("/kv/:key/type", get(get_key_type))
+        .route("/kv/:key/meta", get(get_object_info))
+        .route("/kv/:key/rename", post(rename_key))
+        .route("/kv/:key/copy", post(copy_key))
+        .route("/kv/:key/touch", post(touch_key))
+        .route("/lock/:key", post(acquire_lock))
+        .route("/lock/:key/renew", post(renew_lock))
+        .route("/lock/:key/release", post(release_lock))
+        .route("/json/_bulk", post(set_json_bulk))
         .route("/json/:key", get(get_json_value))
         .route("/json/:key", post(set_json_value))
+        .route("/json/:key/rotate-encryption", post(rotate_json_encryption))
+        .route("/blob", post(put_blob))
+        .route("/blob/:hash", get(get_blob))
+        .route("/blob/:hash", delete(release_blob))
+        .route("/stream/:key", get(read_stream).post(append_stream))
+        .route("/stream/:key/groups", get(get_stream_groups).post(create_stream_group))
+        .route("/stream/:key/groups/read", post(read_stream_group))
+        .route("/crdt/:key", get(get_crdt))
+        .route("/crdt/:key", post(merge_crdt))
+        .route("/crdt/:key/incr", post(increment_counter));
+    #[cfg(feature = "chaos")]
+    let router = router.route("/admin/chaos", get(get_chaos).post(set_chaos).delete(clear_chaos));
+
+    // Roles are only enforced once users are configured
+    let access = (!config.users.is_empty()).then(|| Arc::new(AccessControl::new(config.users.iter().cloned())));
+    let router = match &access {
+        Some(access) => router
+            .route("/admin/audit", get(get_audit_log))
+            .layer(Extension(access.clone())),
+        None => router,
+    };
+
+    let router = router
+        .layer(middleware::from_fn_with_state(idempotency, idempotency_layer))
+        .layer(middleware::map_response_with_state(cluster.clone(), ring_version_header));
+
+    // Authorize before idempotent responses are replayed
+    let router = match &access {
+        Some(access) => router.layer(middleware::from_fn_with_state(access.clone(), auth_layer)),
+        None => router,
+    };
+
+    // Throttle before bodies are buffered
+    let router = match config.rate_limit {
+        Some(rate_limit) => {
+            let quotas = ClientQuotas::new(rate_limit);
+            let quotas = Arc::new(match access {
+                Some(access) => quotas.with_access(access),
+                None => quotas,
+            });
+            ClientQuotas::spawn_purger(&quotas);
+            router.layer(middleware::from_fn_with_state(quotas, rate_limit_layer))
+        }
+        None => router,
+    };
+
+    router
+        .layer(DefaultBodyLimit::max(limits.max_body_bytes))
+        .layer(Extension(limits))
+        .layer(Extension(DecryptTokens(Arc::new(config.decrypt_tokens.clone()))))
         .layer(cors)
         .with_state(cluster)
 }
 
+pub(crate) fn error_response(status: StatusCode, message: String) -> Response {
+    (status, Json(ApiResponse { success: false, message })).into_response()
+}
+
+fn kv_error_status(err: &KVError) -> StatusCode {
+    match err {
+        KVError::Timeout => StatusCode::GATEWAY_TIMEOUT,
+        KVError::Json(_) | KVError::Encryption(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        KVError::WrongType(_) | KVError::SchemaMismatch { .. } => StatusCode::CONFLICT,
+        KVError::KeyNotFound(_) => StatusCode::NOT_FOUND,
+        KVError::KeyExists(_) => StatusCode::CONFLICT,
+        KVError::NotEnoughReplicas { .. } => StatusCode::SERVICE_UNAVAILABLE,
+        KVError::ValueTooLarge { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+        KVError::QuotaExceeded { .. } => StatusCode::INSUFFICIENT_STORAGE,
+        KVError::NodeUnavailable(_) | KVError::ReadOnly | KVError::Maintenance => {
+            StatusCode::SERVICE_UNAVAILABLE
+        }
+    }
+}
+
+fn kv_error_response(err: KVError) -> Response {
+    error_response(kv_error_status(&err), err.to_string())
+}
+
+/// Write options supplied by the client through request headers
+pub struct ClientWriteOptions(pub WriteOptions);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for ClientWriteOptions {
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let header = |name: &str| parts.headers.get(name).map(|v| v.to_str().unwrap_or_default());
+        let timeout = match header(TIMEOUT_HEADER) {
+            None => None,
+            Some(value) => match value.trim().parse::<u64>() {
+                Ok(ms) => Some(Duration::from_millis(ms)),
+                Err(_) => {
+                    return Err(error_response(
+                        StatusCode::BAD_REQUEST,
+                        format!("Invalid {} header: expected milliseconds", TIMEOUT_HEADER),
+                    ))
+                }
+            },
+        };
+        let ack = match header(ACK_HEADER) {
+            None => None,
+            Some(value) => match value.parse::<AckMode>() {
+                Ok(ack) => Some(ack),
+                Err(e) => {
+                    return Err(error_response(
+                        StatusCode::BAD_REQUEST,
+                        format!("Invalid {} header: {}", ACK_HEADER, e),
+                    ))
+                }
+            },
+        };
+        Ok(ClientWriteOptions(WriteOptions::new(timeout, ack)))
+    }
+}
+
+/// The tracking client a read is made for, if the client sent one
+pub struct TrackingClient(pub Option<ClientId>);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for TrackingClient {
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        match parts.headers.get(TRACKING_HEADER) {
+            None => Ok(TrackingClient(None)),
+            Some(value) => match value.to_str().unwrap_or_default().trim().parse::<ClientId>() {
+                Ok(client) => Ok(TrackingClient(Some(client))),
+                Err(_) => Err(error_response(
+                    StatusCode::BAD_REQUEST,
+                    format!("Invalid {} header: expected a client id", TRACKING_HEADER),
+                )),
+            },
+        }
+    }
+}
+
+/// Tracks `key` for the client a read is made for, or a 400 response if the
+/// client is not connected, since its cache would miss the key's invalidations
+fn track_read_rejection(cluster: &KVCluster, client: Option<ClientId>, key: impl AsRef<[u8]>) -> Option<Response> {
+    let client = client?;
+    (!cluster.track_read(client, key)).then(|| {
+        error_response(StatusCode::BAD_REQUEST, format!("Tracking client {} is not connected", client))
+    })
+}
+
+fn request_limits(extensions: &axum::http::Extensions) -> ApiLimits {
+    extensions.get::<ApiLimits>().copied().unwrap_or_default()
+}
+
+/// 400 response for a key longer than the key limit
+fn key_len_rejection(limits: &ApiLimits, key: impl AsRef<[u8]>) -> Option<Response> {
+    key_len_error(limits, key).map(|message| error_response(StatusCode::BAD_REQUEST, message))
+}
+
+fn key_len_error(limits: &ApiLimits, key: impl AsRef<[u8]>) -> Option<String> {
+    let len = key.as_ref().len();
+    (len > limits.max_key_bytes)
+        .then(|| format!("Key of {} bytes exceeds the {} byte limit", len, limits.max_key_bytes))
+}
+
+/// Decodes `%XX` escapes into raw bytes, so a path segment can carry any
+/// byte rather than only valid UTF-8
+fn percent_decode(segment: &str) -> Result<Vec<u8>, String> {
+    let bytes = segment.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = bytes
+                .get(i + 1..i + 3)
+                .and_then(|hex| std::str::from_utf8(hex).ok())
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                .ok_or_else(|| format!("Invalid percent-encoding in key '{}'", segment))?;
+            decoded.push(hex);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    Ok(decoded)
+}
+
+#[derive(Deserialize)]
+struct KeyEncodingQuery {
+    key_encoding: Option<String>,
+}
+
+/// Reads the `:key` path parameter. Keys are percent-decoded into raw bytes;
+/// with `?key_encoding=base64` the parameter is URL-safe base64 instead,
+/// for clients that would rather not escape binary keys byte by byte.
+async fn path_key<S: Send + Sync>(parts: &mut Parts, state: &S) -> Result<Key, Response> {
+    // Axum's own path parameters must be UTF-8, so the key is taken from the
+    // raw path at the position of `:key` in the matched route
+    let matched = MatchedPath::from_request_parts(parts, state)
+        .await
+        .map_err(|e| error_response(e.status(), e.body_text()))?;
+    let raw = matched
+        .as_str()
+        .split('/')
+        .position(|segment| segment == ":key")
+        .and_then(|position| parts.uri.path().split('/').nth(position));
+    let Some(raw) = raw else {
+        return Err(error_response(StatusCode::INTERNAL_SERVER_ERROR, "No key in route".to_string()));
+    };
+    let bytes = percent_decode(raw).map_err(|message| error_response(StatusCode::BAD_REQUEST, message))?;
+    let Query(query) = Query::<KeyEncodingQuery>::try_from_uri(&parts.uri)
+        .map_err(|e| error_response(e.status(), e.body_text()))?;
+    let key = match query.key_encoding.as_deref() {
+        None | Some("utf8") | Some("percent") => Key::from(bytes),
+        Some("base64") => BASE64_KEY
+            .decode(&bytes)
+            .map(Key::from)
+            .map_err(|e| error_response(StatusCode::BAD_REQUEST, format!("Invalid base64 key: {}", e)))?,
+        Some(other) => {
+            return Err(error_response(
+                StatusCode::BAD_REQUEST,
+                format!("Invalid key_encoding '{}': expected percent or base64", other),
+            ))
+        }
+    };
+    match key_len_rejection(&request_limits(&parts.extensions), &key) {
+        Some(rejection) => Err(rejection),
+        None => Ok(key),
+    }
+}
+
+/// Base64 of binary keys in paths: URL-safe alphabet, padding optional
+const BASE64_KEY: GeneralPurpose = GeneralPurpose::new(
+    &alphabet::URL_SAFE,
+    GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
+
+/// Key path parameter, rejected with 400 when longer than the key limit
+pub struct KeyPath(pub Key);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for KeyPath {
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        path_key(parts, state).await.map(KeyPath)
+    }
+}
+
+/// Key path parameter of routes for typed values (JSON documents, CRDTs,
+/// streams), whose keys must be UTF-8
+pub struct TextKeyPath(pub String);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for TextKeyPath {
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let key = path_key(parts, state).await?;
+        match key.as_str() {
+            Some(key) => Ok(TextKeyPath(key.to_string())),
+            None => Err(error_response(
+                StatusCode::BAD_REQUEST,
+                format!("Key '{}' is not valid UTF-8", key),
+            )),
+        }
+    }
+}
+
+/// JSON request body, checked against the body size and nesting depth limits
+/// before it is deserialized. Rejections use the API's error format.
+pub struct JsonBody<T>(pub T);
+
+#[async_trait]
+impl<S: Send + Sync, T: DeserializeOwned> FromRequest<S> for JsonBody<T> {
+    type Rejection = Response;
+
+    async fn from_request(request: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let is_json = request
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.split(';').next())
+            .map(|v| v.trim().to_ascii_lowercase())
+            .is_some_and(|v| v == "application/json" || v.ends_with("+json"));
+        if !is_json {
+            return Err(error_response(
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                "Expected request with `Content-Type: application/json`".to_string(),
+            ));
+        }
+        let limits = request_limits(request.extensions());
+        let body = Bytes::from_request(request, state).await.map_err(|e| match e.status() {
+            StatusCode::PAYLOAD_TOO_LARGE => error_response(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                format!("Request body exceeds the {} byte limit", limits.max_body_bytes),
+            ),
+            status => error_response(status, e.body_text()),
+        })?;
+        let max_depth = limits.max_json_depth;
+        if json_depth_exceeds(&body, max_depth) {
+            return Err(error_response(
+                StatusCode::BAD_REQUEST,
+                format!("JSON body nests deeper than {} levels", max_depth),
+            ));
+        }
+        serde_json::from_slice(&body).map(JsonBody).map_err(|e| {
+            let status = match e.classify() {
+                serde_json::error::Category::Data => StatusCode::UNPROCESSABLE_ENTITY,
+                _ => StatusCode::BAD_REQUEST,
+            };
+            error_response(status, format!("Invalid JSON body: {}", e))
+        })
+    }
+}
+
+async fn ring_version_header(State(cluster): State<Arc<KVCluster>>, mut response: Response) -> Response {
+    response
+        .headers_mut()
+        .insert(RING_VERSION_HEADER, HeaderValue::from(cluster.ring_version()));
+    response
+}
+
 // Health check endpoint
 async fn health_check() -> impl IntoResponse {
     StatusCode::OK
 }
 
+fn health_response(report: HealthReport) -> impl IntoResponse {
+    let status = if report.is_ok() {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(report))
+}
+
+// Liveness probe: node background tasks are running
+async fn liveness_check(State(cluster): State<Arc<KVCluster>>) -> impl IntoResponse {
+    health_response(cluster.liveness())
+}
+
+// Readiness probe: tasks are running and replica channels are not saturated
+async fn readiness_check(State(cluster): State<Arc<KVCluster>>) -> impl IntoResponse {
+    health_response(cluster.readiness())
+}
+
+// Cluster and per-node counters
+async fn get_stats(State(cluster): State<Arc<KVCluster>>) -> impl IntoResponse {
+    Json(cluster.stats())
+}
+
+// Server release, API revision and features, for clients to negotiate with
+async fn get_version() -> impl IntoResponse {
+    Json(VersionInfo::current())
+}
+
+// Connect a tracking client: the socket receives a hello with the client's
+// id, then invalidations for keys read with that id in the tracking header
+async fn track_invalidations(State(cluster): State<Arc<KVCluster>>, ws: WebSocketUpgrade) -> Response {
+    ws.on_upgrade(move |socket| serve_tracking(socket, cluster))
+}
+
+async fn serve_tracking(mut socket: WebSocket, cluster: Arc<KVCluster>) {
+    let mut client = cluster.track_client();
+    let hello = TrackingMessage::Hello { client_id: client.id() };
+    if send_tracking_message(&mut socket, &hello).await.is_err() {
+        return;
+    }
+    loop {
+        tokio::select! {
+            key = client.recv() => {
+                let Some(key) = key else {
+                    // Fell behind; the client must drop its whole cache
+                    let frame = CloseFrame {
+                        code: close_code::AGAIN,
+                        reason: "too many pending invalidations".into(),
+                    };
+                    let _ = socket.send(Message::Close(Some(frame))).await;
+                    return;
+                };
+                let mut keys = vec![key];
+                while keys.len() < MAX_INVALIDATION_BATCH {
+                    match client.try_recv() {
+                        Some(key) => keys.push(key),
+                        None => break,
+                    }
+                }
+                if send_tracking_message(&mut socket, &TrackingMessage::Invalidate { keys }).await.is_err() {
+                    return;
+                }
+            }
+            message = socket.recv() => match message {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return,
+                // Pings are answered by axum; clients have nothing else to say
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+}
+
+async fn send_tracking_message(socket: &mut WebSocket, message: &TrackingMessage) -> Result<(), axum::Error> {
+    let text = serde_json::to_string(message).map_err(axum::Error::new)?;
+    socket.send(Message::Text(text)).await
+}
+
+#[derive(Deserialize)]
+pub struct ConsistencyProbeQuery {
+    key: String,
+}
+
+#[derive(Deserialize)]
+pub struct KeyspaceReportQuery {
+    sample: Option<usize>,
+}
+
+// Sampled keyspace analytics for capacity planning
+async fn keyspace_report(
+    State(cluster): State<Arc<KVCluster>>,
+    Query(query): Query<KeyspaceReportQuery>,
+) -> impl IntoResponse {
+    let sample = query.sample.unwrap_or(DEFAULT_KEYSPACE_SAMPLE);
+    Json(cluster.keyspace_report(sample))
+}
+
+// Ring layout for clients that route keys to nodes themselves
+async fn get_ring(State(cluster): State<Arc<KVCluster>>) -> impl IntoResponse {
+    Json(cluster.topology())
+}
+
+// Every replica's copy of a key, to spot replicas that diverged
+async fn consistency_probe(
+    State(cluster): State<Arc<KVCluster>>,
+    Extension(limits): Extension<ApiLimits>,
+    Query(query): Query<ConsistencyProbeQuery>,
+) -> Response {
+    if let Some(rejection) = key_len_rejection(&limits, &query.key) {
+        return rejection;
+    }
+    Json(cluster.consistency_probe(&query.key)).into_response()
+}
+
+// Take a node out of service, failing its keys over to their replicas
+async fn mark_node_down(State(cluster): State<Arc<KVCluster>>, Path(node): Path<String>) -> Response {
+    if !cluster.mark_node_down(&node) {
+        return error_response(StatusCode::NOT_FOUND, format!("Node '{}' not found", node));
+    }
+    let status = cluster.node_status(&node);
+    Json(NodeStatusResponse { node, status }).into_response()
+}
+
+// Bring a node back into service once it has caught up
+async fn mark_node_up(State(cluster): State<Arc<KVCluster>>, Path(node): Path<String>) -> Response {
+    match cluster.mark_node_up(&node).await {
+        Ok(Some(report)) => Json(report).into_response(),
+        Ok(None) => error_response(StatusCode::NOT_FOUND, format!("Node '{}' not found", node)),
+        Err(e) => kv_error_response(e),
+    }
+}
+
+// Whether writes are currently rejected by the read-only or maintenance flags
+async fn get_write_modes(State(cluster): State<Arc<KVCluster>>) -> Json<WriteModesResponse> {
+    Json(WriteModesResponse {
+        read_only: cluster.is_read_only(),
+        maintenance: cluster.in_maintenance(),
+    })
+}
+
+// Toggle read-only mode
+async fn set_read_only(
+    State(cluster): State<Arc<KVCluster>>,
+    JsonBody(request): JsonBody<ToggleRequest>,
+) -> Json<WriteModesResponse> {
+    cluster.set_read_only(request.enabled);
+    get_write_modes(State(cluster)).await
+}
+
+// Toggle maintenance mode
+async fn set_maintenance(
+    State(cluster): State<Arc<KVCluster>>,
+    JsonBody(request): JsonBody<ToggleRequest>,
+) -> Json<WriteModesResponse> {
+    cluster.set_maintenance(request.enabled);
+    get_write_modes(State(cluster)).await
+}
+
+// Expire or delete every key under a prefix
+async fn expire_prefix(
+    State(cluster): State<Arc<KVCluster>>,
+    ClientWriteOptions(options): ClientWriteOptions,
+    JsonBody(request): JsonBody<ExpirePrefixRequest>,
+) -> Response {
+    // An empty prefix would match every key in the cluster
+    if request.prefix.is_empty() {
+        return error_response(StatusCode::BAD_REQUEST, "prefix must not be empty".to_string());
+    }
+    let ttl = Duration::from_millis(request.ttl_ms);
+    match cluster.expire_prefix_with_options(&request.prefix, ttl, options).await {
+        Ok(keys) => Json(ExpirePrefixResponse { keys }).into_response(),
+        Err(e) => kv_error_response(e),
+    }
+}
+
+// Key quotas of namespaces and how much of each is used
+async fn get_namespace_quotas(State(cluster): State<Arc<KVCluster>>) -> Json<NamespaceQuotasResponse> {
+    Json(NamespaceQuotasResponse {
+        namespaces: cluster.quota_usage(),
+    })
+}
+
+// Set or remove the key quota of a namespace
+async fn set_namespace_quota(
+    State(cluster): State<Arc<KVCluster>>,
+    JsonBody(request): JsonBody<NamespaceQuotaRequest>,
+) -> Response {
+    if let Err(message) = cluster.set_namespace_quota(&request.namespace, request.quota) {
+        return error_response(StatusCode::BAD_REQUEST, message);
+    }
+    get_namespace_quotas(State(cluster)).await.into_response()
+}
+
+// Namespaces whose keys have a sliding TTL
+async fn get_sliding_namespaces(State(cluster): State<Arc<KVCluster>>) -> Json<SlidingNamespacesResponse> {
+    Json(SlidingNamespacesResponse {
+        namespaces: cluster.sliding_namespaces(),
+    })
+}
+
+// Turn sliding TTLs on or off for a namespace
+async fn set_sliding_namespace(
+    State(cluster): State<Arc<KVCluster>>,
+    JsonBody(request): JsonBody<SlidingNamespaceRequest>,
+) -> Json<SlidingNamespacesResponse> {
+    cluster.set_sliding_namespace(&request.namespace, request.enabled);
+    get_sliding_namespaces(State(cluster)).await
+}
+
+// Recent admin requests, when access control is enabled
+async fn get_audit_log(Extension(access): Extension<Arc<AccessControl>>) -> Json<Vec<AuditRecord>> {
+    Json(access.audit_log())
+}
+
+// JSON fields encrypted per namespace
+async fn get_sensitive_fields(State(cluster): State<Arc<KVCluster>>) -> Json<SensitiveFieldsResponse> {
+    Json(SensitiveFieldsResponse {
+        namespaces: cluster.sensitive_fields(),
+    })
+}
+
+// Declare the JSON fields encrypted in a namespace
+async fn set_sensitive_fields(
+    State(cluster): State<Arc<KVCluster>>,
+    JsonBody(request): JsonBody<SensitiveFieldsRequest>,
+) -> Response {
+    let paths: Vec<&str> = request.paths.iter().map(String::as_str).collect();
+    if let Err(message) = cluster.set_sensitive_fields(&request.namespace, &paths) {
+        return error_response(StatusCode::BAD_REQUEST, message);
+    }
+    get_sensitive_fields(State(cluster)).await.into_response()
+}
+
+// Namespaces that keep deleted keys in the trash
+async fn get_soft_delete(State(cluster): State<Arc<KVCluster>>) -> Json<SoftDeleteResponse> {
+    Json(SoftDeleteResponse {
+        namespaces: cluster
+            .soft_delete_namespaces()
+            .into_iter()
+            .map(|(namespace, retention)| (namespace, retention.as_secs()))
+            .collect(),
+    })
+}
+
+// Turn soft delete on or off for a namespace
+async fn set_soft_delete(
+    State(cluster): State<Arc<KVCluster>>,
+    JsonBody(request): JsonBody<SoftDeleteRequest>,
+) -> Json<SoftDeleteResponse> {
+    cluster.set_soft_delete(&request.namespace, request.retention_seconds.map(Duration::from_secs));
+    get_soft_delete(State(cluster)).await
+}
+
+// Keys in the trash
+async fn get_trash(State(cluster): State<Arc<KVCluster>>) -> Json<Vec<TrashedKey>> {
+    Json(cluster.trash())
+}
+
+// Empty the trash
+async fn purge_trash(State(cluster): State<Arc<KVCluster>>) -> Json<PurgeResponse> {
+    Json(PurgeResponse { purged: cluster.purge() })
+}
+
+// Bring a deleted key back from the trash
+async fn restore_key(
+    State(cluster): State<Arc<KVCluster>>,
+    KeyPath(key): KeyPath,
+    ClientWriteOptions(options): ClientWriteOptions,
+) -> Response {
+    if let Err(e) = cluster.restore_with_options(&key, options).await {
+        return kv_error_response(e);
+    }
+    let message = format!("Key '{}' restored", key);
+    (StatusCode::OK, Json(ApiResponse { success: true, message })).into_response()
+}
+
+// Every live key as a newline-delimited JSON dump, for `volt-cli diff`
+async fn export_dump(State(cluster): State<Arc<KVCluster>>) -> Response {
+    let mut body = Vec::new();
+    if let Err(e) = write_dump(&cluster.export(), &mut body) {
+        return error_response(StatusCode::INTERNAL_SERVER_ERROR, format!("Export failed: {}", e));
+    }
+    ([(header::CONTENT_TYPE, "application/x-ndjson")], body).into_response()
+}
+
+// Faults currently injected into the cluster
+#[cfg(feature = "chaos")]
+async fn get_chaos(State(cluster): State<Arc<KVCluster>>) -> impl IntoResponse {
+    Json(cluster.faults().config())
+}
+
+// Replace the injected faults
+#[cfg(feature = "chaos")]
+async fn set_chaos(
+    State(cluster): State<Arc<KVCluster>>,
+    JsonBody(config): JsonBody<crate::chaos::ChaosConfig>,
+) -> Response {
+    match cluster.configure_chaos(config) {
+        Ok(()) => Json(cluster.faults().config()).into_response(),
+        Err(message) => error_response(StatusCode::BAD_REQUEST, message),
+    }
+}
+
+// Stop injecting faults
+#[cfg(feature = "chaos")]
+async fn clear_chaos(State(cluster): State<Arc<KVCluster>>) -> impl IntoResponse {
+    cluster.faults().reset();
+    StatusCode::NO_CONTENT
+}
+
 // Get a value
 async fn get_value(
     State(cluster): State<Arc<KVCluster>>,
-    Path(key): Path<String>,
+    KeyPath(key): KeyPath,
+    TrackingClient(client): TrackingClient,
 ) -> impl IntoResponse {
-    if let Some(value) = cluster.get(&key) {
+    if let Some(response) = track_read_rejection(&cluster, client, &key) {
+        return response;
+    }
+    if let Some(value) = cluster.get(&key).await {
         let value_str = String::from_utf8_lossy(&value).to_string();
         (StatusCode::OK, Json(GetResponse { value: value_str })).into_response()
     } else {
@@ -85,17 +1045,187 @@ async fn get_value(
     }
 }
 
+// Check whether a key exists without transferring its value
+async fn key_exists(State(cluster): State<Arc<KVCluster>>, KeyPath(key): KeyPath) -> StatusCode {
+    if cluster.contains_key(&key).await {
+        StatusCode::OK
+    } else {
+        StatusCode::NOT_FOUND
+    }
+}
+
+// Get the type of a stored value
+async fn get_key_type(State(cluster): State<Arc<KVCluster>>, KeyPath(key): KeyPath) -> Response {
+    match cluster.key_type(&key).await {
+        Some(value_type) => (StatusCode::OK, Json(KeyTypeResponse { value_type })).into_response(),
+        None => error_response(StatusCode::NOT_FOUND, format!("Key '{}' not found", key)),
+    }
+}
+
+// Creation time, access statistics and size of a key
+async fn get_object_info(State(cluster): State<Arc<KVCluster>>, KeyPath(key): KeyPath) -> Response {
+    match cluster.object_info(&key).await {
+        Some(info) => (StatusCode::OK, Json(info)).into_response(),
+        None => error_response(StatusCode::NOT_FOUND, format!("Key '{}' not found", key)),
+    }
+}
+
+// Rename a key, keeping its TTL
+async fn rename_key(
+    State(cluster): State<Arc<KVCluster>>,
+    Extension(limits): Extension<ApiLimits>,
+    KeyPath(key): KeyPath,
+    ClientWriteOptions(options): ClientWriteOptions,
+    JsonBody(payload): JsonBody<RenameRequest>,
+) -> Response {
+    if let Some(rejection) = key_len_rejection(&limits, &payload.to) {
+        return rejection;
+    }
+    if let Err(e) = cluster.rename_with_options(&key, &payload.to, options).await {
+        return kv_error_response(e);
+    }
+    (
+        StatusCode::OK,
+        Json(ApiResponse {
+            success: true,
+            message: format!("Key '{}' renamed to '{}'", key, payload.to),
+        }),
+    ).into_response()
+}
+
+// Copy a key, keeping its TTL
+async fn copy_key(
+    State(cluster): State<Arc<KVCluster>>,
+    Extension(limits): Extension<ApiLimits>,
+    KeyPath(key): KeyPath,
+    ClientWriteOptions(options): ClientWriteOptions,
+    JsonBody(payload): JsonBody<CopyRequest>,
+) -> Response {
+    if let Some(rejection) = key_len_rejection(&limits, &payload.to) {
+        return rejection;
+    }
+    if let Err(e) = cluster
+        .copy_with_options(&key, &payload.to, payload.replace, options)
+        .await
+    {
+        return kv_error_response(e);
+    }
+    (
+        StatusCode::OK,
+        Json(ApiResponse {
+            success: true,
+            message: format!("Key '{}' copied to '{}'", key, payload.to),
+        }),
+    ).into_response()
+}
+
+// Restart a key's TTL
+async fn touch_key(
+    State(cluster): State<Arc<KVCluster>>,
+    KeyPath(key): KeyPath,
+    ClientWriteOptions(options): ClientWriteOptions,
+) -> Response {
+    match cluster.touch_with_options(&key, options).await {
+        Ok(true) => (
+            StatusCode::OK,
+            Json(ApiResponse {
+                success: true,
+                message: format!("Key '{}' touched", key),
+            }),
+        ).into_response(),
+        Ok(false) => error_response(StatusCode::NOT_FOUND, format!("Key '{}' not found", key)),
+        Err(e) => kv_error_response(e),
+    }
+}
+
+fn lock_ttl_rejection() -> Response {
+    error_response(StatusCode::BAD_REQUEST, "ttl_ms must be positive".to_string())
+}
+
+// Acquire a lock; 409 while someone else holds it
+async fn acquire_lock(
+    State(cluster): State<Arc<KVCluster>>,
+    TextKeyPath(name): TextKeyPath,
+    ClientWriteOptions(options): ClientWriteOptions,
+    JsonBody(payload): JsonBody<AcquireLockRequest>,
+) -> Response {
+    if payload.ttl_ms == 0 {
+        return lock_ttl_rejection();
+    }
+    let ttl = Duration::from_millis(payload.ttl_ms);
+    match cluster.acquire_lock_with_options(&name, ttl, options).await {
+        Ok(Some(token)) => (
+            StatusCode::OK,
+            Json(LockResponse {
+                token,
+                ttl_ms: payload.ttl_ms,
+            }),
+        ).into_response(),
+        Ok(None) => error_response(StatusCode::CONFLICT, format!("Lock '{}' is held", name)),
+        Err(e) => kv_error_response(e),
+    }
+}
+
+// Extend a lock held by the given token; 409 once it has expired or changed hands
+async fn renew_lock(
+    State(cluster): State<Arc<KVCluster>>,
+    TextKeyPath(name): TextKeyPath,
+    ClientWriteOptions(options): ClientWriteOptions,
+    JsonBody(payload): JsonBody<RenewLockRequest>,
+) -> Response {
+    if payload.ttl_ms == 0 {
+        return lock_ttl_rejection();
+    }
+    let ttl = Duration::from_millis(payload.ttl_ms);
+    match cluster.renew_lock_with_options(&name, &payload.token, ttl, options).await {
+        Ok(true) => (
+            StatusCode::OK,
+            Json(LockResponse {
+                token: payload.token,
+                ttl_ms: payload.ttl_ms,
+            }),
+        ).into_response(),
+        Ok(false) => error_response(StatusCode::CONFLICT, format!("Lock '{}' is not held by this token", name)),
+        Err(e) => kv_error_response(e),
+    }
+}
+
+// Release a lock held by the given token
+async fn release_lock(
+    State(cluster): State<Arc<KVCluster>>,
+    TextKeyPath(name): TextKeyPath,
+    ClientWriteOptions(options): ClientWriteOptions,
+    JsonBody(payload): JsonBody<ReleaseLockRequest>,
+) -> Response {
+    match cluster.release_lock_with_options(&name, &payload.token, options).await {
+        Ok(true) => (
+            StatusCode::OK,
+            Json(ApiResponse {
+                success: true,
+                message: format!("Lock '{}' released", name),
+            }),
+        ).into_response(),
+        Ok(false) => error_response(StatusCode::CONFLICT, format!("Lock '{}' is not held by this token", name)),
+        Err(e) => kv_error_response(e),
+    }
+}
+
 // Set a value
 async fn set_value(
     State(cluster): State<Arc<KVCluster>>,
-    Path(key): Path<String>,
-    Json(payload): Json<SetRequest>,
-) -> impl IntoResponse {
-    let ttl = payload.ttl_seconds.map(|secs| Duration::from_secs(secs));
+    KeyPath(key): KeyPath,
+    ClientWriteOptions(mut options): ClientWriteOptions,
+    JsonBody(payload): JsonBody<SetRequest>,
+) -> Response {
+    let ttl = payload.ttl_seconds.map(Duration::from_secs);
+    options.sliding_ttl = payload.sliding_ttl;
     
-    cluster
-        .set(key.clone(), payload.value.as_bytes().to_vec(), ttl)
-        .await;
+    if let Err(e) = cluster
+        .set_with_options(key.clone(), payload.value.into_bytes(), ttl, options)
+        .await
+    {
+        return kv_error_response(e);
+    }
     
     (
         StatusCode::OK,
@@ -103,15 +1233,18 @@ async fn set_value(
             success: true,
             message: format!("Key '{}' set successfully", key),
         }),
-    )
+    ).into_response()
 }
 
 // Delete a value
 async fn delete_value(
     State(cluster): State<Arc<KVCluster>>,
-    Path(key): Path<String>,
-) -> impl IntoResponse {
-    cluster.del(&key).await;
+    KeyPath(key): KeyPath,
+    ClientWriteOptions(options): ClientWriteOptions,
+) -> Response {
+    if let Err(e) = cluster.del_with_options(&key, options).await {
+        return kv_error_response(e);
+    }
     
     (
         StatusCode::OK,
@@ -119,16 +1252,49 @@ async fn delete_value(
             success: true,
             message: format!("Key '{}' deleted successfully", key),
         }),
-    )
+    ).into_response()
 }
 
-// Get a JSON value
+// Get a JSON value; `?decrypt=true` decrypts sensitive fields for users
+// with the decrypt permission, or callers holding a decrypt token when no
+// users are configured, and `?fields=a,b.c` returns only the listed fields
 async fn get_json_value(
     State(cluster): State<Arc<KVCluster>>,
-    Path(key): Path<String>,
+    TextKeyPath(key): TextKeyPath,
+    Query(query): Query<GetJsonQuery>,
+    Extension(decrypt_tokens): Extension<DecryptTokens>,
+    user: Option<Extension<User>>,
+    TrackingClient(client): TrackingClient,
+    headers: HeaderMap,
 ) -> impl IntoResponse {
-    match cluster.get_json_value(&key) {
+    if let Some(response) = track_read_rejection(&cluster, client, &key) {
+        return response;
+    }
+    let fields = match query.fields.as_deref().map(Projection::parse_list).transpose() {
+        Ok(fields) => fields,
+        Err(e) => return error_response(StatusCode::BAD_REQUEST, e),
+    };
+    let select = |value: serde_json::Value| match &fields {
+        Some(fields) => project(&value, fields),
+        None => value,
+    };
+    if query.decrypt {
+        let allowed = match user {
+            Some(Extension(user)) => user.decrypt,
+            None => decrypt_tokens.allows(&headers),
+        };
+        if !allowed {
+            return error_response(StatusCode::FORBIDDEN, "not allowed to decrypt documents".to_string());
+        }
+        return match cluster.get_json_value_decrypted(&key).await {
+            Ok(Some(value)) => (StatusCode::OK, Json(GetJsonResponse { value: select(value) })).into_response(),
+            Ok(None) => error_response(StatusCode::NOT_FOUND, format!("Key '{}' not found", key)),
+            Err(e) => kv_error_response(e),
+        };
+    }
+    match cluster.get_json_value(&key).await {
         Ok(Some(value)) => {
+            let value = select(value);
             (StatusCode::OK, Json(GetJsonResponse { value })).into_response()
         },
         Ok(None) => {
@@ -152,28 +1318,229 @@ async fn get_json_value(
     }
 }
 
+// Re-encrypt a document's sensitive fields with the current key
+async fn rotate_json_encryption(
+    State(cluster): State<Arc<KVCluster>>,
+    TextKeyPath(key): TextKeyPath,
+    ClientWriteOptions(options): ClientWriteOptions,
+) -> Response {
+    let message = match cluster.rotate_json_encryption(&key, options).await {
+        Ok(true) => format!("Key '{}' re-encrypted", key),
+        Ok(false) => format!("Key '{}' already uses the current key", key),
+        Err(e) => return kv_error_response(e),
+    };
+    (StatusCode::OK, Json(ApiResponse { success: true, message })).into_response()
+}
+
 // Set a JSON value
 async fn set_json_value(
     State(cluster): State<Arc<KVCluster>>,
-    Path(key): Path<String>,
-    Json(payload): Json<SetJsonRequest>,
-) -> impl IntoResponse {
-    let ttl = payload.ttl_seconds.map(|secs| Duration::from_secs(secs));
+    TextKeyPath(key): TextKeyPath,
+    ClientWriteOptions(mut options): ClientWriteOptions,
+    JsonBody(payload): JsonBody<SetJsonRequest>,
+) -> Response {
+    let ttl = payload.ttl_seconds.map(Duration::from_secs);
+    options.sliding_ttl = payload.sliding_ttl;
     
-    match cluster.set_json_value(key.clone(), &payload.value, ttl).await {
+    match cluster.set_json_value_with_options(key.clone(), &payload.value, ttl, options).await {
         Ok(_) => (
             StatusCode::OK,
             Json(ApiResponse {
                 success: true,
                 message: format!("JSON key '{}' set successfully", key),
             }),
-        ),
-        Err(e) => (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(ApiResponse {
+        ).into_response(),
+        Err(e) => kv_error_response(e),
+    }
+} 
+
+// Write many JSON documents, reporting success or failure per document
+async fn set_json_bulk(
+    State(cluster): State<Arc<KVCluster>>,
+    Extension(limits): Extension<ApiLimits>,
+    ClientWriteOptions(options): ClientWriteOptions,
+    JsonBody(items): JsonBody<Vec<BulkJsonItem>>,
+) -> Json<BulkJsonResponse> {
+    let mut results: Vec<Option<BulkItemResult>> = Vec::with_capacity(items.len());
+    let mut documents = Vec::with_capacity(items.len());
+    let mut positions = Vec::with_capacity(items.len());
+    for item in items {
+        if let Some(message) = key_len_error(&limits, &item.key) {
+            results.push(Some(BulkItemResult {
+                key: item.key,
                 success: false,
-                message: format!("Error setting JSON: {}", e),
-            }),
-        ),
+                status: StatusCode::BAD_REQUEST.as_u16(),
+                error: Some(message),
+            }));
+            continue;
+        }
+        positions.push(results.len());
+        results.push(None);
+        documents.push(BulkDocument {
+            key: item.key,
+            value: item.value,
+            ttl: item.ttl_seconds.map(Duration::from_secs),
+        });
+    }
+
+    let keys: Vec<String> = documents.iter().map(|d| d.key.clone()).collect();
+    let written = cluster.set_json_bulk(documents, options).await;
+    for ((position, key), result) in positions.into_iter().zip(keys).zip(written) {
+        results[position] = Some(match result {
+            Ok(()) => BulkItemResult {
+                key,
+                success: true,
+                status: StatusCode::OK.as_u16(),
+                error: None,
+            },
+            Err(e) => BulkItemResult {
+                key,
+                success: false,
+                status: kv_error_status(&e).as_u16(),
+                error: Some(e.to_string()),
+            },
+        });
+    }
+
+    let results: Vec<BulkItemResult> = results.into_iter().flatten().collect();
+    let succeeded = results.iter().filter(|r| r.success).count();
+    Json(BulkJsonResponse {
+        succeeded,
+        failed: results.len() - succeeded,
+        results,
+    })
+}
+
+// Store a blob under its content hash, or add a reference to an identical one
+async fn put_blob(
+    State(cluster): State<Arc<KVCluster>>,
+    ClientWriteOptions(options): ClientWriteOptions,
+    body: Bytes,
+) -> Response {
+    match cluster.put_blob_with_options(body.to_vec(), options).await {
+        Ok(hash) => {
+            let refs = cluster.blob_refs(&hash).await;
+            (StatusCode::OK, Json(BlobResponse { hash, refs })).into_response()
+        }
+        Err(e) => kv_error_response(e),
+    }
+}
+
+// Get a blob's content
+async fn get_blob(State(cluster): State<Arc<KVCluster>>, Path(hash): Path<String>) -> Response {
+    match cluster.get_blob(&hash).await {
+        Some(bytes) => ([(header::CONTENT_TYPE, "application/octet-stream")], bytes).into_response(),
+        None => error_response(StatusCode::NOT_FOUND, format!("Blob '{}' not found", hash)),
+    }
+}
+
+// Drop a reference to a blob, deleting it with the last one
+async fn release_blob(State(cluster): State<Arc<KVCluster>>, Path(hash): Path<String>) -> Response {
+    match cluster.release_blob(&hash).await {
+        Ok(refs) => (StatusCode::OK, Json(BlobResponse { hash, refs })).into_response(),
+        Err(KVError::KeyNotFound(_)) => error_response(StatusCode::NOT_FOUND, format!("Blob '{}' not found", hash)),
+        Err(e) => kv_error_response(e),
+    }
+}
+
+// Get a CRDT value and its merge state
+async fn get_crdt(
+    State(cluster): State<Arc<KVCluster>>,
+    TextKeyPath(key): TextKeyPath,
+) -> Response {
+    match cluster.get_crdt(&key).await {
+        Ok(Some(state)) => Json(CrdtResponse { value: state.resolved(), state }).into_response(),
+        Ok(None) => error_response(StatusCode::NOT_FOUND, format!("Key '{}' not found", key)),
+        Err(e) => kv_error_response(e),
+    }
+}
+
+// Merge a CRDT state into the stored one
+async fn merge_crdt(
+    State(cluster): State<Arc<KVCluster>>,
+    TextKeyPath(key): TextKeyPath,
+    JsonBody(payload): JsonBody<MergeCrdtRequest>,
+) -> Response {
+    let ttl = payload.ttl_seconds.map(Duration::from_secs);
+    match cluster.merge_crdt(&key, &payload.value, ttl).await {
+        Ok(state) => Json(CrdtResponse { value: state.resolved(), state }).into_response(),
+        Err(e) => kv_error_response(e),
+    }
+}
+
+// Add to a PN-counter
+async fn increment_counter(
+    State(cluster): State<Arc<KVCluster>>,
+    TextKeyPath(key): TextKeyPath,
+    JsonBody(payload): JsonBody<IncrementRequest>,
+) -> Response {
+    match cluster.increment_counter(&key, payload.delta).await {
+        Ok(value) => Json(serde_json::json!({ "value": value })).into_response(),
+        Err(e) => kv_error_response(e),
+    }
+}
+
+// Append an entry to a stream
+async fn append_stream(
+    State(cluster): State<Arc<KVCluster>>,
+    TextKeyPath(key): TextKeyPath,
+    ClientWriteOptions(options): ClientWriteOptions,
+    JsonBody(payload): JsonBody<StreamAddRequest>,
+) -> Response {
+    let limits = (payload.max_len.is_some() || payload.max_age_seconds.is_some()).then(|| StreamLimits {
+        max_len: payload.max_len.unwrap_or(DEFAULT_STREAM_MAX_LEN),
+        max_age_ms: payload.max_age_seconds.map(|secs| secs.saturating_mul(1000)),
+    });
+    match cluster.xadd_with_options(&key, payload.fields, limits, options).await {
+        Ok(id) => Json(StreamAddResponse { id }).into_response(),
+        Err(e) => kv_error_response(e),
+    }
+}
+
+// Read a stream from a position
+async fn read_stream(
+    State(cluster): State<Arc<KVCluster>>,
+    TextKeyPath(key): TextKeyPath,
+    Query(query): Query<StreamReadQuery>,
+) -> Response {
+    let after = query.after.unwrap_or(StreamId::ZERO);
+    let count = query.count.unwrap_or(DEFAULT_STREAM_READ_COUNT);
+    match cluster.xread(&key, after, count).await {
+        Ok(entries) => Json(StreamReadResponse { entries }).into_response(),
+        Err(e) => kv_error_response(e),
+    }
+}
+
+// Consumer groups of a stream
+async fn get_stream_groups(State(cluster): State<Arc<KVCluster>>, TextKeyPath(key): TextKeyPath) -> Response {
+    match cluster.xgroups(&key).await {
+        Ok(groups) => Json(StreamGroupsResponse { groups }).into_response(),
+        Err(e) => kv_error_response(e),
     }
-} 
\ No newline at end of file
+}
+
+// Create a consumer group, or move an existing one
+async fn create_stream_group(
+    State(cluster): State<Arc<KVCluster>>,
+    TextKeyPath(key): TextKeyPath,
+    JsonBody(payload): JsonBody<StreamGroupRequest>,
+) -> Response {
+    let after = payload.after.unwrap_or(StreamId::ZERO);
+    if let Err(e) = cluster.xgroup_create(&key, &payload.group, after).await {
+        return kv_error_response(e);
+    }
+    get_stream_groups(State(cluster), TextKeyPath(key)).await
+}
+
+// Deliver the next entries to a consumer group
+async fn read_stream_group(
+    State(cluster): State<Arc<KVCluster>>,
+    TextKeyPath(key): TextKeyPath,
+    JsonBody(payload): JsonBody<StreamGroupReadRequest>,
+) -> Response {
+    let count = payload.count.unwrap_or(DEFAULT_STREAM_READ_COUNT);
+    match cluster.xread_group(&key, &payload.group, count).await {
+        Ok(entries) => Json(StreamReadResponse { entries }).into_response(),
+        Err(e) => kv_error_response(e),
+    }
+}
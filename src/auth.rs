@@ -0,0 +1,358 @@
+use axum::{
+    extract::{Request, State},
+    http::{header, Method, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, PoisonError};
+use tracing::info;
+
+use crate::api::error_response;
+use crate::changes::unix_millis;
+
+/// Admin requests kept in the audit log before the oldest are dropped
+const AUDIT_LOG_CAPACITY: usize = 1024;
+
+/// What a user may do. Each role includes the ones below it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Read keys, documents and stats
+    Reader,
+    /// Also write and delete data
+    Writer,
+    /// Also call `/admin/*` endpoints: modes, topology, export, chaos
+    Admin,
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "reader" => Ok(Role::Reader),
+            "writer" => Ok(Role::Writer),
+            "admin" => Ok(Role::Admin),
+            _ => Err(format!("invalid role '{}': expected admin, writer or reader", s)),
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Role::Reader => "reader",
+            Role::Writer => "writer",
+            Role::Admin => "admin",
+        })
+    }
+}
+
+/// An API user, authenticated by bearer token
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct User {
+    pub name: String,
+    pub role: Role,
+    pub token: String,
+    /// May read JSON documents with their sensitive fields decrypted,
+    /// whatever the role
+    pub decrypt: bool,
+}
+
+impl User {
+    pub fn new(name: impl Into<String>, role: Role, token: impl Into<String>) -> Self {
+        User {
+            name: name.into(),
+            role,
+            token: token.into(),
+            decrypt: false,
+        }
+    }
+
+    /// Lets the user read decrypted documents
+    pub fn with_decrypt(mut self) -> Self {
+        self.decrypt = true;
+        self
+    }
+}
+
+/// Parses `name:role[+decrypt]:token`, the format of `VOLT_USERS` entries
+impl FromStr for User {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.trim().splitn(3, ':');
+        match (parts.next(), parts.next(), parts.next()) {
+            (Some(name), Some(role), Some(token)) if !name.is_empty() && !token.is_empty() => {
+                let user = match role.split_once('+') {
+                    Some((role, permission)) if permission.trim().eq_ignore_ascii_case("decrypt") => {
+                        User::new(name, role.parse()?, token).with_decrypt()
+                    }
+                    Some((_, permission)) => {
+                        return Err(format!("invalid permission '{}': expected decrypt", permission))
+                    }
+                    None => User::new(name, role.parse()?, token),
+                };
+                Ok(user)
+            }
+            _ => Err(format!("invalid user '{}': expected name:role[+decrypt]:token", s)),
+        }
+    }
+}
+
+/// One request to an admin endpoint
+#[derive(Debug, Clone, Serialize)]
+pub struct AuditRecord {
+    pub timestamp_ms: u64,
+    /// Name of the user, or `None` if the request carried no valid token
+    pub user: Option<String>,
+    pub method: String,
+    pub path: String,
+    pub status: u16,
+}
+
+/// Users allowed to call the API and the audit log of their admin requests
+pub struct AccessControl {
+    users: HashMap<String, User>,
+    audit_log: Mutex<VecDeque<AuditRecord>>,
+}
+
+impl AccessControl {
+    pub fn new(users: impl IntoIterator<Item = User>) -> Self {
+        AccessControl {
+            users: users.into_iter().map(|user| (user.token.clone(), user)).collect(),
+            audit_log: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// The user whose bearer token `request` carries, if it is a known one
+    pub(crate) fn authenticate(&self, request: &Request) -> Option<&User> {
+        let token = request
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))?;
+        self.users.get(token.trim())
+    }
+
+    /// Recent admin requests, oldest first
+    pub fn audit_log(&self) -> Vec<AuditRecord> {
+        let log = self.audit_log.lock().unwrap_or_else(PoisonError::into_inner);
+        log.iter().cloned().collect()
+    }
+
+    fn record(&self, record: AuditRecord) {
+        info!(
+            user = record.user.as_deref().unwrap_or("-"),
+            status = record.status,
+            "admin request: {} {}",
+            record.method,
+            record.path
+        );
+        let mut log = self.audit_log.lock().unwrap_or_else(PoisonError::into_inner);
+        if log.len() == AUDIT_LOG_CAPACITY {
+            log.pop_front();
+        }
+        log.push_back(record);
+    }
+}
+
+/// Role a request needs, or `None` for the health probes, which are open
+fn required_role(method: &Method, path: &str) -> Option<Role> {
+    if path.starts_with("/health") {
+        None
+    } else if path.starts_with("/admin") {
+        Some(Role::Admin)
+    } else if method == Method::GET || method == Method::HEAD || method == Method::OPTIONS {
+        Some(Role::Reader)
+    } else {
+        Some(Role::Writer)
+    }
+}
+
+/// Middleware rejecting requests without a known bearer token (401) or whose
+/// user's role is too low for the route (403). Allowed requests carry their
+/// `User` as an extension. Every admin request, allowed or not, is added to
+/// the audit log.
+pub async fn auth_layer(State(access): State<Arc<AccessControl>>, request: Request, next: Next) -> Response {
+    let path = request.uri().path().to_string();
+    let Some(required) = required_role(request.method(), &path) else {
+        return next.run(request).await;
+    };
+    let user = access.authenticate(&request).cloned();
+    let method = request.method().to_string();
+    let response = match &user {
+        None => error_response(StatusCode::UNAUTHORIZED, "missing or unknown bearer token".to_string()),
+        Some(user) if user.role < required => error_response(
+            StatusCode::FORBIDDEN,
+            format!("user '{}' has role {}; {} required", user.name, user.role, required),
+        ),
+        Some(user) => {
+            // Handed on so inner layers can scope per-user state
+            let mut request = request;
+            request.extensions_mut().insert(user.clone());
+            next.run(request).await
+        }
+    };
+    if required == Role::Admin {
+        access.record(AuditRecord {
+            timestamp_ms: unix_millis(),
+            user: user.map(|user| user.name),
+            method,
+            path,
+            status: response.status().as_u16(),
+        });
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::routing::get;
+    use axum::{middleware, Router};
+    use tower::Service;
+
+    #[test]
+    fn health_probes_are_open() {
+        for path in ["/health", "/health/live", "/health/ready"] {
+            assert_eq!(required_role(&Method::GET, path), None, "{}", path);
+        }
+    }
+
+    #[test]
+    fn reads_need_a_reader() {
+        let reads = [
+            (Method::GET, "/stats"),
+            (Method::GET, "/version"),
+            (Method::GET, "/tracking"),
+            (Method::GET, "/kv/k"),
+            (Method::HEAD, "/kv/k"),
+            (Method::GET, "/kv/k/type"),
+            (Method::GET, "/kv/k/meta"),
+            (Method::GET, "/json/k"),
+            (Method::GET, "/blob/abc"),
+            (Method::GET, "/stream/s"),
+            (Method::GET, "/stream/s/groups"),
+            (Method::GET, "/crdt/c"),
+            (Method::OPTIONS, "/kv/k"),
+        ];
+        for (method, path) in reads {
+            assert_eq!(required_role(&method, path), Some(Role::Reader), "{} {}", method, path);
+        }
+    }
+
+    #[test]
+    fn writes_need_a_writer() {
+        let writes = [
+            (Method::POST, "/kv/k"),
+            (Method::DELETE, "/kv/k"),
+            (Method::POST, "/kv/k/rename"),
+            (Method::POST, "/kv/k/copy"),
+            (Method::POST, "/kv/k/touch"),
+            (Method::POST, "/lock/k"),
+            (Method::POST, "/lock/k/renew"),
+            (Method::POST, "/lock/k/release"),
+            (Method::POST, "/json/_bulk"),
+            (Method::POST, "/json/k"),
+            (Method::POST, "/json/k/rotate-encryption"),
+            (Method::POST, "/blob"),
+            (Method::DELETE, "/blob/abc"),
+            (Method::POST, "/stream/s"),
+            (Method::POST, "/stream/s/groups"),
+            // Reading as a group member moves the group's cursor
+            (Method::POST, "/stream/s/groups/read"),
+            (Method::POST, "/crdt/c"),
+            (Method::POST, "/crdt/c/incr"),
+        ];
+        for (method, path) in writes {
+            assert_eq!(required_role(&method, path), Some(Role::Writer), "{} {}", method, path);
+        }
+    }
+
+    #[test]
+    fn admin_routes_need_an_admin_even_to_read() {
+        let admin = [
+            (Method::GET, "/admin/keyspace-report"),
+            (Method::GET, "/admin/ring"),
+            (Method::GET, "/admin/consistency-probe"),
+            (Method::GET, "/admin/export"),
+            (Method::POST, "/admin/nodes/node1/down"),
+            (Method::POST, "/admin/nodes/node1/up"),
+            (Method::GET, "/admin/readonly"),
+            (Method::POST, "/admin/readonly"),
+            (Method::POST, "/admin/maintenance"),
+            (Method::POST, "/admin/prefix/expire"),
+            (Method::POST, "/admin/quotas"),
+            (Method::POST, "/admin/sliding-ttl"),
+            (Method::GET, "/admin/sensitive-fields"),
+            (Method::POST, "/admin/soft-delete"),
+            (Method::GET, "/admin/trash"),
+            (Method::DELETE, "/admin/trash"),
+            (Method::POST, "/admin/trash/k/restore"),
+            (Method::GET, "/admin/chaos"),
+            (Method::DELETE, "/admin/chaos"),
+            (Method::GET, "/admin/audit"),
+        ];
+        for (method, path) in admin {
+            assert_eq!(required_role(&method, path), Some(Role::Admin), "{} {}", method, path);
+        }
+    }
+
+    fn router(access: Arc<AccessControl>) -> Router {
+        Router::new()
+            .route("/health", get(|| async { "ok" }))
+            .route("/kv/:key", get(|| async { "read" }).post(|| async { "written" }))
+            .route("/admin/ring", get(|| async { "ring" }))
+            .layer(middleware::from_fn_with_state(access, auth_layer))
+    }
+
+    #[test]
+    fn users_parse_with_an_optional_decrypt_permission() {
+        assert_eq!("bi:reader:t1".parse::<User>(), Ok(User::new("bi", Role::Reader, "t1")));
+        assert_eq!(
+            "bi:reader+decrypt:t:1".parse::<User>(),
+            Ok(User::new("bi", Role::Reader, "t:1").with_decrypt())
+        );
+        assert!("bi:reader+write:t1".parse::<User>().is_err());
+        assert!("bi:reader".parse::<User>().is_err());
+    }
+
+    async fn status(app: &Router, method: Method, path: &str, token: Option<&str>) -> StatusCode {
+        let mut request = Request::builder().method(method).uri(path);
+        if let Some(token) = token {
+            request = request.header(header::AUTHORIZATION, format!("Bearer {}", token));
+        }
+        let Ok(response) = app.clone().call(request.body(Body::empty()).unwrap()).await;
+        response.status()
+    }
+
+    #[tokio::test]
+    async fn layer_enforces_roles_and_audits_admin_requests() {
+        let access = Arc::new(AccessControl::new([
+            User::new("bi", Role::Reader, "t-reader"),
+            User::new("app", Role::Writer, "t-writer"),
+            User::new("ops", Role::Admin, "t-admin"),
+        ]));
+        let app = router(access.clone());
+
+        assert_eq!(status(&app, Method::GET, "/health", None).await, StatusCode::OK);
+        assert_eq!(status(&app, Method::GET, "/kv/k", None).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(status(&app, Method::GET, "/kv/k", Some("made-up")).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(status(&app, Method::GET, "/kv/k", Some("t-reader")).await, StatusCode::OK);
+        assert_eq!(status(&app, Method::POST, "/kv/k", Some("t-reader")).await, StatusCode::FORBIDDEN);
+        assert_eq!(status(&app, Method::POST, "/kv/k", Some("t-writer")).await, StatusCode::OK);
+        assert_eq!(status(&app, Method::GET, "/admin/ring", Some("t-writer")).await, StatusCode::FORBIDDEN);
+        assert_eq!(status(&app, Method::GET, "/admin/ring", Some("t-admin")).await, StatusCode::OK);
+
+        // Only the admin requests are logged, refused ones included
+        let log = access.audit_log();
+        let entries: Vec<_> = log.iter().map(|r| (r.user.as_deref(), r.status)).collect();
+        assert_eq!(entries, [(Some("app"), 403), (Some("ops"), 200)]);
+    }
+}
@@ -1,11 +1,31 @@
 use std::net::SocketAddr;
-use volt::KVCluster;
-use volt::server::run_server;
+use std::sync::Arc;
+use std::time::Duration;
+use volt::{AckMode, KVCluster};
+use volt::config::VoltConfig;
+use volt::encryption::{KeyProvider, StaticKeyProvider};
+use volt::limits::{OversizePolicy, ValueLimit};
+use volt::quota::parse_quotas;
+use volt::replication::{serve_replication, BridgeConfig, ReplicationBridge};
+use volt::runtime::NodeRuntime;
+use volt::server::run_server_with_config;
+use volt::warmup::{HttpBackend, WarmupSource};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // `--check-config` validates the configuration and runs the self-test,
+    // then exits without starting anything
+    let check_only = std::env::args().skip(1).any(|arg| arg == "--check-config");
+    
     // Create a new KV cluster
-    let mut cluster = KVCluster::new(100, 3);
+    let replication_factor = match std::env::var("VOLT_REPLICATION_FACTOR") {
+        Ok(factor) => factor.parse::<usize>().map_err(|e| format!("invalid VOLT_REPLICATION_FACTOR: {}", e))?,
+        Err(_) => 3,
+    };
+    let mut cluster = KVCluster::new(100, replication_factor);
+    if let Ok(cluster_id) = std::env::var("VOLT_CLUSTER_ID") {
+        cluster.set_cluster_id(cluster_id);
+    }
     
     // Add nodes to the cluster
     let node_count = std::env::var("VOLT_NODE_COUNT")
@@ -13,10 +33,113 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .parse::<usize>()
         .unwrap_or(3);
     
+    // Run node background tasks off the request-handling runtime:
+    // shared (default), dedicated[:threads] or per-node[:threads]
+    if let Ok(runtime) = std::env::var("VOLT_NODE_RUNTIME") {
+        cluster.set_node_runtime(runtime.parse::<NodeRuntime>()?)?;
+    }
+    
     for i in 0..node_count {
         cluster.add_node(format!("node{}", i));
     }
     
+    // Collapse repeated replica writes to hot keys within this window
+    if let Ok(window_us) = std::env::var("VOLT_COALESCE_WINDOW_US") {
+        let window_us = window_us
+            .parse::<u64>()
+            .map_err(|e| format!("invalid VOLT_COALESCE_WINDOW_US: {}", e))?;
+        cluster.set_write_coalescing(Some(Duration::from_micros(window_us)));
+    }
+    
+    // Replica acknowledgements writes wait for: primary, all, or a replica count
+    if let Ok(mode) = std::env::var("VOLT_ACK_MODE") {
+        cluster.set_ack_mode(mode.parse::<AckMode>()?);
+    }
+    
+    // Cap stored value sizes; VOLT_OVERSIZE_POLICY is reject (default) or truncate
+    if let Ok(max_bytes) = std::env::var("VOLT_MAX_VALUE_BYTES") {
+        let policy = match std::env::var("VOLT_OVERSIZE_POLICY") {
+            Ok(policy) => policy.parse::<OversizePolicy>()?,
+            Err(_) => OversizePolicy::default(),
+        };
+        cluster.set_value_limit(Some(ValueLimit::new(max_bytes.parse()?, policy)));
+    }
+    
+    // Key-count quotas, e.g. VOLT_NAMESPACE_QUOTAS="tenant-a=10000,tenant-b=500:evict-oldest"
+    if let Ok(quotas) = std::env::var("VOLT_NAMESPACE_QUOTAS") {
+        for (namespace, quota) in parse_quotas(&quotas)? {
+            cluster.set_namespace_quota(&namespace, Some(quota))?;
+        }
+    }
+    
+    // Cap on keys tracked for client-side caching across all tracking clients
+    if let Ok(max_keys) = std::env::var("VOLT_TRACKING_MAX_KEYS") {
+        let max_keys = max_keys
+            .parse::<usize>()
+            .map_err(|e| format!("invalid VOLT_TRACKING_MAX_KEYS: {}", e))?;
+        cluster.set_tracking_max_keys(max_keys);
+    }
+    
+    // Advertise per-node addresses for client-side routing,
+    // e.g. VOLT_NODE_ADDRS="node0=http://10.0.0.1:3000,node1=http://10.0.0.2:3000"
+    if let Ok(addrs) = std::env::var("VOLT_NODE_ADDRS") {
+        for (node_id, addr) in addrs.split(',').filter_map(|pair| pair.split_once('=')) {
+            if !cluster.set_node_addr(node_id.trim(), addr.trim().to_string()) {
+                eprintln!("Ignoring address for unknown node '{}'", node_id.trim());
+            }
+        }
+    }
+    
+    // Key for sensitive JSON fields, declared through /admin/sensitive-fields,
+    // and for encrypted warm-up snapshots
+    let keys: Option<Arc<dyn KeyProvider>> = match StaticKeyProvider::from_env()? {
+        Some(provider) => Some(Arc::new(provider)),
+        None => None,
+    };
+    if let Some(keys) = &keys {
+        cluster.set_field_key_provider(keys.clone());
+    }
+    
+    // Preload a snapshot and/or keys from the backing store; /health/ready
+    // fails until done or VOLT_WARMUP_TIMEOUT_SECS (60) elapses
+    let mut warmup = Vec::new();
+    if let Ok(path) = std::env::var("VOLT_WARMUP_SNAPSHOT") {
+        warmup.push(WarmupSource::Snapshot { path: path.into(), keys: keys.clone() });
+    }
+    if let Ok(url) = std::env::var("VOLT_WARMUP_BACKEND_URL") {
+        let keys_file = std::env::var("VOLT_WARMUP_KEYS_FILE")
+            .map_err(|_| "VOLT_WARMUP_BACKEND_URL needs VOLT_WARMUP_KEYS_FILE, one key per line")?;
+        let keys = std::fs::read_to_string(&keys_file)
+            .map_err(|e| format!("cannot read VOLT_WARMUP_KEYS_FILE {}: {}", keys_file, e))?
+            .lines()
+            .map(str::trim)
+            .filter(|key| !key.is_empty())
+            .map(String::from)
+            .collect();
+        let ttl = match std::env::var("VOLT_WARMUP_TTL_SECS") {
+            Ok(secs) => Some(Duration::from_secs(secs.parse()?)),
+            Err(_) => None,
+        };
+        warmup.push(WarmupSource::Backend { backend: Arc::new(HttpBackend::new(url)), keys, ttl });
+    }
+    let warmup_timeout = match std::env::var("VOLT_WARMUP_TIMEOUT_SECS") {
+        Ok(secs) => Duration::from_secs(secs.parse()?),
+        Err(_) => Duration::from_secs(60),
+    };
+    
+    // Fail over nodes whose background tasks stop, checking every interval
+    let failure_detection = match std::env::var("VOLT_FAILURE_DETECTION_MS") {
+        Ok(ms) => match ms.parse::<u64>() {
+            Ok(0) => return Err("invalid VOLT_FAILURE_DETECTION_MS: must be at least 1".into()),
+            Ok(ms) => Some(Duration::from_millis(ms)),
+            Err(e) => return Err(format!("invalid VOLT_FAILURE_DETECTION_MS: {}", e).into()),
+        },
+        Err(_) => None,
+    };
+    
+    // HTTP request limits and client quotas
+    let config = VoltConfig::from_env()?;
+    
     // Get the server address from environment or use default
     let host = std::env::var("VOLT_HOST").unwrap_or_else(|_| "0.0.0.0".to_string());
     let port = std::env::var("VOLT_PORT")
@@ -26,6 +149,60 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     
     let addr = format!("{}:{}", host, port).parse::<SocketAddr>()?;
     
+    // Fail fast on settings that cannot work, then prove the store works
+    // before binding anything
+    let mut problems = match config.validate() {
+        Ok(()) => Vec::new(),
+        Err(errors) => errors.0,
+    };
+    problems.extend(cluster.check_setup());
+    for source in &warmup {
+        if let WarmupSource::Snapshot { path, .. } = source {
+            if let Err(e) = std::fs::File::open(path) {
+                problems.push(format!("cannot read VOLT_WARMUP_SNAPSHOT {}: {}", path.display(), e));
+            }
+        }
+    }
+    if problems.is_empty() {
+        if let Err(e) = cluster.self_test().await {
+            problems.push(e);
+        }
+    }
+    if !problems.is_empty() {
+        for problem in &problems {
+            eprintln!("Configuration error: {}", problem);
+        }
+        std::process::exit(1);
+    }
+    if check_only {
+        println!("Configuration OK: {} nodes, replication factor {}, address {}", node_count, replication_factor, addr);
+        return Ok(());
+    }
+    
+    // Ship writes to a standby cluster (active-passive DR)
+    if let Ok(target) = std::env::var("VOLT_REPLICATION_TARGET") {
+        ReplicationBridge::start(&cluster, BridgeConfig::new(target));
+    }
+    
+    // Accept writes replicated from a primary cluster
+    if let Ok(listen) = std::env::var("VOLT_REPLICATION_LISTEN") {
+        let listener = tokio::net::TcpListener::bind(&listen).await?;
+        let replica_cluster = cluster.clone();
+        tokio::spawn(async move {
+            if let Err(e) = serve_replication(replica_cluster, listener).await {
+                eprintln!("Replication listener failed: {}", e);
+            }
+        });
+    }
+    
+    if !warmup.is_empty() {
+        cluster.start_warmup(warmup, warmup_timeout);
+    }
+    
+    if let Some(interval) = failure_detection {
+        cluster.start_failure_detector(interval);
+    }
+    
     // Run the server
-    run_server(cluster, addr).await
+    run_server_with_config(cluster, addr, config).await
 } 
\ No newline at end of file
@@ -0,0 +1,291 @@
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+use serde::Serialize;
+use serde_json::json;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::time::MissedTickBehavior;
+use volt::workload::{KeyChooser, KeyDistribution, LatencySummary};
+use volt::KVCluster;
+
+use crate::{connect, CliResult, USAGE};
+
+/// Where the load is sent
+enum Backend {
+    Embedded(KVCluster),
+    Remote { client: reqwest::Client, base_url: String },
+}
+
+impl Backend {
+    async fn set(&self, key: String, value: &str) -> Result<(), String> {
+        match self {
+            Backend::Embedded(cluster) => cluster
+                .set(key, value.as_bytes().to_vec(), None)
+                .await
+                .map_err(|e| e.to_string()),
+            Backend::Remote { client, base_url } => client
+                .post(format!("{}/kv/{}", base_url, key))
+                .json(&json!({ "value": value }))
+                .send()
+                .await
+                .and_then(|r| r.error_for_status())
+                .map(drop)
+                .map_err(|e| e.to_string()),
+        }
+    }
+
+    async fn get(&self, key: &str) -> Result<(), String> {
+        match self {
+            Backend::Embedded(cluster) => {
+                cluster.get(key).await;
+                Ok(())
+            }
+            Backend::Remote { client, base_url } => {
+                let response = client
+                    .get(format!("{}/kv/{}", base_url, key))
+                    .send()
+                    .await
+                    .map_err(|e| e.to_string())?;
+                // A miss is a valid answer, not a failed request
+                if response.status().is_success() || response.status() == reqwest::StatusCode::NOT_FOUND {
+                    Ok(())
+                } else {
+                    Err(format!("HTTP {}", response.status()))
+                }
+            }
+        }
+    }
+}
+
+struct BenchOptions {
+    target: Option<String>,
+    read_ratio: f64,
+    keys: u64,
+    distribution: KeyDistribution,
+    value_size: usize,
+    /// Target operations per second across all workers; `None` runs flat out
+    rate: Option<f64>,
+    duration: Duration,
+    concurrency: usize,
+    seed: u64,
+    nodes: usize,
+    replication: usize,
+    json: bool,
+}
+
+impl BenchOptions {
+    fn parse(args: &[String]) -> CliResult<Self> {
+        let mut options = BenchOptions {
+            target: None,
+            read_ratio: 0.9,
+            keys: 10_000,
+            distribution: KeyDistribution::Uniform,
+            value_size: 100,
+            rate: None,
+            duration: Duration::from_secs(10),
+            concurrency: 16,
+            seed: 0,
+            nodes: 3,
+            replication: 3,
+            json: false,
+        };
+        let mut args = args.iter();
+        while let Some(flag) = args.next() {
+            if flag == "--json" {
+                options.json = true;
+                continue;
+            }
+            let value = args.next().ok_or(USAGE)?;
+            match flag.as_str() {
+                "--target" => options.target = Some(value.trim_end_matches('/').to_string()),
+                "--read-ratio" => options.read_ratio = value.parse()?,
+                "--keys" => options.keys = value.parse()?,
+                "--distribution" => options.distribution = value.parse()?,
+                "--value-size" => options.value_size = value.parse()?,
+                "--rate" => options.rate = Some(value.parse::<f64>()?).filter(|r| *r > 0.0),
+                "--duration" => options.duration = Duration::from_secs_f64(value.parse()?),
+                "--concurrency" => options.concurrency = value.parse::<usize>()?.max(1),
+                "--seed" => options.seed = value.parse()?,
+                "--nodes" => options.nodes = value.parse::<usize>()?.max(1),
+                "--replication" => options.replication = value.parse::<usize>()?.max(1),
+                _ => return Err(USAGE.into()),
+            }
+        }
+        if !(0.0..=1.0).contains(&options.read_ratio) {
+            return Err("--read-ratio must be between 0 and 1".into());
+        }
+        Ok(options)
+    }
+
+    fn backend(&self) -> Backend {
+        match &self.target {
+            Some(url) => Backend::Remote {
+                client: reqwest::Client::new(),
+                base_url: url.clone(),
+            },
+            None => {
+                let mut cluster = KVCluster::new(100, self.replication);
+                for i in 0..self.nodes {
+                    cluster.add_node(format!("node{}", i));
+                }
+                Backend::Embedded(cluster)
+            }
+        }
+    }
+}
+
+fn bench_key(index: u64) -> String {
+    format!("bench:{}", index)
+}
+
+#[derive(Default)]
+struct WorkerResult {
+    reads: Vec<Duration>,
+    writes: Vec<Duration>,
+    errors: usize,
+    first_error: Option<String>,
+}
+
+async fn run_worker(
+    backend: Arc<Backend>,
+    chooser: Arc<KeyChooser>,
+    options: Arc<BenchOptions>,
+    value: Arc<str>,
+    worker: usize,
+    deadline: Instant,
+) -> WorkerResult {
+    let mut rng = SmallRng::seed_from_u64(options.seed.wrapping_add(worker as u64));
+    let mut pacer = options.rate.map(|rate| {
+        let mut interval = tokio::time::interval(Duration::from_secs_f64(options.concurrency as f64 / rate));
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        interval
+    });
+    let mut result = WorkerResult::default();
+    while Instant::now() < deadline {
+        if let Some(pacer) = pacer.as_mut() {
+            pacer.tick().await;
+        }
+        let key = bench_key(chooser.next_index(&mut rng));
+        let read = rng.gen_bool(options.read_ratio);
+        let start = Instant::now();
+        let outcome = if read {
+            backend.get(&key).await
+        } else {
+            backend.set(key, &value).await
+        };
+        let elapsed = start.elapsed();
+        match outcome {
+            Ok(()) if read => result.reads.push(elapsed),
+            Ok(()) => result.writes.push(elapsed),
+            Err(e) => {
+                result.errors += 1;
+                result.first_error.get_or_insert(e);
+            }
+        }
+    }
+    result
+}
+
+#[derive(Serialize)]
+struct BenchReport {
+    target: String,
+    duration_secs: f64,
+    operations: usize,
+    throughput: f64,
+    errors: usize,
+    reads: LatencySummary,
+    writes: LatencySummary,
+}
+
+fn print_summary(label: &str, summary: &LatencySummary) {
+    println!(
+        "{:<6} {:>10} {:>10.1} {:>10} {:>10} {:>10} {:>10}",
+        label, summary.count, summary.mean_us, summary.p50_us, summary.p95_us, summary.p99_us, summary.max_us
+    );
+}
+
+pub async fn bench(args: &[String]) -> CliResult<std::process::ExitCode> {
+    let options = Arc::new(BenchOptions::parse(args)?);
+    if let Some(url) = &options.target {
+        connect(&reqwest::Client::new(), url).await?;
+    }
+    let backend = Arc::new(options.backend());
+    let chooser = Arc::new(KeyChooser::new(options.keys, options.distribution));
+    let value: Arc<str> = "x".repeat(options.value_size).into();
+    let target = options.target.clone().unwrap_or_else(|| "embedded".to_string());
+
+    // Load every key first so reads measure hits
+    eprintln!("Loading {} keys into {}...", options.keys, target);
+    let loaders: Vec<_> = (0..options.concurrency as u64)
+        .map(|worker| {
+            let (backend, options, value) = (backend.clone(), options.clone(), value.clone());
+            tokio::spawn(async move {
+                let mut index = worker;
+                while index < options.keys {
+                    backend.set(bench_key(index), &value).await?;
+                    index += options.concurrency as u64;
+                }
+                Ok::<_, String>(())
+            })
+        })
+        .collect();
+    for loader in loaders {
+        loader.await??;
+    }
+
+    eprintln!("Running for {:.1}s with {} workers...", options.duration.as_secs_f64(), options.concurrency);
+    let started = Instant::now();
+    let deadline = started + options.duration;
+    let workers: Vec<_> = (0..options.concurrency)
+        .map(|worker| {
+            tokio::spawn(run_worker(
+                backend.clone(),
+                chooser.clone(),
+                options.clone(),
+                value.clone(),
+                worker,
+                deadline,
+            ))
+        })
+        .collect();
+    let mut total = WorkerResult::default();
+    for worker in workers {
+        let result = worker.await?;
+        total.reads.extend(result.reads);
+        total.writes.extend(result.writes);
+        total.errors += result.errors;
+        if total.first_error.is_none() {
+            total.first_error = result.first_error;
+        }
+    }
+    let elapsed = started.elapsed().as_secs_f64();
+
+    let operations = total.reads.len() + total.writes.len() + total.errors;
+    let report = BenchReport {
+        target,
+        duration_secs: elapsed,
+        operations,
+        throughput: operations as f64 / elapsed,
+        errors: total.errors,
+        reads: LatencySummary::from_samples(&mut total.reads),
+        writes: LatencySummary::from_samples(&mut total.writes),
+    };
+    if options.json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        println!(
+            "{} ops in {:.2}s ({:.0} ops/s), {} errors",
+            report.operations, report.duration_secs, report.throughput, report.errors
+        );
+        println!(
+            "{:<6} {:>10} {:>10} {:>10} {:>10} {:>10} {:>10}",
+            "op", "count", "mean(us)", "p50(us)", "p95(us)", "p99(us)", "max(us)"
+        );
+        print_summary("read", &report.reads);
+        print_summary("write", &report.writes);
+        if let Some(error) = &total.first_error {
+            println!("First error: {}", error);
+        }
+    }
+    Ok(std::process::ExitCode::SUCCESS)
+}
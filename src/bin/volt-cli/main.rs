@@ -0,0 +1,184 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::process::ExitCode;
+use std::time::Duration;
+use volt::dump::{decrypt_dump, diff_dumps, encrypt_dump, read_dump, write_dump, DumpDiff, DumpEntry};
+use volt::encryption::{KeyProvider, StaticKeyProvider};
+use volt::version::{negotiate_with, Negotiated};
+use volt::Key;
+
+mod bench;
+
+const USAGE: &str = "\
+Usage:
+  volt-cli version <server-url>
+  volt-cli export <server-url> [<file>]
+  volt-cli diff <dump-or-url> <dump-or-url> [--ttl-tolerance-ms <ms>] [--json]
+  volt-cli bench [--target <server-url>] [--read-ratio 0.9] [--keys 10000]
+                 [--distribution uniform|zipfian[:theta]] [--value-size 100]
+                 [--rate <ops/s>] [--duration <secs>] [--concurrency 16]
+                 [--seed 0] [--nodes 3] [--replication 3] [--json]
+
+A dump is a file written by `volt-cli export` or by GET /admin/export.
+Sources starting with http:// or https:// are fetched from a live server.
+When VOLT_ENCRYPTION_KEY holds a base64 256-bit key, export encrypts values
+written to a file with AES-256-GCM and diff decrypts encrypted dumps.
+Without --target, bench runs against an embedded cluster.";
+
+/// Expiries this close together are not reported as drift by default
+const DEFAULT_TTL_TOLERANCE_MS: u64 = 1000;
+
+/// Differences listed per category before the text report truncates
+const MAX_LISTED: usize = 20;
+
+type CliResult<T> = Result<T, Box<dyn std::error::Error>>;
+
+fn is_url(source: &str) -> bool {
+    source.starts_with("http://") || source.starts_with("https://")
+}
+
+/// Negotiates with the server at `base_url` before talking to it, so a
+/// server that no longer serves this client fails up front
+async fn connect(client: &reqwest::Client, base_url: &str) -> CliResult<Negotiated> {
+    let negotiated = negotiate_with(client, base_url).await?;
+    if negotiated.server_version.is_none() {
+        eprintln!("{} predates GET /version; assuming API revision 0", base_url);
+    }
+    Ok(negotiated)
+}
+
+async fn fetch_dump(base_url: &str) -> CliResult<Vec<DumpEntry>> {
+    connect(&reqwest::Client::new(), base_url).await?;
+    let url = format!("{}/admin/export", base_url.trim_end_matches('/'));
+    let body = reqwest::get(&url).await?.error_for_status()?.bytes().await?;
+    Ok(read_dump(&body[..])?)
+}
+
+async fn load_dump(source: &str, keys: Option<&dyn KeyProvider>) -> CliResult<Vec<DumpEntry>> {
+    if is_url(source) {
+        return fetch_dump(source).await;
+    }
+    let mut entries = read_dump(BufReader::new(File::open(source)?))?;
+    decrypt_dump(&mut entries, keys).map_err(|e| format!("{}: {}", source, e))?;
+    Ok(entries)
+}
+
+async fn export(args: &[String]) -> CliResult<ExitCode> {
+    let (url, file) = match args {
+        [url] => (url, None),
+        [url, file] => (url, Some(file)),
+        _ => return Err(USAGE.into()),
+    };
+    let mut entries = fetch_dump(url).await?;
+    match file {
+        Some(file) => {
+            // Values only hit disk encrypted when a key is configured
+            if let Some(keys) = StaticKeyProvider::from_env()? {
+                encrypt_dump(&mut entries, &keys)?;
+            }
+            write_dump(&entries, BufWriter::new(File::create(file)?))?
+        }
+        None => write_dump(&entries, std::io::stdout().lock())?,
+    }
+    eprintln!("Exported {} keys", entries.len());
+    Ok(ExitCode::SUCCESS)
+}
+
+async fn version(args: &[String]) -> CliResult<ExitCode> {
+    let [url] = args else {
+        return Err(USAGE.into());
+    };
+    let negotiated = connect(&reqwest::Client::new(), url).await?;
+    println!("{}", serde_json::to_string_pretty(&negotiated)?);
+    Ok(ExitCode::SUCCESS)
+}
+
+fn print_keys(label: &str, keys: &[Key]) {
+    if keys.is_empty() {
+        return;
+    }
+    println!("{} ({}):", label, keys.len());
+    for key in keys.iter().take(MAX_LISTED) {
+        println!("  {}", key);
+    }
+    if keys.len() > MAX_LISTED {
+        println!("  ... and {} more", keys.len() - MAX_LISTED);
+    }
+}
+
+fn print_report(a: &str, b: &str, diff: &DumpDiff) {
+    println!("A: {} ({} keys)", a, diff.keys_a);
+    println!("B: {} ({} keys)", b, diff.keys_b);
+    if diff.is_empty() {
+        println!("No differences");
+        return;
+    }
+    print_keys("Missing from B", &diff.only_in_a);
+    print_keys("Missing from A", &diff.only_in_b);
+    print_keys("Value mismatches", &diff.value_mismatches);
+    if !diff.ttl_drift.is_empty() {
+        println!("TTL drift ({}):", diff.ttl_drift.len());
+        let describe = |ms: Option<u64>| ms.map_or("no ttl".to_string(), |ms| ms.to_string());
+        for drift in diff.ttl_drift.iter().take(MAX_LISTED) {
+            println!(
+                "  {}: {} vs {}",
+                drift.key,
+                describe(drift.a_expires_at_ms),
+                describe(drift.b_expires_at_ms)
+            );
+        }
+        if diff.ttl_drift.len() > MAX_LISTED {
+            println!("  ... and {} more", diff.ttl_drift.len() - MAX_LISTED);
+        }
+    }
+}
+
+async fn diff(args: &[String]) -> CliResult<ExitCode> {
+    let mut sources = Vec::new();
+    let mut tolerance_ms = DEFAULT_TTL_TOLERANCE_MS;
+    let mut json = false;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--json" => json = true,
+            "--ttl-tolerance-ms" => {
+                tolerance_ms = args.next().ok_or(USAGE)?.parse()?;
+            }
+            _ => sources.push(arg.as_str()),
+        }
+    }
+    let [a, b] = sources[..] else {
+        return Err(USAGE.into());
+    };
+
+    let keys = StaticKeyProvider::from_env()?;
+    let keys = keys.as_ref().map(|keys| keys as &dyn KeyProvider);
+    let (dump_a, dump_b) = tokio::try_join!(load_dump(a, keys), load_dump(b, keys))?;
+    let diff = diff_dumps(&dump_a, &dump_b, Duration::from_millis(tolerance_ms));
+    if json {
+        println!("{}", serde_json::to_string_pretty(&diff)?);
+    } else {
+        print_report(a, b, &diff);
+    }
+    // Non-zero exit lets scripts gate a migration on the result
+    Ok(if diff.is_empty() { ExitCode::SUCCESS } else { ExitCode::from(1) })
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let result = match args.first().map(String::as_str) {
+        Some("version") => version(&args[1..]).await,
+        Some("export") => export(&args[1..]).await,
+        Some("diff") => diff(&args[1..]).await,
+        Some("bench") => bench::bench(&args[1..]).await,
+        _ => Err(USAGE.into()),
+    };
+    match result {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::from(2)
+        }
+    }
+}
@@ -0,0 +1,224 @@
+//! Content-addressed blobs: content is stored once under its hash, however
+//! many times it is put, and deleted once every reference is released.
+//!
+//! Reference counts are kept in memory on the cluster handle, not with the
+//! blobs. They are lost on restart and are not part of an export, so blobs
+//! loaded from a snapshot or dump have no references until they are put
+//! again. Deleting a blob's key directly bypasses them too: its count stays
+//! behind, and the next put of that content stores it again.
+
+use dashmap::DashMap;
+use sha2::{Digest, Sha256};
+use std::fmt::Write;
+use std::sync::Arc;
+use tokio::sync::{Mutex, OwnedMutexGuard};
+
+use crate::{KVCluster, KVError, WriteOptions};
+
+/// Prefix of the keys blobs are stored under, followed by the hex SHA-256 of
+/// the content
+pub const BLOB_KEY_PREFIX: &str = "__blob:sha256:";
+
+/// Reference counts of stored blobs, by content hash. Each count has a lock
+/// of its own, held while the blob is written or deleted, so that puts and
+/// releases of the same content take turns while others go ahead.
+#[derive(Default)]
+pub(crate) struct BlobRefs {
+    counts: DashMap<String, Arc<Mutex<u64>>>,
+}
+
+impl BlobRefs {
+    /// Locks the count of `hash`, starting it at 0 if there is none
+    async fn lock_or_insert(&self, hash: &str) -> LockedCount<'_> {
+        loop {
+            let count = self.counts.entry(hash.to_string()).or_default().clone();
+            if let Some(locked) = self.lock_current(hash, count).await {
+                return locked;
+            }
+        }
+    }
+
+    /// Locks the count of `hash`, if there is one
+    async fn lock(&self, hash: &str) -> Option<LockedCount<'_>> {
+        loop {
+            let count = self.counts.get(hash)?.clone();
+            if let Some(locked) = self.lock_current(hash, count).await {
+                return Some(locked);
+            }
+        }
+    }
+
+    /// Locks `count`, unless it was forgotten while this call waited for it
+    async fn lock_current(&self, hash: &str, count: Arc<Mutex<u64>>) -> Option<LockedCount<'_>> {
+        let guard = count.clone().lock_owned().await;
+        let current = self.counts.get(hash).is_some_and(|current| Arc::ptr_eq(&current, &count));
+        current.then(|| LockedCount {
+            refs: self,
+            hash: hash.to_string(),
+            count,
+            guard,
+        })
+    }
+}
+
+/// A blob's reference count, locked. A count left at 0 is forgotten.
+struct LockedCount<'a> {
+    refs: &'a BlobRefs,
+    hash: String,
+    count: Arc<Mutex<u64>>,
+    guard: OwnedMutexGuard<u64>,
+}
+
+impl Drop for LockedCount<'_> {
+    fn drop(&mut self) {
+        if *self.guard == 0 {
+            self.refs
+                .counts
+                .remove_if(&self.hash, |_, current| Arc::ptr_eq(current, &self.count));
+        }
+    }
+}
+
+/// Hex SHA-256 of `bytes`, the address of a blob with that content
+pub fn blob_hash(bytes: &[u8]) -> String {
+    let digest = Sha256::digest(bytes);
+    let mut hash = String::with_capacity(digest.len() * 2);
+    for byte in digest {
+        let _ = write!(hash, "{:02x}", byte);
+    }
+    hash
+}
+
+fn blob_key(hash: &str) -> String {
+    format!("{}{}", BLOB_KEY_PREFIX, hash)
+}
+
+impl KVCluster {
+    /// Stores `bytes` under its content hash and returns the hash. Storing
+    /// content that is already present only adds a reference to it; the blob
+    /// is deleted once every reference is released. Blobs over the value
+    /// limit are always rejected, since a truncated copy would not match its
+    /// hash.
+    pub async fn put_blob(&self, bytes: Vec<u8>) -> Result<String, KVError> {
+        self.put_blob_with_options(bytes, WriteOptions::default()).await
+    }
+
+    /// Stores a blob with the same timeout and acknowledgement options as
+    /// `set_with_options`
+    pub async fn put_blob_with_options(
+        &self,
+        bytes: Vec<u8>,
+        options: WriteOptions,
+    ) -> Result<String, KVError> {
+        if let Some(limit) = self.value_limit().filter(|limit| bytes.len() > limit.max_bytes) {
+            return Err(limit.exceeded(bytes.len(), false));
+        }
+        let hash = blob_hash(&bytes);
+        // Reference counting and the write happen under the count's lock, so
+        // a release cannot delete content another caller just referenced
+        let mut refs = self.state.blob_refs.lock_or_insert(&hash).await;
+        if *refs.guard == 0 || !self.contains_key(&blob_key(&hash)).await {
+            self.set_with_options(blob_key(&hash), bytes, None, options).await?;
+        }
+        *refs.guard += 1;
+        Ok(hash)
+    }
+
+    /// Content of the blob with `hash`
+    pub async fn get_blob(&self, hash: &str) -> Option<Vec<u8>> {
+        self.get(&blob_key(hash)).await
+    }
+
+    /// References held on the blob with `hash`; 0 if it is not stored
+    pub async fn blob_refs(&self, hash: &str) -> u64 {
+        let count = self.state.blob_refs.counts.get(hash).map(|count| count.clone());
+        match count {
+            Some(count) => *count.lock().await,
+            None => 0,
+        }
+    }
+
+    /// Drops one reference to the blob with `hash`, deleting the blob when
+    /// none are left. Returns the references remaining.
+    pub async fn release_blob(&self, hash: &str) -> Result<u64, KVError> {
+        let Some(mut refs) = self.state.blob_refs.lock(hash).await else {
+            return Err(KVError::KeyNotFound(blob_key(hash)));
+        };
+        if *refs.guard == 1 {
+            self.del(&blob_key(hash)).await?;
+        }
+        *refs.guard -= 1;
+        Ok(*refs.guard)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cluster() -> KVCluster {
+        let mut cluster = KVCluster::new(16, 3);
+        for i in 0..3 {
+            cluster.add_node(format!("node{}", i));
+        }
+        cluster
+    }
+
+    #[test]
+    fn blobs_are_addressed_by_sha256() {
+        assert_eq!(
+            blob_hash(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[tokio::test]
+    async fn identical_content_is_stored_once_and_counted() {
+        let cluster = cluster();
+        let hash = cluster.put_blob(b"content".to_vec()).await.unwrap();
+        assert_eq!(cluster.put_blob(b"content".to_vec()).await.unwrap(), hash);
+        assert_eq!(cluster.blob_refs(&hash).await, 2);
+        assert_eq!(cluster.get_blob(&hash).await, Some(b"content".to_vec()));
+
+        let other = cluster.put_blob(b"other".to_vec()).await.unwrap();
+        assert_ne!(other, hash);
+        assert_eq!(cluster.blob_refs(&other).await, 1);
+    }
+
+    #[tokio::test]
+    async fn blobs_are_deleted_with_their_last_reference() {
+        let cluster = cluster();
+        let hash = cluster.put_blob(b"content".to_vec()).await.unwrap();
+        cluster.put_blob(b"content".to_vec()).await.unwrap();
+
+        assert_eq!(cluster.release_blob(&hash).await.unwrap(), 1);
+        assert_eq!(cluster.get_blob(&hash).await, Some(b"content".to_vec()));
+        assert_eq!(cluster.release_blob(&hash).await.unwrap(), 0);
+        assert_eq!(cluster.get_blob(&hash).await, None);
+        assert_eq!(cluster.blob_refs(&hash).await, 0);
+        assert!(matches!(cluster.release_blob(&hash).await, Err(KVError::KeyNotFound(_))));
+
+        // Putting the content again starts a new count
+        cluster.put_blob(b"content".to_vec()).await.unwrap();
+        assert_eq!(cluster.blob_refs(&hash).await, 1);
+    }
+
+    #[tokio::test]
+    async fn releasing_an_unknown_blob_is_not_found() {
+        let cluster = cluster();
+        let hash = blob_hash(b"never stored");
+        assert!(matches!(cluster.release_blob(&hash).await, Err(KVError::KeyNotFound(_))));
+    }
+
+    #[tokio::test]
+    async fn a_deleted_blob_key_is_stored_again_on_the_next_put() {
+        let cluster = cluster();
+        let hash = cluster.put_blob(b"content".to_vec()).await.unwrap();
+        cluster.del(&blob_key(&hash)).await.unwrap();
+        assert_eq!(cluster.get_blob(&hash).await, None);
+
+        cluster.put_blob(b"content".to_vec()).await.unwrap();
+        assert_eq!(cluster.get_blob(&hash).await, Some(b"content".to_vec()));
+        assert_eq!(cluster.blob_refs(&hash).await, 2);
+    }
+}
@@ -0,0 +1,56 @@
+use serde_json::Value as JsonValue;
+use std::collections::BTreeMap;
+use std::time::Duration;
+use tokio::task::JoinSet;
+
+use crate::{KVCluster, KVError, WriteOptions};
+
+/// One JSON document in a bulk write
+#[derive(Debug, Clone)]
+pub struct BulkDocument {
+    pub key: String,
+    pub value: JsonValue,
+    pub ttl: Option<Duration>,
+}
+
+impl KVCluster {
+    /// Stores many JSON documents, returning one result per document in input
+    /// order. Documents are grouped by primary node; groups are written
+    /// concurrently and the documents within a group in order, so a key
+    /// repeated in the batch ends up with its last value. A failed document
+    /// does not stop the others.
+    pub async fn set_json_bulk(
+        &self,
+        documents: Vec<BulkDocument>,
+        options: WriteOptions,
+    ) -> Vec<Result<(), KVError>> {
+        let mut groups: BTreeMap<usize, Vec<(usize, BulkDocument)>> = BTreeMap::new();
+        for (position, document) in documents.into_iter().enumerate() {
+            let node = self.primary_idx(document.key.as_bytes()).unwrap_or_default();
+            groups.entry(node).or_default().push((position, document));
+        }
+
+        let mut tasks = JoinSet::new();
+        for (_, group) in groups {
+            let cluster = self.clone();
+            tasks.spawn(async move {
+                let mut results = Vec::with_capacity(group.len());
+                for (position, document) in group {
+                    let result = cluster
+                        .set_json_value_with_options(document.key, &document.value, document.ttl, options)
+                        .await;
+                    results.push((position, result));
+                }
+                results
+            });
+        }
+
+        let mut results: Vec<(usize, Result<(), KVError>)> = Vec::new();
+        while let Some(joined) = tasks.join_next().await {
+            // A write task only panics if the store itself is broken
+            results.extend(joined.expect("bulk write task panicked"));
+        }
+        results.sort_unstable_by_key(|(position, _)| *position);
+        results.into_iter().map(|(_, result)| result).collect()
+    }
+}
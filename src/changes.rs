@@ -0,0 +1,119 @@
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::broadcast;
+
+use crate::{KVCluster, Key};
+
+/// Events buffered per change subscriber before the slowest one starts missing events
+pub(crate) const CHANGE_FEED_CAPACITY: usize = 65_536;
+
+/// Milliseconds since the Unix epoch, used to order writes across clusters
+pub fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum ChangeKind {
+    Set {
+        #[serde(with = "base64_bytes")]
+        value: Vec<u8>,
+        /// Absolute expiry in Unix milliseconds, so transit time doesn't extend the TTL
+        expires_at_ms: Option<u64>,
+    },
+    Del,
+    /// The key's TTL was restarted from the time of the event
+    Touch,
+}
+
+/// A write applied to a key's primary copy
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChangeEvent {
+    pub key: Key,
+    #[serde(flatten)]
+    pub kind: ChangeKind,
+    /// Wall-clock time of the write in Unix milliseconds
+    pub timestamp_ms: u64,
+    /// Whether the write arrived from another cluster rather than a local client
+    #[serde(skip)]
+    pub replicated: bool,
+}
+
+impl ChangeEvent {
+    pub(crate) fn set(key: Key, value: Vec<u8>, ttl: Option<Duration>, replicated: bool) -> Self {
+        let timestamp_ms = unix_millis();
+        ChangeEvent {
+            key,
+            kind: ChangeKind::Set {
+                value,
+                expires_at_ms: ttl.map(|ttl| timestamp_ms + ttl.as_millis() as u64),
+            },
+            timestamp_ms,
+            replicated,
+        }
+    }
+
+    pub(crate) fn del(key: Key, replicated: bool) -> Self {
+        ChangeEvent {
+            key,
+            kind: ChangeKind::Del,
+            timestamp_ms: unix_millis(),
+            replicated,
+        }
+    }
+
+    pub(crate) fn touch(key: Key, replicated: bool) -> Self {
+        ChangeEvent {
+            key,
+            kind: ChangeKind::Touch,
+            timestamp_ms: unix_millis(),
+            replicated,
+        }
+    }
+}
+
+impl KVCluster {
+    /// Subscribes to writes applied through this cluster handle or its clones.
+    /// A subscriber that falls more than [`CHANGE_FEED_CAPACITY`] events behind
+    /// receives `RecvError::Lagged` and skips the missed events.
+    pub fn subscribe_changes(&self) -> broadcast::Receiver<ChangeEvent> {
+        self.state.changes.subscribe()
+    }
+
+    /// Publishes a change if anyone is listening, and invalidates the key for
+    /// tracking clients that read it. Building the event clones the value, so
+    /// the closure is only called when there are subscribers.
+    pub(crate) fn publish_change(&self, event: impl FnOnce() -> ChangeEvent) {
+        let subscribed = self.state.changes.receiver_count() > 0;
+        if !subscribed && !self.state.tracking.is_active() {
+            return;
+        }
+        let event = event();
+        // Touching a key leaves its value as cached
+        if event.kind != ChangeKind::Touch {
+            self.state.tracking.invalidate(event.key.as_bytes());
+        }
+        if subscribed {
+            let _ = self.state.changes.send(event);
+        }
+    }
+}
+
+/// Serializes byte buffers as standard base64 strings
+pub(crate) mod base64_bytes {
+    use base64::engine::general_purpose::STANDARD;
+    use base64::Engine;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&STANDARD.encode(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        STANDARD.decode(encoded).map_err(serde::de::Error::custom)
+    }
+}
@@ -0,0 +1,179 @@
+//! Fault injection for chaos testing, compiled in with the `chaos` feature.
+//!
+//! Faults are injected where nodes handle operations: replica operations can
+//! be dropped or delayed before they are applied, and a node can be slowed
+//! down or taken down for the reads and writes it serves as primary. A failed
+//! node also discards every replica operation sent to it.
+
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+
+use crate::error::KVError;
+use crate::KVCluster;
+
+/// Faults currently injected into a cluster. The default injects none.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChaosConfig {
+    /// Percentage of replica operations discarded instead of applied, 0 to 100
+    pub replica_drop_percent: f64,
+    /// Delay before each replica operation is applied, in milliseconds
+    pub replica_delay_ms: u64,
+    /// Latency added to every read and write a node serves as primary, in
+    /// milliseconds, by node id
+    pub node_latency_ms: BTreeMap<String, u64>,
+    /// Nodes taken down: they fail primary operations and discard replica ones
+    pub failed_nodes: BTreeSet<String>,
+}
+
+impl ChaosConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(0.0..=100.0).contains(&self.replica_drop_percent) {
+            return Err(format!(
+                "replica_drop_percent must be between 0 and 100, got {}",
+                self.replica_drop_percent
+            ));
+        }
+        Ok(())
+    }
+
+    /// Node ids referenced by the config
+    fn node_ids(&self) -> impl Iterator<Item = &String> {
+        self.node_latency_ms.keys().chain(self.failed_nodes.iter())
+    }
+}
+
+/// Injects the faults described by a `ChaosConfig` into a cluster's nodes
+#[derive(Default)]
+pub struct FaultInjector {
+    config: Mutex<ChaosConfig>,
+    replica_ops_dropped: AtomicU64,
+}
+
+impl FaultInjector {
+    fn config_lock(&self) -> MutexGuard<'_, ChaosConfig> {
+        self.config.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn config(&self) -> ChaosConfig {
+        self.config_lock().clone()
+    }
+
+    /// Replaces every injected fault
+    pub fn configure(&self, config: ChaosConfig) -> Result<(), String> {
+        config.validate()?;
+        *self.config_lock() = config;
+        Ok(())
+    }
+
+    /// Stops injecting faults
+    pub fn reset(&self) {
+        *self.config_lock() = ChaosConfig::default();
+    }
+
+    pub fn set_replica_drop_percent(&self, percent: f64) -> Result<(), String> {
+        let mut config = self.config_lock();
+        let updated = ChaosConfig {
+            replica_drop_percent: percent,
+            ..config.clone()
+        };
+        updated.validate()?;
+        *config = updated;
+        Ok(())
+    }
+
+    /// Delays every replica operation by `delay`; `None` removes the delay
+    pub fn set_replica_delay(&self, delay: Option<Duration>) {
+        self.config_lock().replica_delay_ms = delay.map_or(0, |d| d.as_millis() as u64);
+    }
+
+    /// Slows down operations `node_id` serves as primary; `None` removes the latency
+    pub fn set_node_latency(&self, node_id: &str, latency: Option<Duration>) {
+        let mut config = self.config_lock();
+        match latency {
+            Some(latency) => {
+                config
+                    .node_latency_ms
+                    .insert(node_id.to_string(), latency.as_millis() as u64);
+            }
+            None => {
+                config.node_latency_ms.remove(node_id);
+            }
+        }
+    }
+
+    /// Takes a node down until `recover_node` is called
+    pub fn fail_node(&self, node_id: &str) {
+        self.config_lock().failed_nodes.insert(node_id.to_string());
+    }
+
+    pub fn recover_node(&self, node_id: &str) {
+        self.config_lock().failed_nodes.remove(node_id);
+    }
+
+    pub(crate) fn is_failed(&self, node_id: &str) -> bool {
+        self.config_lock().failed_nodes.contains(node_id)
+    }
+
+    /// Replica operations discarded so far, by dropping or by a failed node
+    pub fn replica_ops_dropped(&self) -> u64 {
+        self.replica_ops_dropped.load(Ordering::Relaxed)
+    }
+
+    /// Waits out `node_id`'s injected latency, then fails if it is down
+    pub(crate) async fn before_primary_op(&self, node_id: &str) -> Result<(), KVError> {
+        let (latency_ms, failed) = {
+            let config = self.config_lock();
+            (
+                config.node_latency_ms.get(node_id).copied().unwrap_or(0),
+                config.failed_nodes.contains(node_id),
+            )
+        };
+        if latency_ms > 0 {
+            tokio::time::sleep(Duration::from_millis(latency_ms)).await;
+        }
+        if failed {
+            return Err(KVError::NodeUnavailable(node_id.to_string()));
+        }
+        Ok(())
+    }
+
+    /// Decides whether `node_id` applies its next replica operation, waiting
+    /// out any injected delay first
+    pub(crate) async fn admit_replica_op(&self, node_id: &str) -> bool {
+        let (delay_ms, dropped) = {
+            let config = self.config_lock();
+            let dropped = config.failed_nodes.contains(node_id)
+                || (config.replica_drop_percent > 0.0
+                    && rand::thread_rng().gen::<f64>() * 100.0 < config.replica_drop_percent);
+            (config.replica_delay_ms, dropped)
+        };
+        if dropped {
+            self.replica_ops_dropped.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+        if delay_ms > 0 {
+            tokio::time::sleep(Duration::from_millis(delay_ms)).await;
+        }
+        true
+    }
+}
+
+impl KVCluster {
+    /// Fault injector shared by every node of the cluster
+    pub fn faults(&self) -> &FaultInjector {
+        &self.state.node_settings.faults
+    }
+
+    /// Replaces the injected faults, rejecting configs that name unknown nodes
+    pub fn configure_chaos(&self, config: ChaosConfig) -> Result<(), String> {
+        if let Some(id) = config.node_ids().find(|id| !self.nodes.iter().any(|n| &n.id == *id)) {
+            return Err(format!("unknown node '{}'", id));
+        }
+        self.faults().configure(config)
+    }
+}
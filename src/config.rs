@@ -0,0 +1,204 @@
+use std::collections::HashSet;
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::auth::User;
+use crate::limits::ApiLimits;
+use crate::metrics::{MetricsPushConfig, MetricsTarget};
+use crate::ratelimit::RateLimitConfig;
+
+/// Settings of the HTTP server
+#[derive(Debug, Clone, Default)]
+pub struct VoltConfig {
+    pub api_limits: ApiLimits,
+    /// Per-client quotas; `None` leaves clients unthrottled. A client is a
+    /// configured user, or else an IP address. The concurrency limits count
+    /// in-flight requests, not open connections.
+    pub rate_limit: Option<RateLimitConfig>,
+    /// Bearer tokens allowed to read JSON documents with their sensitive
+    /// fields decrypted, when no users are configured. Users get the
+    /// `decrypt` permission instead.
+    pub decrypt_tokens: Vec<String>,
+    /// API users and their roles; when empty, every route is open
+    pub users: Vec<User>,
+    /// Periodic push of the cluster stats to StatsD or Graphite
+    pub metrics_push: Option<MetricsPushConfig>,
+}
+
+/// Problems found by `VoltConfig::validate`, one per line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigErrors(pub Vec<String>);
+
+impl fmt::Display for ConfigErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0.join("\n"))
+    }
+}
+
+impl Error for ConfigErrors {}
+
+/// Whether `addr` looks like `host:port`
+fn is_host_port(addr: &str) -> bool {
+    addr.rsplit_once(':')
+        .is_some_and(|(host, port)| !host.is_empty() && port.parse::<u16>().is_ok())
+}
+
+fn env_var<T>(name: &str) -> Result<Option<T>, Box<dyn Error>>
+where
+    T: FromStr,
+    T::Err: Error + 'static,
+{
+    match std::env::var(name) {
+        Ok(value) => value
+            .trim()
+            .parse()
+            .map(Some)
+            .map_err(|e| format!("invalid {}: {}", name, e).into()),
+        Err(_) => Ok(None),
+    }
+}
+
+impl VoltConfig {
+    /// Reads the configuration from `VOLT_*` environment variables, using
+    /// defaults for the ones not set
+    pub fn from_env() -> Result<Self, Box<dyn Error>> {
+        let mut config = VoltConfig::default();
+
+        let limits = &mut config.api_limits;
+        if let Some(max_body) = env_var("VOLT_MAX_BODY_BYTES")? {
+            limits.max_body_bytes = max_body;
+        }
+        if let Some(max_depth) = env_var("VOLT_MAX_JSON_DEPTH")? {
+            limits.max_json_depth = max_depth;
+        }
+        if let Some(max_key) = env_var("VOLT_MAX_KEY_BYTES")? {
+            limits.max_key_bytes = max_key;
+        }
+
+        // Rate limiting is enabled by setting a sustained rate
+        if let Some(rate) = env_var::<f64>("VOLT_RATE_LIMIT_RPS")? {
+            let burst = env_var("VOLT_RATE_LIMIT_BURST")?.unwrap_or(rate.ceil().max(1.0) as u32);
+            let mut rate_limit = RateLimitConfig::new(rate, burst);
+            rate_limit.max_concurrent_per_client = env_var("VOLT_MAX_CONCURRENT_PER_CLIENT")?;
+            rate_limit.max_concurrent = env_var("VOLT_MAX_CONCURRENT_REQUESTS")?;
+            config.rate_limit = Some(rate_limit);
+        }
+
+        // Comma-separated tokens for `GET /json/:key?decrypt=true`
+        if let Ok(tokens) = std::env::var("VOLT_DECRYPT_TOKENS") {
+            config.decrypt_tokens = tokens
+                .split(',')
+                .map(str::trim)
+                .filter(|token| !token.is_empty())
+                .map(String::from)
+                .collect();
+        }
+
+        // Role-based access control, e.g. VOLT_USERS="ops:admin:t1,app:writer+decrypt:t2"
+        if let Ok(users) = std::env::var("VOLT_USERS") {
+            config.users = users
+                .split(',')
+                .filter(|user| !user.trim().is_empty())
+                .map(|user| user.parse::<User>())
+                .collect::<Result<_, _>>()
+                .map_err(|e| format!("invalid VOLT_USERS: {}", e))?;
+        }
+
+        // Metrics push is enabled by setting a StatsD or Graphite address
+        let target = match (std::env::var("VOLT_STATSD_ADDR"), std::env::var("VOLT_GRAPHITE_ADDR")) {
+            (Ok(_), Ok(_)) => return Err("set only one of VOLT_STATSD_ADDR and VOLT_GRAPHITE_ADDR".into()),
+            (Ok(addr), _) => Some(MetricsTarget::Statsd(addr.trim().to_string())),
+            (_, Ok(addr)) => Some(MetricsTarget::Graphite(addr.trim().to_string())),
+            _ => None,
+        };
+        if let Some(target) = target {
+            config.metrics_push = Some(MetricsPushConfig {
+                target,
+                prefix: std::env::var("VOLT_METRICS_PREFIX").unwrap_or_else(|_| "volt".to_string()),
+                interval: Duration::from_secs(env_var("VOLT_METRICS_INTERVAL_SECS")?.unwrap_or(10).max(1)),
+            });
+        }
+        Ok(config)
+    }
+
+    /// Checks for settings that parse but cannot work, or contradict each
+    /// other, reporting every problem found rather than the first
+    pub fn validate(&self) -> Result<(), ConfigErrors> {
+        let mut errors = Vec::new();
+
+        let limits = &self.api_limits;
+        for (value, name) in [
+            (limits.max_body_bytes, "VOLT_MAX_BODY_BYTES"),
+            (limits.max_json_depth, "VOLT_MAX_JSON_DEPTH"),
+            (limits.max_key_bytes, "VOLT_MAX_KEY_BYTES"),
+        ] {
+            if value == 0 {
+                errors.push(format!("{} must be positive", name));
+            }
+        }
+
+        if let Some(rate_limit) = &self.rate_limit {
+            if !(rate_limit.requests_per_sec.is_finite() && rate_limit.requests_per_sec > 0.0) {
+                errors.push("VOLT_RATE_LIMIT_RPS must be a positive number".to_string());
+            }
+            if rate_limit.burst == 0 {
+                errors.push("VOLT_RATE_LIMIT_BURST must be positive".to_string());
+            }
+            if rate_limit.max_concurrent_per_client == Some(0) {
+                errors.push("VOLT_MAX_CONCURRENT_PER_CLIENT must be positive".to_string());
+            }
+            if rate_limit.max_concurrent == Some(0) {
+                errors.push("VOLT_MAX_CONCURRENT_REQUESTS must be positive".to_string());
+            }
+            if let (Some(per_client), Some(total)) = (rate_limit.max_concurrent_per_client, rate_limit.max_concurrent) {
+                if per_client > total {
+                    errors.push(format!(
+                        "VOLT_MAX_CONCURRENT_PER_CLIENT ({}) exceeds VOLT_MAX_CONCURRENT_REQUESTS ({})",
+                        per_client, total
+                    ));
+                }
+            }
+        }
+
+        let mut names = HashSet::new();
+        let mut tokens = HashSet::new();
+        for user in &self.users {
+            if !names.insert(&user.name) {
+                errors.push(format!("VOLT_USERS lists user '{}' more than once", user.name));
+            }
+            if !tokens.insert(&user.token) {
+                errors.push(format!("VOLT_USERS: user '{}' reuses another user's token", user.name));
+            }
+        }
+
+        // With users configured, a request needs a user's token to get past
+        // the role check, so a separate decrypt token could never be used
+        if !self.users.is_empty() && !self.decrypt_tokens.is_empty() {
+            errors.push(
+                "VOLT_DECRYPT_TOKENS cannot be used with VOLT_USERS; grant decrypt in VOLT_USERS as name:role+decrypt:token"
+                    .to_string(),
+            );
+        }
+
+        if let Some(push) = &self.metrics_push {
+            let addr = match &push.target {
+                MetricsTarget::Statsd(addr) => ("VOLT_STATSD_ADDR", addr),
+                MetricsTarget::Graphite(addr) => ("VOLT_GRAPHITE_ADDR", addr),
+            };
+            if !is_host_port(addr.1) {
+                errors.push(format!("{} must be host:port, got '{}'", addr.0, addr.1));
+            }
+            if push.prefix.is_empty() {
+                errors.push("VOLT_METRICS_PREFIX must not be empty".to_string());
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigErrors(errors))
+        }
+    }
+}
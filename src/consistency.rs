@@ -0,0 +1,120 @@
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tokio::time::Instant;
+use xxhash_rust::xxh32::xxh32;
+
+use crate::{KVCluster, Key};
+
+/// How long replica operations waited on a node's channel before being
+/// applied
+#[derive(Default)]
+pub(crate) struct ApplyLag {
+    applied: AtomicU64,
+    last_us: AtomicU64,
+    max_us: AtomicU64,
+}
+
+impl ApplyLag {
+    pub(crate) fn record(&self, lag: Duration) {
+        let us = u64::try_from(lag.as_micros()).unwrap_or(u64::MAX);
+        self.applied.fetch_add(1, Ordering::Relaxed);
+        self.last_us.store(us, Ordering::Relaxed);
+        self.max_us.fetch_max(us, Ordering::Relaxed);
+    }
+
+    pub(crate) fn snapshot(&self) -> ApplyLagStats {
+        ApplyLagStats {
+            replica_ops_applied: self.applied.load(Ordering::Relaxed),
+            last_apply_lag_ms: self.last_us.load(Ordering::Relaxed) as f64 / 1000.0,
+            max_apply_lag_ms: self.max_us.load(Ordering::Relaxed) as f64 / 1000.0,
+        }
+    }
+}
+
+/// Replica apply counters of one node
+#[derive(Serialize, Debug, Clone, Copy)]
+pub struct ApplyLagStats {
+    pub replica_ops_applied: u64,
+    /// Time between the primary sending the last applied operation and the
+    /// replica applying it
+    pub last_apply_lag_ms: f64,
+    /// Longest such time since the node started
+    pub max_apply_lag_ms: f64,
+}
+
+/// One node's copy of a probed key
+#[derive(Serialize, Debug, Clone)]
+pub struct ReplicaCopy {
+    pub node: String,
+    pub primary: bool,
+    pub present: bool,
+    /// Hex xxh32 of the value, for comparing copies without returning them
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub digest: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ttl_ms: Option<u64>,
+}
+
+/// Every replica's copy of a key and whether they agree
+#[derive(Serialize, Debug, Clone)]
+pub struct ConsistencyReport {
+    pub key: Key,
+    /// Whether every copy is present with the primary's value, or every copy
+    /// is absent. TTLs are reported but not compared, since replicas apply
+    /// them slightly later than the primary.
+    pub consistent: bool,
+    /// Nodes whose copy differs from the primary's
+    pub divergent: Vec<String>,
+    pub copies: Vec<ReplicaCopy>,
+}
+
+impl KVCluster {
+    /// Reads `key` straight from each of its replicas, bypassing the usual
+    /// primary-only read path, and reports copies that differ from the
+    /// primary's. Nodes that are down are skipped while another is up.
+    /// Probing does not count as an access or slide TTLs.
+    pub fn consistency_probe(&self, key: impl AsRef<[u8]>) -> ConsistencyReport {
+        let key = key.as_ref();
+        let now = Instant::now();
+        let copies: Vec<ReplicaCopy> = self
+            .route(key)
+            .unwrap_or_else(|| self.preference_list(key))
+            .into_iter()
+            .map(|idx| &self.nodes[idx])
+            .enumerate()
+            .map(|(position, node)| {
+                let entry = node.store.get(key).filter(|entry| !entry.is_expired(now));
+                ReplicaCopy {
+                    node: node.id.clone(),
+                    primary: position == 0,
+                    present: entry.is_some(),
+                    digest: entry
+                        .as_ref()
+                        .map(|entry| format!("{:08x}", xxh32(&entry.value, 0))),
+                    size: entry.as_ref().map(|entry| entry.value.len()),
+                    ttl_ms: entry
+                        .as_ref()
+                        .and_then(|entry| entry.expiry)
+                        .map(|expiry| expiry.saturating_duration_since(now).as_millis() as u64),
+                }
+            })
+            .collect();
+        let divergent: Vec<String> = match copies.split_first() {
+            Some((primary, replicas)) => replicas
+                .iter()
+                .filter(|copy| copy.digest != primary.digest)
+                .map(|copy| copy.node.clone())
+                .collect(),
+            None => Vec::new(),
+        };
+        ConsistencyReport {
+            key: Key::from(key),
+            consistent: divergent.is_empty(),
+            divergent,
+            copies,
+        }
+    }
+}
@@ -0,0 +1,574 @@
+//! Conflict-free replicated data types.
+//!
+//! CRDT values are stored like any other value, encoded as [`CRDT_MAGIC`]
+//! followed by their JSON state. Wherever two copies of a CRDT meet — a
+//! replica applying an update from its primary, or a cluster applying a write
+//! replicated from another cluster — the states are merged instead of one
+//! overwriting the other, so concurrent writes converge regardless of order.
+
+use dashmap::mapref::entry::Entry;
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::Duration;
+use tokio::time::Instant;
+
+use crate::changes::{unix_millis, ChangeEvent};
+use crate::key::key_name;
+use crate::{KVCluster, KVEntry, KVError, KVOperation, Key, Ttl, WriteOptions};
+
+/// Prefix marking a stored value as an encoded CRDT
+pub const CRDT_MAGIC: &[u8] = b"\0crdt:";
+
+/// Grow-only counter: one monotonically increasing count per replica
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GCounter {
+    counts: BTreeMap<String, u64>,
+}
+
+impl GCounter {
+    pub fn increment(&mut self, replica: &str, by: u64) {
+        *self.counts.entry(replica.to_string()).or_insert(0) += by;
+    }
+
+    pub fn value(&self) -> u64 {
+        self.counts.values().sum()
+    }
+
+    pub fn merge(&mut self, other: &GCounter) {
+        for (replica, count) in &other.counts {
+            let ours = self.counts.entry(replica.clone()).or_insert(0);
+            *ours = (*ours).max(*count);
+        }
+    }
+}
+
+/// Counter supporting increments and decrements, as a pair of grow-only counters
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PNCounter {
+    increments: GCounter,
+    decrements: GCounter,
+}
+
+impl PNCounter {
+    pub fn add(&mut self, replica: &str, delta: i64) {
+        if delta >= 0 {
+            self.increments.increment(replica, delta as u64);
+        } else {
+            self.decrements.increment(replica, delta.unsigned_abs());
+        }
+    }
+
+    pub fn value(&self) -> i64 {
+        self.increments.value() as i64 - self.decrements.value() as i64
+    }
+
+    pub fn merge(&mut self, other: &PNCounter) {
+        self.increments.merge(&other.increments);
+        self.decrements.merge(&other.decrements);
+    }
+}
+
+/// Register holding the most recently written value. Ties on the timestamp
+/// are broken by replica id so every copy picks the same winner.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LwwRegister {
+    value: JsonValue,
+    timestamp_ms: u64,
+    replica: String,
+}
+
+impl LwwRegister {
+    pub fn new(value: JsonValue, replica: &str) -> Self {
+        LwwRegister {
+            value,
+            timestamp_ms: unix_millis(),
+            replica: replica.to_string(),
+        }
+    }
+
+    pub fn value(&self) -> &JsonValue {
+        &self.value
+    }
+
+    pub fn merge(&mut self, other: &LwwRegister) {
+        if (other.timestamp_ms, &other.replica) > (self.timestamp_ms, &self.replica) {
+            *self = other.clone();
+        }
+    }
+}
+
+/// Observed-remove set: an element is present while it has an add tag that
+/// has not been removed, so a concurrent add wins over a remove
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OrSet {
+    adds: BTreeMap<String, BTreeSet<String>>,
+    removed: BTreeSet<String>,
+    clock: BTreeMap<String, u64>,
+}
+
+impl OrSet {
+    pub fn add(&mut self, replica: &str, member: &str) {
+        let counter = self.clock.entry(replica.to_string()).or_insert(0);
+        *counter += 1;
+        let tag = format!("{}:{}", replica, counter);
+        self.adds.entry(member.to_string()).or_default().insert(tag);
+    }
+
+    pub fn remove(&mut self, member: &str) {
+        if let Some(tags) = self.adds.get(member) {
+            self.removed.extend(tags.iter().cloned());
+        }
+    }
+
+    pub fn contains(&self, member: &str) -> bool {
+        self.adds
+            .get(member)
+            .is_some_and(|tags| tags.iter().any(|t| !self.removed.contains(t)))
+    }
+
+    pub fn members(&self) -> Vec<&str> {
+        self.adds
+            .keys()
+            .filter(|m| self.contains(m))
+            .map(String::as_str)
+            .collect()
+    }
+
+    pub fn merge(&mut self, other: &OrSet) {
+        for (member, tags) in &other.adds {
+            self.adds.entry(member.clone()).or_default().extend(tags.iter().cloned());
+        }
+        self.removed.extend(other.removed.iter().cloned());
+        for (replica, counter) in &other.clock {
+            let ours = self.clock.entry(replica.clone()).or_insert(0);
+            *ours = (*ours).max(*counter);
+        }
+    }
+}
+
+/// A CRDT stored as a value
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum CrdtValue {
+    GCounter(GCounter),
+    PnCounter(PNCounter),
+    LwwRegister(LwwRegister),
+    OrSet(OrSet),
+}
+
+impl CrdtValue {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            CrdtValue::GCounter(_) => "g_counter",
+            CrdtValue::PnCounter(_) => "pn_counter",
+            CrdtValue::LwwRegister(_) => "lww_register",
+            CrdtValue::OrSet(_) => "or_set",
+        }
+    }
+
+    /// Merges `other` into `self`. Both must be the same kind of CRDT.
+    pub fn merge(&mut self, other: &CrdtValue) -> Result<(), KVError> {
+        match (self, other) {
+            (CrdtValue::GCounter(a), CrdtValue::GCounter(b)) => a.merge(b),
+            (CrdtValue::PnCounter(a), CrdtValue::PnCounter(b)) => a.merge(b),
+            (CrdtValue::LwwRegister(a), CrdtValue::LwwRegister(b)) => a.merge(b),
+            (CrdtValue::OrSet(a), CrdtValue::OrSet(b)) => a.merge(b),
+            (a, b) => {
+                return Err(KVError::WrongType(format!(
+                    "cannot merge {} into {}",
+                    b.type_name(),
+                    a.type_name()
+                )))
+            }
+        }
+        Ok(())
+    }
+
+    /// Plain JSON view of the current value: a number for counters, the
+    /// stored value for registers and the member list for sets
+    pub fn resolved(&self) -> JsonValue {
+        match self {
+            CrdtValue::GCounter(c) => JsonValue::from(c.value()),
+            CrdtValue::PnCounter(c) => JsonValue::from(c.value()),
+            CrdtValue::LwwRegister(r) => r.value().clone(),
+            CrdtValue::OrSet(s) => JsonValue::from(s.members()),
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = CRDT_MAGIC.to_vec();
+        // Serializing these types cannot fail: all map keys are strings
+        serde_json::to_writer(&mut bytes, self).expect("CRDT state is serializable");
+        bytes
+    }
+
+    /// Decodes a stored value. Returns `None` if the bytes are not a CRDT.
+    pub fn decode(bytes: &[u8]) -> Option<Result<CrdtValue, KVError>> {
+        bytes
+            .strip_prefix(CRDT_MAGIC)
+            .map(|state| serde_json::from_slice(state).map_err(KVError::from))
+    }
+}
+
+impl EncodedValue for CrdtValue {
+    const DESCRIPTION: &'static str = "a CRDT";
+
+    fn decode(bytes: &[u8]) -> Option<Result<Self, KVError>> {
+        CrdtValue::decode(bytes)
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        CrdtValue::encode(self)
+    }
+}
+
+pub fn is_crdt(bytes: &[u8]) -> bool {
+    bytes.starts_with(CRDT_MAGIC)
+}
+
+/// Merges two encoded CRDT states, or returns `None` if they can't be merged
+pub(crate) fn merge_encoded(existing: &[u8], incoming: &[u8]) -> Option<Vec<u8>> {
+    let mut merged = CrdtValue::decode(existing)?.ok()?;
+    let incoming = CrdtValue::decode(incoming)?.ok()?;
+    merged.merge(&incoming).ok()?;
+    Some(merged.encode())
+}
+
+/// A structured value stored behind a type prefix and updated in place on
+/// the key's primary: CRDTs and streams
+pub(crate) trait EncodedValue: Sized {
+    /// How errors refer to the type, e.g. "a CRDT"
+    const DESCRIPTION: &'static str;
+
+    /// Decodes a stored value, or returns `None` if it is of another type
+    fn decode(bytes: &[u8]) -> Option<Result<Self, KVError>>;
+
+    fn encode(&self) -> Vec<u8>;
+}
+
+impl KVCluster {
+    /// Atomically updates the CRDT at `key` on its primary and ships the
+    /// resulting state to the replicas. `ttl` replaces the key's TTL when set;
+    /// otherwise the current one is kept.
+    async fn update_crdt<F>(
+        &self,
+        key: &[u8],
+        ttl: Option<Duration>,
+        options: WriteOptions,
+        update: F,
+    ) -> Result<CrdtValue, KVError>
+    where
+        F: FnOnce(Option<CrdtValue>) -> Result<CrdtValue, KVError>,
+    {
+        self.update_encoded(key, options, |current| Ok((update(current)?, ttl))).await
+    }
+
+    /// Atomically updates the encoded value at `key` on its primary and
+    /// ships the resulting state to the replicas. `update` returns the new
+    /// state and, optionally, a TTL replacing the key's; otherwise the
+    /// current TTL is kept.
+    pub(crate) async fn update_encoded<V, F>(&self, key: &[u8], options: WriteOptions, update: F) -> Result<V, KVError>
+    where
+        V: EncodedValue,
+        F: FnOnce(Option<V>) -> Result<(V, Option<Duration>), KVError>,
+    {
+        self.check_writable(&options)?;
+        let nodes = self.get_nodes(key)?;
+        let required = self.required_acks(&nodes, &options)?;
+        // A truncated state cannot be decoded, so oversized states are
+        // always rejected
+        let limit = self.value_limit();
+        let encode_within_limit = |state: &V| {
+            let value = state.encode();
+            match limit {
+                Some(limit) if value.len() > limit.max_bytes => Err(limit.exceeded(value.len(), false)),
+                _ => Ok(value),
+            }
+        };
+        let primary = &nodes[0];
+        primary.enter().await?;
+        let admission = self.admit_key(key, &options)?;
+        let now = Instant::now();
+        let (state, expiry, entry_ttl) = match primary.store.entry(Key::from(key)) {
+            Entry::Occupied(mut occupied) => {
+                let live = occupied.get().expiry.is_none_or(|e| e > now);
+                let current = if live {
+                    match V::decode(&occupied.get().value) {
+                        Some(decoded) => Some(decoded?),
+                        None => {
+                            return Err(KVError::WrongType(format!(
+                                "'{}' does not hold {}",
+                                key_name(key),
+                                V::DESCRIPTION
+                            )))
+                        }
+                    }
+                } else {
+                    None
+                };
+                let (state, ttl) = update(current)?;
+                let value = encode_within_limit(&state)?;
+                let (expiry, entry_ttl) = match ttl {
+                    Some(ttl) => (Some(now + ttl), Some(Ttl::new(ttl, false))),
+                    None if live => (occupied.get().expiry, occupied.get().ttl),
+                    None => (None, None),
+                };
+                let previous = occupied.get().expiry;
+                occupied.get_mut().overwrite(value, expiry, entry_ttl, now);
+                primary.track_expiry(occupied.key(), previous, expiry);
+                (state, expiry, entry_ttl)
+            }
+            Entry::Vacant(vacant) => {
+                let (state, ttl) = update(None)?;
+                let value = encode_within_limit(&state)?;
+                let expiry = ttl.map(|ttl| now + ttl);
+                let entry_ttl = ttl.map(|ttl| Ttl::new(ttl, false));
+                primary.track_expiry(vacant.key(), None, expiry);
+                vacant.insert(KVEntry::new(value, expiry, entry_ttl));
+                (state, expiry, entry_ttl)
+            }
+        };
+
+        let evicting = self.evict(admission, options);
+        let encoded = state.encode();
+        let remaining = expiry.map(|e| e.saturating_duration_since(now));
+        self.publish_change(|| {
+            ChangeEvent::set(Key::from(key), encoded.clone(), remaining, options.replicated)
+        });
+        let replicated = self
+            .replicate(&nodes[1..], required, options.deadline(), |ack| {
+                let ttl = remaining.zip(entry_ttl).map(|(remaining, ttl)| Ttl::new(remaining, ttl.sliding));
+                KVOperation::Set(Key::from(key), encoded.clone(), ttl, ack)
+            })
+            .await;
+        evicting.await;
+        replicated?;
+        Ok(state)
+    }
+
+    /// Merges `value` into the CRDT stored at `key`, creating it if absent,
+    /// and returns the merged state
+    pub async fn merge_crdt(
+        &self,
+        key: &str,
+        value: &CrdtValue,
+        ttl: Option<Duration>,
+    ) -> Result<CrdtValue, KVError> {
+        self.merge_crdt_with_options(key.as_bytes(), value, ttl, WriteOptions::default()).await
+    }
+
+    pub(crate) async fn merge_crdt_with_options(
+        &self,
+        key: &[u8],
+        value: &CrdtValue,
+        ttl: Option<Duration>,
+        options: WriteOptions,
+    ) -> Result<CrdtValue, KVError> {
+        self.update_crdt(key, ttl, options, |current| match current {
+            Some(mut state) => {
+                state.merge(value)?;
+                Ok(state)
+            }
+            None => Ok(value.clone()),
+        })
+        .await
+    }
+
+    /// Reads the CRDT stored at `key`
+    pub async fn get_crdt(&self, key: &str) -> Result<Option<CrdtValue>, KVError> {
+        match self.get(key).await {
+            Some(bytes) => match CrdtValue::decode(&bytes) {
+                Some(state) => state.map(Some),
+                None => Err(KVError::WrongType(format!("'{}' does not hold a CRDT", key))),
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// Adds `delta` to the PN-counter at `key` on behalf of this cluster and
+    /// returns the new total
+    pub async fn increment_counter(&self, key: &str, delta: i64) -> Result<i64, KVError> {
+        let replica = self.cluster_id.clone();
+        let state = self
+            .update_crdt(key.as_bytes(), None, WriteOptions::default(), |current| {
+                let mut counter = match current {
+                    Some(CrdtValue::PnCounter(counter)) => counter,
+                    None => PNCounter::default(),
+                    Some(other) => {
+                        return Err(KVError::WrongType(format!(
+                            "'{}' holds a {}, not a pn_counter",
+                            key,
+                            other.type_name()
+                        )))
+                    }
+                };
+                counter.add(&replica, delta);
+                Ok(CrdtValue::PnCounter(counter))
+            })
+            .await?;
+        match state {
+            CrdtValue::PnCounter(counter) => Ok(counter.value()),
+            _ => unreachable!("update always stores a pn_counter"),
+        }
+    }
+
+    /// Adds or removes `member` in the OR-set at `key` on behalf of this cluster
+    pub async fn update_set(&self, key: &str, member: &str, present: bool) -> Result<(), KVError> {
+        let replica = self.cluster_id.clone();
+        self.update_crdt(key.as_bytes(), None, WriteOptions::default(), |current| {
+            let mut set = match current {
+                Some(CrdtValue::OrSet(set)) => set,
+                None => OrSet::default(),
+                Some(other) => {
+                    return Err(KVError::WrongType(format!(
+                        "'{}' holds a {}, not an or_set",
+                        key,
+                        other.type_name()
+                    )))
+                }
+            };
+            if present {
+                set.add(&replica, member);
+            } else {
+                set.remove(member);
+            }
+            Ok(CrdtValue::OrSet(set))
+        })
+        .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn merged(a: &CrdtValue, b: &CrdtValue) -> CrdtValue {
+        let mut merged = a.clone();
+        merged.merge(b).unwrap();
+        merged
+    }
+
+    /// Checks that merging the states in any order, any number of times,
+    /// gives the same state
+    fn assert_converges(a: CrdtValue, b: CrdtValue, c: CrdtValue) {
+        assert_eq!(merged(&a, &b), merged(&b, &a), "commutative");
+        assert_eq!(merged(&merged(&a, &b), &c), merged(&a, &merged(&b, &c)), "associative");
+        assert_eq!(merged(&a, &a), a, "idempotent");
+        let ab = merged(&a, &b);
+        assert_eq!(merged(&ab, &b), ab, "idempotent after a merge");
+    }
+
+    fn g_counter(replica: &str, by: u64) -> GCounter {
+        let mut counter = GCounter::default();
+        counter.increment(replica, by);
+        counter
+    }
+
+    fn register(value: JsonValue, timestamp_ms: u64, replica: &str) -> CrdtValue {
+        CrdtValue::LwwRegister(LwwRegister {
+            value,
+            timestamp_ms,
+            replica: replica.to_string(),
+        })
+    }
+
+    #[test]
+    fn g_counters_converge_to_the_sum_of_replica_maxima() {
+        let mut a = g_counter("a", 3);
+        let b = g_counter("b", 5);
+        let mut c = g_counter("a", 1);
+        c.increment("c", 2);
+        assert_converges(
+            CrdtValue::GCounter(a.clone()),
+            CrdtValue::GCounter(b.clone()),
+            CrdtValue::GCounter(c.clone()),
+        );
+
+        a.merge(&b);
+        a.merge(&c);
+        assert_eq!(a.value(), 3 + 5 + 2);
+    }
+
+    #[test]
+    fn pn_counters_converge() {
+        let mut a = PNCounter::default();
+        a.add("a", 10);
+        let mut b = PNCounter::default();
+        b.add("b", -4);
+        let mut c = PNCounter::default();
+        c.add("a", 2);
+        c.add("c", -1);
+        assert_converges(
+            CrdtValue::PnCounter(a.clone()),
+            CrdtValue::PnCounter(b.clone()),
+            CrdtValue::PnCounter(c.clone()),
+        );
+
+        a.merge(&b);
+        a.merge(&c);
+        assert_eq!(a.value(), 10 - 4 - 1);
+    }
+
+    #[test]
+    fn lww_registers_keep_the_latest_write_and_break_ties_by_replica() {
+        let a = register(json!("old"), 1, "a");
+        let b = register(json!("new"), 2, "a");
+        let c = register(json!("tie"), 2, "b");
+        assert_converges(a.clone(), b.clone(), c.clone());
+
+        assert_eq!(merged(&a, &b).resolved(), json!("new"));
+        assert_eq!(merged(&b, &c).resolved(), json!("tie"));
+        assert_eq!(merged(&c, &b).resolved(), json!("tie"));
+    }
+
+    #[test]
+    fn or_sets_converge_and_a_concurrent_add_wins_over_a_remove() {
+        let mut base = OrSet::default();
+        base.add("a", "x");
+        base.add("a", "y");
+
+        // One replica removes x while another adds it again
+        let mut removed = base.clone();
+        removed.remove("x");
+        let mut readded = base.clone();
+        readded.add("b", "x");
+        let mut other = base.clone();
+        other.remove("y");
+        other.add("c", "z");
+        assert_converges(
+            CrdtValue::OrSet(removed.clone()),
+            CrdtValue::OrSet(readded.clone()),
+            CrdtValue::OrSet(other.clone()),
+        );
+
+        removed.merge(&readded);
+        removed.merge(&other);
+        assert_eq!(removed.members(), ["x", "z"]);
+    }
+
+    #[test]
+    fn merging_different_kinds_fails() {
+        let mut counter = CrdtValue::GCounter(g_counter("a", 1));
+        let set = CrdtValue::OrSet(OrSet::default());
+        assert!(matches!(counter.merge(&set), Err(KVError::WrongType(_))));
+        assert_eq!(counter, CrdtValue::GCounter(g_counter("a", 1)));
+    }
+
+    #[test]
+    fn encoded_states_round_trip_and_merge() {
+        let a = CrdtValue::GCounter(g_counter("a", 2));
+        let b = CrdtValue::GCounter(g_counter("b", 3));
+        let encoded = a.encode();
+        assert!(is_crdt(&encoded));
+        assert_eq!(CrdtValue::decode(&encoded).unwrap().unwrap(), a);
+        assert!(CrdtValue::decode(b"plain value").is_none());
+
+        let merged_bytes = merge_encoded(&encoded, &b.encode()).unwrap();
+        assert_eq!(CrdtValue::decode(&merged_bytes).unwrap().unwrap(), merged(&a, &b));
+        assert_eq!(merge_encoded(&encoded, b"plain value"), None);
+        assert_eq!(merge_encoded(&encoded, &CrdtValue::OrSet(OrSet::default()).encode()), None);
+    }
+}
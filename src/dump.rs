@@ -0,0 +1,192 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, Write};
+use std::time::Duration;
+use tokio::time::Instant;
+
+use crate::changes::{base64_bytes, unix_millis};
+use crate::encryption::{self, KeyProvider};
+use crate::{KVCluster, KVError, Key};
+
+/// One key in an export dump. Dumps are newline-delimited JSON, one entry per line.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DumpEntry {
+    pub key: Key,
+    #[serde(with = "base64_bytes")]
+    pub value: Vec<u8>,
+    /// Absolute expiry in Unix milliseconds, so dumps taken at different
+    /// times can be compared
+    pub expires_at_ms: Option<u64>,
+    /// Id of the key `value` is encrypted with, if it is encrypted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_id: Option<String>,
+}
+
+/// Encrypts every value in a dump with the provider's current key. Each
+/// value is bound to its key, so values cannot be swapped between entries.
+/// Values that are already encrypted are left alone.
+pub fn encrypt_dump(entries: &mut [DumpEntry], provider: &dyn KeyProvider) -> io::Result<()> {
+    for entry in entries.iter_mut().filter(|entry| entry.key_id.is_none()) {
+        let (key_id, sealed) = encryption::seal(provider, entry.key.as_bytes(), &entry.value)?;
+        entry.value = sealed;
+        entry.key_id = Some(key_id);
+    }
+    Ok(())
+}
+
+/// Decrypts the encrypted values in a dump. Fails if any value is encrypted
+/// and no provider is given.
+pub fn decrypt_dump(entries: &mut [DumpEntry], provider: Option<&dyn KeyProvider>) -> io::Result<()> {
+    for entry in entries.iter_mut() {
+        let Some(key_id) = entry.key_id.take() else {
+            continue;
+        };
+        let Some(provider) = provider else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("dump is encrypted with key '{}' but no key was provided", key_id),
+            ));
+        };
+        entry.value = encryption::open(provider, &key_id, entry.key.as_bytes(), &entry.value)?;
+    }
+    Ok(())
+}
+
+/// Writes entries as newline-delimited JSON
+pub fn write_dump<W: Write>(entries: &[DumpEntry], mut writer: W) -> io::Result<()> {
+    for entry in entries {
+        serde_json::to_writer(&mut writer, entry)?;
+        writer.write_all(b"\n")?;
+    }
+    writer.flush()
+}
+
+/// Reads a newline-delimited JSON dump, skipping blank lines
+pub fn read_dump<R: BufRead>(reader: R) -> io::Result<Vec<DumpEntry>> {
+    let mut entries = Vec::new();
+    for (number, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry = serde_json::from_str(&line).map_err(|e| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("line {}: {}", number + 1, e))
+        })?;
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+/// A key whose expiry differs between two dumps by more than the tolerance
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TtlDrift {
+    pub key: Key,
+    pub a_expires_at_ms: Option<u64>,
+    pub b_expires_at_ms: Option<u64>,
+}
+
+/// Key-by-key differences between two dumps
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct DumpDiff {
+    pub keys_a: usize,
+    pub keys_b: usize,
+    pub only_in_a: Vec<Key>,
+    pub only_in_b: Vec<Key>,
+    pub value_mismatches: Vec<Key>,
+    pub ttl_drift: Vec<TtlDrift>,
+}
+
+impl DumpDiff {
+    /// True when the dumps hold the same keys and values and their TTLs agree
+    pub fn is_empty(&self) -> bool {
+        self.only_in_a.is_empty()
+            && self.only_in_b.is_empty()
+            && self.value_mismatches.is_empty()
+            && self.ttl_drift.is_empty()
+    }
+}
+
+/// Compares two dumps key by key. Expiries further apart than `ttl_tolerance`,
+/// or present in only one dump, are reported as drift. Keys are reported in
+/// sorted order.
+pub fn diff_dumps(a: &[DumpEntry], b: &[DumpEntry], ttl_tolerance: Duration) -> DumpDiff {
+    let a: BTreeMap<&Key, &DumpEntry> = a.iter().map(|e| (&e.key, e)).collect();
+    let b: BTreeMap<&Key, &DumpEntry> = b.iter().map(|e| (&e.key, e)).collect();
+    let tolerance = ttl_tolerance.as_millis() as u64;
+    let mut diff = DumpDiff {
+        keys_a: a.len(),
+        keys_b: b.len(),
+        ..Default::default()
+    };
+
+    for (key, entry_a) in &a {
+        let Some(entry_b) = b.get(key) else {
+            diff.only_in_a.push((*key).clone());
+            continue;
+        };
+        if entry_a.value != entry_b.value {
+            diff.value_mismatches.push((*key).clone());
+        }
+        let drifted = match (entry_a.expires_at_ms, entry_b.expires_at_ms) {
+            (Some(x), Some(y)) => x.abs_diff(y) > tolerance,
+            (None, None) => false,
+            _ => true,
+        };
+        if drifted {
+            diff.ttl_drift.push(TtlDrift {
+                key: (*key).clone(),
+                a_expires_at_ms: entry_a.expires_at_ms,
+                b_expires_at_ms: entry_b.expires_at_ms,
+            });
+        }
+    }
+    diff.only_in_b = b
+        .keys()
+        .filter(|key| !a.contains_key(*key))
+        .map(|key| (*key).clone())
+        .collect();
+    diff
+}
+
+impl KVCluster {
+    /// Snapshots every live key with its value and expiry, reading each
+    /// key from its primary
+    pub fn export(&self) -> Vec<DumpEntry> {
+        let now = Instant::now();
+        let now_ms = unix_millis();
+        self.partitions()
+            .iter()
+            .flat_map(|partition| partition.entries().collect::<Vec<_>>())
+            .map(|(key, entry)| DumpEntry {
+                key,
+                value: entry.value.into(),
+                expires_at_ms: entry
+                    .expiry
+                    .map(|expiry| now_ms + expiry.saturating_duration_since(now).as_millis() as u64),
+                key_id: None,
+            })
+            .collect()
+    }
+
+    /// Writes the entries of a dump back into the cluster with their
+    /// remaining TTLs, skipping entries that have expired since the dump was
+    /// taken. Values must already be decrypted. Returns the keys written.
+    pub async fn import(&self, entries: Vec<DumpEntry>) -> Result<usize, KVError> {
+        let mut imported = 0;
+        for entry in entries {
+            if entry.key_id.is_some() {
+                return Err(KVError::Encryption(format!("value of '{}' is still encrypted", entry.key)));
+            }
+            let ttl = match entry.expires_at_ms {
+                Some(expires_at) => match expires_at.checked_sub(unix_millis()) {
+                    Some(remaining) if remaining > 0 => Some(Duration::from_millis(remaining)),
+                    _ => continue,
+                },
+                None => None,
+            };
+            self.set(entry.key, entry.value, ttl).await?;
+            imported += 1;
+        }
+        Ok(imported)
+    }
+}
@@ -0,0 +1,49 @@
+//! Embedded mode: a single-node store for programs that use Volt as a
+//! library and cannot have it spawn tasks, e.g. short-lived CLI tools.
+//!
+//! A `KVStore` is a cluster of one node that runs no background work. Keys
+//! expire lazily, when a read finds them past their TTL; keys that are never
+//! read again stay in memory until `sweep` drops them.
+
+use std::ops::Deref;
+use tokio::time::Instant;
+
+use crate::KVCluster;
+
+/// Id of the only node of an embedded store
+const EMBEDDED_NODE_ID: &str = "local";
+
+/// A single-node store with no background tasks. It offers every operation
+/// of `KVCluster`, through `Deref`, and needs no tokio runtime to be built.
+#[derive(Clone)]
+pub struct KVStore {
+    cluster: KVCluster,
+}
+
+impl KVStore {
+    pub fn new() -> Self {
+        let mut cluster = KVCluster::new(1, 1);
+        cluster.add_passive_node(EMBEDDED_NODE_ID.to_string());
+        KVStore { cluster }
+    }
+
+    /// Drops every key whose TTL has run out, returning how many were dropped
+    pub fn sweep(&self) -> usize {
+        let now = Instant::now();
+        self.cluster.nodes.iter().map(|node| node.sweep(now)).sum()
+    }
+}
+
+impl Default for KVStore {
+    fn default() -> Self {
+        KVStore::new()
+    }
+}
+
+impl Deref for KVStore {
+    type Target = KVCluster;
+
+    fn deref(&self) -> &KVCluster {
+        &self.cluster
+    }
+}
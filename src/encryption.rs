@@ -0,0 +1,137 @@
+//! AES-256-GCM encryption for data Volt writes to disk. Key material comes
+//! from a [`KeyProvider`], so it can be read from the environment or fetched
+//! from a KMS; ciphertexts record the id of the key that sealed them.
+
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use std::collections::HashMap;
+use std::io;
+
+/// Environment variable holding a base64-encoded 256-bit key
+pub const KEY_ENV: &str = "VOLT_ENCRYPTION_KEY";
+
+/// Environment variable naming the key in `VOLT_ENCRYPTION_KEY`
+pub const KEY_ID_ENV: &str = "VOLT_ENCRYPTION_KEY_ID";
+
+/// Environment variable holding retired keys, still accepted for decryption,
+/// as comma-separated `id=base64` pairs
+pub const RETIRED_KEYS_ENV: &str = "VOLT_ENCRYPTION_RETIRED_KEYS";
+
+/// Key id used when `VOLT_ENCRYPTION_KEY_ID` is not set
+const DEFAULT_KEY_ID: &str = "env";
+
+const NONCE_LEN: usize = 12;
+
+/// A 256-bit AES key
+pub type DataKey = [u8; 32];
+
+/// Source of encryption keys. Providers that rotate keys should keep
+/// resolving retired ids so older data stays readable.
+pub trait KeyProvider: Send + Sync {
+    /// Id of the key new data is encrypted with
+    fn current_key_id(&self) -> &str;
+
+    /// Key material for `key_id`
+    fn key(&self, key_id: &str) -> io::Result<DataKey>;
+}
+
+/// A provider holding a fixed set of keys: the current one and any retired
+/// ones still needed to read older data
+pub struct StaticKeyProvider {
+    key_id: String,
+    keys: HashMap<String, DataKey>,
+}
+
+impl StaticKeyProvider {
+    pub fn new(key_id: impl Into<String>, key: DataKey) -> Self {
+        let key_id = key_id.into();
+        StaticKeyProvider {
+            keys: HashMap::from([(key_id.clone(), key)]),
+            key_id,
+        }
+    }
+
+    /// Adds a key that is only used for decryption
+    pub fn with_retired_key(mut self, key_id: impl Into<String>, key: DataKey) -> Self {
+        self.keys.entry(key_id.into()).or_insert(key);
+        self
+    }
+
+    /// Reads the key from `VOLT_ENCRYPTION_KEY`, or returns `None` if it is unset or empty
+    pub fn from_env() -> io::Result<Option<Self>> {
+        let encoded = match std::env::var(KEY_ENV) {
+            Ok(encoded) if !encoded.trim().is_empty() => encoded,
+            _ => return Ok(None),
+        };
+        let key_id = std::env::var(KEY_ID_ENV).unwrap_or_else(|_| DEFAULT_KEY_ID.to_string());
+        let mut provider = StaticKeyProvider::new(key_id, decode_key(KEY_ENV, &encoded)?);
+        if let Ok(retired) = std::env::var(RETIRED_KEYS_ENV) {
+            for pair in retired.split(',').filter(|pair| !pair.trim().is_empty()) {
+                let (id, encoded) = pair
+                    .split_once('=')
+                    .ok_or_else(|| invalid(format!("{} entries must be id=base64", RETIRED_KEYS_ENV)))?;
+                let key = decode_key(RETIRED_KEYS_ENV, encoded)?;
+                provider = provider.with_retired_key(id.trim(), key);
+            }
+        }
+        Ok(Some(provider))
+    }
+}
+
+fn decode_key(var: &str, encoded: &str) -> io::Result<DataKey> {
+    let bytes = BASE64
+        .decode(encoded.trim())
+        .map_err(|e| invalid(format!("{} is not valid base64: {}", var, e)))?;
+    bytes
+        .try_into()
+        .map_err(|bytes: Vec<u8>| invalid(format!("{} keys must be 32 bytes, got {}", var, bytes.len())))
+}
+
+impl KeyProvider for StaticKeyProvider {
+    fn current_key_id(&self) -> &str {
+        &self.key_id
+    }
+
+    fn key(&self, key_id: &str) -> io::Result<DataKey> {
+        self.keys
+            .get(key_id)
+            .copied()
+            .ok_or_else(|| invalid(format!("unknown encryption key '{}'", key_id)))
+    }
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn cipher(provider: &dyn KeyProvider, key_id: &str) -> io::Result<Aes256Gcm> {
+    let key = provider.key(key_id)?;
+    Ok(Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key)))
+}
+
+/// Encrypts `plaintext` with the provider's current key under a random
+/// nonce. `aad` is authenticated but not encrypted; decryption must pass the
+/// same bytes. Returns the key id and the nonce followed by the ciphertext.
+pub fn seal(provider: &dyn KeyProvider, aad: &[u8], plaintext: &[u8]) -> io::Result<(String, Vec<u8>)> {
+    let key_id = provider.current_key_id().to_string();
+    let nonce: [u8; NONCE_LEN] = rand::random();
+    let ciphertext = cipher(provider, &key_id)?
+        .encrypt(Nonce::from_slice(&nonce), Payload { msg: plaintext, aad })
+        .map_err(|_| invalid("encryption failed".to_string()))?;
+    let mut sealed = Vec::with_capacity(NONCE_LEN + ciphertext.len());
+    sealed.extend_from_slice(&nonce);
+    sealed.extend_from_slice(&ciphertext);
+    Ok((key_id, sealed))
+}
+
+/// Reverses [`seal`], failing if the data or `aad` was tampered with
+pub fn open(provider: &dyn KeyProvider, key_id: &str, aad: &[u8], sealed: &[u8]) -> io::Result<Vec<u8>> {
+    if sealed.len() < NONCE_LEN {
+        return Err(invalid("encrypted value is truncated".to_string()));
+    }
+    let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+    cipher(provider, key_id)?
+        .decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad })
+        .map_err(|_| invalid(format!("cannot decrypt with key '{}': wrong key or corrupted data", key_id)))
+}
@@ -0,0 +1,88 @@
+use std::fmt;
+
+/// Errors returned by cluster operations
+#[derive(Debug)]
+pub enum KVError {
+    /// The operation did not complete before its deadline. The primary copy
+    /// may already have been written.
+    Timeout,
+    /// A value could not be encoded or decoded as JSON
+    Json(serde_json::Error),
+    /// The value stored at the key does not support the operation
+    WrongType(String),
+    /// Fewer replicas than the ack mode requires applied the write. The
+    /// primary copy may already have been written.
+    NotEnoughReplicas { required: usize, available: usize },
+    /// The key does not exist
+    KeyNotFound(String),
+    /// The destination key already exists and the operation may not replace it
+    KeyExists(String),
+    /// The value exceeds the configured size limit. If `truncated` is set, a
+    /// truncated copy was stored anyway.
+    ValueTooLarge { size: usize, max: usize, truncated: bool },
+    /// The node that owns the key is down
+    NodeUnavailable(String),
+    /// Writes are rejected because the cluster is read-only
+    ReadOnly,
+    /// Writes are rejected because the cluster is in maintenance mode
+    Maintenance,
+    /// A value could not be encrypted or decrypted
+    Encryption(String),
+    /// The write would create a key in a namespace already holding as many
+    /// keys as its quota allows
+    QuotaExceeded { namespace: String, max_keys: usize },
+    /// A typed value was written with a schema the reading codec cannot read
+    SchemaMismatch { expected: String, found: Option<String> },
+}
+
+impl fmt::Display for KVError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KVError::Timeout => write!(f, "operation timed out"),
+            KVError::Json(e) => write!(f, "JSON error: {}", e),
+            KVError::WrongType(msg) => write!(f, "wrong value type: {}", msg),
+            KVError::NotEnoughReplicas { required, available } => write!(
+                f,
+                "write needs {} replica acknowledgements but only {} available",
+                required, available
+            ),
+            KVError::KeyNotFound(key) => write!(f, "key '{}' not found", key),
+            KVError::KeyExists(key) => write!(f, "key '{}' already exists", key),
+            KVError::ValueTooLarge { size, max, truncated } => {
+                write!(f, "value of {} bytes exceeds the {} byte limit", size, max)?;
+                if *truncated {
+                    write!(f, "; stored truncated to {} bytes", max)?;
+                }
+                Ok(())
+            }
+            KVError::NodeUnavailable(node) => write!(f, "node '{}' is unavailable", node),
+            KVError::ReadOnly => write!(f, "cluster is read-only"),
+            KVError::Maintenance => write!(f, "cluster is in maintenance mode"),
+            KVError::Encryption(msg) => write!(f, "encryption error: {}", msg),
+            KVError::SchemaMismatch { expected, found: Some(found) } => {
+                write!(f, "value has schema '{}' but '{}' was expected", found, expected)
+            }
+            KVError::SchemaMismatch { expected, found: None } => {
+                write!(f, "value has no schema id but '{}' was expected", expected)
+            }
+            KVError::QuotaExceeded { namespace, max_keys } => {
+                write!(f, "namespace '{}' is at its quota of {} keys", namespace, max_keys)
+            }
+        }
+    }
+}
+
+impl std::error::Error for KVError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            KVError::Json(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<serde_json::Error> for KVError {
+    fn from(e: serde_json::Error) -> Self {
+        KVError::Json(e)
+    }
+}
@@ -0,0 +1,312 @@
+//! When each of a node's keys expires, so the sweeper can drop expired keys
+//! without scanning the store.
+//!
+//! Expiries are rounded up to whole milliseconds, called ticks, and keys are
+//! kept in a bucket per tick. The buckets are split into shards by key hash,
+//! so writers to different keys rarely wait on the same lock. Each shard is a
+//! two-level timing wheel: keys due in the current window of
+//! [`WHEEL_SLOTS`] ticks sit in the wheel's slots, and keys due later in a
+//! bucket per window, which moves into the slots once its window comes up.
+//! Scheduling and cancelling a key take constant time, and the sweeper
+//! handles each key once, however far ahead it expires.
+//!
+//! A bucket only says when to look at a key. The sweeper still checks the
+//! stored entry before dropping it, so a key that was rewritten meanwhile,
+//! or is left in a bucket it no longer belongs to, stays in the store.
+
+use std::collections::{HashMap, HashSet};
+use std::mem;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use tokio::time::Instant;
+use xxhash_rust::xxh32::xxh32;
+
+use crate::Key;
+
+/// Ticks per wheel window
+const WHEEL_SLOTS: u64 = 256;
+
+/// Independently locked parts of a node's wheel
+const SHARDS: usize = 16;
+
+/// One shard's keys, by the tick they expire at
+struct WheelShard {
+    /// Ticks up to this one have been swept
+    swept: u64,
+    /// Windows up to this one have been moved into `slots`
+    cascaded: u64,
+    /// Keys due within the cascaded windows, by tick modulo `WHEEL_SLOTS`.
+    /// Only ticks after `swept` are in there, so no two share a slot.
+    slots: Vec<HashSet<Key>>,
+    slotted: usize,
+    /// Keys due in later windows, by window, with the tick each is due at
+    later: HashMap<u64, HashMap<Key, u64>>,
+    waiting: usize,
+}
+
+fn window(tick: u64) -> u64 {
+    tick / WHEEL_SLOTS
+}
+
+impl WheelShard {
+    fn new() -> Self {
+        WheelShard {
+            swept: 0,
+            cascaded: 0,
+            slots: (0..WHEEL_SLOTS).map(|_| HashSet::new()).collect(),
+            slotted: 0,
+            later: HashMap::new(),
+            waiting: 0,
+        }
+    }
+
+    fn insert(&mut self, key: Key, tick: u64) {
+        // Already past: sweep it with the next tick
+        let tick = tick.max(self.swept + 1);
+        if window(tick) <= self.cascaded {
+            if self.slots[(tick % WHEEL_SLOTS) as usize].insert(key) {
+                self.slotted += 1;
+            }
+        } else if self.later.entry(window(tick)).or_default().insert(key, tick).is_none() {
+            self.waiting += 1;
+        }
+    }
+
+    fn remove(&mut self, key: &[u8], tick: u64) {
+        if tick <= self.swept {
+            return;
+        }
+        if window(tick) <= self.cascaded {
+            if self.slots[(tick % WHEEL_SLOTS) as usize].remove(key) {
+                self.slotted -= 1;
+            }
+        } else if let Some(bucket) = self.later.get_mut(&window(tick)) {
+            if bucket.remove(key).is_some() {
+                self.waiting -= 1;
+                if bucket.is_empty() {
+                    self.later.remove(&window(tick));
+                }
+            }
+        }
+    }
+
+    /// Moves the keys of windows up to `upto` into the slots
+    fn cascade(&mut self, upto: u64) {
+        while self.cascaded < upto {
+            self.cascaded += 1;
+            let Some(bucket) = self.later.remove(&self.cascaded) else {
+                continue;
+            };
+            self.waiting -= bucket.len();
+            for (key, tick) in bucket {
+                if self.slots[(tick % WHEEL_SLOTS) as usize].insert(key) {
+                    self.slotted += 1;
+                }
+            }
+        }
+    }
+
+    /// Takes the keys due at ticks up to `now`
+    fn take_due(&mut self, now: u64, due: &mut Vec<Key>) {
+        while self.swept < now {
+            // After a long pause with nothing in the wheel, jump to the tick
+            // before the next window holding keys rather than step to it
+            if self.slotted == 0 && now - self.swept > WHEEL_SLOTS {
+                let next = self.later.keys().min().map(|window| window * WHEEL_SLOTS - 1);
+                let skip_to = next.map_or(now, |tick| tick.min(now));
+                if skip_to > self.swept {
+                    self.swept = skip_to;
+                    self.cascaded = self.cascaded.max(window(skip_to));
+                    continue;
+                }
+            }
+            let tick = self.swept + 1;
+            self.cascade(window(tick));
+            let slot = mem::take(&mut self.slots[(tick % WHEEL_SLOTS) as usize]);
+            self.slotted -= slot.len();
+            due.extend(slot);
+            self.swept = tick;
+        }
+    }
+}
+
+/// A node's expiry schedule
+pub(crate) struct ExpiryWheel {
+    /// Tick 0
+    started: Instant,
+    shards: Box<[Mutex<WheelShard>]>,
+}
+
+impl ExpiryWheel {
+    pub(crate) fn new() -> Self {
+        ExpiryWheel {
+            started: Instant::now(),
+            shards: (0..SHARDS).map(|_| Mutex::new(WheelShard::new())).collect(),
+        }
+    }
+
+    fn shard(&self, key: &[u8]) -> MutexGuard<'_, WheelShard> {
+        let idx = xxh32(key, 0) as usize % SHARDS;
+        self.shards[idx].lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// The first tick at or after `expiry`
+    fn tick_after(&self, expiry: Instant) -> u64 {
+        let since = expiry.saturating_duration_since(self.started);
+        since.as_nanos().div_ceil(1_000_000) as u64
+    }
+
+    /// Moves `key` from the bucket of its `old` expiry to that of its `new`
+    /// one. Call it while holding the key's store entry, so that concurrent
+    /// writes to the key reschedule it in the order they were stored.
+    pub(crate) fn reschedule(&self, key: &Key, old: Option<Instant>, new: Option<Instant>) {
+        let old = old.map(|old| self.tick_after(old));
+        let new = new.map(|new| self.tick_after(new));
+        if old == new {
+            return;
+        }
+        let mut shard = self.shard(key.as_bytes());
+        if let Some(old) = old {
+            shard.remove(key.as_bytes(), old);
+        }
+        if let Some(new) = new {
+            shard.insert(key.clone(), new);
+        }
+    }
+
+    /// Takes the keys due to expire by `now`
+    pub(crate) fn take_due(&self, now: Instant) -> Vec<Key> {
+        let now = now.saturating_duration_since(self.started).as_millis() as u64;
+        let mut due = Vec::new();
+        for shard in self.shards.iter() {
+            shard.lock().unwrap_or_else(PoisonError::into_inner).take_due(now, &mut due);
+        }
+        due
+    }
+
+    /// Keys scheduled to expire
+    pub(crate) fn len(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| {
+                let shard = shard.lock().unwrap_or_else(PoisonError::into_inner);
+                shard.slotted + shard.waiting
+            })
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn at(wheel: &ExpiryWheel, ms: u64) -> Instant {
+        wheel.started + Duration::from_millis(ms)
+    }
+
+    fn schedule(wheel: &ExpiryWheel, key: &str, ms: u64) {
+        wheel.reschedule(&Key::from(key), None, Some(at(wheel, ms)));
+    }
+
+    fn due(wheel: &ExpiryWheel, ms: u64) -> Vec<String> {
+        let mut keys: Vec<String> = wheel
+            .take_due(at(wheel, ms))
+            .iter()
+            .map(|key| String::from_utf8_lossy(key.as_bytes()).into_owned())
+            .collect();
+        keys.sort();
+        keys
+    }
+
+    #[test]
+    fn keys_of_the_next_window_cascade_into_the_slots() {
+        let wheel = ExpiryWheel::new();
+        schedule(&wheel, "a", WHEEL_SLOTS - 1);
+        schedule(&wheel, "b", WHEEL_SLOTS);
+        schedule(&wheel, "c", WHEEL_SLOTS + 10);
+        assert_eq!(wheel.len(), 3);
+
+        assert_eq!(due(&wheel, WHEEL_SLOTS - 2), Vec::<String>::new());
+        assert_eq!(due(&wheel, WHEEL_SLOTS - 1), ["a"]);
+        assert_eq!(due(&wheel, WHEEL_SLOTS), ["b"]);
+        assert_eq!(due(&wheel, WHEEL_SLOTS + 9), Vec::<String>::new());
+        assert_eq!(due(&wheel, WHEEL_SLOTS + 10), ["c"]);
+        assert_eq!(wheel.len(), 0);
+    }
+
+    #[test]
+    fn keys_beyond_the_wheel_span_expire_on_time() {
+        let wheel = ExpiryWheel::new();
+        let far = 10 * WHEEL_SLOTS + 5;
+        let farther = 1_000 * WHEEL_SLOTS + 7;
+        schedule(&wheel, "far", far);
+        schedule(&wheel, "farther", farther);
+
+        // Long pauses with nothing due skip ahead to the window holding keys
+        assert_eq!(due(&wheel, far - 1), Vec::<String>::new());
+        assert_eq!(due(&wheel, far), ["far"]);
+        assert_eq!(due(&wheel, farther - 1), Vec::<String>::new());
+        assert_eq!(due(&wheel, farther), ["farther"]);
+
+        // A sweep that passes several deadlines at once takes them all
+        schedule(&wheel, "x", farther + 3 * WHEEL_SLOTS);
+        schedule(&wheel, "y", farther + 7 * WHEEL_SLOTS + 1);
+        assert_eq!(due(&wheel, farther + 10 * WHEEL_SLOTS), ["x", "y"]);
+        assert_eq!(wheel.len(), 0);
+    }
+
+    #[test]
+    fn rearming_a_key_moves_it_to_its_new_deadline() {
+        let wheel = ExpiryWheel::new();
+        let key = Key::from("k");
+        let first = at(&wheel, 100);
+        let later = at(&wheel, 3 * WHEEL_SLOTS);
+        let sooner = at(&wheel, 50);
+        wheel.reschedule(&key, None, Some(first));
+        wheel.reschedule(&key, Some(first), Some(later));
+        assert_eq!(wheel.len(), 1);
+        assert_eq!(due(&wheel, 100), Vec::<String>::new());
+
+        // Back from a later window into the current one
+        wheel.reschedule(&key, Some(later), Some(at(&wheel, 150)));
+        wheel.reschedule(&key, Some(at(&wheel, 150)), Some(at(&wheel, 150)));
+        assert_eq!(wheel.len(), 1);
+        assert_eq!(due(&wheel, 150), ["k"]);
+        assert_eq!(due(&wheel, 4 * WHEEL_SLOTS), Vec::<String>::new());
+
+        // Re-armed to a deadline already swept: due with the next tick
+        wheel.reschedule(&key, None, Some(sooner));
+        assert_eq!(due(&wheel, 4 * WHEEL_SLOTS + 1), ["k"]);
+        assert_eq!(wheel.len(), 0);
+    }
+
+    #[test]
+    fn cancelled_keys_are_never_due() {
+        let wheel = ExpiryWheel::new();
+        let near = Key::from("near");
+        let far = Key::from("far");
+        wheel.reschedule(&near, None, Some(at(&wheel, 10)));
+        wheel.reschedule(&far, None, Some(at(&wheel, 5 * WHEEL_SLOTS)));
+        wheel.reschedule(&near, Some(at(&wheel, 10)), None);
+        wheel.reschedule(&far, Some(at(&wheel, 5 * WHEEL_SLOTS)), None);
+        assert_eq!(wheel.len(), 0);
+        assert_eq!(due(&wheel, 10 * WHEEL_SLOTS), Vec::<String>::new());
+
+        // Cancelling after the deadline was swept changes nothing
+        wheel.reschedule(&near, Some(at(&wheel, 10)), None);
+        assert_eq!(wheel.len(), 0);
+    }
+
+    #[test]
+    fn past_deadlines_are_due_with_the_next_tick() {
+        let wheel = ExpiryWheel::new();
+        assert_eq!(due(&wheel, 2 * WHEEL_SLOTS), Vec::<String>::new());
+
+        schedule(&wheel, "past", 10);
+        wheel.reschedule(&Key::from("before_start"), None, Some(wheel.started - Duration::from_millis(1)));
+        assert_eq!(wheel.len(), 2);
+        assert_eq!(due(&wheel, 2 * WHEEL_SLOTS), Vec::<String>::new());
+        assert_eq!(due(&wheel, 2 * WHEEL_SLOTS + 1), ["before_start", "past"]);
+        assert_eq!(wheel.len(), 0);
+    }
+}
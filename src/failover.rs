@@ -0,0 +1,524 @@
+//! Failover for nodes that are down.
+//!
+//! A key is stored on its preference list: the node owning it on the ring,
+//! then the next distinct nodes clockwise, up to the replication factor.
+//! While a node is down, the first node of each preference list that is up
+//! serves as the key's primary and the down node receives nothing. When the
+//! node returns it is brought up to date from the acting primaries before it
+//! takes its keys back.
+//!
+//! A node taking keys over, in either direction, may still have replica
+//! operations from their previous primary queued. It applies those before it
+//! serves as primary again, so none of them lands on top of a newer write.
+
+use dashmap::mapref::entry::Entry;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, PoisonError, RwLock};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::time::Instant;
+use tracing::{info, warn};
+
+use crate::{KVCluster, KVError, KVNode, KVOperation, Key, Ttl};
+
+/// Wait before retrying a catch-up operation on a full replica channel
+const CATCH_UP_RETRY: Duration = Duration::from_millis(1);
+
+/// Whether a node serves the keys it owns
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum NodeStatus {
+    #[default]
+    Up,
+    /// Skipped by reads and writes; its keys are served by their replicas
+    Down,
+    /// Back from being down and catching up. It receives writes as a
+    /// replica but does not serve as primary until it has caught up.
+    Recovering,
+}
+
+/// Node statuses, shared by every clone of a cluster handle
+#[derive(Default)]
+pub(crate) struct FailoverState {
+    /// Nodes that are not up, by id
+    statuses: RwLock<HashMap<String, NodeStatus>>,
+    /// Nodes taken down by the failure detector rather than by hand, which
+    /// the detector may bring back
+    detected: RwLock<HashSet<String>>,
+    /// Bumped on every status change, so clients refetch the ring
+    changes: AtomicU64,
+    /// Number of nodes not up, so routing skips the lookup while all are
+    degraded: AtomicUsize,
+}
+
+impl FailoverState {
+    pub(crate) fn changes(&self) -> u64 {
+        self.changes.load(Ordering::Acquire)
+    }
+
+    pub(crate) fn is_degraded(&self) -> bool {
+        self.degraded.load(Ordering::Acquire) > 0
+    }
+
+    pub(crate) fn status(&self, node_id: &str) -> NodeStatus {
+        if !self.is_degraded() {
+            return NodeStatus::Up;
+        }
+        let statuses = self.statuses.read().unwrap_or_else(PoisonError::into_inner);
+        statuses.get(node_id).copied().unwrap_or_default()
+    }
+
+    /// Sets a node's status, returning the previous one
+    fn set_status(&self, node_id: &str, status: NodeStatus) -> NodeStatus {
+        let mut statuses = self.statuses.write().unwrap_or_else(PoisonError::into_inner);
+        let previous = match status {
+            NodeStatus::Up => statuses.remove(node_id),
+            _ => statuses.insert(node_id.to_string(), status),
+        }
+        .unwrap_or_default();
+        if previous != status {
+            self.degraded.store(statuses.len(), Ordering::Release);
+            self.changes.fetch_add(1, Ordering::AcqRel);
+        }
+        previous
+    }
+
+    /// Reorders a preference list for the nodes' statuses: the first node
+    /// that is up becomes primary, and nodes that are down are dropped.
+    /// Returns false, leaving the list as it was, if no node is up.
+    fn route(&self, owners: &mut Vec<usize>, nodes: &[Arc<KVNode>]) -> bool {
+        let statuses = self.statuses.read().unwrap_or_else(PoisonError::into_inner);
+        let status = |idx: usize| statuses.get(&nodes[idx].id).copied().unwrap_or_default();
+        let Some(primary) = owners.iter().position(|idx| status(*idx) == NodeStatus::Up) else {
+            return false;
+        };
+        let primary = owners.remove(primary);
+        owners.retain(|idx| status(*idx) != NodeStatus::Down);
+        owners.insert(0, primary);
+        true
+    }
+}
+
+/// What a node returning from being down was sent to catch up
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct CatchUpReport {
+    pub node: String,
+    /// Keys missing or stale on the node, copied from their acting primaries
+    pub keys_copied: usize,
+    /// Keys the node still had that were deleted or expired meanwhile
+    pub keys_removed: usize,
+}
+
+impl KVCluster {
+    /// Status of the node with this id; unknown nodes read as up
+    pub fn node_status(&self, node_id: &str) -> NodeStatus {
+        self.state.failover.status(node_id)
+    }
+
+    /// Nodes that are not up, with their status
+    pub fn degraded_nodes(&self) -> Vec<(String, NodeStatus)> {
+        self.nodes
+            .iter()
+            .map(|node| (node.id.clone(), self.node_status(&node.id)))
+            .filter(|(_, status)| *status != NodeStatus::Up)
+            .collect()
+    }
+
+    /// Takes a node out of service. Its keys are served by the next replica
+    /// in their preference lists, and writes skip it until `mark_node_up`.
+    /// Returns false if no node has that id.
+    pub fn mark_node_down(&self, node_id: &str) -> bool {
+        if !self.nodes.iter().any(|node| node.id == node_id) {
+            return false;
+        }
+        // Replicas taking over may still hold writes from the node going down
+        for node in &self.nodes {
+            node.fence_ops();
+        }
+        if self.state.failover.set_status(node_id, NodeStatus::Down) != NodeStatus::Down {
+            warn!(node = %node_id, "Node marked down; its replicas take over its keys");
+        }
+        true
+    }
+
+    /// Brings a node back into service. The node first receives writes as a
+    /// replica while every key it holds a copy of is sent to it from the
+    /// key's acting primary, and only then serves its keys again. Returns
+    /// `None` if no node has that id.
+    pub async fn mark_node_up(&self, node_id: &str) -> Result<Option<CatchUpReport>, KVError> {
+        let Some(idx) = self.nodes.iter().position(|node| node.id == node_id) else {
+            return Ok(None);
+        };
+        self.state
+            .failover
+            .detected
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(node_id);
+        if self.node_status(node_id) == NodeStatus::Up {
+            return Ok(Some(CatchUpReport {
+                node: node_id.to_string(),
+                ..CatchUpReport::default()
+            }));
+        }
+        self.state.failover.set_status(node_id, NodeStatus::Recovering);
+        let report = match self.catch_up(idx).await {
+            Ok(report) => report,
+            Err(e) => {
+                self.state.failover.set_status(node_id, NodeStatus::Down);
+                return Err(e);
+            }
+        };
+        // Writes it received while catching up go before those it serves
+        self.nodes[idx].fence_ops();
+        self.state.failover.set_status(node_id, NodeStatus::Up);
+        info!(
+            node = %node_id,
+            copied = report.keys_copied,
+            removed = report.keys_removed,
+            "Node caught up and back in service"
+        );
+        Ok(Some(report))
+    }
+
+    /// Sends the node at `idx` the current state of every key it holds a
+    /// copy of, through its replica channel so the catch-up stays in order
+    /// with live writes, and waits for it to be applied
+    async fn catch_up(&self, idx: usize) -> Result<CatchUpReport, KVError> {
+        let node = self.nodes[idx].clone();
+        let mut report = CatchUpReport {
+            node: node.id.clone(),
+            ..CatchUpReport::default()
+        };
+
+        // Read the acting primaries once they have applied the writes queued
+        // on them before the node went down
+        for peer in &self.nodes {
+            if self.node_status(&peer.id) == NodeStatus::Up {
+                peer.pass_fence().await;
+            }
+        }
+
+        // Keys the node has, and keys written elsewhere that it should have
+        let mut keys: HashSet<Key> = node.store.iter().map(|entry| entry.key().clone()).collect();
+        for (other, peer) in self.nodes.iter().enumerate() {
+            if other == idx {
+                continue;
+            }
+            for entry in peer.store.iter() {
+                let key = entry.key().as_bytes();
+                if self.primary_idx(key) == Some(other) && self.preference_list(key).contains(&idx) {
+                    keys.insert(entry.key().clone());
+                }
+            }
+        }
+
+        // Room for an acknowledgement from every operation
+        let (ack_tx, mut ack_rx) = mpsc::channel(keys.len().max(1));
+        let mut sent = 0;
+        for key in keys {
+            let Some(primary) = self.primary_idx(key.as_bytes()) else {
+                continue;
+            };
+            let primary = &self.nodes[primary];
+            loop {
+                // Holding the primary's entry while enqueueing orders the
+                // operation before any later write to the key, whose replica
+                // operation is only sent after it updates the primary
+                let queued = {
+                    let entry = primary.store.entry(key.clone());
+                    let now = Instant::now();
+                    let held = node.store.get(key.as_bytes()).filter(|held| !held.is_expired(now));
+                    let op = match &entry {
+                        Entry::Occupied(occupied) if !occupied.get().is_expired(now) => {
+                            let stored = occupied.get();
+                            let current = held.is_some_and(|held| {
+                                held.value == stored.value && held.expiry.is_some() == stored.expiry.is_some()
+                            });
+                            let ttl = stored.expiry.map(|expiry| {
+                                let sliding = stored.ttl.is_some_and(|ttl| ttl.sliding);
+                                Ttl::new(expiry.saturating_duration_since(now), sliding)
+                            });
+                            (!current).then(|| {
+                                KVOperation::Set(key.clone(), stored.value.to_vec(), ttl, Some(ack_tx.clone()))
+                            })
+                        }
+                        _ => held.is_some().then(|| KVOperation::Del(key.clone(), Some(ack_tx.clone()))),
+                    };
+                    op.map(|op| {
+                        let copied = matches!(op, KVOperation::Set(..));
+                        node.try_send_op(op).map(|()| copied)
+                    })
+                };
+                match queued {
+                    // The node's copy is already current
+                    None => {}
+                    Some(Ok(copied)) => {
+                        sent += 1;
+                        if copied {
+                            report.keys_copied += 1;
+                        } else {
+                            report.keys_removed += 1;
+                        }
+                    }
+                    // Rebuilt from the primary on retry, which may have changed by then
+                    Some(Err(TrySendError::Full(_))) => {
+                        tokio::time::sleep(CATCH_UP_RETRY).await;
+                        continue;
+                    }
+                    Some(Err(TrySendError::Closed(_))) => return Err(KVError::NodeUnavailable(node.id.clone())),
+                }
+                break;
+            }
+        }
+        drop(ack_tx);
+        // Injected faults may drop operations, leaving fewer acknowledgements
+        for _ in 0..sent {
+            if ack_rx.recv().await.is_none() {
+                break;
+            }
+        }
+        Ok(report)
+    }
+
+    /// Marks nodes down while they fail, and back up once they recover,
+    /// checking every `interval` until the returned task is aborted. A node
+    /// fails when one of its background tasks has stopped, or when a fault
+    /// injected with the `chaos` feature has taken it down. Nodes marked
+    /// down by hand are left for `mark_node_up`.
+    pub fn start_failure_detector(&self, interval: Duration) -> tokio::task::JoinHandle<()> {
+        let cluster = self.clone();
+        tokio::spawn(async move {
+            let mut ticks = tokio::time::interval(interval);
+            ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                ticks.tick().await;
+                cluster.detect_failures().await;
+            }
+        })
+    }
+
+    async fn detect_failures(&self) {
+        for node in &self.nodes {
+            let failing = node.is_failing();
+            let detected = self
+                .state
+                .failover
+                .detected
+                .read()
+                .unwrap_or_else(PoisonError::into_inner)
+                .contains(&node.id);
+            if failing && self.node_status(&node.id) == NodeStatus::Up {
+                self.state
+                    .failover
+                    .detected
+                    .write()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .insert(node.id.clone());
+                self.mark_node_down(&node.id);
+            } else if !failing && detected {
+                if let Err(e) = self.mark_node_up(&node.id).await {
+                    warn!(node = %node.id, "Cannot bring node back: {}", e);
+                }
+            }
+        }
+    }
+
+    /// `preference_list` reordered for node statuses, or `None` if no node
+    /// on it is up
+    pub(crate) fn route(&self, key: &[u8]) -> Option<Vec<usize>> {
+        let mut owners = self.preference_list(key);
+        if !self.state.failover.is_degraded() {
+            return Some(owners);
+        }
+        self.state.failover.route(&mut owners, &self.nodes).then_some(owners)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::WriteOptions;
+
+    fn cluster(nodes: usize, replication_factor: usize) -> KVCluster {
+        let mut cluster = KVCluster::new(16, replication_factor);
+        for i in 0..nodes {
+            cluster.add_node(format!("node{}", i));
+        }
+        cluster
+    }
+
+    fn stored(cluster: &KVCluster, idx: usize, key: &str) -> Option<Vec<u8>> {
+        cluster.nodes[idx].store.get(key.as_bytes()).map(|entry| entry.value.to_vec())
+    }
+
+    /// Lets replicas apply what was sent to them
+    async fn settle() {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn routing_skips_down_nodes_and_keeps_recovering_ones_as_replicas() {
+        let cluster = cluster(3, 3);
+        let owners = cluster.preference_list(b"k");
+        let owner = &cluster.nodes[owners[0]].id;
+
+        cluster.state.failover.set_status(owner, NodeStatus::Down);
+        let routed = cluster.route(b"k").unwrap();
+        assert_eq!(routed, owners[1..]);
+
+        cluster.state.failover.set_status(owner, NodeStatus::Recovering);
+        let routed = cluster.route(b"k").unwrap();
+        assert_eq!(routed, [owners[1], owners[0], owners[2]]);
+
+        // With every node down the key has nowhere to go
+        for node in &cluster.nodes {
+            cluster.state.failover.set_status(&node.id, NodeStatus::Down);
+        }
+        assert_eq!(cluster.route(b"k"), None);
+        assert!(matches!(cluster.set("k", b"v".to_vec(), None).await, Err(KVError::NodeUnavailable(_))));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn catch_up_sends_what_a_down_node_missed() {
+        let cluster = cluster(3, 3);
+        cluster.set("kept", b"v1".to_vec(), None).await.unwrap();
+        cluster.set("changed", b"v1".to_vec(), None).await.unwrap();
+        cluster.set("deleted", b"v1".to_vec(), None).await.unwrap();
+        settle().await;
+
+        assert!(cluster.mark_node_down("node0"));
+        assert_eq!(cluster.node_status("node0"), NodeStatus::Down);
+        cluster.set("changed", b"v2".to_vec(), None).await.unwrap();
+        cluster.del("deleted").await.unwrap();
+        cluster.set("added", b"v2".to_vec(), None).await.unwrap();
+        settle().await;
+        assert_eq!(stored(&cluster, 0, "changed"), Some(b"v1".to_vec()));
+        assert_eq!(stored(&cluster, 0, "added"), None);
+
+        let report = cluster.mark_node_up("node0").await.unwrap().unwrap();
+        assert_eq!((report.keys_copied, report.keys_removed), (2, 1));
+        assert_eq!(cluster.node_status("node0"), NodeStatus::Up);
+        assert_eq!(stored(&cluster, 0, "kept"), Some(b"v1".to_vec()));
+        assert_eq!(stored(&cluster, 0, "changed"), Some(b"v2".to_vec()));
+        assert_eq!(stored(&cluster, 0, "added"), Some(b"v2".to_vec()));
+        assert_eq!(stored(&cluster, 0, "deleted"), None);
+
+        // Nothing is sent to a node that missed nothing
+        cluster.mark_node_down("node0");
+        let report = cluster.mark_node_up("node0").await.unwrap().unwrap();
+        assert_eq!((report.keys_copied, report.keys_removed), (0, 0));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn catch_up_only_sends_keys_the_node_holds_a_copy_of() {
+        let cluster = cluster(4, 2);
+        let keys: Vec<String> = (0..64).map(|i| format!("key:{}", i)).collect();
+        assert!(cluster.mark_node_down("node0"));
+        for key in &keys {
+            cluster.set(key.clone(), b"v".to_vec(), None).await.unwrap();
+        }
+
+        let report = cluster.mark_node_up("node0").await.unwrap().unwrap();
+        let held = keys
+            .iter()
+            .filter(|key| cluster.preference_list(key.as_bytes()).contains(&0))
+            .count();
+        assert!(held > 0 && held < keys.len());
+        assert_eq!(report.keys_copied, held);
+        assert_eq!(cluster.nodes[0].store.len(), held);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn replica_taking_over_applies_queued_writes_first() {
+        let cluster = cluster(2, 2);
+        let owners = cluster.preference_list(b"k");
+        let (owner, replica) = (owners[0], owners[1]);
+
+        // Still queued for the replica when its owner goes down
+        cluster.set("k", b"v1".to_vec(), None).await.unwrap();
+        assert!(cluster.mark_node_down(&cluster.nodes[owner].id));
+        cluster.set("k", b"v2".to_vec(), None).await.unwrap();
+        settle().await;
+        assert_eq!(stored(&cluster, replica, "k"), Some(b"v2".to_vec()));
+        assert_eq!(cluster.get("k").await, Some(b"v2".to_vec()));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn replica_taking_over_after_a_sliding_read_applies_queued_writes_first() {
+        let cluster = cluster(2, 2);
+        let owners = cluster.preference_list(b"k");
+        let (owner, replica) = (owners[0], owners[1]);
+        let ttl = Some(Duration::from_secs(60));
+        cluster
+            .set_with_options("k", b"v1".to_vec(), ttl, WriteOptions::with_sliding_ttl())
+            .await
+            .unwrap();
+        settle().await;
+
+        // The read sends the replica a touch, queued ahead of the next write
+        assert_eq!(cluster.get("k").await, Some(b"v1".to_vec()));
+        cluster
+            .set_with_options("k", b"v2".to_vec(), ttl, WriteOptions::with_sliding_ttl())
+            .await
+            .unwrap();
+        assert!(cluster.mark_node_down(&cluster.nodes[owner].id));
+        cluster
+            .set_with_options("k", b"v3".to_vec(), ttl, WriteOptions::with_sliding_ttl())
+            .await
+            .unwrap();
+        settle().await;
+        // An operation done but never counted as queued would let the fence
+        // taken on failover pass one operation early
+        let node = &cluster.nodes[replica];
+        assert_eq!(node.ops_done.load(Ordering::Acquire), node.ops_queued.load(Ordering::Acquire));
+        assert_eq!(stored(&cluster, replica, "k"), Some(b"v3".to_vec()));
+        assert_eq!(cluster.get("k").await, Some(b"v3".to_vec()));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn unknown_nodes_are_reported() {
+        let cluster = cluster(2, 2);
+        assert!(!cluster.mark_node_down("node9"));
+        assert!(cluster.mark_node_up("node9").await.unwrap().is_none());
+        assert_eq!(cluster.node_status("node9"), NodeStatus::Up);
+        assert!(cluster.degraded_nodes().is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn detector_takes_failing_nodes_down_and_brings_them_back() {
+        let cluster = cluster(3, 3);
+        cluster.set("k", b"v1".to_vec(), None).await.unwrap();
+        settle().await;
+
+        // A node whose task stopped is failing
+        cluster.nodes[1].sweeper_alive.store(false, Ordering::Release);
+        cluster.detect_failures().await;
+        assert_eq!(cluster.degraded_nodes(), [("node1".to_string(), NodeStatus::Down)]);
+
+        cluster.set("k", b"v2".to_vec(), None).await.unwrap();
+        cluster.nodes[1].sweeper_alive.store(true, Ordering::Release);
+        cluster.detect_failures().await;
+        assert_eq!(cluster.node_status("node1"), NodeStatus::Up);
+        assert_eq!(stored(&cluster, 1, "k"), Some(b"v2".to_vec()));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn detector_leaves_nodes_marked_down_by_hand() {
+        let cluster = cluster(3, 3);
+        let detector = cluster.start_failure_detector(Duration::from_millis(10));
+        cluster.mark_node_down("node2");
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(cluster.node_status("node2"), NodeStatus::Down);
+
+        // Until it is brought back by hand, after which it is watched again
+        cluster.mark_node_up("node2").await.unwrap();
+        cluster.nodes[2].sweeper_alive.store(false, Ordering::Release);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(cluster.node_status("node2"), NodeStatus::Down);
+        detector.abort();
+    }
+}
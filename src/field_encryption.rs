@@ -0,0 +1,328 @@
+//! Field-level encryption of JSON documents.
+//!
+//! Sensitive fields are declared per namespace (the part of a key before its
+//! first ':') as JSONPath-style paths. When a JSON document is written, each
+//! matching field is replaced by a marker object holding its encrypted value:
+//!
+//! ```json
+//! {"email": {"$encrypted": {"key_id": "k2", "data": "<base64>"}}}
+//! ```
+//!
+//! Plain reads return the markers; `get_json_value_decrypted` restores the
+//! original fields for callers allowed to see them.
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use serde_json::{json, Value as JsonValue};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, PoisonError, RwLock};
+use std::time::Duration;
+use tokio::time::Instant;
+
+use crate::encryption::{self, KeyProvider};
+use crate::{KVCluster, KVError, WriteOptions};
+
+/// Name of the field marking an encrypted value
+pub const ENCRYPTED_MARKER: &str = "$encrypted";
+
+/// Associated data sealed with every field, so field ciphertexts cannot be
+/// passed off as other encrypted data
+const FIELD_AAD: &[u8] = b"volt:json-field";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Field(String),
+    Index(usize),
+    /// `[*]`: every item of an array
+    AllItems,
+}
+
+/// A path to fields of a JSON document, in a JSONPath subset: `$` followed
+/// by `.field`, `[index]` and `[*]` segments, e.g. `$.contacts[*].email`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldPath {
+    raw: String,
+    segments: Vec<Segment>,
+}
+
+impl FromStr for FieldPath {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = |reason: &str| format!("invalid field path '{}': {}", s, reason);
+        let mut rest = s.trim().strip_prefix('$').ok_or_else(|| invalid("must start with '$'"))?;
+        let mut segments = Vec::new();
+        while !rest.is_empty() {
+            if let Some(after) = rest.strip_prefix('.') {
+                let end = after.find(['.', '[']).unwrap_or(after.len());
+                if end == 0 {
+                    return Err(invalid("empty field name"));
+                }
+                segments.push(Segment::Field(after[..end].to_string()));
+                rest = &after[end..];
+            } else if let Some(after) = rest.strip_prefix('[') {
+                let end = after.find(']').ok_or_else(|| invalid("unclosed '['"))?;
+                segments.push(match &after[..end] {
+                    "*" => Segment::AllItems,
+                    index => Segment::Index(index.parse().map_err(|_| invalid("expected an index or '*'"))?),
+                });
+                rest = &after[end + 1..];
+            } else {
+                return Err(invalid("expected '.' or '['"));
+            }
+        }
+        if segments.is_empty() {
+            return Err(invalid("cannot encrypt the whole document"));
+        }
+        Ok(FieldPath {
+            raw: s.trim().to_string(),
+            segments,
+        })
+    }
+}
+
+impl fmt::Display for FieldPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.raw)
+    }
+}
+
+/// Calls `apply` on every value `segments` leads to. Missing fields and
+/// mismatched types are skipped.
+fn visit<F>(value: &mut JsonValue, segments: &[Segment], apply: &mut F) -> Result<(), KVError>
+where
+    F: FnMut(&mut JsonValue) -> Result<(), KVError>,
+{
+    let Some((segment, rest)) = segments.split_first() else {
+        return apply(value);
+    };
+    match (segment, value) {
+        (Segment::Field(name), JsonValue::Object(map)) => match map.get_mut(name) {
+            Some(child) => visit(child, rest, apply),
+            None => Ok(()),
+        },
+        (Segment::Index(index), JsonValue::Array(items)) => match items.get_mut(*index) {
+            Some(child) => visit(child, rest, apply),
+            None => Ok(()),
+        },
+        (Segment::AllItems, JsonValue::Array(items)) => {
+            items.iter_mut().try_for_each(|child| visit(child, rest, apply))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn crypto_error(e: std::io::Error) -> KVError {
+    KVError::Encryption(e.to_string())
+}
+
+/// Key id and ciphertext of an encrypted field marker
+fn marker_parts(value: &JsonValue) -> Option<(&str, &str)> {
+    let JsonValue::Object(map) = value else {
+        return None;
+    };
+    if map.len() != 1 {
+        return None;
+    }
+    let sealed = map.get(ENCRYPTED_MARKER)?;
+    Some((sealed.get("key_id")?.as_str()?, sealed.get("data")?.as_str()?))
+}
+
+fn encrypt_field(provider: &dyn KeyProvider, field: &mut JsonValue) -> Result<(), KVError> {
+    if marker_parts(field).is_some() {
+        return Ok(());
+    }
+    let plaintext = serde_json::to_vec(field)?;
+    let (key_id, sealed) = encryption::seal(provider, FIELD_AAD, &plaintext).map_err(crypto_error)?;
+    *field = json!({ ENCRYPTED_MARKER: { "key_id": key_id, "data": BASE64.encode(sealed) } });
+    Ok(())
+}
+
+/// Plaintext of an encrypted field marker, or `None` if `value` is not one
+fn open_field(provider: &dyn KeyProvider, value: &JsonValue) -> Result<Option<JsonValue>, KVError> {
+    let Some((key_id, data)) = marker_parts(value) else {
+        return Ok(None);
+    };
+    let sealed = BASE64
+        .decode(data)
+        .map_err(|e| KVError::Encryption(format!("malformed encrypted field: {}", e)))?;
+    let plaintext = encryption::open(provider, key_id, FIELD_AAD, &sealed).map_err(crypto_error)?;
+    Ok(Some(serde_json::from_slice(&plaintext)?))
+}
+
+/// Calls `apply` on every encrypted field marker in `value`
+fn visit_markers<F>(value: &mut JsonValue, apply: &mut F) -> Result<(), KVError>
+where
+    F: FnMut(&mut JsonValue) -> Result<(), KVError>,
+{
+    if marker_parts(value).is_some() {
+        return apply(value);
+    }
+    match value {
+        JsonValue::Object(map) => map.values_mut().try_for_each(|child| visit_markers(child, apply)),
+        JsonValue::Array(items) => items.iter_mut().try_for_each(|child| visit_markers(child, apply)),
+        _ => Ok(()),
+    }
+}
+
+/// Replaces every encrypted field marker in `value` with the field it holds
+fn decrypt_fields(provider: &dyn KeyProvider, value: &mut JsonValue) -> Result<(), KVError> {
+    visit_markers(value, &mut |field| {
+        if let Some(plaintext) = open_field(provider, field)? {
+            *field = plaintext;
+        }
+        Ok(())
+    })
+}
+
+/// Encrypts again, with the current key, every field of `value` encrypted
+/// with another key. Returns the number of fields re-encrypted.
+fn reseal_fields(provider: &dyn KeyProvider, value: &mut JsonValue) -> Result<usize, KVError> {
+    let mut resealed = 0;
+    visit_markers(value, &mut |field| {
+        if marker_parts(field).is_some_and(|(key_id, _)| key_id == provider.current_key_id()) {
+            return Ok(());
+        }
+        if let Some(mut plaintext) = open_field(provider, field)? {
+            encrypt_field(provider, &mut plaintext)?;
+            *field = plaintext;
+            resealed += 1;
+        }
+        Ok(())
+    })?;
+    Ok(resealed)
+}
+
+/// Key provider and sensitive field declarations of a cluster
+#[derive(Default)]
+pub(crate) struct FieldEncryption {
+    provider: RwLock<Option<Arc<dyn KeyProvider>>>,
+    fields: RwLock<HashMap<String, Vec<FieldPath>>>,
+}
+
+impl FieldEncryption {
+    fn provider(&self) -> Option<Arc<dyn KeyProvider>> {
+        self.provider.read().unwrap_or_else(PoisonError::into_inner).clone()
+    }
+
+    fn paths(&self, key: &str) -> Option<Vec<FieldPath>> {
+        let (namespace, _) = key.split_once(':')?;
+        let fields = self.fields.read().unwrap_or_else(PoisonError::into_inner);
+        fields.get(namespace).filter(|paths| !paths.is_empty()).cloned()
+    }
+}
+
+impl KVCluster {
+    /// Sets the key provider used to encrypt and decrypt sensitive fields
+    pub fn set_field_key_provider(&self, provider: Arc<dyn KeyProvider>) {
+        *self
+            .state
+            .field_encryption
+            .provider
+            .write()
+            .unwrap_or_else(PoisonError::into_inner) = Some(provider);
+    }
+
+    /// Declares the fields encrypted in JSON documents of `namespace`,
+    /// replacing any declared before. Only documents written afterwards are
+    /// affected. Fails if a path is invalid or no key provider is set.
+    pub fn set_sensitive_fields(&self, namespace: &str, paths: &[&str]) -> Result<(), String> {
+        if !paths.is_empty() && self.state.field_encryption.provider().is_none() {
+            return Err("no key provider is set for field encryption".to_string());
+        }
+        let paths = paths
+            .iter()
+            .map(|path| path.parse::<FieldPath>())
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut fields = self
+            .state
+            .field_encryption
+            .fields
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+        if paths.is_empty() {
+            fields.remove(namespace);
+        } else {
+            fields.insert(namespace.to_string(), paths);
+        }
+        Ok(())
+    }
+
+    /// Sensitive field paths of every namespace that declares any
+    pub fn sensitive_fields(&self) -> BTreeMap<String, Vec<String>> {
+        let fields = self
+            .state
+            .field_encryption
+            .fields
+            .read()
+            .unwrap_or_else(PoisonError::into_inner);
+        fields
+            .iter()
+            .map(|(namespace, paths)| (namespace.clone(), paths.iter().map(|p| p.to_string()).collect()))
+            .collect()
+    }
+
+    /// Serializes a JSON document for storage under `key`, encrypting the
+    /// fields its namespace declares sensitive
+    pub(crate) fn encode_json_document(&self, key: &str, value: &JsonValue) -> Result<Vec<u8>, KVError> {
+        let Some(paths) = self.state.field_encryption.paths(key) else {
+            return Ok(serde_json::to_vec(value)?);
+        };
+        let provider = self
+            .state
+            .field_encryption
+            .provider()
+            .ok_or_else(|| KVError::Encryption("no key provider is set".to_string()))?;
+        let mut document = value.clone();
+        for path in &paths {
+            visit(&mut document, &path.segments, &mut |field| encrypt_field(provider.as_ref(), field))?;
+        }
+        Ok(serde_json::to_vec(&document)?)
+    }
+
+    /// Retrieves a JSON document with its encrypted fields decrypted
+    pub async fn get_json_value_decrypted(&self, key: &str) -> Result<Option<JsonValue>, KVError> {
+        let Some(mut document) = self.get_json_value(key).await? else {
+            return Ok(None);
+        };
+        let provider = self.state.field_encryption.provider();
+        match provider {
+            Some(provider) => decrypt_fields(provider.as_ref(), &mut document)?,
+            None => visit_markers(&mut document, &mut |_| {
+                Err(KVError::Encryption("no key provider is set".to_string()))
+            })?,
+        }
+        Ok(Some(document))
+    }
+
+    /// Re-encrypts the encrypted fields of the document at `key` with the
+    /// provider's current key, keeping the document's TTL. Run it over the
+    /// keyspace after rotating keys, before retiring the old key. Returns
+    /// false if the document was already up to date.
+    pub async fn rotate_json_encryption(&self, key: &str, options: WriteOptions) -> Result<bool, KVError> {
+        let Some(provider) = self.state.field_encryption.provider() else {
+            return Err(KVError::Encryption("no key provider is set".to_string()));
+        };
+        let entry = self
+            .peek(key.as_bytes())
+            .await
+            .ok_or_else(|| KVError::KeyNotFound(key.to_string()))?;
+        let mut document: JsonValue = serde_json::from_slice(&entry.value)?;
+        // Fields are sealed again where they were found, even if the
+        // namespace no longer declares them
+        if reseal_fields(provider.as_ref(), &mut document)? == 0 {
+            return Ok(false);
+        }
+        let ttl: Option<Duration> = entry
+            .expiry
+            .map(|expiry| expiry.saturating_duration_since(Instant::now()));
+        let options = WriteOptions {
+            sliding_ttl: options.sliding_ttl || entry.ttl.is_some_and(|ttl| ttl.sliding),
+            ..options
+        };
+        let encoded = serde_json::to_vec(&document)?;
+        self.set_with_options(key.to_string(), encoded, ttl, options).await?;
+        Ok(true)
+    }
+}
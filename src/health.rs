@@ -0,0 +1,159 @@
+use serde::Serialize;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use crate::{KVCluster, KVNode};
+
+/// Fraction of a node's channel capacity above which the cluster stops reporting ready
+const READY_BACKLOG_RATIO: f64 = 0.8;
+
+/// Key written and expired by the startup self-test
+const SELF_TEST_KEY: &str = "__volt_self_test__";
+
+/// TTL of the self-test key; the self-test waits this long for it to expire
+const SELF_TEST_TTL: Duration = Duration::from_millis(50);
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum HealthStatus {
+    Ok,
+    Degraded,
+}
+
+/// Per-node view of background task state and replica channel backlog
+#[derive(Serialize, Debug, Clone)]
+pub struct NodeHealth {
+    pub id: String,
+    pub ops_task_alive: bool,
+    pub sweeper_task_alive: bool,
+    pub backlog: usize,
+    pub backlog_capacity: usize,
+}
+
+/// Result of a liveness or readiness check, with the reasons for any degradation
+#[derive(Serialize, Debug, Clone)]
+pub struct HealthReport {
+    pub status: HealthStatus,
+    pub nodes: Vec<NodeHealth>,
+    pub issues: Vec<String>,
+}
+
+impl HealthReport {
+    pub fn is_ok(&self) -> bool {
+        self.status == HealthStatus::Ok
+    }
+
+    fn from_issues(nodes: Vec<NodeHealth>, issues: Vec<String>) -> Self {
+        let status = if issues.is_empty() {
+            HealthStatus::Ok
+        } else {
+            HealthStatus::Degraded
+        };
+        HealthReport { status, nodes, issues }
+    }
+}
+
+fn node_health(node: &KVNode) -> NodeHealth {
+    let backlog_capacity = node.tx.max_capacity();
+    NodeHealth {
+        id: node.id.clone(),
+        ops_task_alive: node.ops_alive.load(Ordering::Acquire),
+        sweeper_task_alive: node.sweeper_alive.load(Ordering::Acquire),
+        backlog: backlog_capacity - node.tx.capacity(),
+        backlog_capacity,
+    }
+}
+
+fn task_issues(nodes: &[NodeHealth]) -> Vec<String> {
+    let mut issues = Vec::new();
+    for node in nodes {
+        if !node.ops_task_alive {
+            issues.push(format!("{}: operation consumer task is not running", node.id));
+        }
+        if !node.sweeper_task_alive {
+            issues.push(format!("{}: TTL sweeper task is not running", node.id));
+        }
+    }
+    issues
+}
+
+impl KVCluster {
+    /// Checks that every node's background tasks are still running
+    pub fn liveness(&self) -> HealthReport {
+        let nodes: Vec<NodeHealth> = self.nodes.iter().map(|n| node_health(n)).collect();
+        let issues = task_issues(&nodes);
+        HealthReport::from_issues(nodes, issues)
+    }
+
+    /// Checks that the cluster can take traffic: it is not in maintenance or
+    /// warming up, tasks are live and no replica channel is backed up past the readiness
+    /// threshold
+    pub fn readiness(&self) -> HealthReport {
+        let nodes: Vec<NodeHealth> = self.nodes.iter().map(|n| node_health(n)).collect();
+        let mut issues = task_issues(&nodes);
+        if nodes.is_empty() {
+            issues.push("cluster has no nodes".to_string());
+        }
+        if self.in_maintenance() {
+            issues.push("cluster is in maintenance mode".to_string());
+        }
+        if self.is_warming_up() {
+            issues.push(format!("cluster is warming up: {} keys loaded", self.warmup_loaded()));
+        }
+        for node in &nodes {
+            let threshold = (node.backlog_capacity as f64 * READY_BACKLOG_RATIO) as usize;
+            if node.backlog > threshold {
+                issues.push(format!(
+                    "{}: replica channel backlog {}/{} exceeds threshold {}",
+                    node.id, node.backlog, node.backlog_capacity, threshold
+                ));
+            }
+        }
+        HealthReport::from_issues(nodes, issues)
+    }
+
+    /// Checks that the cluster is set up consistently: it has nodes, at
+    /// least as many as its replication factor, and room for values
+    pub fn check_setup(&self) -> Vec<String> {
+        let mut issues = Vec::new();
+        if self.nodes.is_empty() {
+            issues.push("cluster has no nodes".to_string());
+        }
+        if self.replication_factor == 0 {
+            issues.push("replication factor must be at least 1".to_string());
+        } else if self.replication_factor > self.nodes.len() {
+            issues.push(format!(
+                "replication factor {} needs at least {} nodes, but the cluster has {}",
+                self.replication_factor,
+                self.replication_factor,
+                self.nodes.len()
+            ));
+        }
+        if self.value_limit().is_some_and(|limit| limit.max_bytes == 0) {
+            issues.push("value size limit must be positive".to_string());
+        }
+        issues
+    }
+
+    /// Writes, reads and expires a scratch key, so a cluster that cannot
+    /// serve traffic fails before it starts listening rather than on the
+    /// first request
+    pub async fn self_test(&self) -> Result<(), String> {
+        let value = b"ok".to_vec();
+        self.set(SELF_TEST_KEY, value.clone(), Some(SELF_TEST_TTL))
+            .await
+            .map_err(|e| format!("self-test write failed: {}", e))?;
+        if self.get(SELF_TEST_KEY).await != Some(value) {
+            return Err("self-test read did not return the value just written".to_string());
+        }
+        match self.ttl(SELF_TEST_KEY).await {
+            Some(Some(ttl)) if ttl <= SELF_TEST_TTL => {}
+            ttl => return Err(format!("self-test key has TTL {:?}, expected at most {:?}", ttl, SELF_TEST_TTL)),
+        }
+        tokio::time::sleep(SELF_TEST_TTL).await;
+        if self.contains_key(SELF_TEST_KEY).await {
+            return Err("self-test key did not expire".to_string());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,119 @@
+//! Hedged reads: when a key's primary is slow to answer, the same read goes
+//! to its replicas and the first copy found wins, trading a little extra
+//! load for a shorter latency tail.
+
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinSet;
+use tokio::time::Instant;
+
+use crate::{KVCluster, KVNode, Key};
+
+/// Hedged read counters of a cluster
+#[derive(Default)]
+pub(crate) struct HedgeCounters {
+    reads: AtomicU64,
+    hedged: AtomicU64,
+    replica_wins: AtomicU64,
+}
+
+impl HedgeCounters {
+    pub(crate) fn snapshot(&self) -> HedgeStats {
+        let reads = self.reads.load(Ordering::Relaxed);
+        let hedged = self.hedged.load(Ordering::Relaxed);
+        HedgeStats {
+            reads,
+            hedged,
+            replica_wins: self.replica_wins.load(Ordering::Relaxed),
+            hedge_rate: if reads == 0 { 0.0 } else { hedged as f64 / reads as f64 },
+        }
+    }
+}
+
+/// How often hedged reads had to fall back to replicas
+#[derive(Serialize, Debug, Clone, Copy)]
+pub struct HedgeStats {
+    /// Keys read through `mget_hedged`
+    pub reads: u64,
+    /// Reads whose primary did not answer within the hedge threshold
+    pub hedged: u64,
+    /// Hedged reads answered by a replica before the primary
+    pub replica_wins: u64,
+    /// Fraction of reads that were hedged
+    pub hedge_rate: f64,
+}
+
+/// Result of one key of a hedged multi-get
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HedgedRead {
+    pub value: Option<Vec<u8>>,
+    /// Whether a replica answered first. Replicas apply writes
+    /// asynchronously, so such a value may lag behind the primary's.
+    pub from_replica: bool,
+}
+
+/// Reads `key` from a replica's own store, without recording an access
+async fn read_replica(node: Arc<KVNode>, key: Key) -> Option<Vec<u8>> {
+    node.enter().await.ok()?;
+    let entry = node.store.get(key.as_bytes())?;
+    (!entry.is_expired(Instant::now())).then(|| entry.value.to_vec())
+}
+
+impl KVCluster {
+    /// Reads `keys` concurrently, returning results in the order of `keys`.
+    /// A read whose primary has not answered within `hedge_after` is also
+    /// sent to the key's replicas, and the first copy found is returned.
+    /// A replica's miss does not count as an answer, since the replica may
+    /// not have applied the write yet, so missing keys wait for the primary.
+    pub async fn mget_hedged<K: AsRef<[u8]>>(&self, keys: &[K], hedge_after: Duration) -> Vec<HedgedRead> {
+        let mut reads = JoinSet::new();
+        for (index, key) in keys.iter().enumerate() {
+            let cluster = self.clone();
+            let key = Key::from(key.as_ref());
+            reads.spawn(async move { (index, cluster.get_hedged(key, hedge_after).await) });
+        }
+        let mut results = vec![
+            HedgedRead {
+                value: None,
+                from_replica: false,
+            };
+            keys.len()
+        ];
+        while let Some(joined) = reads.join_next().await {
+            // A read only panics if the store itself is broken
+            let (index, read) = joined.expect("hedged read panicked");
+            results[index] = read;
+        }
+        results
+    }
+
+    async fn get_hedged(&self, key: Key, hedge_after: Duration) -> HedgedRead {
+        let counters = &self.state.hedges;
+        counters.reads.fetch_add(1, Ordering::Relaxed);
+        let primary = self.get(&key);
+        tokio::pin!(primary);
+        tokio::select! {
+            value = &mut primary => return HedgedRead { value, from_replica: false },
+            _ = tokio::time::sleep(hedge_after) => {}
+        }
+
+        counters.hedged.fetch_add(1, Ordering::Relaxed);
+        let mut backups = JoinSet::new();
+        for replica in self.get_nodes(key.as_bytes()).unwrap_or_default().into_iter().skip(1) {
+            backups.spawn(read_replica(replica, key.clone()));
+        }
+        loop {
+            tokio::select! {
+                value = &mut primary => return HedgedRead { value, from_replica: false },
+                Some(joined) = backups.join_next() => {
+                    if let Ok(Some(value)) = joined {
+                        counters.replica_wins.fetch_add(1, Ordering::Relaxed);
+                        return HedgedRead { value: Some(value), from_replica: true };
+                    }
+                }
+            }
+        }
+    }
+}
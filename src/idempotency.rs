@@ -0,0 +1,475 @@
+use axum::{
+    body::{to_bytes, Body, Bytes},
+    extract::{Request, State},
+    http::{header, HeaderValue, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use dashmap::mapref::entry::Entry;
+use http_body_util::LengthLimitError;
+use dashmap::DashMap;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError, Weak};
+use std::time::{Duration, Instant};
+use sha2::{Digest, Sha256};
+
+use crate::api::error_response;
+use crate::auth::User;
+use crate::limits::ApiLimits;
+
+/// Request header carrying the client-chosen idempotency key
+pub const IDEMPOTENCY_HEADER: &str = "idempotency-key";
+
+/// Response header set when a stored response is replayed
+pub const REPLAYED_HEADER: &str = "idempotent-replayed";
+
+/// How long completed requests are remembered by default
+pub const DEFAULT_IDEMPOTENCY_TTL: Duration = Duration::from_secs(600);
+
+/// Records kept before the oldest are dropped, so clients sending a fresh
+/// key with every request cannot grow the store without bound
+const RECORDS_CAPACITY: usize = 100_000;
+
+/// SHA-256 of a request body, telling a retry from a different request
+type Fingerprint = [u8; 32];
+
+/// Whose request a record is for: the authenticated user, if users are
+/// configured, and the route with its query, so one key cannot replay
+/// another user's or another endpoint's response
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct RecordKey {
+    user: Option<String>,
+    method: Method,
+    path_and_query: String,
+    idempotency_key: String,
+}
+
+enum Record {
+    InFlight {
+        fingerprint: Fingerprint,
+    },
+    Completed {
+        fingerprint: Fingerprint,
+        status: StatusCode,
+        content_type: Option<HeaderValue>,
+        body: Bytes,
+        stored_at: Instant,
+    },
+}
+
+/// A record and when it was created, relative to the store's other records
+struct Stored {
+    seq: u64,
+    record: Record,
+}
+
+/// Short-lived record of completed write requests, keyed by idempotency key
+pub struct IdempotencyStore {
+    records: DashMap<RecordKey, Stored>,
+    /// Keys of the records created, oldest first. Records removed meanwhile
+    /// leave their key behind until it comes up.
+    created: Mutex<VecDeque<(u64, RecordKey)>>,
+    next_seq: AtomicU64,
+    capacity: usize,
+    ttl: Duration,
+}
+
+impl IdempotencyStore {
+    pub fn new(ttl: Duration) -> Self {
+        IdempotencyStore {
+            records: DashMap::new(),
+            created: Mutex::new(VecDeque::new()),
+            next_seq: AtomicU64::new(0),
+            capacity: RECORDS_CAPACITY,
+            ttl,
+        }
+    }
+
+    /// Drops completed records older than the store's TTL
+    pub fn purge_expired(&self) {
+        let ttl = self.ttl;
+        self.records.retain(|_, stored| match &stored.record {
+            Record::Completed { stored_at, .. } => stored_at.elapsed() < ttl,
+            Record::InFlight { .. } => true,
+        });
+    }
+
+    /// Counts a record just created, dropping the oldest ones once the store
+    /// is full. Call it without holding any of the store's entries.
+    fn created(&self, seq: u64, key: RecordKey) {
+        let mut created = self.created.lock().unwrap_or_else(PoisonError::into_inner);
+        created.push_back((seq, key));
+        while created.len() > self.capacity {
+            let Some((oldest, key)) = created.pop_front() else {
+                break;
+            };
+            self.records.remove_if(&key, |_, stored| stored.seq == oldest);
+        }
+    }
+
+    /// Spawns a task that periodically purges expired records until the store is dropped
+    pub fn spawn_purger(store: &Arc<IdempotencyStore>) {
+        let weak: Weak<IdempotencyStore> = Arc::downgrade(store);
+        let period = store.ttl.min(Duration::from_secs(60));
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            loop {
+                interval.tick().await;
+                match weak.upgrade() {
+                    Some(store) => store.purge_expired(),
+                    None => break,
+                }
+            }
+        });
+    }
+}
+
+/// Releases an in-flight record if the request future is dropped before
+/// completing, e.g. when the client disconnects
+struct InFlightGuard<'a> {
+    store: &'a IdempotencyStore,
+    key: Option<RecordKey>,
+    seq: u64,
+}
+
+impl InFlightGuard<'_> {
+    /// Stores the completed request, unless its record was dropped to make
+    /// room meanwhile
+    fn complete(mut self, record: Option<Record>) {
+        let Some(key) = self.key.take() else { return };
+        match record {
+            Some(record) => {
+                if let Some(mut stored) = self.store.records.get_mut(&key) {
+                    if stored.seq == self.seq {
+                        stored.record = record;
+                    }
+                }
+            }
+            None => {
+                self.store.records.remove_if(&key, |_, stored| stored.seq == self.seq);
+            }
+        }
+    }
+}
+
+impl Drop for InFlightGuard<'_> {
+    fn drop(&mut self) {
+        if let Some(key) = self.key.take() {
+            self.store.records.remove_if(&key, |_, stored| stored.seq == self.seq);
+        }
+    }
+}
+
+fn reject(status: StatusCode, message: &str) -> Response {
+    error_response(status, message.to_string())
+}
+
+/// Middleware that makes POST and DELETE requests carrying an `Idempotency-Key`
+/// header safe to retry: the first completed response is stored and replayed
+/// for retries instead of applying the operation again. Keys are scoped per
+/// user when users are configured; without them, all clients share one scope.
+pub async fn idempotency_layer(
+    State(store): State<Arc<IdempotencyStore>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if request.method() != Method::POST && request.method() != Method::DELETE {
+        return next.run(request).await;
+    }
+    let Some(idempotency_key) = request
+        .headers()
+        .get(IDEMPOTENCY_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string)
+    else {
+        return next.run(request).await;
+    };
+
+    let record_key = RecordKey {
+        user: request.extensions().get::<User>().map(|user| user.name.clone()),
+        method: request.method().clone(),
+        path_and_query: request
+            .uri()
+            .path_and_query()
+            .map_or_else(|| request.uri().path().to_string(), |target| target.as_str().to_string()),
+        idempotency_key,
+    };
+    let max_body = request
+        .extensions()
+        .get::<ApiLimits>()
+        .map_or(usize::MAX, |limits| limits.max_body_bytes);
+    let (parts, body) = request.into_parts();
+    let body = match to_bytes(body, max_body).await {
+        Ok(body) => body,
+        Err(e) if std::error::Error::source(&e).is_some_and(|e| e.is::<LengthLimitError>()) => {
+            return error_response(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                format!("Request body exceeds the {} byte limit", max_body),
+            )
+        }
+        Err(_) => return reject(StatusCode::BAD_REQUEST, "Failed to read request body"),
+    };
+    let fingerprint: Fingerprint = Sha256::digest(&body).into();
+
+    let seq = store.next_seq.fetch_add(1, Ordering::Relaxed);
+    let in_flight = Stored {
+        seq,
+        record: Record::InFlight { fingerprint },
+    };
+    match store.records.entry(record_key.clone()) {
+        Entry::Occupied(mut occupied) => {
+            let expired = matches!(
+                occupied.get().record,
+                Record::Completed { stored_at, .. } if stored_at.elapsed() >= store.ttl
+            );
+            if expired {
+                occupied.insert(in_flight);
+            } else {
+                return match &occupied.get().record {
+                    Record::Completed { fingerprint: stored, .. }
+                    | Record::InFlight { fingerprint: stored }
+                        if *stored != fingerprint =>
+                    {
+                        reject(
+                            StatusCode::UNPROCESSABLE_ENTITY,
+                            "Idempotency-Key was already used with a different request body",
+                        )
+                    }
+                    Record::InFlight { .. } => reject(
+                        StatusCode::CONFLICT,
+                        "A request with this Idempotency-Key is still in progress",
+                    ),
+                    Record::Completed { status, content_type, body, .. } => {
+                        let mut response = (*status, body.clone()).into_response();
+                        if let Some(content_type) = content_type {
+                            response.headers_mut().insert(header::CONTENT_TYPE, content_type.clone());
+                        }
+                        response
+                            .headers_mut()
+                            .insert(REPLAYED_HEADER, HeaderValue::from_static("true"));
+                        response
+                    }
+                };
+            }
+        }
+        Entry::Vacant(vacant) => {
+            vacant.insert(in_flight);
+        }
+    }
+    store.created(seq, record_key.clone());
+
+    let guard = InFlightGuard {
+        store: &store,
+        key: Some(record_key),
+        seq,
+    };
+    let response = next.run(Request::from_parts(parts, Body::from(body))).await;
+
+    // Server errors are not stored so the client can retry them for real
+    if response.status().is_server_error() {
+        guard.complete(None);
+        return response;
+    }
+    let (parts, body) = response.into_parts();
+    let body = match to_bytes(body, usize::MAX).await {
+        Ok(body) => body,
+        Err(_) => {
+            guard.complete(None);
+            return reject(StatusCode::INTERNAL_SERVER_ERROR, "Failed to buffer response body");
+        }
+    };
+    guard.complete(Some(Record::Completed {
+        fingerprint,
+        status: parts.status,
+        content_type: parts.headers.get(header::CONTENT_TYPE).cloned(),
+        body: body.clone(),
+        stored_at: Instant::now(),
+    }));
+    Response::from_parts(parts, Body::from(body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::routing::post;
+    use axum::{middleware, Router};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::sync::Notify;
+    use tower::Service;
+
+    use crate::auth::Role;
+
+    /// A router counting the requests its handler ran, whose handler waits
+    /// for `gate` when one is given
+    fn router(calls: Arc<AtomicUsize>, gate: Option<Arc<Notify>>) -> Router {
+        router_with(IdempotencyStore::new(DEFAULT_IDEMPOTENCY_TTL), calls, gate)
+    }
+
+    fn router_with(store: IdempotencyStore, calls: Arc<AtomicUsize>, gate: Option<Arc<Notify>>) -> Router {
+        let store = Arc::new(store);
+        Router::new()
+            .route(
+                "/kv/:key",
+                post(move |body: Bytes| async move {
+                    let call = calls.fetch_add(1, Ordering::SeqCst) + 1;
+                    if let Some(gate) = gate {
+                        gate.notified().await;
+                    }
+                    format!("call {} with {} bytes", call, body.len())
+                }),
+            )
+            .layer(middleware::from_fn_with_state(store, idempotency_layer))
+    }
+
+    fn write(key: &str, body: &'static str) -> Request {
+        write_to("/kv/k", key, body)
+    }
+
+    fn write_to(uri: &str, key: &str, body: &'static str) -> Request {
+        Request::post(uri)
+            .header(IDEMPOTENCY_HEADER, key)
+            .body(Body::from(body))
+            .unwrap()
+    }
+
+    async fn send(app: &Router, request: Request) -> Response {
+        send_owned(app.clone(), request).await
+    }
+
+    async fn send_owned(mut app: Router, request: Request) -> Response {
+        let Ok(response) = app.call(request).await;
+        response
+    }
+
+    async fn body_text(response: Response) -> String {
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn retry_replays_the_first_response() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let app = router(calls.clone(), None);
+
+        let first = send(&app, write("a", "v1")).await;
+        assert!(first.headers().get(REPLAYED_HEADER).is_none());
+        assert_eq!(body_text(first).await, "call 1 with 2 bytes");
+
+        let retry = send(&app, write("a", "v1")).await;
+        assert_eq!(retry.status(), StatusCode::OK);
+        assert_eq!(retry.headers().get(REPLAYED_HEADER).unwrap(), "true");
+        assert_eq!(body_text(retry).await, "call 1 with 2 bytes");
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // Another key runs the handler again
+        let other = send(&app, write("b", "v1")).await;
+        assert_eq!(body_text(other).await, "call 2 with 2 bytes");
+    }
+
+    #[tokio::test]
+    async fn reused_key_with_another_body_is_rejected() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let app = router(calls.clone(), None);
+
+        send(&app, write("a", "v1")).await;
+        let reused = send(&app, write("a", "v2")).await;
+        assert_eq!(reused.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn retry_while_in_flight_is_rejected() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let gate = Arc::new(Notify::new());
+        let app = router(calls.clone(), Some(gate.clone()));
+
+        let first = tokio::spawn(send_owned(app.clone(), write("a", "v1")));
+        while calls.load(Ordering::SeqCst) == 0 {
+            tokio::task::yield_now().await;
+        }
+        let retry = send(&app, write("a", "v1")).await;
+        assert_eq!(retry.status(), StatusCode::CONFLICT);
+
+        gate.notify_one();
+        let first = first.await.unwrap();
+        assert_eq!(body_text(first).await, "call 1 with 2 bytes");
+        let replayed = send(&app, write("a", "v1")).await;
+        assert_eq!(replayed.headers().get(REPLAYED_HEADER).unwrap(), "true");
+    }
+
+    #[tokio::test]
+    async fn dropped_request_releases_its_key() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let gate = Arc::new(Notify::new());
+        let app = router(calls.clone(), Some(gate.clone()));
+
+        let first = tokio::spawn(send_owned(app.clone(), write("a", "v1")));
+        while calls.load(Ordering::SeqCst) == 0 {
+            tokio::task::yield_now().await;
+        }
+        first.abort();
+        assert!(first.await.unwrap_err().is_cancelled());
+
+        // The key is free again, and the retry runs the handler for real
+        let retry = tokio::spawn(send_owned(app.clone(), write("a", "v1")));
+        while calls.load(Ordering::SeqCst) == 1 {
+            tokio::task::yield_now().await;
+        }
+        gate.notify_one();
+        let retry = retry.await.unwrap();
+        assert_eq!(retry.status(), StatusCode::OK);
+        assert_eq!(body_text(retry).await, "call 2 with 2 bytes");
+    }
+
+    #[tokio::test]
+    async fn records_are_scoped_per_user() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let app = router(calls.clone(), None);
+        let as_user = |name: &str| {
+            let mut request = write("a", "v1");
+            request.extensions_mut().insert(User::new(name, Role::Writer, name));
+            request
+        };
+
+        let alice = send(&app, as_user("alice")).await;
+        assert_eq!(body_text(alice).await, "call 1 with 2 bytes");
+        let bob = send(&app, as_user("bob")).await;
+        assert!(bob.headers().get(REPLAYED_HEADER).is_none());
+        assert_eq!(body_text(bob).await, "call 2 with 2 bytes");
+        let alice_again = send(&app, as_user("alice")).await;
+        assert_eq!(body_text(alice_again).await, "call 1 with 2 bytes");
+    }
+
+    #[tokio::test]
+    async fn records_are_scoped_per_query() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let app = router(calls.clone(), None);
+
+        send(&app, write("a", "v1")).await;
+        let encoded = send(&app, write_to("/kv/k?key_encoding=base64", "a", "v1")).await;
+        assert!(encoded.headers().get(REPLAYED_HEADER).is_none());
+        assert_eq!(body_text(encoded).await, "call 2 with 2 bytes");
+        let encoded_again = send(&app, write_to("/kv/k?key_encoding=base64", "a", "v1")).await;
+        assert_eq!(body_text(encoded_again).await, "call 2 with 2 bytes");
+    }
+
+    #[tokio::test]
+    async fn oldest_records_are_dropped_once_full() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let store = IdempotencyStore {
+            capacity: 2,
+            ..IdempotencyStore::new(DEFAULT_IDEMPOTENCY_TTL)
+        };
+        let app = router_with(store, calls.clone(), None);
+
+        for key in ["a", "b", "c"] {
+            send(&app, write(key, "v1")).await;
+        }
+        let kept = send(&app, write("c", "v1")).await;
+        assert_eq!(kept.headers().get(REPLAYED_HEADER).unwrap(), "true");
+        let dropped = send(&app, write("a", "v1")).await;
+        assert!(dropped.headers().get(REPLAYED_HEADER).is_none());
+        assert_eq!(body_text(dropped).await, "call 4 with 2 bytes");
+    }
+}
@@ -0,0 +1,88 @@
+//! Writes whose caller stops waiting on them, e.g. because an HTTP client
+//! disconnected mid-request or a `tokio::time::timeout` around the call ran
+//! out.
+//!
+//! Dropping a write's future must not leave it half done. A write applies to
+//! its primary and starts sending its replica operations in one step, with
+//! nothing awaited in between, and the sending is finished by a task of its
+//! own. A caller that goes away before then leaves nothing behind; one that
+//! goes away after only stops waiting for replicas to acknowledge. The
+//! counters here show how often the latter happens.
+
+use serde::Serialize;
+use std::future::{poll_fn, Future};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::task::Poll;
+
+/// Interrupted write counters of a cluster
+#[derive(Default)]
+pub(crate) struct InterruptCounters {
+    writes: AtomicU64,
+}
+
+impl InterruptCounters {
+    /// Waits for `write`, counting it as interrupted if this future is
+    /// dropped first
+    pub(crate) async fn watch<T>(&self, write: impl Future<Output = T>) -> T {
+        let mut pending = Pending {
+            counters: self,
+            done: false,
+        };
+        let result = write.await;
+        pending.done = true;
+        result
+    }
+
+    pub(crate) fn snapshot(&self) -> InterruptStats {
+        InterruptStats {
+            writes: self.writes.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Counts a watched write as interrupted unless it completed
+struct Pending<'a> {
+    counters: &'a InterruptCounters,
+    done: bool,
+}
+
+impl Drop for Pending<'_> {
+    fn drop(&mut self) {
+        if !self.done {
+            self.counters.writes.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// How many writes lost their caller partway
+#[derive(Serialize, Debug, Clone, Copy)]
+pub struct InterruptStats {
+    /// Writes applied to their primaries whose caller stopped waiting before
+    /// they completed. Their replicas got them all the same.
+    pub writes: u64,
+}
+
+/// Runs `task` inline until it first has to wait, then finishes it on a task
+/// of its own, which runs to completion whether or not this future is
+/// awaited to the end.
+///
+/// Running the first step inline keeps the order writes were applied in:
+/// replica operations sent right away, or queued behind a full channel, go
+/// ahead of those of any write applied later.
+pub(crate) async fn detach<T, F>(task: F) -> T
+where
+    T: Send + 'static,
+    F: Future<Output = T> + Send + 'static,
+{
+    let mut task = Box::pin(task);
+    if let Poll::Ready(output) = poll_fn(|cx| Poll::Ready(task.as_mut().poll(cx))).await {
+        return output;
+    }
+    match tokio::spawn(task).await {
+        Ok(output) => output,
+        Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+        // Only a runtime shutting down cancels the task, and it is about to
+        // drop this future as well
+        Err(_) => std::future::pending().await,
+    }
+}
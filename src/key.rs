@@ -0,0 +1,205 @@
+//! Keys are arbitrary byte strings. Text keys convert without copying, and
+//! binary keys, e.g. composite keys packing integers, need no encoding.
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use std::borrow::Borrow;
+use std::fmt;
+
+/// A key: cheaply clonable bytes, ordered and hashed as a byte slice
+#[derive(Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Key(Bytes);
+
+impl Key {
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// The key as text, if it is valid UTF-8
+    pub fn as_str(&self) -> Option<&str> {
+        std::str::from_utf8(&self.0).ok()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// The part of `key` before its first ':', if it has one
+pub(crate) fn namespace_of(key: &[u8]) -> Option<&[u8]> {
+    key.iter().position(|&b| b == b':').map(|end| &key[..end])
+}
+
+impl From<String> for Key {
+    fn from(key: String) -> Self {
+        Key(Bytes::from(key))
+    }
+}
+
+impl From<&String> for Key {
+    fn from(key: &String) -> Self {
+        Key(Bytes::copy_from_slice(key.as_bytes()))
+    }
+}
+
+impl From<&str> for Key {
+    fn from(key: &str) -> Self {
+        Key(Bytes::copy_from_slice(key.as_bytes()))
+    }
+}
+
+impl From<Vec<u8>> for Key {
+    fn from(key: Vec<u8>) -> Self {
+        Key(Bytes::from(key))
+    }
+}
+
+impl From<&[u8]> for Key {
+    fn from(key: &[u8]) -> Self {
+        Key(Bytes::copy_from_slice(key))
+    }
+}
+
+impl From<Bytes> for Key {
+    fn from(key: Bytes) -> Self {
+        Key(key)
+    }
+}
+
+impl From<&Key> for Key {
+    fn from(key: &Key) -> Self {
+        key.clone()
+    }
+}
+
+impl AsRef<[u8]> for Key {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+/// Lets maps keyed by `Key` be queried with a plain byte slice
+impl Borrow<[u8]> for Key {
+    fn borrow(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl PartialEq<str> for Key {
+    fn eq(&self, other: &str) -> bool {
+        self.as_bytes() == other.as_bytes()
+    }
+}
+
+impl PartialEq<&str> for Key {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_bytes() == other.as_bytes()
+    }
+}
+
+/// `key` for messages and logs: text keys as themselves, binary keys as
+/// `base64:<encoded>`
+pub(crate) fn key_name(key: &[u8]) -> String {
+    match std::str::from_utf8(key) {
+        Ok(text) => text.to_string(),
+        Err(_) => format!("base64:{}", STANDARD.encode(key)),
+    }
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&key_name(&self.0))
+    }
+}
+
+impl fmt::Debug for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.as_str() {
+            Some(text) => fmt::Debug::fmt(text, f),
+            None => write!(f, "base64:{}", STANDARD.encode(&self.0)),
+        }
+    }
+}
+
+/// Wire form of a key: text keys serialize as a JSON string, binary keys as
+/// `{"base64": "..."}`, so dumps and change events round-trip every key
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum KeyRepr {
+    Text(String),
+    Binary { base64: String },
+}
+
+impl Serialize for Key {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.as_str() {
+            Some(text) => serializer.serialize_str(text),
+            None => KeyRepr::Binary {
+                base64: STANDARD.encode(&self.0),
+            }
+            .serialize(serializer),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Key {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        match KeyRepr::deserialize(deserializer)? {
+            KeyRepr::Text(text) => Ok(Key::from(text)),
+            KeyRepr::Binary { base64 } => STANDARD
+                .decode(base64)
+                .map(Key::from)
+                .map_err(serde::de::Error::custom),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn namespace_is_the_part_before_the_first_colon() {
+        assert_eq!(namespace_of(b"tenant:a:1"), Some(&b"tenant"[..]));
+        assert_eq!(namespace_of(b":k"), Some(&b""[..]));
+        assert_eq!(namespace_of(b"plain"), None);
+        assert_eq!(namespace_of(b"\xff\x00:k"), Some(&b"\xff\x00"[..]));
+    }
+
+    #[test]
+    fn binary_keys_are_named_in_base64() {
+        assert_eq!(key_name(b"user:1"), "user:1");
+        assert_eq!(key_name(b"\xff\x01"), "base64:/wE=");
+        assert_eq!(Key::from(&b"\xff\x01"[..]).to_string(), "base64:/wE=");
+        assert_eq!(format!("{:?}", Key::from("a\"b")), "\"a\\\"b\"");
+    }
+
+    #[test]
+    fn keys_round_trip_through_json() {
+        let text = Key::from("user:1");
+        assert_eq!(serde_json::to_string(&text).unwrap(), "\"user:1\"");
+        let binary = Key::from(vec![0xff, 0x00, 0x01]);
+        let json = serde_json::to_string(&binary).unwrap();
+        assert_eq!(json, "{\"base64\":\"/wAB\"}");
+        for key in [text, binary, Key::default()] {
+            let json = serde_json::to_string(&key).unwrap();
+            assert_eq!(serde_json::from_str::<Key>(&json).unwrap(), key);
+        }
+        assert!(serde_json::from_str::<Key>("{\"base64\":\"not base64!\"}").is_err());
+    }
+
+    #[test]
+    fn keys_order_and_compare_as_bytes() {
+        let mut keys = vec![Key::from("b"), Key::from(vec![0xff]), Key::from("a:2"), Key::from("a")];
+        keys.sort();
+        assert_eq!(keys, [Key::from("a"), Key::from("a:2"), Key::from("b"), Key::from(vec![0xff])]);
+        assert_eq!(Key::from("a"), "a");
+        assert_eq!(Key::from(vec![0xff]).as_str(), None);
+    }
+}
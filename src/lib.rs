@@ -1,39 +1,834 @@
+use dashmap::mapref::entry::Entry;
 use dashmap::DashMap;
-use priority_queue::PriorityQueue;
-use std::collections::BTreeMap;
-use std::sync::{Arc, Mutex};
-use std::time::{Duration, Instant};
-use tokio::sync::mpsc;
+use std::any::Any;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError, RwLock};
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc, Mutex as AsyncMutex, Notify};
+use tokio::time::Instant;
+use tracing::error;
 use xxhash_rust::xxh32::xxh32;
 use serde::{Serialize, Deserialize};
 use serde_json::{Value as JsonValue, Error as JsonError};
 
 // Expose our API and server modules
+pub mod analytics;
 pub mod api;
+pub mod auth;
+pub mod blob;
+pub mod bulk;
+pub mod changes;
+#[cfg(feature = "chaos")]
+pub mod chaos;
+pub mod config;
+pub mod consistency;
+pub mod crdt;
+pub mod dump;
+pub mod embedded;
+pub mod encryption;
+pub mod error;
+pub mod expiry;
+pub mod failover;
+pub mod field_encryption;
+pub mod health;
+pub mod hedge;
+pub mod idempotency;
+pub mod interrupt;
+pub mod key;
+pub mod limits;
+pub mod lock;
+pub mod meta;
+pub mod metrics;
+pub mod partition;
+pub mod prefix;
+pub mod projection;
+pub mod quota;
+pub mod ratelimit;
+pub mod replication;
+pub mod runtime;
 pub mod server;
+pub mod stats;
+pub mod stream;
+pub mod topology;
+pub mod tracking;
+pub mod trash;
+pub mod typed;
+pub mod value;
+pub mod version;
+pub mod warmup;
+pub mod workload;
+
+use changes::{ChangeEvent, CHANGE_FEED_CAPACITY};
+pub use error::KVError;
+pub use key::Key;
+use key::{key_name, namespace_of};
+use limits::ValueLimit;
+use meta::EntryMeta;
+use replication::ReplicationStatus;
+use runtime::{NodeRuntime, RuntimePlacement};
+use topology::RING_HASH_SEED;
+use value::StoredValue;
+
+/// Capacity of each node's replica operation channel
+const NODE_CHANNEL_CAPACITY: usize = 1000;
+
+/// Most replica operations applied as one coalesced batch
+const COALESCE_BATCH_SIZE: usize = 256;
+
+/// Pause before restarting a node task that panicked, so a task that panics
+/// on every run does not spin
+const TASK_RESTART_BACKOFF: Duration = Duration::from_millis(10);
+
+/// Coarse classification of a stored value, inferred from its bytes
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum ValueType {
+    Crdt,
+    Stream,
+    /// Written with `set_typed` by a codec that names a schema
+    Typed,
+    Json,
+    String,
+    Binary,
+}
+
+impl ValueType {
+    pub fn of(bytes: &[u8]) -> Self {
+        if crdt::is_crdt(bytes) {
+            return ValueType::Crdt;
+        }
+        if bytes.starts_with(stream::STREAM_MAGIC) {
+            return ValueType::Stream;
+        }
+        if bytes.starts_with(typed::TYPED_MAGIC) {
+            return ValueType::Typed;
+        }
+        let first = bytes.iter().find(|b| !b.is_ascii_whitespace());
+        if matches!(first, Some(b'{') | Some(b'['))
+            && serde_json::from_slice::<serde::de::IgnoredAny>(bytes).is_ok()
+        {
+            ValueType::Json
+        } else if std::str::from_utf8(bytes).is_ok() {
+            ValueType::String
+        } else {
+            ValueType::Binary
+        }
+    }
+}
+
+/// Time to live an entry was written with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Ttl {
+    duration: Duration,
+    /// Reads restart the TTL from the time of the read
+    sliding: bool,
+}
+
+impl Ttl {
+    fn new(duration: Duration, sliding: bool) -> Self {
+        Ttl { duration, sliding }
+    }
+}
 
 #[derive(Clone)]
 struct KVEntry {
-    value: Vec<u8>,
+    value: StoredValue,
     expiry: Option<Instant>,
+    ttl: Option<Ttl>,
+    meta: EntryMeta,
+}
+
+impl KVEntry {
+    fn new(value: Vec<u8>, expiry: Option<Instant>, ttl: Option<Ttl>) -> Self {
+        KVEntry {
+            value: value.into(),
+            expiry,
+            ttl,
+            meta: EntryMeta::new(),
+        }
+    }
+
+    fn is_expired(&self, now: Instant) -> bool {
+        self.expiry.is_some_and(|expiry| expiry <= now)
+    }
+
+    /// Replaces the value and expiry. Metadata carries over unless the entry
+    /// being replaced had already expired, in which case the key is new.
+    fn overwrite(&mut self, value: Vec<u8>, expiry: Option<Instant>, ttl: Option<Ttl>, now: Instant) {
+        if self.is_expired(now) {
+            self.meta = EntryMeta::new();
+        }
+        self.value = value.into();
+        self.expiry = expiry;
+        self.ttl = ttl;
+    }
 }
 
+/// Reply channel a replica signals once it has applied an operation
+type Ack = mpsc::Sender<()>;
+
 enum KVOperation {
-    Set(String, Vec<u8>, Option<Duration>),
-    Del(String),
+    Set(Key, Vec<u8>, Option<Ttl>, Option<Ack>),
+    Del(Key, Option<Ack>),
+    /// Restarts a key's TTL so it expires the given duration from now
+    Touch(Key, Duration, Option<Ack>),
+}
+
+/// An operation on a replica's channel, stamped with when it was sent so
+/// the replica can measure how far behind it is
+struct QueuedOp {
+    op: KVOperation,
+    sent_at: Instant,
+}
+
+impl QueuedOp {
+    fn new(op: KVOperation) -> Self {
+        QueuedOp {
+            op,
+            sent_at: Instant::now(),
+        }
+    }
+}
+
+impl KVOperation {
+    fn key(&self) -> &Key {
+        match self {
+            KVOperation::Set(key, ..) | KVOperation::Del(key, _) | KVOperation::Touch(key, ..) => key,
+        }
+    }
+
+    fn take_ack(&mut self) -> Option<Ack> {
+        match self {
+            KVOperation::Set(_, _, _, ack) | KVOperation::Del(_, ack) | KVOperation::Touch(_, _, ack) => {
+                ack.take()
+            }
+        }
+    }
+
+    /// Whether the operation replaces the key's value, making earlier
+    /// operations on the key moot
+    fn replaces_value(&self) -> bool {
+        !matches!(self, KVOperation::Touch(..))
+    }
+}
+
+/// How many replicas must apply a write before the write returns
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AckMode {
+    /// Return once the primary copy is written; replicas apply asynchronously
+    #[default]
+    Primary,
+    /// Return once at least this many replicas have applied the write
+    Replicas(usize),
+    /// Return once every replica has applied the write
+    All,
+}
+
+impl std::str::FromStr for AckMode {
+    type Err = String;
+
+    /// Parses `primary` (or `async`), `all` (or `sync`), or a replica count
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "primary" | "async" => Ok(AckMode::Primary),
+            "all" | "sync" => Ok(AckMode::All),
+            n => n
+                .parse()
+                .map(AckMode::Replicas)
+                .map_err(|_| format!("invalid ack mode '{}': expected primary, all or a replica count", s)),
+        }
+    }
+}
+
+impl AckMode {
+    /// Number of replica acknowledgements to wait for, out of `replicas`
+    fn required(self, replicas: usize) -> Result<usize, KVError> {
+        match self {
+            AckMode::Primary => Ok(0),
+            AckMode::All => Ok(replicas),
+            AckMode::Replicas(required) if required > replicas => Err(KVError::NotEnoughReplicas {
+                required,
+                available: replicas,
+            }),
+            AckMode::Replicas(required) => Ok(required),
+        }
+    }
+}
+
+/// Settings shared by all nodes of a cluster, adjustable at runtime
+#[derive(Default)]
+struct NodeSettings {
+    /// Write coalescing delay window in microseconds; 0 disables coalescing
+    coalesce_window_us: AtomicU64,
+    #[cfg(feature = "chaos")]
+    faults: chaos::FaultInjector,
+}
+
+impl NodeSettings {
+    fn coalesce_window(&self) -> Option<Duration> {
+        match self.coalesce_window_us.load(Ordering::Relaxed) {
+            0 => None,
+            us => Some(Duration::from_micros(us)),
+        }
+    }
 }
 
 struct KVNode {
     id: String,
-    store: DashMap<String, KVEntry>,
-    ttl_queue: Mutex<PriorityQueue<String, Instant>>,
-    tx: mpsc::Sender<KVOperation>,
+    store: DashMap<Key, KVEntry>,
+    /// Keys with a TTL, by when they expire
+    expiries: expiry::ExpiryWheel,
+    tx: mpsc::Sender<QueuedOp>,
+    /// Replica operations queued on `tx`, and how many of those the node
+    /// has applied or discarded
+    ops_queued: AtomicU64,
+    ops_done: AtomicU64,
+    ops_done_notify: Notify,
+    /// Operations that must be done before the node serves as primary: those
+    /// queued before it took keys over from a node that went down, or took
+    /// its own back, so an older write cannot overwrite a newer one
+    ops_fence: AtomicU64,
+    ops_alive: AtomicBool,
+    sweeper_alive: AtomicBool,
+    task_restarts: AtomicU64,
+    ops_coalesced: AtomicU64,
+    apply_lag: consistency::ApplyLag,
+    settings: Arc<NodeSettings>,
+    /// Clients to tell about keys the sweeper expires
+    tracking: Arc<tracking::Tracking>,
+    /// Where the node's background tasks run; `None` for a node without any
+    runtime: Option<runtime::TaskRuntime>,
+    task_times: runtime::NodeTaskTimes,
+}
+
+impl KVNode {
+    /// Moves `key` from its `old` expiry to its `new` one, either of which
+    /// may be none. Call it while holding the key's store entry.
+    fn track_expiry(&self, key: &Key, old: Option<Instant>, new: Option<Instant>) {
+        self.expiries.reschedule(key, old, new);
+    }
+
+    /// Removes `key` from the store, along with its pending expiry
+    fn remove_entry(&self, key: &[u8]) -> Option<(Key, KVEntry)> {
+        let removed = self.store.remove(key);
+        if let Some((key, entry)) = &removed {
+            self.track_expiry(key, entry.expiry, None);
+        }
+        removed
+    }
+
+    /// Applies injected faults ahead of a read or write served as primary,
+    /// then waits for the replica operations queued before the node last
+    /// took keys over. Without the `chaos` feature there are no faults.
+    async fn enter(&self) -> Result<(), KVError> {
+        #[cfg(feature = "chaos")]
+        self.settings.faults.before_primary_op(&self.id).await?;
+        self.pass_fence().await;
+        Ok(())
+    }
+
+    /// Waits until the operations queued before the last `fence_ops` are
+    /// done, unless the task applying them has stopped
+    async fn pass_fence(&self) {
+        let fence = self.ops_fence.load(Ordering::Acquire);
+        while self.ops_done.load(Ordering::Acquire) < fence && self.ops_alive.load(Ordering::Acquire) {
+            let done = self.ops_done_notify.notified();
+            tokio::pin!(done);
+            done.as_mut().enable();
+            if self.ops_done.load(Ordering::Acquire) >= fence {
+                break;
+            }
+            done.await;
+        }
+    }
+
+    /// Makes the node apply the replica operations queued so far before it
+    /// next serves as primary
+    fn fence_ops(&self) {
+        self.ops_fence
+            .fetch_max(self.ops_queued.load(Ordering::Acquire), Ordering::AcqRel);
+    }
+
+    /// Queues an operation on the node's replica channel, giving up once the
+    /// deadline passes
+    async fn send_op(&self, op: KVOperation, deadline: Option<Instant>) -> Result<(), KVError> {
+        let mut sending = self.start_op();
+        let op = QueuedOp::new(op);
+        let sent = match deadline {
+            Some(deadline) => tokio::time::timeout_at(deadline, self.tx.send(op))
+                .await
+                .map_err(|_| KVError::Timeout)?,
+            None => self.tx.send(op).await,
+        };
+        sending.queued = sent.is_ok();
+        Ok(())
+    }
+
+    /// Queues an operation on the node's replica channel if it has room
+    fn try_send_op(&self, op: KVOperation) -> Result<(), mpsc::error::TrySendError<QueuedOp>> {
+        let mut sending = self.start_op();
+        self.tx.try_send(QueuedOp::new(op))?;
+        sending.queued = true;
+        Ok(())
+    }
+
+    /// Counts an operation as queued before it is sent, so that a fence
+    /// taken meanwhile covers it even if the node applies it right away
+    fn start_op(&self) -> SendingOp<'_> {
+        self.ops_queued.fetch_add(1, Ordering::AcqRel);
+        SendingOp { node: self, queued: false }
+    }
+
+    /// Whether the node is failing: one of its background tasks has stopped,
+    /// or an injected fault has taken it down
+    fn is_failing(&self) -> bool {
+        #[cfg(feature = "chaos")]
+        if self.settings.faults.is_failed(&self.id) {
+            return true;
+        }
+        !self.ops_alive.load(Ordering::Acquire) || !self.sweeper_alive.load(Ordering::Acquire)
+    }
+
+    /// Applies injected faults to a replica operation; false discards it
+    async fn admit_replica_op(&self) -> bool {
+        #[cfg(feature = "chaos")]
+        if !self.settings.faults.admit_replica_op(&self.id).await {
+            return false;
+        }
+        true
+    }
+
+    /// Drops keys whose TTL has run out by `now`, returning how many
+    fn sweep(&self, now: Instant) -> usize {
+        let mut removed = 0;
+        for key in self.expiries.take_due(now) {
+            // The key may have been rewritten since it was scheduled; only
+            // drop it if the stored copy has itself expired
+            if self
+                .store
+                .remove_if(&key, |_, entry| entry.expiry.is_some_and(|e| e <= now))
+                .is_some()
+            {
+                self.tracking.invalidate(key.as_bytes());
+                removed += 1;
+            }
+        }
+        removed
+    }
+
+    /// Stores a value, keeping the metadata of a live entry it overwrites,
+    /// and schedules its expiry
+    fn put(&self, key: Key, value: Vec<u8>, ttl: Option<Ttl>, now: Instant) {
+        let expiry = ttl.map(|ttl| now + ttl.duration);
+        match self.store.entry(key) {
+            Entry::Occupied(mut occupied) => {
+                let previous = occupied.get().expiry;
+                occupied.get_mut().overwrite(value, expiry, ttl, now);
+                self.track_expiry(occupied.key(), previous, expiry);
+            }
+            Entry::Vacant(vacant) => {
+                self.track_expiry(vacant.key(), None, expiry);
+                vacant.insert(KVEntry::new(value, expiry, ttl));
+            }
+        }
+    }
+
+    /// Stores `value` under `key` unless the key holds a live value already.
+    /// Returns whether it was stored.
+    fn put_if_absent(&self, key: Key, value: Vec<u8>, ttl: Option<Ttl>, now: Instant) -> bool {
+        let expiry = ttl.map(|ttl| now + ttl.duration);
+        match self.store.entry(key) {
+            Entry::Occupied(occupied) if !occupied.get().is_expired(now) => return false,
+            Entry::Occupied(mut occupied) => {
+                let previous = occupied.get().expiry;
+                occupied.get_mut().overwrite(value, expiry, ttl, now);
+                self.track_expiry(occupied.key(), previous, expiry);
+            }
+            Entry::Vacant(vacant) => {
+                self.track_expiry(vacant.key(), None, expiry);
+                vacant.insert(KVEntry::new(value, expiry, ttl));
+            }
+        }
+        true
+    }
+
+    /// Restarts the TTL of a live `key` so it expires `duration` from `now`.
+    /// Keys without a TTL are left alone. Returns whether the TTL was restarted.
+    fn refresh_ttl(&self, key: &[u8], duration: Duration, now: Instant) -> bool {
+        let Some(mut entry) = self.store.get_mut(key) else {
+            return false;
+        };
+        if entry.is_expired(now) {
+            return false;
+        }
+        let Some(ttl) = entry.ttl.as_mut() else {
+            return false;
+        };
+        ttl.duration = duration;
+        let previous = entry.expiry.replace(now + duration);
+        self.track_expiry(entry.key(), previous, entry.expiry);
+        true
+    }
+
+    /// Applies an operation taken off the replica channel, recording how
+    /// long it waited there
+    fn apply_queued(&self, queued: QueuedOp) {
+        self.apply_lag.record(queued.sent_at.elapsed());
+        self.apply(queued.op);
+    }
+
+    fn apply(&self, op: KVOperation) {
+        let ack = match op {
+            KVOperation::Set(key, value, ttl, ack) => {
+                // Replicas merge CRDT states rather than overwrite them
+                let value = match self.store.get(&key) {
+                    Some(existing) if crdt::is_crdt(&value) => {
+                        crdt::merge_encoded(&existing.value, &value).unwrap_or(value)
+                    }
+                    _ => value,
+                };
+                self.put(key, value, ttl, Instant::now());
+                ack
+            }
+            KVOperation::Del(key, ack) => {
+                self.remove_entry(key.as_bytes());
+                ack
+            }
+            KVOperation::Touch(key, duration, ack) => {
+                self.refresh_ttl(key.as_bytes(), duration, Instant::now());
+                ack
+            }
+        };
+        if let Some(ack) = ack {
+            // The channel has room for every replica; a closed one means the
+            // writer stopped waiting
+            let _ = ack.try_send(());
+        }
+    }
+}
+
+/// Clears a task liveness flag when the task exits, including by panic.
+struct LivenessGuard<'a>(&'a AtomicBool);
+
+impl Drop for LivenessGuard<'_> {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::Release);
+    }
+}
+
+/// Background tasks run by every node
+#[derive(Clone, Copy)]
+enum NodeTask {
+    Ops,
+    Sweeper,
+}
+
+impl NodeTask {
+    fn name(self) -> &'static str {
+        match self {
+            NodeTask::Ops => "operation consumer",
+            NodeTask::Sweeper => "TTL sweeper",
+        }
+    }
+
+    fn alive_flag(self, node: &KVNode) -> &AtomicBool {
+        match self {
+            NodeTask::Ops => &node.ops_alive,
+            NodeTask::Sweeper => &node.sweeper_alive,
+        }
+    }
+
+    fn time(self, node: &KVNode) -> &runtime::TaskTime {
+        match self {
+            NodeTask::Ops => &node.task_times.ops,
+            NodeTask::Sweeper => &node.task_times.sweeper,
+        }
+    }
+}
+
+/// Runs a node background task on the node's runtime, restarting it each
+/// time it panics.
+fn spawn_supervised<F, Fut>(node: Arc<KVNode>, task: NodeTask, mut start: F)
+where
+    F: FnMut() -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    let runtime = node.runtime.clone().expect("supervised tasks run on nodes with a runtime");
+    runtime.spawn(async move {
+        let _alive = LivenessGuard(task.alive_flag(&node));
+        loop {
+            let run = start();
+            let timed_node = node.clone();
+            match tokio::spawn(async move { task.time(&timed_node).run(run).await }).await {
+                Err(err) if err.is_panic() => {
+                    let restarts = node.task_restarts.fetch_add(1, Ordering::Relaxed) + 1;
+                    error!(
+                        node = %node.id,
+                        restarts,
+                        "{} task panicked, restarting: {}",
+                        task.name(),
+                        panic_message(err.into_panic().as_ref())
+                    );
+                    tokio::time::sleep(TASK_RESTART_BACKOFF).await;
+                }
+                // The task returned or was cancelled: nothing left to supervise
+                _ => break,
+            }
+        }
+    });
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(msg) = payload.downcast_ref::<&str>() {
+        msg
+    } else if let Some(msg) = payload.downcast_ref::<String>() {
+        msg
+    } else {
+        "unknown panic payload"
+    }
+}
+
+async fn consume_ops(node: Arc<KVNode>, rx: Arc<AsyncMutex<mpsc::Receiver<QueuedOp>>>) {
+    let mut rx = rx.lock().await;
+    let mut batch = Vec::with_capacity(COALESCE_BATCH_SIZE);
+    loop {
+        let Some(window) = node.settings.coalesce_window() else {
+            let Some(queued) = rx.recv().await else {
+                break;
+            };
+            let _done = OpsDone { node: &node, count: 1 };
+            if node.admit_replica_op().await {
+                node.apply_queued(queued);
+            }
+            continue;
+        };
+
+        if rx.recv_many(&mut batch, COALESCE_BATCH_SIZE).await == 0 {
+            break;
+        }
+        let mut done = OpsDone {
+            node: &node,
+            count: batch.len() as u64,
+        };
+        // Give writes to the same keys a bounded window to pile up
+        if batch.len() < COALESCE_BATCH_SIZE {
+            tokio::time::sleep(window).await;
+            while batch.len() < COALESCE_BATCH_SIZE {
+                match rx.try_recv() {
+                    Ok(op) => batch.push(op),
+                    Err(_) => break,
+                }
+            }
+        }
+        let received = batch.len();
+        done.count = received as u64;
+        let (ops, superseded) = coalesce(&mut batch);
+        node.ops_coalesced
+            .fetch_add((received - ops.len()) as u64, Ordering::Relaxed);
+        for queued in ops {
+            if node.admit_replica_op().await {
+                node.apply_queued(queued);
+            }
+        }
+        // A superseded write counts as applied once the write replacing it is
+        for ack in superseded {
+            let _ = ack.try_send(());
+        }
+    }
+}
+
+/// An operation counted as queued on a node but not sent yet. If it never
+/// gets sent, it counts as done as well: taking it back off `ops_queued`
+/// instead could leave a fence taken meanwhile waiting for it forever.
+struct SendingOp<'a> {
+    node: &'a KVNode,
+    queued: bool,
+}
+
+impl Drop for SendingOp<'_> {
+    fn drop(&mut self) {
+        if !self.queued {
+            self.node.ops_done.fetch_add(1, Ordering::AcqRel);
+            self.node.ops_done_notify.notify_waiters();
+        }
+    }
+}
+
+/// Counts replica operations taken off a node's channel as done once
+/// dropped, whether they were applied, discarded, or lost to a panic
+struct OpsDone<'a> {
+    node: &'a KVNode,
+    count: u64,
+}
+
+impl Drop for OpsDone<'_> {
+    fn drop(&mut self) {
+        self.node.ops_done.fetch_add(self.count, Ordering::AcqRel);
+        self.node.ops_done_notify.notify_waiters();
+    }
+}
+
+/// Drains a batch keeping only the last operation for each key. Sets and
+/// deletes both replace whatever came before them, so earlier ones are moot.
+/// Touches only supersede earlier touches, and are themselves superseded by
+/// a later set or delete.
+/// Also returns the reply channels of the dropped operations.
+fn coalesce(batch: &mut Vec<QueuedOp>) -> (Vec<QueuedOp>, Vec<Ack>) {
+    let mut replaced = HashSet::with_capacity(batch.len());
+    let mut touched = HashSet::new();
+    let mut kept = Vec::with_capacity(batch.len());
+    let mut superseded = Vec::new();
+    for mut queued in batch.drain(..).rev() {
+        let op = &mut queued.op;
+        let key = op.key().clone();
+        let moot = replaced.contains(&key) || (!op.replaces_value() && !touched.insert(key.clone()));
+        if op.replaces_value() {
+            replaced.insert(key);
+        }
+        if !moot {
+            kept.push(queued);
+        } else if let Some(ack) = op.take_ack() {
+            superseded.push(ack);
+        }
+    }
+    kept.reverse();
+    (kept, superseded)
+}
+
+async fn sweep_expired(node: Arc<KVNode>) {
+    loop {
+        tokio::time::sleep(Duration::from_millis(1)).await;
+        node.sweep(Instant::now());
+    }
+}
+
+/// Per-call settings for write operations
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WriteOptions {
+    /// Upper bound on the time spent dispatching the write to its replicas.
+    /// `None` waits for as long as replica channels take to accept it.
+    pub timeout: Option<Duration>,
+    /// Replica acknowledgements to wait for; `None` uses the cluster default
+    pub ack: Option<AckMode>,
+    /// Restart the value's TTL every time it is read, instead of counting
+    /// down from the write
+    pub sliding_ttl: bool,
+    /// Set when applying a write received from another cluster, so it is not
+    /// forwarded back out
+    pub(crate) replicated: bool,
+}
+
+impl WriteOptions {
+    pub fn new(timeout: Option<Duration>, ack: Option<AckMode>) -> Self {
+        WriteOptions {
+            timeout,
+            ack,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_timeout(timeout: Duration) -> Self {
+        WriteOptions {
+            timeout: Some(timeout),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_ack(ack: AckMode) -> Self {
+        WriteOptions {
+            ack: Some(ack),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_sliding_ttl() -> Self {
+        WriteOptions {
+            sliding_ttl: true,
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn replicated() -> Self {
+        WriteOptions {
+            replicated: true,
+            ..Default::default()
+        }
+    }
+
+    fn deadline(&self) -> Option<Instant> {
+        self.timeout.map(|t| Instant::now() + t)
+    }
+}
+
+/// Waits for `required` replicas to acknowledge an operation, up to the deadline
+async fn await_acks(
+    mut acks: mpsc::Receiver<()>,
+    required: usize,
+    deadline: Option<Instant>,
+) -> Result<(), KVError> {
+    for acked in 0..required {
+        let received = match deadline {
+            Some(deadline) => tokio::time::timeout_at(deadline, acks.recv())
+                .await
+                .map_err(|_| KVError::Timeout)?,
+            None => acks.recv().await,
+        };
+        // Every sender is gone: the remaining replicas dropped the operation
+        if received.is_none() {
+            return Err(KVError::NotEnoughReplicas {
+                required,
+                available: acked,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Index of the node owning `key` as primary on `ring`
+fn ring_owner(ring: &BTreeMap<u32, usize>, key: &[u8]) -> Option<usize> {
+    let khash = xxh32(key, RING_HASH_SEED);
+    ring.range(khash..).chain(ring.iter()).next().map(|(_, idx)| *idx)
+}
+
+/// Runtime state shared by every clone of a cluster handle
+struct ClusterState {
+    changes: broadcast::Sender<ChangeEvent>,
+    replication: Mutex<ReplicationStatus>,
+    node_settings: Arc<NodeSettings>,
+    ack_mode: Mutex<AckMode>,
+    value_limit: Mutex<Option<ValueLimit>>,
+    read_only: AtomicBool,
+    maintenance: AtomicBool,
+    sliding_namespaces: RwLock<HashSet<String>>,
+    blob_refs: blob::BlobRefs,
+    field_encryption: field_encryption::FieldEncryption,
+    trash: trash::Trash,
+    hedges: hedge::HedgeCounters,
+    warmup: warmup::WarmupState,
+    failover: failover::FailoverState,
+    quotas: quota::NamespaceQuotas,
+    schemas: typed::Schemas,
+    tracking: Arc<tracking::Tracking>,
+    interrupts: interrupt::InterruptCounters,
+}
+
+/// A cluster id that is unique enough when none is configured
+fn default_cluster_id() -> String {
+    let seed = format!("{}:{}", std::process::id(), changes::unix_millis());
+    format!("{:08x}", xxh32(seed.as_bytes(), RING_HASH_SEED))
 }
 
 #[derive(Clone)]
 pub struct KVCluster {
+    state: Arc<ClusterState>,
+    cluster_id: String,
     nodes: Vec<Arc<KVNode>>,
     ring: Arc<BTreeMap<u32, usize>>,
+    ring_version: u64,
+    node_addrs: HashMap<String, String>,
+    node_runtime: RuntimePlacement,
     vnodes_per_node: usize,
     replication_factor: usize,
 }
@@ -41,127 +836,684 @@ pub struct KVCluster {
 impl KVCluster {
     pub fn new(vnodes_per_node: usize, replication_factor: usize) -> Self {
         KVCluster {
+            state: Arc::new(ClusterState {
+                changes: broadcast::channel(CHANGE_FEED_CAPACITY).0,
+                replication: Mutex::new(ReplicationStatus::default()),
+                node_settings: Arc::new(NodeSettings::default()),
+                ack_mode: Mutex::new(AckMode::default()),
+                value_limit: Mutex::new(None),
+                read_only: AtomicBool::new(false),
+                maintenance: AtomicBool::new(false),
+                sliding_namespaces: RwLock::new(HashSet::new()),
+                blob_refs: blob::BlobRefs::default(),
+                field_encryption: field_encryption::FieldEncryption::default(),
+                trash: trash::Trash::default(),
+                hedges: hedge::HedgeCounters::default(),
+                warmup: warmup::WarmupState::default(),
+                failover: failover::FailoverState::default(),
+                quotas: quota::NamespaceQuotas::default(),
+                schemas: typed::Schemas::default(),
+                tracking: Arc::new(tracking::Tracking::default()),
+                interrupts: interrupt::InterruptCounters::default(),
+            }),
+            cluster_id: default_cluster_id(),
             nodes: Vec::new(),
             ring: Arc::new(BTreeMap::new()),
+            ring_version: 0,
+            node_addrs: HashMap::new(),
+            node_runtime: RuntimePlacement::default(),
             vnodes_per_node,
             replication_factor,
         }
     }
 
+    /// Identity of this cluster in CRDT state. Clusters replicating into each
+    /// other must use distinct ids.
+    pub fn cluster_id(&self) -> &str {
+        &self.cluster_id
+    }
+
+    pub fn set_cluster_id(&mut self, cluster_id: String) {
+        self.cluster_id = cluster_id;
+    }
+
+    /// Sets where the background tasks of nodes added from now on run.
+    /// Nodes already in the cluster keep their runtime. Fails if a dedicated
+    /// runtime cannot be started.
+    pub fn set_node_runtime(&mut self, runtime: NodeRuntime) -> std::io::Result<()> {
+        self.node_runtime = RuntimePlacement::new(runtime)?;
+        Ok(())
+    }
+
+    pub fn node_runtime(&self) -> NodeRuntime {
+        self.node_runtime.config()
+    }
+
+    /// Adds a node. Unless a dedicated node runtime is configured, this must
+    /// be called from within a tokio runtime, which then runs the node's tasks.
     pub fn add_node(&mut self, node_id: String) {
-        let (tx, mut rx) = mpsc::channel::<KVOperation>(1000);
+        let runtime = self.node_runtime.for_node(&node_id);
+        let (node, rx) = self.insert_node(node_id, Some(runtime));
+
+        // The receiver lives outside the consumer task so a restarted consumer
+        // picks up the same channel
+        let rx = Arc::new(AsyncMutex::new(rx));
+        let node_for_ops = node.clone();
+        spawn_supervised(node.clone(), NodeTask::Ops, move || {
+            consume_ops(node_for_ops.clone(), rx.clone())
+        });
+
+        let ttl_node = node.clone();
+        spawn_supervised(node, NodeTask::Sweeper, move || sweep_expired(ttl_node.clone()));
+    }
+
+    /// Adds a node without background tasks. It cannot apply replica
+    /// operations, and expires keys only as they are read or swept.
+    fn add_passive_node(&mut self, node_id: String) {
+        self.insert_node(node_id, None);
+    }
+
+    /// Puts a new node on the ring, returning it with the receiving end of
+    /// its replica channel
+    fn insert_node(
+        &mut self,
+        node_id: String,
+        runtime: Option<runtime::TaskRuntime>,
+    ) -> (Arc<KVNode>, mpsc::Receiver<QueuedOp>) {
+        let (tx, rx) = mpsc::channel::<QueuedOp>(NODE_CHANNEL_CAPACITY);
         let node = Arc::new(KVNode {
             id: node_id.clone(),
             store: DashMap::new(),
-            ttl_queue: Mutex::new(PriorityQueue::new()),
+            expiries: expiry::ExpiryWheel::new(),
             tx,
+            ops_queued: AtomicU64::new(0),
+            ops_done: AtomicU64::new(0),
+            ops_done_notify: Notify::new(),
+            ops_fence: AtomicU64::new(0),
+            // Cleared when a task stops; a node without tasks has none to stop
+            ops_alive: AtomicBool::new(true),
+            sweeper_alive: AtomicBool::new(true),
+            task_restarts: AtomicU64::new(0),
+            ops_coalesced: AtomicU64::new(0),
+            apply_lag: consistency::ApplyLag::default(),
+            settings: self.state.node_settings.clone(),
+            tracking: self.state.tracking.clone(),
+            runtime,
+            task_times: runtime::NodeTaskTimes::default(),
         });
         let node_idx = self.nodes.len();
         self.nodes.push(node.clone());
 
-        let node_for_ops = node.clone();
-        tokio::spawn(async move {
-            while let Some(op) = rx.recv().await {
-                match op {
-                    KVOperation::Set(key, value, ttl) => {
-                        let expiry = ttl.map(|d| Instant::now() + d);
-                        node_for_ops.store.insert(key.clone(), KVEntry { value, expiry });
-                        if let Some(exp) = expiry {
-                            node_for_ops.ttl_queue.lock().unwrap().push(key, exp);
-                        }
-                    }
-                    KVOperation::Del(key) => {
-                        node_for_ops.store.remove(&key);
-                        node_for_ops.ttl_queue.lock().unwrap().remove(&key);
-                    }
-                }
-            }
-        });
-
         let ring = Arc::get_mut(&mut self.ring).unwrap();
         for i in 0..self.vnodes_per_node {
-            let vhash = xxh32(format!("{}:{}", node_id, i).as_bytes(), 0);
+            let vhash = xxh32(format!("{}:{}", node_id, i).as_bytes(), RING_HASH_SEED);
             ring.insert(vhash, node_idx);
         }
+        self.ring_version += 1;
+        (node, rx)
+    }
 
-        let ttl_node = node.clone();
-        tokio::spawn(async move {
-            loop {
-                tokio::time::sleep(Duration::from_millis(1)).await;
-                let mut queue = ttl_node.ttl_queue.lock().unwrap();
-                while let Some((key, expiry)) = queue.peek() {
-                    if *expiry > Instant::now() {
-                        break;
-                    }
-                    ttl_node.store.remove(key);
-                    queue.pop();
-                }
+    /// Enables write coalescing on replicas: each node's consumer waits up to
+    /// `window` for replica operations to accumulate, then applies only the
+    /// last operation per key. `None` applies every operation as it arrives.
+    pub fn set_write_coalescing(&self, window: Option<Duration>) {
+        let us = window.map_or(0, |w| w.as_micros().max(1) as u64);
+        self.state.node_settings.coalesce_window_us.store(us, Ordering::Relaxed);
+    }
+
+    /// Sets how many replicas writes wait for unless a call overrides it
+    pub fn set_ack_mode(&self, mode: AckMode) {
+        *self.state.ack_mode.lock().unwrap_or_else(PoisonError::into_inner) = mode;
+    }
+
+    pub fn ack_mode(&self) -> AckMode {
+        *self.state.ack_mode.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Caps the size of values written through the cluster. `None` removes the cap.
+    pub fn set_value_limit(&self, limit: Option<ValueLimit>) {
+        *self.state.value_limit.lock().unwrap_or_else(PoisonError::into_inner) = limit;
+    }
+
+    pub fn value_limit(&self) -> Option<ValueLimit> {
+        *self.state.value_limit.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Rejects writes while still serving reads, e.g. while a snapshot is taken
+    pub fn set_read_only(&self, read_only: bool) {
+        self.state.read_only.store(read_only, Ordering::Release);
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.state.read_only.load(Ordering::Acquire)
+    }
+
+    /// Rejects writes and reports the cluster as not ready, so load balancers
+    /// drain it, while reads keep being served
+    pub fn set_maintenance(&self, maintenance: bool) {
+        self.state.maintenance.store(maintenance, Ordering::Release);
+    }
+
+    pub fn in_maintenance(&self) -> bool {
+        self.state.maintenance.load(Ordering::Acquire)
+    }
+
+    /// Fails if a cluster-wide flag currently rejects writes. Writes
+    /// replicated from another cluster are let through so the two do not diverge.
+    fn check_writable(&self, options: &WriteOptions) -> Result<(), KVError> {
+        if options.replicated {
+            return Ok(());
+        }
+        if self.in_maintenance() {
+            return Err(KVError::Maintenance);
+        }
+        if self.is_read_only() {
+            return Err(KVError::ReadOnly);
+        }
+        Ok(())
+    }
+
+    /// Gives every key in `namespace` a sliding TTL, as if written with
+    /// `WriteOptions::sliding_ttl`. A key's namespace is the part of the key
+    /// before its first ':'.
+    pub fn set_sliding_namespace(&self, namespace: &str, sliding: bool) {
+        let mut namespaces = self
+            .state
+            .sliding_namespaces
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+        if sliding {
+            namespaces.insert(namespace.to_string());
+        } else {
+            namespaces.remove(namespace);
+        }
+    }
+
+    /// Namespaces whose keys have a sliding TTL, sorted
+    pub fn sliding_namespaces(&self) -> Vec<String> {
+        let namespaces = self.state.sliding_namespaces.read().unwrap_or_else(PoisonError::into_inner);
+        let mut namespaces: Vec<String> = namespaces.iter().cloned().collect();
+        namespaces.sort();
+        namespaces
+    }
+
+    fn slides_namespace(&self, key: &[u8]) -> bool {
+        let Some(Ok(namespace)) = namespace_of(key).map(std::str::from_utf8) else {
+            return false;
+        };
+        self.state
+            .sliding_namespaces
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .contains(namespace)
+    }
+
+    /// Records the address clients should use to reach a node directly.
+    /// Returns false if no node has that id.
+    pub fn set_node_addr(&mut self, node_id: &str, addr: String) -> bool {
+        if !self.nodes.iter().any(|n| n.id == node_id) {
+            return false;
+        }
+        self.node_addrs.insert(node_id.to_string(), addr);
+        self.ring_version += 1;
+        true
+    }
+
+    /// The nodes `key` belongs on, whatever their status: its owner on the
+    /// ring, then the next distinct nodes clockwise, up to the replication factor
+    fn preference_list(&self, key: &[u8]) -> Vec<usize> {
+        let khash = xxh32(key, RING_HASH_SEED);
+        let wanted = self.replication_factor.min(self.nodes.len());
+        let mut owners: Vec<usize> = Vec::with_capacity(wanted);
+        for (_, idx) in self.ring.range(khash..).chain(self.ring.range(..khash)) {
+            if owners.len() == wanted {
+                break;
             }
-        });
+            if !owners.contains(idx) {
+                owners.push(*idx);
+            }
+        }
+        owners
     }
 
-    fn get_nodes(&self, key: &str) -> Vec<Arc<KVNode>> {
-        let khash = xxh32(key.as_bytes(), 0);
-        let mut nodes = Vec::with_capacity(self.replication_factor);
-        let mut iter = self.ring.range(khash..).chain(self.ring.iter());
-        for _ in 0..self.replication_factor {
-            if let Some((_, idx)) = iter.next() {
-                nodes.push(self.nodes[*idx].clone());
+    /// Index of the node serving `key` as primary: its owner on the ring,
+    /// or the next replica that is up while the owner is down
+    fn primary_idx(&self, key: &[u8]) -> Option<usize> {
+        if !self.state.failover.is_degraded() {
+            return ring_owner(&self.ring, key);
+        }
+        self.route(key)?.first().copied()
+    }
+
+    /// The nodes holding `key`: its primary, then its replicas. Nodes that
+    /// are down are left out. Fails if every node the key belongs on is down.
+    fn get_nodes(&self, key: &[u8]) -> Result<Vec<Arc<KVNode>>, KVError> {
+        match self.route(key) {
+            Some(owners) => Ok(owners.into_iter().map(|idx| self.nodes[idx].clone()).collect()),
+            None => {
+                let owner = ring_owner(&self.ring, key).map(|idx| self.nodes[idx].id.clone());
+                Err(KVError::NodeUnavailable(owner.unwrap_or_default()))
             }
         }
-        nodes
     }
 
-    pub async fn set(&self, key: String, value: Vec<u8>, ttl: Option<Duration>) {
-        let nodes = self.get_nodes(&key);
+    pub async fn set(&self, key: impl Into<Key>, value: Vec<u8>, ttl: Option<Duration>) -> Result<(), KVError> {
+        self.set_with_options(key, value, ttl, WriteOptions::default()).await
+    }
+
+    /// Dispatches an operation to `replicas` and waits for as many of them to
+    /// apply it as `required`
+    async fn replicate<F>(
+        &self,
+        replicas: &[Arc<KVNode>],
+        required: usize,
+        deadline: Option<Instant>,
+        make_op: F,
+    ) -> Result<(), KVError>
+    where
+        F: Fn(Option<Ack>) -> KVOperation,
+    {
+        let (ack_tx, ack_rx) = match required {
+            0 => (None, None),
+            _ => {
+                let (ack_tx, ack_rx) = mpsc::channel(replicas.len());
+                (Some(ack_tx), Some(ack_rx))
+            }
+        };
+        let ops: Vec<_> = replicas
+            .iter()
+            .map(|replica| (replica.clone(), make_op(ack_tx.clone())))
+            .collect();
+        drop(ack_tx);
+        // Detached, so replicas get the operation even if the caller stops
+        // waiting for it
+        let dispatch = interrupt::detach(async move {
+            for (replica, op) in ops {
+                replica.send_op(op, deadline).await?;
+            }
+            Ok::<_, KVError>(())
+        });
+        self.state
+            .interrupts
+            .watch(async {
+                dispatch.await?;
+                match ack_rx {
+                    Some(acks) => await_acks(acks, required, deadline).await,
+                    None => Ok(()),
+                }
+            })
+            .await
+    }
+
+    /// Replica acknowledgements a write to `nodes` must wait for
+    fn required_acks(&self, nodes: &[Arc<KVNode>], options: &WriteOptions) -> Result<usize, KVError> {
+        options
+            .ack
+            .unwrap_or_else(|| self.ack_mode())
+            .required(nodes.len().saturating_sub(1))
+    }
+
+    /// Stores a value. Replica dispatch is bounded by `options.timeout`, and
+    /// the call returns once `options.ack` (or the cluster's ack mode) is met.
+    /// Values over the cluster's value limit are rejected or truncated.
+    pub async fn set_with_options(
+        &self,
+        key: impl Into<Key>,
+        value: Vec<u8>,
+        ttl: Option<Duration>,
+        options: WriteOptions,
+    ) -> Result<(), KVError> {
+        self.store_value(key.into(), value, ttl, options, false).await
+    }
+
+    /// Stores a value as `set_with_options` does. With `if_absent`, fails
+    /// with `KeyExists` instead if the key holds a live value, checked in
+    /// the same step as the store.
+    pub(crate) async fn store_value(
+        &self,
+        key: Key,
+        mut value: Vec<u8>,
+        ttl: Option<Duration>,
+        options: WriteOptions,
+        if_absent: bool,
+    ) -> Result<(), KVError> {
+        self.check_writable(&options)?;
+        let limit = self.value_limit();
+        let truncated_from = match &limit {
+            Some(limit) => limit.enforce(&mut value)?,
+            None => None,
+        };
+        let deadline = options.deadline();
+        let nodes = self.get_nodes(key.as_bytes())?;
+        let required = self.required_acks(&nodes, &options)?;
         let primary = &nodes[0];
-        let expiry = ttl.map(|d| Instant::now() + d);
-        primary.store.insert(key.clone(), KVEntry { value: value.clone(), expiry });
-        if let Some(exp) = expiry {
-            primary.ttl_queue.lock().unwrap().push(key.clone(), exp);
+        primary.enter().await?;
+        let admission = self.admit_key(key.as_bytes(), &options)?;
+        let entry_ttl = ttl.map(|duration| Ttl::new(duration, options.sliding_ttl));
+        if if_absent {
+            if !primary.put_if_absent(key.clone(), value.clone(), entry_ttl, Instant::now()) {
+                return Err(KVError::KeyExists(key_name(key.as_bytes())));
+            }
+        } else {
+            primary.put(key.clone(), value.clone(), entry_ttl, Instant::now());
         }
-        for replica in &nodes[1..] {
-            let _ = replica.tx.send(KVOperation::Set(key.clone(), value.clone(), ttl)).await;
+        let evicting = self.evict(admission, options);
+        self.publish_change(|| ChangeEvent::set(key.clone(), value.clone(), ttl, options.replicated));
+        let replicated = self
+            .replicate(&nodes[1..], required, deadline, |ack| {
+                KVOperation::Set(key.clone(), value.clone(), entry_ttl, ack)
+            })
+            .await;
+        evicting.await;
+        replicated?;
+        match (limit, truncated_from) {
+            (Some(limit), Some(size)) => Err(limit.exceeded(size, true)),
+            _ => Ok(()),
         }
     }
 
-    pub fn get(&self, key: &str) -> Option<Vec<u8>> {
-        let nodes = self.get_nodes(key);
-        let primary = &nodes[0];
-        primary.store.get(key).and_then(|entry| {
-            if let Some(expiry) = entry.expiry {
-                if expiry <= Instant::now() {
-                    primary.store.remove(key);
-                    primary.ttl_queue.lock().unwrap().remove(key);
-                    return None;
-                }
+    /// Reads the live entry for `key` from its primary and records the
+    /// access, removing the entry if it has expired. Reading a key with a
+    /// sliding TTL restarts the TTL.
+    fn read_entry(&self, key: &[u8]) -> Option<KVEntry> {
+        let mut entry = self.lookup_entry(key, true)?;
+        let ttl = entry.ttl.filter(|ttl| ttl.sliding || self.slides_namespace(key));
+        if let Some(ttl) = ttl {
+            let now = Instant::now();
+            if self.nodes[self.primary_idx(key)?].refresh_ttl(key, ttl.duration, now) {
+                entry.expiry = Some(now + ttl.duration);
+                self.propagate_refresh(key, ttl.duration);
+            }
+        }
+        Some(entry)
+    }
+
+    /// Passes a TTL restarted by a read on to the key's replicas and the
+    /// change feed. Reads do not wait on replicas, so a replica whose channel
+    /// is full misses the refresh.
+    fn propagate_refresh(&self, key: &[u8], duration: Duration) {
+        let replicas = self.get_nodes(key).unwrap_or_default();
+        for replica in replicas.iter().skip(1) {
+            let _ = replica.try_send_op(KVOperation::Touch(Key::from(key), duration, None));
+        }
+        self.publish_change(|| ChangeEvent::touch(Key::from(key), false));
+    }
+
+    /// Like `read_entry`, without counting as an access
+    fn peek_entry(&self, key: &[u8]) -> Option<KVEntry> {
+        self.lookup_entry(key, false)
+    }
+
+    fn lookup_entry(&self, key: &[u8], record_access: bool) -> Option<KVEntry> {
+        let primary = &self.nodes[self.primary_idx(key)?];
+        // Clone out of the map so no shard lock is held while removing
+        let entry = primary.store.get(key).map(|entry| {
+            if record_access {
+                entry.meta.record_access();
+            }
+            entry.clone()
+        })?;
+        if entry.is_expired(Instant::now()) {
+            primary.remove_entry(key);
+            return None;
+        }
+        Some(entry)
+    }
+
+    /// `read_entry` behind the primary's injected faults. A primary that is
+    /// down reads as a miss.
+    async fn read(&self, key: &[u8]) -> Option<KVEntry> {
+        let primary = &self.nodes[self.primary_idx(key)?];
+        primary.enter().await.ok()?;
+        self.read_entry(key)
+    }
+
+    /// `peek_entry` behind the primary's injected faults
+    async fn peek(&self, key: &[u8]) -> Option<KVEntry> {
+        let primary = &self.nodes[self.primary_idx(key)?];
+        primary.enter().await.ok()?;
+        self.peek_entry(key)
+    }
+
+    pub async fn get(&self, key: impl AsRef<[u8]>) -> Option<Vec<u8>> {
+        self.read(key.as_ref()).await.map(|entry| entry.value.into())
+    }
+
+    pub async fn contains_key(&self, key: impl AsRef<[u8]>) -> bool {
+        self.read(key.as_ref()).await.is_some()
+    }
+
+    /// Counts how many of `keys` exist. Repeated keys are counted each time.
+    pub async fn exists<K: AsRef<[u8]>>(&self, keys: &[K]) -> usize {
+        let mut count = 0;
+        for key in keys {
+            if self.read(key.as_ref()).await.is_some() {
+                count += 1;
             }
-            Some(entry.value.clone())
+        }
+        count
+    }
+
+    /// Type of the value stored at `key`, inferred from its bytes
+    pub async fn key_type(&self, key: impl AsRef<[u8]>) -> Option<ValueType> {
+        self.read(key.as_ref()).await.map(|entry| ValueType::of(&entry.value))
+    }
+
+    /// Remaining time to live of `key`: `None` if the key does not exist,
+    /// `Some(None)` if it exists without a TTL. Looking up the TTL does not
+    /// count as an access, so it does not restart a sliding TTL.
+    pub async fn ttl(&self, key: impl AsRef<[u8]>) -> Option<Option<Duration>> {
+        self.peek(key.as_ref())
+            .await
+            .map(|entry| entry.expiry.map(|expiry| expiry.saturating_duration_since(Instant::now())))
+    }
+
+    /// Synchronous variant of `get`, for embedders calling from outside an
+    /// async context
+    #[cfg(feature = "sync")]
+    pub fn get_sync(&self, key: impl AsRef<[u8]>) -> Option<Vec<u8>> {
+        self.read_entry(key.as_ref()).map(|entry| entry.value.into())
+    }
+
+    /// Synchronous variant of `contains_key`
+    #[cfg(feature = "sync")]
+    pub fn contains_key_sync(&self, key: impl AsRef<[u8]>) -> bool {
+        self.read_entry(key.as_ref()).is_some()
+    }
+
+    /// Synchronous variant of `ttl`
+    #[cfg(feature = "sync")]
+    pub fn ttl_sync(&self, key: impl AsRef<[u8]>) -> Option<Option<Duration>> {
+        self.peek_entry(key.as_ref())
+            .map(|entry| entry.expiry.map(|expiry| expiry.saturating_duration_since(Instant::now())))
+    }
+
+    pub async fn del(&self, key: impl AsRef<[u8]>) -> Result<(), KVError> {
+        self.del_with_options(key, WriteOptions::default()).await
+    }
+
+    /// Deletes a value, with the same timeout and acknowledgement options as
+    /// `set_with_options`. In a namespace that soft-deletes, the value moves
+    /// to the trash.
+    pub async fn del_with_options(&self, key: impl AsRef<[u8]>, options: WriteOptions) -> Result<(), KVError> {
+        self.remove(key.as_ref(), options, true).await
+    }
+
+    /// Deletes a value, keeping it in the trash if `trash` is set and the
+    /// key's namespace soft-deletes
+    async fn remove(&self, key: &[u8], options: WriteOptions, trash: bool) -> Result<(), KVError> {
+        self.check_writable(&options)?;
+        let deadline = options.deadline();
+        let nodes = self.get_nodes(key)?;
+        let required = self.required_acks(&nodes, &options)?;
+        let primary = &nodes[0];
+        primary.enter().await?;
+        let removed = primary.remove_entry(key);
+        self.state.quotas.forget(key);
+        if let Some((_, entry)) = removed.filter(|_| trash) {
+            self.move_to_trash(key, entry);
+        }
+        self.publish_change(|| ChangeEvent::del(Key::from(key), options.replicated));
+        self.replicate(&nodes[1..], required, deadline, |ack| {
+            KVOperation::Del(Key::from(key), ack)
         })
+        .await
     }
 
-    pub async fn del(&self, key: &str) {
-        let nodes = self.get_nodes(key);
+    /// Counts as an access to `key` and restarts its TTL from now, using the
+    /// duration it was written with. Returns false if the key does not exist.
+    pub async fn touch(&self, key: impl AsRef<[u8]>) -> Result<bool, KVError> {
+        self.touch_with_options(key, WriteOptions::default()).await
+    }
+
+    /// Touches a key, with the same timeout and acknowledgement options as
+    /// `set_with_options` for passing the new expiry to replicas
+    pub async fn touch_with_options(&self, key: impl AsRef<[u8]>, options: WriteOptions) -> Result<bool, KVError> {
+        let key = key.as_ref();
+        let nodes = self.get_nodes(key)?;
+        let required = self.required_acks(&nodes, &options)?;
         let primary = &nodes[0];
-        primary.store.remove(key);
-        primary.ttl_queue.lock().unwrap().remove(key);
-        for replica in &nodes[1..] {
-            let _ = replica.tx.send(KVOperation::Del(key.to_string())).await;
+        primary.enter().await?;
+        let Some(entry) = self.lookup_entry(key, true) else {
+            return Ok(false);
+        };
+        let Some(ttl) = entry.ttl else {
+            return Ok(true);
+        };
+        primary.refresh_ttl(key, ttl.duration, Instant::now());
+        self.publish_change(|| ChangeEvent::touch(Key::from(key), options.replicated));
+        self.replicate(&nodes[1..], required, options.deadline(), |ack| {
+            KVOperation::Touch(Key::from(key), ttl.duration, ack)
+        })
+        .await?;
+        Ok(true)
+    }
+
+    /// Restarts the TTL of `key` so it expires `ttl` from now, but only if
+    /// its value is `expected`. The check and the extension are one step on
+    /// the key's primary, so a lease holder renewing its lease cannot extend
+    /// one that expired and was taken by someone else in between. Returns
+    /// whether the TTL was extended.
+    pub async fn extend_ttl_if(&self, key: impl AsRef<[u8]>, expected: &[u8], ttl: Duration) -> Result<bool, KVError> {
+        self.extend_ttl_if_with_options(key, expected, ttl, WriteOptions::default()).await
+    }
+
+    /// Conditionally extends a TTL, with the same timeout and acknowledgement
+    /// options as `set_with_options` for passing the new expiry to replicas
+    pub async fn extend_ttl_if_with_options(
+        &self,
+        key: impl AsRef<[u8]>,
+        expected: &[u8],
+        ttl: Duration,
+        options: WriteOptions,
+    ) -> Result<bool, KVError> {
+        let key = key.as_ref();
+        self.check_writable(&options)?;
+        let nodes = self.get_nodes(key)?;
+        let required = self.required_acks(&nodes, &options)?;
+        let primary = &nodes[0];
+        primary.enter().await?;
+        let now = Instant::now();
+        let expiry = now + ttl;
+        let entry_ttl = {
+            let Some(mut entry) = primary.store.get_mut(key) else {
+                return Ok(false);
+            };
+            if entry.is_expired(now) || entry.value != expected {
+                return Ok(false);
+            }
+            let entry_ttl = Ttl::new(ttl, entry.ttl.is_some_and(|ttl| ttl.sliding));
+            entry.ttl = Some(entry_ttl);
+            let previous = entry.expiry.replace(expiry);
+            primary.track_expiry(entry.key(), previous, Some(expiry));
+            entry_ttl
+        };
+        // Replicas and other clusters get the new TTL as a rewrite of the
+        // same value, since a touch would restart the TTL the key had before
+        self.publish_change(|| ChangeEvent::set(Key::from(key), expected.to_vec(), Some(ttl), options.replicated));
+        self.replicate(&nodes[1..], required, options.deadline(), |ack| {
+            KVOperation::Set(Key::from(key), expected.to_vec(), Some(entry_ttl), ack)
+        })
+        .await?;
+        Ok(true)
+    }
+
+    /// Moves the value at `old` to `new`, keeping its remaining TTL and
+    /// replacing any value at `new`
+    pub async fn rename(&self, old: impl AsRef<[u8]>, new: impl AsRef<[u8]>) -> Result<(), KVError> {
+        self.rename_with_options(old, new, WriteOptions::default()).await
+    }
+
+    /// Renames a key with the same timeout and acknowledgement options as
+    /// `set_with_options`. The two keys may live on different nodes, so the
+    /// move is a set of `new` followed by a delete of `old`; a concurrent
+    /// writer can briefly observe both keys.
+    pub async fn rename_with_options(
+        &self,
+        old: impl AsRef<[u8]>,
+        new: impl AsRef<[u8]>,
+        options: WriteOptions,
+    ) -> Result<(), KVError> {
+        let (old, new) = (old.as_ref(), new.as_ref());
+        self.check_writable(&options)?;
+        if old == new {
+            return match self.read(old).await {
+                Some(_) => Ok(()),
+                None => Err(KVError::KeyNotFound(key_name(old))),
+            };
         }
+        self.copy_with_options(old, new, true, options).await?;
+        // The value lives on under `new`, so there is nothing to restore
+        self.remove(old, options, false).await
+    }
+
+    /// Copies the value at `src` to `dst`, keeping its remaining TTL. Fails
+    /// with `KeyExists` if `dst` exists and `replace` is false.
+    pub async fn copy(&self, src: impl AsRef<[u8]>, dst: impl AsRef<[u8]>, replace: bool) -> Result<(), KVError> {
+        self.copy_with_options(src, dst, replace, WriteOptions::default()).await
+    }
+
+    /// Copies a key with the same timeout and acknowledgement options as `set_with_options`
+    pub async fn copy_with_options(
+        &self,
+        src: impl AsRef<[u8]>,
+        dst: impl AsRef<[u8]>,
+        replace: bool,
+        options: WriteOptions,
+    ) -> Result<(), KVError> {
+        let (src, dst) = (src.as_ref(), dst.as_ref());
+        let entry = self
+            .read(src)
+            .await
+            .ok_or_else(|| KVError::KeyNotFound(key_name(src)))?;
+        if src == dst {
+            return if replace { Ok(()) } else { Err(KVError::KeyExists(key_name(dst))) };
+        }
+        if !replace && self.read(dst).await.is_some() {
+            return Err(KVError::KeyExists(key_name(dst)));
+        }
+        let ttl = entry
+            .expiry
+            .map(|expiry| expiry.saturating_duration_since(Instant::now()));
+        let options = WriteOptions {
+            sliding_ttl: options.sliding_ttl || entry.ttl.is_some_and(|ttl| ttl.sliding),
+            ..options
+        };
+        self.set_with_options(dst, entry.value.into(), ttl, options).await
     }
 
     // New methods for handling JSON documents
 
-    /// Stores a serialized JSON document
-    pub async fn set_json<T: Serialize>(&self, key: String, value: &T, ttl: Option<Duration>) -> Result<(), JsonError> {
-        let json_bytes = serde_json::to_vec(value)?;
-        self.set(key, json_bytes, ttl).await;
-        Ok(())
+    /// Stores a serialized JSON document, encrypting the fields its namespace
+    /// declares sensitive
+    pub async fn set_json<T: Serialize>(&self, key: String, value: &T, ttl: Option<Duration>) -> Result<(), KVError> {
+        let json_bytes = self.encode_json_document(&key, &serde_json::to_value(value)?)?;
+        self.set(key, json_bytes, ttl).await
     }
 
     /// Retrieves a JSON document and deserializes it to the specified type
-    pub fn get_json<T: for<'de> Deserialize<'de>>(&self, key: &str) -> Result<Option<T>, JsonError> {
-        match self.get(key) {
+    pub async fn get_json<T: for<'de> Deserialize<'de>>(&self, key: &str) -> Result<Option<T>, JsonError> {
+        match self.get(key).await {
             Some(bytes) => {
                 let value = serde_json::from_slice(&bytes)?;
                 Ok(Some(value))
@@ -171,15 +1523,26 @@ impl KVCluster {
     }
 
     /// Stores a generic JSON document (serde_json::Value)
-    pub async fn set_json_value(&self, key: String, value: &JsonValue, ttl: Option<Duration>) -> Result<(), JsonError> {
-        let json_bytes = serde_json::to_vec(value)?;
-        self.set(key, json_bytes, ttl).await;
-        Ok(())
+    pub async fn set_json_value(&self, key: String, value: &JsonValue, ttl: Option<Duration>) -> Result<(), KVError> {
+        let json_bytes = self.encode_json_document(&key, value)?;
+        self.set(key, json_bytes, ttl).await
+    }
+
+    /// Stores a generic JSON document, bounding replica dispatch by `options.timeout`
+    pub async fn set_json_value_with_options(
+        &self,
+        key: String,
+        value: &JsonValue,
+        ttl: Option<Duration>,
+        options: WriteOptions,
+    ) -> Result<(), KVError> {
+        let json_bytes = self.encode_json_document(&key, value)?;
+        self.set_with_options(key, json_bytes, ttl, options).await
     }
 
     /// Retrieves a JSON document as a generic value (serde_json::Value)
-    pub fn get_json_value(&self, key: &str) -> Result<Option<JsonValue>, JsonError> {
-        match self.get(key) {
+    pub async fn get_json_value(&self, key: &str) -> Result<Option<JsonValue>, JsonError> {
+        match self.get(key).await {
             Some(bytes) => {
                 let value = serde_json::from_slice(&bytes)?;
                 Ok(Some(value))
@@ -0,0 +1,116 @@
+use std::str::FromStr;
+
+use crate::KVError;
+
+/// What to do with a value larger than the configured maximum
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OversizePolicy {
+    /// Refuse the write
+    #[default]
+    Reject,
+    /// Store the first `max_bytes` bytes and still report the write as an
+    /// error, so the caller knows the stored value is incomplete
+    Truncate,
+}
+
+impl FromStr for OversizePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "reject" => Ok(OversizePolicy::Reject),
+            "truncate" => Ok(OversizePolicy::Truncate),
+            _ => Err(format!("invalid oversize policy '{}': expected reject or truncate", s)),
+        }
+    }
+}
+
+/// Upper bound on the size of a single stored value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ValueLimit {
+    pub max_bytes: usize,
+    pub policy: OversizePolicy,
+}
+
+impl ValueLimit {
+    pub fn new(max_bytes: usize, policy: OversizePolicy) -> Self {
+        ValueLimit { max_bytes, policy }
+    }
+
+    /// Applies the limit to a value about to be written. Returns an error if
+    /// the write must not happen, or the original size if the value was
+    /// truncated and the write should be reported as failed once stored.
+    pub(crate) fn enforce(&self, value: &mut Vec<u8>) -> Result<Option<usize>, KVError> {
+        let size = value.len();
+        if size <= self.max_bytes {
+            return Ok(None);
+        }
+        match self.policy {
+            OversizePolicy::Reject => Err(self.exceeded(size, false)),
+            OversizePolicy::Truncate => {
+                value.truncate(self.max_bytes);
+                Ok(Some(size))
+            }
+        }
+    }
+
+    pub(crate) fn exceeded(&self, size: usize, truncated: bool) -> KVError {
+        KVError::ValueTooLarge {
+            size,
+            max: self.max_bytes,
+            truncated,
+        }
+    }
+}
+
+/// Request limits enforced by the HTTP API
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ApiLimits {
+    /// Largest accepted request body, in bytes
+    pub max_body_bytes: usize,
+    /// Deepest accepted nesting of JSON arrays and objects in a request body
+    pub max_json_depth: usize,
+    /// Longest accepted key, in bytes
+    pub max_key_bytes: usize,
+}
+
+impl Default for ApiLimits {
+    fn default() -> Self {
+        ApiLimits {
+            max_body_bytes: 2 << 20,
+            max_json_depth: 64,
+            max_key_bytes: 1024,
+        }
+    }
+}
+
+/// Returns true if `json` nests arrays and objects deeper than `max_depth`.
+/// Only brackets outside string literals count; the input need not be valid.
+pub(crate) fn json_depth_exceeds(json: &[u8], max_depth: usize) -> bool {
+    let mut depth = 0usize;
+    let mut in_string = false;
+    let mut escaped = false;
+    for &b in json {
+        if in_string {
+            match b {
+                _ if escaped => escaped = false,
+                b'\\' => escaped = true,
+                b'"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match b {
+            b'"' => in_string = true,
+            b'{' | b'[' => {
+                depth += 1;
+                if depth > max_depth {
+                    return true;
+                }
+            }
+            b'}' | b']' => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+    false
+}
@@ -0,0 +1,203 @@
+//! Leases for coordinating clients. A lock is the key `lock:<name>` holding
+//! a random token handed to whoever acquired it; the lock is released when
+//! its holder says so or when its TTL runs out. Every step checks the token
+//! on the key's primary, so a holder whose lease lapsed cannot renew or
+//! release a lock someone else has since acquired.
+
+use dashmap::mapref::entry::Entry;
+use std::time::Duration;
+use tokio::time::Instant;
+
+use crate::changes::ChangeEvent;
+use crate::{KVCluster, KVEntry, KVError, KVOperation, Key, Ttl, WriteOptions};
+
+/// Prefix of the keys locks are stored under
+pub const LOCK_KEY_PREFIX: &str = "lock:";
+
+fn lock_key(name: &str) -> Key {
+    Key::from(format!("{}{}", LOCK_KEY_PREFIX, name))
+}
+
+impl KVCluster {
+    /// Takes the lock `name` for `ttl` if no one holds it, returning the
+    /// token that renews and releases it. Returns `None` if the lock is held.
+    pub async fn acquire_lock(&self, name: &str, ttl: Duration) -> Result<Option<String>, KVError> {
+        self.acquire_lock_with_options(name, ttl, WriteOptions::default()).await
+    }
+
+    /// Acquires a lock with the same timeout and acknowledgement options as
+    /// `set_with_options`
+    pub async fn acquire_lock_with_options(
+        &self,
+        name: &str,
+        ttl: Duration,
+        options: WriteOptions,
+    ) -> Result<Option<String>, KVError> {
+        let key = lock_key(name);
+        self.check_writable(&options)?;
+        let nodes = self.get_nodes(key.as_bytes())?;
+        let required = self.required_acks(&nodes, &options)?;
+        let primary = &nodes[0];
+        primary.enter().await?;
+        let admission = self.admit_key(key.as_bytes(), &options)?;
+        let token = format!("{:032x}", rand::random::<u128>());
+        let value = token.clone().into_bytes();
+        let now = Instant::now();
+        let expiry = now + ttl;
+        let entry_ttl = Ttl::new(ttl, false);
+        match primary.store.entry(key.clone()) {
+            Entry::Occupied(mut occupied) if occupied.get().is_expired(now) => {
+                let previous = occupied.get().expiry;
+                occupied.get_mut().overwrite(value.clone(), Some(expiry), Some(entry_ttl), now);
+                primary.track_expiry(occupied.key(), previous, Some(expiry));
+            }
+            Entry::Occupied(_) => return Ok(None),
+            Entry::Vacant(vacant) => {
+                primary.track_expiry(vacant.key(), None, Some(expiry));
+                vacant.insert(KVEntry::new(value.clone(), Some(expiry), Some(entry_ttl)));
+            }
+        }
+        let evicting = self.evict(admission, options);
+        self.publish_change(|| ChangeEvent::set(key.clone(), value.clone(), Some(ttl), options.replicated));
+        let replicated = self
+            .replicate(&nodes[1..], required, options.deadline(), |ack| {
+                KVOperation::Set(key.clone(), value.clone(), Some(entry_ttl), ack)
+            })
+            .await;
+        evicting.await;
+        replicated?;
+        Ok(Some(token))
+    }
+
+    /// Extends the lock `name` to expire `ttl` from now if `token` still
+    /// holds it. Returns false if the lock has expired or changed hands.
+    pub async fn renew_lock(&self, name: &str, token: &str, ttl: Duration) -> Result<bool, KVError> {
+        self.renew_lock_with_options(name, token, ttl, WriteOptions::default()).await
+    }
+
+    pub async fn renew_lock_with_options(
+        &self,
+        name: &str,
+        token: &str,
+        ttl: Duration,
+        options: WriteOptions,
+    ) -> Result<bool, KVError> {
+        self.extend_ttl_if_with_options(lock_key(name), token.as_bytes(), ttl, options)
+            .await
+    }
+
+    /// Releases the lock `name` if `token` still holds it. Returns false if
+    /// the lock has expired or changed hands.
+    pub async fn release_lock(&self, name: &str, token: &str) -> Result<bool, KVError> {
+        self.release_lock_with_options(name, token, WriteOptions::default()).await
+    }
+
+    pub async fn release_lock_with_options(
+        &self,
+        name: &str,
+        token: &str,
+        options: WriteOptions,
+    ) -> Result<bool, KVError> {
+        let key = lock_key(name);
+        self.check_writable(&options)?;
+        let nodes = self.get_nodes(key.as_bytes())?;
+        let required = self.required_acks(&nodes, &options)?;
+        let primary = &nodes[0];
+        primary.enter().await?;
+        let now = Instant::now();
+        let released = primary.store.remove_if(key.as_bytes(), |_, entry| {
+            !entry.is_expired(now) && entry.value == token.as_bytes()
+        });
+        let Some((_, entry)) = released else {
+            return Ok(false);
+        };
+        primary.track_expiry(&key, entry.expiry, None);
+        self.state.quotas.forget(key.as_bytes());
+        self.publish_change(|| ChangeEvent::del(key.clone(), options.replicated));
+        self.replicate(&nodes[1..], required, options.deadline(), |ack| {
+            KVOperation::Del(key.clone(), ack)
+        })
+        .await?;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cluster() -> KVCluster {
+        let mut cluster = KVCluster::new(16, 3);
+        for i in 0..3 {
+            cluster.add_node(format!("node{}", i));
+        }
+        cluster
+    }
+
+    const TTL: Duration = Duration::from_millis(100);
+
+    #[tokio::test(start_paused = true)]
+    async fn only_the_holder_releases_a_lock() {
+        let cluster = cluster();
+        let token = cluster.acquire_lock("job", TTL).await.unwrap().unwrap();
+        assert_eq!(cluster.acquire_lock("job", TTL).await.unwrap(), None);
+
+        // Other locks are independent
+        assert!(cluster.acquire_lock("other", TTL).await.unwrap().is_some());
+
+        assert!(!cluster.release_lock("job", "not-the-token").await.unwrap());
+        assert_eq!(cluster.acquire_lock("job", TTL).await.unwrap(), None);
+        assert!(cluster.release_lock("job", &token).await.unwrap());
+        assert!(!cluster.release_lock("job", &token).await.unwrap());
+
+        let next = cluster.acquire_lock("job", TTL).await.unwrap().unwrap();
+        assert_ne!(next, token);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn an_expired_lock_can_be_taken_and_its_old_holder_is_locked_out() {
+        let cluster = cluster();
+        let stale = cluster.acquire_lock("job", TTL).await.unwrap().unwrap();
+        tokio::time::advance(TTL + Duration::from_millis(1)).await;
+
+        let token = cluster.acquire_lock("job", TTL).await.unwrap().unwrap();
+        assert!(!cluster.renew_lock("job", &stale, TTL).await.unwrap());
+        assert!(!cluster.release_lock("job", &stale).await.unwrap());
+        assert!(cluster.renew_lock("job", &token, TTL).await.unwrap());
+        assert!(cluster.release_lock("job", &token).await.unwrap());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn renewing_extends_a_lock_from_now() {
+        let cluster = cluster();
+        let token = cluster.acquire_lock("job", TTL).await.unwrap().unwrap();
+        tokio::time::advance(Duration::from_millis(60)).await;
+        assert!(cluster.renew_lock("job", &token, TTL).await.unwrap());
+
+        // Past the first TTL, but within the renewed one
+        tokio::time::advance(Duration::from_millis(60)).await;
+        assert_eq!(cluster.acquire_lock("job", TTL).await.unwrap(), None);
+
+        tokio::time::advance(Duration::from_millis(50)).await;
+        assert!(!cluster.renew_lock("job", &token, TTL).await.unwrap());
+        assert!(cluster.acquire_lock("job", TTL).await.unwrap().is_some());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn replicas_follow_acquire_and_release() {
+        let cluster = cluster();
+        let key = lock_key("job");
+        let token = cluster.acquire_lock("job", TTL).await.unwrap().unwrap();
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        for node in &cluster.nodes {
+            let held = node.store.get(key.as_bytes()).map(|entry| entry.value.to_vec());
+            assert_eq!(held, Some(token.clone().into_bytes()), "{}", node.id);
+        }
+
+        assert!(cluster.release_lock("job", &token).await.unwrap());
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        for node in &cluster.nodes {
+            assert!(node.store.get(key.as_bytes()).is_none(), "{}", node.id);
+        }
+    }
+}
@@ -0,0 +1,86 @@
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::time::Instant;
+
+use crate::changes::unix_millis;
+use crate::{KVCluster, ValueType};
+
+/// Creation time and access statistics of a stored entry. Reads update the
+/// access fields through a shared reference, so recording one costs two
+/// relaxed atomic stores.
+#[derive(Debug)]
+pub(crate) struct EntryMeta {
+    created_at_ms: u64,
+    /// Unix milliseconds of the last read, 0 if never read
+    last_accessed_ms: AtomicU64,
+    access_count: AtomicU64,
+}
+
+impl EntryMeta {
+    pub(crate) fn new() -> Self {
+        EntryMeta {
+            created_at_ms: unix_millis(),
+            last_accessed_ms: AtomicU64::new(0),
+            access_count: AtomicU64::new(0),
+        }
+    }
+
+    pub(crate) fn created_at_ms(&self) -> u64 {
+        self.created_at_ms
+    }
+
+    pub(crate) fn record_access(&self) {
+        self.last_accessed_ms.store(unix_millis(), Ordering::Relaxed);
+        self.access_count.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+impl Clone for EntryMeta {
+    fn clone(&self) -> Self {
+        EntryMeta {
+            created_at_ms: self.created_at_ms,
+            last_accessed_ms: AtomicU64::new(self.last_accessed_ms.load(Ordering::Relaxed)),
+            access_count: AtomicU64::new(self.access_count.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+/// Metadata about a stored key, as reported by `object_info`
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct ObjectInfo {
+    /// When the key was created, in Unix milliseconds. Overwriting a live key
+    /// keeps its creation time.
+    pub created_at_ms: u64,
+    /// When the key was last read, in Unix milliseconds
+    pub last_accessed_ms: Option<u64>,
+    /// Reads served since the key was created
+    pub access_count: u64,
+    /// Size of the value in bytes
+    pub size: usize,
+    #[serde(rename = "type")]
+    pub value_type: ValueType,
+    /// Remaining time to live in milliseconds, if the key has one
+    pub ttl_ms: Option<u64>,
+}
+
+impl KVCluster {
+    /// Metadata about `key` on its primary. Looking it up does not count as
+    /// an access.
+    pub async fn object_info(&self, key: impl AsRef<[u8]>) -> Option<ObjectInfo> {
+        let key = key.as_ref();
+        let primary = &self.nodes[self.primary_idx(key)?];
+        primary.enter().await.ok()?;
+        let entry = self.peek_entry(key)?;
+        let last_accessed_ms = entry.meta.last_accessed_ms.load(Ordering::Relaxed);
+        Some(ObjectInfo {
+            created_at_ms: entry.meta.created_at_ms,
+            last_accessed_ms: (last_accessed_ms > 0).then_some(last_accessed_ms),
+            access_count: entry.meta.access_count.load(Ordering::Relaxed),
+            size: entry.value.len(),
+            value_type: ValueType::of(&entry.value),
+            ttl_ms: entry
+                .expiry
+                .map(|expiry| expiry.saturating_duration_since(Instant::now()).as_millis() as u64),
+        })
+    }
+}
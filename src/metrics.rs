@@ -0,0 +1,205 @@
+//! Pushing cluster metrics to StatsD or Graphite, for deployments without a
+//! scraper polling `/stats`.
+//!
+//! Every numeric field of `ClusterStats` is sent as a gauge named after its
+//! path, e.g. `volt.nodes.node0.replica_backlog`. Counters are sent as their
+//! running totals; the receiving side derives rates from them.
+
+use axum::async_trait;
+use serde_json::Value as JsonValue;
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpStream, UdpSocket};
+use tracing::warn;
+
+use crate::changes::unix_millis;
+use crate::stats::ClusterStats;
+use crate::KVCluster;
+
+/// Largest StatsD datagram sent, small enough to avoid IP fragmentation
+const STATSD_MAX_PACKET: usize = 1432;
+
+/// Time allowed to connect to Graphite and write one push
+const GRAPHITE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// One reading of a metric
+#[derive(Debug, Clone, PartialEq)]
+pub struct Metric {
+    /// Dotted name, without the reporter's prefix
+    pub name: String,
+    pub value: f64,
+}
+
+/// Somewhere metrics are pushed to
+#[async_trait]
+pub trait MetricsSink: Send + Sync {
+    async fn send(&self, prefix: &str, metrics: &[Metric]) -> io::Result<()>;
+}
+
+/// Sends gauges over UDP in the StatsD line protocol
+pub struct StatsdSink {
+    addr: String,
+}
+
+impl StatsdSink {
+    pub fn new(addr: impl Into<String>) -> Self {
+        StatsdSink { addr: addr.into() }
+    }
+}
+
+#[async_trait]
+impl MetricsSink for StatsdSink {
+    async fn send(&self, prefix: &str, metrics: &[Metric]) -> io::Result<()> {
+        let socket = UdpSocket::bind("0.0.0.0:0").await?;
+        socket.connect(&self.addr).await?;
+        let mut packet = String::new();
+        for metric in metrics {
+            let line = format!("{}.{}:{}|g\n", prefix, metric.name, metric.value);
+            if !packet.is_empty() && packet.len() + line.len() > STATSD_MAX_PACKET {
+                socket.send(packet.as_bytes()).await?;
+                packet.clear();
+            }
+            packet.push_str(&line);
+        }
+        if !packet.is_empty() {
+            socket.send(packet.as_bytes()).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Sends metrics over TCP in the Graphite plaintext protocol
+pub struct GraphiteSink {
+    addr: String,
+}
+
+impl GraphiteSink {
+    pub fn new(addr: impl Into<String>) -> Self {
+        GraphiteSink { addr: addr.into() }
+    }
+}
+
+#[async_trait]
+impl MetricsSink for GraphiteSink {
+    async fn send(&self, prefix: &str, metrics: &[Metric]) -> io::Result<()> {
+        let timestamp = unix_millis() / 1000;
+        let mut body = String::new();
+        for metric in metrics {
+            body.push_str(&format!("{}.{} {} {}\n", prefix, metric.name, metric.value, timestamp));
+        }
+        let push = async {
+            let mut stream = TcpStream::connect(&self.addr).await?;
+            stream.write_all(body.as_bytes()).await?;
+            stream.shutdown().await
+        };
+        tokio::time::timeout(GRAPHITE_TIMEOUT, push)
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "Graphite push timed out"))?
+    }
+}
+
+/// Where and how often metrics are pushed
+#[derive(Debug, Clone)]
+pub struct MetricsPushConfig {
+    pub target: MetricsTarget,
+    /// Prepended to every metric name
+    pub prefix: String,
+    pub interval: Duration,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MetricsTarget {
+    /// A StatsD `host:port`, over UDP
+    Statsd(String),
+    /// A Graphite plaintext `host:port`, over TCP
+    Graphite(String),
+}
+
+impl MetricsTarget {
+    pub fn sink(&self) -> Arc<dyn MetricsSink> {
+        match self {
+            MetricsTarget::Statsd(addr) => Arc::new(StatsdSink::new(addr.clone())),
+            MetricsTarget::Graphite(addr) => Arc::new(GraphiteSink::new(addr.clone())),
+        }
+    }
+}
+
+/// Makes a stats field or node id usable as one segment of a dotted name
+fn metric_segment(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '_' || c == '-' { c } else { '_' })
+        .collect()
+}
+
+fn flatten(name: &str, value: &JsonValue, metrics: &mut Vec<Metric>) {
+    let child = |segment: &str| match name {
+        "" => metric_segment(segment),
+        _ => format!("{}.{}", name, metric_segment(segment)),
+    };
+    match value {
+        JsonValue::Number(number) => {
+            if let Some(value) = number.as_f64() {
+                metrics.push(Metric {
+                    name: name.to_string(),
+                    value,
+                });
+            }
+        }
+        JsonValue::Bool(flag) => metrics.push(Metric {
+            name: name.to_string(),
+            value: if *flag { 1.0 } else { 0.0 },
+        }),
+        JsonValue::Object(fields) => {
+            for (field, value) in fields {
+                flatten(&child(field), value, metrics);
+            }
+        }
+        // Nodes are named by id rather than position, which changes as nodes come and go
+        JsonValue::Array(items) => {
+            for (index, item) in items.iter().enumerate() {
+                let segment = item
+                    .get("id")
+                    .and_then(JsonValue::as_str)
+                    .map_or_else(|| index.to_string(), String::from);
+                flatten(&child(&segment), item, metrics);
+            }
+        }
+        JsonValue::String(_) | JsonValue::Null => {}
+    }
+}
+
+/// The numeric fields of `stats` as metrics named by their path
+pub fn cluster_metrics(stats: &ClusterStats) -> Vec<Metric> {
+    let mut metrics = Vec::new();
+    if let Ok(stats) = serde_json::to_value(stats) {
+        flatten("", &stats, &mut metrics);
+    }
+    metrics
+}
+
+impl KVCluster {
+    /// Pushes the cluster's stats to `sink` every `interval` until the
+    /// returned task is aborted. Failed pushes are logged and retried at the
+    /// next interval.
+    pub fn start_metrics_push(
+        &self,
+        sink: Arc<dyn MetricsSink>,
+        prefix: String,
+        interval: Duration,
+    ) -> tokio::task::JoinHandle<()> {
+        let cluster = self.clone();
+        tokio::spawn(async move {
+            let mut ticks = tokio::time::interval(interval);
+            ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                ticks.tick().await;
+                let metrics = cluster_metrics(&cluster.stats());
+                if let Err(e) = sink.send(&prefix, &metrics).await {
+                    warn!("Cannot push metrics: {}", e);
+                }
+            }
+        })
+    }
+}
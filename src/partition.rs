@@ -0,0 +1,115 @@
+use std::collections::BTreeSet;
+use std::sync::Arc;
+use tokio::time::Instant;
+
+use crate::{KVCluster, KVEntry, KVNode, Key};
+
+/// One slice of the keyspace: the keys a single node serves as primary.
+/// While a node is down its partition is empty, and its keys are in the
+/// partitions of the nodes serving them in its place.
+///
+/// Handles are independent of each other and of the cluster handle, so a batch
+/// job can move each one into its own task. No locks are held between items.
+#[derive(Clone)]
+pub struct PartitionHandle {
+    index: usize,
+    node: Arc<KVNode>,
+    cluster: KVCluster,
+}
+
+impl PartitionHandle {
+    /// Position of this partition in `KVCluster::partitions`
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    pub fn node_id(&self) -> &str {
+        &self.node.id
+    }
+
+    /// Snapshot of the live keys in this partition
+    pub fn keys(&self) -> Vec<Key> {
+        let now = Instant::now();
+        self.node
+            .store
+            .iter()
+            .filter(|entry| entry.expiry.is_none_or(|expiry| expiry > now))
+            .filter(|entry| self.cluster.primary_idx(entry.key().as_bytes()) == Some(self.index))
+            .map(|entry| entry.key().clone())
+            .collect()
+    }
+
+    /// Iterates the partition's key/value pairs. Keys are snapshotted up front
+    /// and values read as the iterator advances, so keys deleted or expired in
+    /// the meantime are skipped and keys added in the meantime are not seen.
+    pub fn iter(&self) -> impl Iterator<Item = (Key, Vec<u8>)> + '_ {
+        self.entries().map(|(key, entry)| (key, entry.value.into()))
+    }
+
+    /// Like `iter`, but yields whole entries including their expiry
+    pub(crate) fn entries(&self) -> impl Iterator<Item = (Key, KVEntry)> + '_ {
+        self.keys().into_iter().filter_map(move |key| {
+            let entry = self.node.store.get(key.as_bytes()).and_then(|entry| {
+                match entry.expiry {
+                    Some(expiry) if expiry <= Instant::now() => None,
+                    _ => Some(entry.clone()),
+                }
+            })?;
+            Some((key, entry))
+        })
+    }
+}
+
+impl KVCluster {
+    /// Splits the keyspace into one partition per node, for processing it in
+    /// parallel. Together the partitions cover every live key exactly once.
+    pub fn partitions(&self) -> Vec<PartitionHandle> {
+        self.nodes
+            .iter()
+            .enumerate()
+            .map(|(index, node)| PartitionHandle {
+                index,
+                node: node.clone(),
+                cluster: self.clone(),
+            })
+            .collect()
+    }
+
+    /// Up to `count` live key/value pairs in key order, starting at the
+    /// first key not less than `start`. Keys are hash-partitioned, so each
+    /// scan visits every key in the cluster; it suits batch jobs and
+    /// benchmarks, not hot paths. Keys compare as bytes, so text keys come
+    /// in lexicographic order.
+    pub fn scan(&self, start: impl AsRef<[u8]>, count: usize) -> Vec<(Key, Vec<u8>)> {
+        let start = start.as_ref();
+        if count == 0 {
+            return Vec::new();
+        }
+        let now = Instant::now();
+        // The `count` smallest keys seen so far
+        let mut keys = BTreeSet::new();
+        for (index, node) in self.nodes.iter().enumerate() {
+            for entry in node.store.iter() {
+                let key = entry.key();
+                if key.as_bytes() < start
+                    || entry.is_expired(now)
+                    || self.primary_idx(key.as_bytes()) != Some(index)
+                    || (keys.len() == count && keys.last().is_some_and(|last: &Key| key >= last))
+                {
+                    continue;
+                }
+                keys.insert(key.clone());
+                if keys.len() > count {
+                    keys.pop_last();
+                }
+            }
+        }
+        keys.into_iter()
+            .filter_map(|key| {
+                let node = &self.nodes[self.primary_idx(key.as_bytes())?];
+                let value = node.store.get(key.as_bytes()).filter(|entry| !entry.is_expired(now))?.value.to_vec();
+                Some((key, value))
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,233 @@
+//! Bulk expiry and deletion of every key under a prefix, for invalidating a
+//! whole tenant's cache without knowing its keys.
+//!
+//! Each node works through its own copies, primary and replica alike, on a
+//! blocking thread of its own, and the operation as a whole runs on a task
+//! that finishes it even if the caller stops waiting. Only keys that exist
+//! when a node reaches them are affected; keys written under the prefix
+//! while the call runs may be missed.
+
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinSet;
+use tokio::time::Instant;
+
+use crate::changes::ChangeEvent;
+use crate::{interrupt, KVCluster, KVError, KVNode, Key, Ttl, WriteOptions};
+
+impl KVNode {
+    /// Makes every live key under `prefix` expire `ttl` from `now`,
+    /// returning the keys changed
+    fn expire_prefix(&self, prefix: &[u8], ttl: Duration, now: Instant) -> Vec<Key> {
+        let expiry = now + ttl;
+        let mut changed = Vec::new();
+        for mut entry in self.store.iter_mut() {
+            if !entry.key().as_bytes().starts_with(prefix) || entry.is_expired(now) {
+                continue;
+            }
+            let previous = entry.expiry.replace(expiry);
+            entry.ttl = Some(Ttl::new(ttl, false));
+            self.track_expiry(entry.key(), previous, Some(expiry));
+            changed.push(entry.key().clone());
+        }
+        changed
+    }
+
+    /// Drops every key under `prefix`, returning the live keys dropped
+    fn del_prefix(&self, prefix: &[u8], now: Instant) -> Vec<Key> {
+        let mut live = Vec::new();
+        self.store.retain(|key, entry| {
+            if !key.as_bytes().starts_with(prefix) {
+                return true;
+            }
+            if !entry.is_expired(now) {
+                live.push(key.clone());
+            }
+            self.track_expiry(key, entry.expiry, None);
+            false
+        });
+        live
+    }
+}
+
+impl KVCluster {
+    /// Makes every existing key under `prefix` expire `ttl` from now,
+    /// whatever TTL it had, returning how many keys were changed. A zero
+    /// `ttl` deletes the keys at once.
+    pub async fn expire_prefix(&self, prefix: impl AsRef<[u8]>, ttl: Duration) -> Result<usize, KVError> {
+        self.expire_prefix_with_options(prefix, ttl, WriteOptions::default())
+            .await
+    }
+
+    pub async fn expire_prefix_with_options(
+        &self,
+        prefix: impl AsRef<[u8]>,
+        ttl: Duration,
+        options: WriteOptions,
+    ) -> Result<usize, KVError> {
+        if ttl.is_zero() {
+            return self.del_prefix_with_options(prefix, options).await;
+        }
+        self.check_writable(&options)?;
+        let (cluster, prefix) = (self.clone(), prefix.as_ref().to_vec());
+        let count = self.finish_detached(async move {
+            let now = Instant::now();
+            let changed = cluster
+                .on_every_node(&prefix, move |node, prefix| node.expire_prefix(prefix, ttl, now))
+                .await;
+            let mut count = 0;
+            for (idx, keys) in changed {
+                for key in keys {
+                    if cluster.primary_idx(key.as_bytes()) != Some(idx) {
+                        continue;
+                    }
+                    count += 1;
+                    let node = &cluster.nodes[idx];
+                    cluster.publish_change(|| match node.store.get(key.as_bytes()) {
+                        Some(entry) => {
+                            ChangeEvent::set(key.clone(), entry.value.to_vec(), Some(ttl), options.replicated)
+                        }
+                        None => ChangeEvent::del(key.clone(), options.replicated),
+                    });
+                }
+            }
+            count
+        });
+        Ok(count.await)
+    }
+
+    /// Deletes every existing key under `prefix`, returning how many were
+    /// deleted. Deleted keys skip the trash, even in namespaces that soft-delete.
+    pub async fn del_prefix(&self, prefix: impl AsRef<[u8]>) -> Result<usize, KVError> {
+        self.del_prefix_with_options(prefix, WriteOptions::default()).await
+    }
+
+    pub async fn del_prefix_with_options(&self, prefix: impl AsRef<[u8]>, options: WriteOptions) -> Result<usize, KVError> {
+        self.check_writable(&options)?;
+        let (cluster, prefix) = (self.clone(), prefix.as_ref().to_vec());
+        let count = self.finish_detached(async move {
+            let now = Instant::now();
+            let dropped = cluster
+                .on_every_node(&prefix, move |node, prefix| node.del_prefix(prefix, now))
+                .await;
+            let mut count = 0;
+            for (idx, keys) in dropped {
+                for key in keys {
+                    cluster.state.quotas.forget(key.as_bytes());
+                    if cluster.primary_idx(key.as_bytes()) != Some(idx) {
+                        continue;
+                    }
+                    count += 1;
+                    cluster.publish_change(|| ChangeEvent::del(key.clone(), options.replicated));
+                }
+            }
+            count
+        });
+        Ok(count.await)
+    }
+
+    /// Runs a prefix operation to the end on a task of its own, so a caller
+    /// that stops waiting leaves no node half done and no change unpublished
+    async fn finish_detached<F>(&self, work: F) -> usize
+    where
+        F: Future<Output = usize> + Send + 'static,
+    {
+        self.state.interrupts.watch(interrupt::detach(work)).await
+    }
+
+    /// Runs `work` over every node in parallel on blocking threads, returning
+    /// each node's index with its result
+    async fn on_every_node<F>(&self, prefix: &[u8], work: F) -> Vec<(usize, Vec<Key>)>
+    where
+        F: Fn(&KVNode, &[u8]) -> Vec<Key> + Send + Sync + 'static,
+    {
+        let work = Arc::new(work);
+        let prefix: Arc<[u8]> = Arc::from(prefix);
+        let mut tasks = JoinSet::new();
+        for (idx, node) in self.nodes.iter().enumerate() {
+            let (node, prefix, work) = (node.clone(), prefix.clone(), work.clone());
+            tasks.spawn_blocking(move || (idx, work(&node, &prefix)));
+        }
+        let mut results = Vec::with_capacity(self.nodes.len());
+        while let Some(joined) = tasks.join_next().await {
+            // Only a broken store panics while iterating
+            results.push(joined.expect("prefix task panicked"));
+        }
+        results
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Keys just inside and just outside the `tenant:a:` prefix
+    const INSIDE: &[&str] = &["tenant:a:", "tenant:a:1", "tenant:a:10", "tenant:a:x:y"];
+    const OUTSIDE: &[&str] = &["tenant:a", "tenant:a;1", "tenant:ab:1", "tenant:b:1", "Tenant:a:1", "x:tenant:a:1"];
+
+    async fn cluster() -> KVCluster {
+        let mut cluster = KVCluster::new(16, 2);
+        for i in 0..3 {
+            cluster.add_node(format!("node{}", i));
+        }
+        for key in INSIDE.iter().chain(OUTSIDE) {
+            cluster.set(*key, b"v".to_vec(), None).await.unwrap();
+        }
+        // Let replicas apply their copies
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        cluster
+    }
+
+    /// Nodes holding a copy of `key`
+    fn copies(cluster: &KVCluster, key: &str) -> usize {
+        cluster
+            .nodes
+            .iter()
+            .filter(|node| node.store.contains_key(key.as_bytes()))
+            .count()
+    }
+
+    #[tokio::test]
+    async fn del_prefix_only_drops_keys_under_the_prefix() {
+        let cluster = cluster().await;
+        assert_eq!(cluster.del_prefix("tenant:a:").await.unwrap(), INSIDE.len());
+        for key in INSIDE {
+            assert_eq!(copies(&cluster, key), 0, "{}", key);
+        }
+        for key in OUTSIDE {
+            assert_eq!(cluster.get(key).await, Some(b"v".to_vec()), "{}", key);
+            assert_eq!(copies(&cluster, key), 2, "{}", key);
+        }
+        assert_eq!(cluster.del_prefix("tenant:a:").await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn expire_prefix_only_sets_the_ttl_of_keys_under_the_prefix() {
+        let cluster = cluster().await;
+        let ttl = Duration::from_secs(60);
+        assert_eq!(cluster.expire_prefix("tenant:a:", ttl).await.unwrap(), INSIDE.len());
+        for key in INSIDE {
+            let left = cluster.ttl(key).await.flatten().unwrap();
+            assert!(left <= ttl && left > ttl - Duration::from_secs(1), "{}", key);
+        }
+        for key in OUTSIDE {
+            assert_eq!(cluster.ttl(key).await, Some(None), "{}", key);
+        }
+    }
+
+    #[tokio::test]
+    async fn a_zero_ttl_deletes_and_prefixes_match_raw_bytes() {
+        let cluster = cluster().await;
+        cluster.set(b"tenant:a:\xff".to_vec(), b"v".to_vec(), None).await.unwrap();
+        cluster.set(b"tenant:a\xff".to_vec(), b"v".to_vec(), None).await.unwrap();
+        assert_eq!(cluster.expire_prefix(b"tenant:a:", Duration::ZERO).await.unwrap(), INSIDE.len() + 1);
+        assert!(cluster.get(b"tenant:a:\xff").await.is_none());
+        assert_eq!(cluster.get(b"tenant:a\xff").await, Some(b"v".to_vec()));
+
+        // The empty prefix covers every key
+        let left = OUTSIDE.len() + 1;
+        assert_eq!(cluster.del_prefix(b"").await.unwrap(), left);
+        assert!(cluster.nodes.iter().all(|node| node.store.is_empty()));
+    }
+}
@@ -0,0 +1,77 @@
+//! Projection of JSON documents: reading only selected fields, so clients
+//! that need a few fields of a large document do not download all of it.
+
+use serde_json::{Map, Value as JsonValue};
+use std::fmt;
+use std::str::FromStr;
+
+use crate::{KVCluster, KVError};
+
+/// A dotted path to a field nested in objects, e.g. `metadata.views`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Projection(Vec<String>);
+
+impl FromStr for Projection {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.split('.').any(str::is_empty) {
+            return Err(format!("invalid field '{}': expected names separated by '.'", s));
+        }
+        Ok(Projection(s.split('.').map(String::from).collect()))
+    }
+}
+
+impl fmt::Display for Projection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0.join("."))
+    }
+}
+
+impl Projection {
+    /// Parses a comma-separated list of fields, e.g. `name,metadata.views`
+    pub fn parse_list(list: &str) -> Result<Vec<Projection>, String> {
+        list.split(',').map(str::parse).collect()
+    }
+
+    fn select<'a>(&self, document: &'a JsonValue) -> Option<&'a JsonValue> {
+        self.0.iter().try_fold(document, |value, name| value.as_object()?.get(name))
+    }
+}
+
+/// Copies the fields of `document` that `fields` select into a document of
+/// the same shape. Fields the document lacks, or that run through a value
+/// which is not an object, are left out.
+pub fn project(document: &JsonValue, fields: &[Projection]) -> JsonValue {
+    let mut projected = Map::new();
+    for field in fields {
+        let Some(value) = field.select(document) else {
+            continue;
+        };
+        let (name, parents) = field.0.split_last().expect("projections have at least one name");
+        let mut target = &mut projected;
+        for parent in parents {
+            let child = target
+                .entry(parent.clone())
+                .or_insert_with(|| JsonValue::Object(Map::new()));
+            // The whole parent was selected too, and already holds the field
+            let JsonValue::Object(child) = child else {
+                unreachable!("a selected path only runs through objects");
+            };
+            target = child;
+        }
+        target.insert(name.clone(), value.clone());
+    }
+    JsonValue::Object(projected)
+}
+
+impl KVCluster {
+    /// Retrieves only the given fields of a JSON document
+    pub async fn get_json_fields(&self, key: &str, fields: &[Projection]) -> Result<Option<JsonValue>, KVError> {
+        match self.get_json_value(key).await? {
+            Some(document) => Ok(Some(project(&document, fields))),
+            None => Ok(None),
+        }
+    }
+}
@@ -0,0 +1,463 @@
+//! Key-count quotas per namespace, so one tenant filling the cache with keys
+//! cannot crowd out the rest. A key's namespace is the part of the key
+//! before its first ':'.
+//!
+//! Only writes that create a key are checked. Each namespace with a quota
+//! tracks its keys in creation order; keys deleted through the cluster leave
+//! the count at once, keys that expire once the count is next pruned.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::mem;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, RwLock};
+use std::time::Duration;
+use tokio::time::Instant;
+use tracing::warn;
+
+use crate::key::{key_name, namespace_of};
+use crate::{KVCluster, KVError, Key, WriteOptions};
+
+/// Least time between two prunes of a namespace's expired keys
+const PRUNE_INTERVAL: Duration = Duration::from_millis(100);
+
+/// What a write creating a key does when its namespace is full
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum QuotaPolicy {
+    /// Fail the write with `KVError::QuotaExceeded`
+    #[default]
+    Reject,
+    /// Delete the namespace's oldest keys to make room
+    EvictOldest,
+}
+
+impl FromStr for QuotaPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "reject" => Ok(QuotaPolicy::Reject),
+            "evict" | "evict-oldest" | "evict_oldest" => Ok(QuotaPolicy::EvictOldest),
+            _ => Err(format!("invalid quota policy '{}': expected reject or evict-oldest", s)),
+        }
+    }
+}
+
+/// Most keys a namespace may hold, and what happens past that
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NamespaceQuota {
+    pub max_keys: usize,
+    #[serde(default)]
+    pub policy: QuotaPolicy,
+}
+
+impl FromStr for NamespaceQuota {
+    type Err = String;
+
+    /// Parses `max_keys[:policy]`, e.g. `1000` or `1000:evict-oldest`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (max_keys, policy) = match s.split_once(':') {
+            Some((max_keys, policy)) => (max_keys, policy.parse()?),
+            None => (s, QuotaPolicy::default()),
+        };
+        let max_keys = max_keys
+            .trim()
+            .parse()
+            .map_err(|_| format!("invalid quota '{}': expected max_keys[:policy]", s))?;
+        Ok(NamespaceQuota { max_keys, policy })
+    }
+}
+
+/// Parses a comma-separated list of `namespace=max_keys[:policy]`
+pub fn parse_quotas(list: &str) -> Result<Vec<(String, NamespaceQuota)>, String> {
+    list.split(',')
+        .filter(|item| !item.trim().is_empty())
+        .map(|item| {
+            let (namespace, quota) = item
+                .split_once('=')
+                .ok_or_else(|| format!("invalid quota '{}': expected namespace=max_keys[:policy]", item))?;
+            Ok((namespace.trim().to_string(), quota.parse()?))
+        })
+        .collect()
+}
+
+/// The keys of a namespace, oldest first
+#[derive(Default)]
+struct TrackedKeys {
+    by_age: BTreeMap<u64, Key>,
+    age: HashMap<Key, u64>,
+    next: u64,
+    pruned_at: Option<Instant>,
+    /// Keys admitted by writes that have not stored them yet, with how many
+    /// such writes there are and whether one of them has stored the key
+    pending: HashMap<Key, (usize, bool)>,
+}
+
+impl TrackedKeys {
+    fn len(&self) -> usize {
+        self.age.len()
+    }
+
+    fn contains(&self, key: &[u8]) -> bool {
+        self.age.contains_key(key)
+    }
+
+    fn insert(&mut self, key: Key) {
+        if self.age.contains_key(&key) {
+            return;
+        }
+        self.by_age.insert(self.next, key.clone());
+        self.age.insert(key, self.next);
+        self.next += 1;
+    }
+
+    fn remove(&mut self, key: &[u8]) {
+        if let Some(age) = self.age.remove(key) {
+            self.by_age.remove(&age);
+        }
+    }
+
+    /// Takes the oldest key, with its age so it can be put back
+    fn pop_oldest(&mut self) -> Option<(u64, Key)> {
+        let (age, key) = self.by_age.pop_first()?;
+        self.age.remove(&key);
+        Some((age, key))
+    }
+
+    /// Puts back a key taken by `pop_oldest`, unless it was counted again
+    /// meanwhile
+    fn restore(&mut self, age: u64, key: Key) {
+        if self.age.contains_key(&key) {
+            return;
+        }
+        self.by_age.insert(age, key.clone());
+        self.age.insert(key, age);
+    }
+
+    /// Drops keys that no longer exist, at most once per `PRUNE_INTERVAL`
+    /// unless `force` is set. Keys still being written are kept.
+    fn prune(&mut self, now: Instant, force: bool, is_live: impl Fn(&[u8]) -> bool) {
+        if !force && self.pruned_at.is_some_and(|pruned_at| now - pruned_at < PRUNE_INTERVAL) {
+            return;
+        }
+        self.pruned_at = Some(now);
+        let dead: Vec<Key> = self
+            .age
+            .keys()
+            .filter(|key| !self.pending.contains_key(*key) && !is_live(key.as_bytes()))
+            .cloned()
+            .collect();
+        for key in dead {
+            self.remove(key.as_bytes());
+        }
+    }
+
+    /// Marks `key` as being written
+    fn start_write(&mut self, key: &Key) {
+        self.pending.entry(key.clone()).or_default().0 += 1;
+    }
+
+    /// Ends a write started by `start_write`. Once the last write of a key
+    /// ends, the key stops being counted unless one of them stored it.
+    fn end_write(&mut self, key: &Key, stored: bool) {
+        let Some((writes, any_stored)) = self.pending.get_mut(key) else {
+            return;
+        };
+        *writes -= 1;
+        *any_stored |= stored;
+        if *writes == 0 {
+            let any_stored = *any_stored;
+            self.pending.remove(key);
+            if !any_stored {
+                self.remove(key.as_bytes());
+            }
+        }
+    }
+}
+
+struct QuotaState {
+    quota: RwLock<NamespaceQuota>,
+    keys: Mutex<TrackedKeys>,
+    rejected: AtomicU64,
+    evicted: AtomicU64,
+}
+
+impl QuotaState {
+    fn quota(&self) -> NamespaceQuota {
+        *self.quota.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn keys(&self) -> MutexGuard<'_, TrackedKeys> {
+        self.keys.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// A write let in by its namespace's quota, and the keys it pushes out.
+/// Its key counts against the quota from the start, and is kept out of
+/// prunes until the write is stored. Dropping it without `KVCluster::evict`
+/// counts the namespace's keys as they were, for a write that did not store
+/// its key after all.
+#[must_use]
+#[derive(Default)]
+pub(crate) struct Admission {
+    state: Option<Arc<QuotaState>>,
+    /// The key being written, if it is counted
+    key: Option<Key>,
+    victims: Vec<(u64, Key)>,
+}
+
+impl Drop for Admission {
+    fn drop(&mut self) {
+        let Some(state) = self.state.take() else {
+            return;
+        };
+        let mut keys = state.keys();
+        if let Some(key) = self.key.take() {
+            keys.end_write(&key, false);
+        }
+        for (age, key) in self.victims.drain(..) {
+            keys.restore(age, key);
+        }
+    }
+}
+
+/// Quotas of a cluster's namespaces and the keys counted against them
+#[derive(Default)]
+pub(crate) struct NamespaceQuotas {
+    namespaces: RwLock<HashMap<String, Arc<QuotaState>>>,
+}
+
+impl NamespaceQuotas {
+    fn of(&self, key: &[u8]) -> Option<(String, Arc<QuotaState>)> {
+        let namespace = std::str::from_utf8(namespace_of(key)?).ok()?;
+        let state = self.of_namespace(namespace)?;
+        Some((namespace.to_string(), state))
+    }
+
+    fn of_namespace(&self, namespace: &str) -> Option<Arc<QuotaState>> {
+        let namespaces = self.namespaces.read().unwrap_or_else(PoisonError::into_inner);
+        namespaces.get(namespace).cloned()
+    }
+
+    /// Stops counting a deleted key
+    pub(crate) fn forget(&self, key: &[u8]) {
+        if let Some((_, state)) = self.of(key) {
+            state.keys().remove(key);
+        }
+    }
+}
+
+/// A namespace's quota and how much of it is used
+#[derive(Serialize, Debug, Clone)]
+pub struct QuotaUsage {
+    pub max_keys: usize,
+    pub policy: QuotaPolicy,
+    /// Live keys in the namespace
+    pub keys: usize,
+    /// Writes rejected because the namespace was full
+    pub rejected: u64,
+    /// Keys deleted to make room for new ones
+    pub evicted: u64,
+}
+
+impl KVCluster {
+    /// Limits how many keys `namespace` may hold. `None` removes the limit.
+    /// A namespace already over a new limit keeps its keys; the policy
+    /// applies from the next key created in it. Changing the quota of a
+    /// namespace that has one keeps its keys counted and its usage counters.
+    pub fn set_namespace_quota(&self, namespace: &str, quota: Option<NamespaceQuota>) -> Result<(), String> {
+        let Some(quota) = quota else {
+            self.state
+                .quotas
+                .namespaces
+                .write()
+                .unwrap_or_else(PoisonError::into_inner)
+                .remove(namespace);
+            return Ok(());
+        };
+        if quota.max_keys == 0 {
+            return Err(format!("quota of namespace '{}' must allow at least one key", namespace));
+        }
+        if let Some(state) = self.state.quotas.of_namespace(namespace) {
+            *state.quota.write().unwrap_or_else(PoisonError::into_inner) = quota;
+            return Ok(());
+        }
+        let state = Arc::new(QuotaState {
+            quota: RwLock::new(quota),
+            keys: Mutex::new(self.namespace_keys(namespace)),
+            rejected: AtomicU64::new(0),
+            evicted: AtomicU64::new(0),
+        });
+        self.state
+            .quotas
+            .namespaces
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(namespace.to_string(), state);
+        Ok(())
+    }
+
+    /// Quotas of every namespace that has one
+    pub fn namespace_quotas(&self) -> BTreeMap<String, NamespaceQuota> {
+        let namespaces = self.state.quotas.namespaces.read().unwrap_or_else(PoisonError::into_inner);
+        namespaces
+            .iter()
+            .map(|(namespace, state)| (namespace.clone(), state.quota()))
+            .collect()
+    }
+
+    /// Usage of every namespace quota
+    pub fn quota_usage(&self) -> BTreeMap<String, QuotaUsage> {
+        let namespaces: Vec<(String, Arc<QuotaState>)> = {
+            let namespaces = self.state.quotas.namespaces.read().unwrap_or_else(PoisonError::into_inner);
+            namespaces.iter().map(|(namespace, state)| (namespace.clone(), state.clone())).collect()
+        };
+        let now = Instant::now();
+        namespaces
+            .into_iter()
+            .map(|(namespace, state)| {
+                let keys = {
+                    let mut keys = state.keys();
+                    keys.prune(now, false, |key| self.key_is_live(key, now));
+                    keys.len()
+                };
+                let quota = state.quota();
+                let usage = QuotaUsage {
+                    max_keys: quota.max_keys,
+                    policy: quota.policy,
+                    keys,
+                    rejected: state.rejected.load(Ordering::Relaxed),
+                    evicted: state.evicted.load(Ordering::Relaxed),
+                };
+                (namespace, usage)
+            })
+            .collect()
+    }
+
+    /// Checks a write of `key` against its namespace's quota, counting the
+    /// key if the write creates it. Under `EvictOldest`, picks the keys that
+    /// make room, which `evict` deletes once the write is stored. Writes
+    /// replicated from another cluster are let through, since that cluster
+    /// enforced its own quotas.
+    pub(crate) fn admit_key(&self, key: &[u8], options: &WriteOptions) -> Result<Admission, KVError> {
+        if options.replicated {
+            return Ok(Admission::default());
+        }
+        let Some((namespace, state)) = self.state.quotas.of(key) else {
+            return Ok(Admission::default());
+        };
+        let now = Instant::now();
+        let quota = state.quota();
+        let mut keys = state.keys();
+        let key = Key::from(key);
+        if keys.contains(key.as_bytes()) {
+            // Another write admitted the key and has yet to store it
+            if keys.pending.contains_key(&key) {
+                keys.start_write(&key);
+                drop(keys);
+                return Ok(Admission {
+                    state: Some(state),
+                    key: Some(key),
+                    victims: Vec::new(),
+                });
+            }
+            if self.key_is_live(key.as_bytes(), now) {
+                return Ok(Admission::default());
+            }
+            keys.remove(key.as_bytes());
+        }
+        let max_keys = quota.max_keys;
+        if keys.len() >= max_keys {
+            keys.prune(now, false, |key| self.key_is_live(key, now));
+        }
+        let mut victims = Vec::new();
+        while keys.len() >= max_keys {
+            match quota.policy {
+                QuotaPolicy::Reject => {
+                    for (age, victim) in victims {
+                        keys.restore(age, victim);
+                    }
+                    state.rejected.fetch_add(1, Ordering::Relaxed);
+                    return Err(KVError::QuotaExceeded { namespace, max_keys });
+                }
+                QuotaPolicy::EvictOldest => victims.extend(keys.pop_oldest()),
+            }
+        }
+        keys.insert(key.clone());
+        keys.start_write(&key);
+        drop(keys);
+        Ok(Admission {
+            state: Some(state),
+            key: Some(key),
+            victims,
+        })
+    }
+
+    /// Ends the write `admission` let in, and deletes the keys it pushes
+    /// out. Call it right after the write is stored on its primary, once
+    /// the key's store entry is released: the deletions run on a task of
+    /// their own, so they finish even if the caller stops waiting, and the
+    /// returned future waits for them. A key that cannot be deleted is
+    /// logged and stays in the store, no longer counted.
+    pub(crate) fn evict(&self, mut admission: Admission, options: WriteOptions) -> impl Future<Output = ()> {
+        let state = admission.state.take();
+        if let (Some(state), Some(key)) = (&state, admission.key.take()) {
+            state.keys().end_write(&key, true);
+        }
+        let victims = mem::take(&mut admission.victims);
+        let evicting = state.filter(|_| !victims.is_empty()).map(|state| {
+            let cluster = self.clone();
+            tokio::spawn(async move {
+                for (_, key) in victims {
+                    state.evicted.fetch_add(1, Ordering::Relaxed);
+                    // Evicted keys are not kept in the trash, which would let
+                    // them be restored past the quota
+                    if let Err(e) = cluster.remove(key.as_bytes(), options, false).await {
+                        warn!(key = %key_name(key.as_bytes()), "cannot evict key over quota: {}", e);
+                    }
+                }
+            })
+        });
+        async move {
+            if let Some(evicting) = evicting {
+                let _ = evicting.await;
+            }
+        }
+    }
+
+    /// Whether `key` has a live copy on its primary
+    fn key_is_live(&self, key: &[u8], now: Instant) -> bool {
+        self.primary_idx(key).is_some_and(|idx| {
+            self.nodes[idx]
+                .store
+                .get(key)
+                .is_some_and(|entry| !entry.is_expired(now))
+        })
+    }
+
+    /// The live keys of `namespace`, oldest first
+    fn namespace_keys(&self, namespace: &str) -> TrackedKeys {
+        let now = Instant::now();
+        let mut found = Vec::new();
+        for (idx, node) in self.nodes.iter().enumerate() {
+            for entry in node.store.iter() {
+                let key = entry.key().as_bytes();
+                if namespace_of(key) == Some(namespace.as_bytes())
+                    && !entry.is_expired(now)
+                    && self.primary_idx(key) == Some(idx)
+                {
+                    found.push((entry.meta.created_at_ms(), entry.key().clone()));
+                }
+            }
+        }
+        found.sort();
+        let mut keys = TrackedKeys::default();
+        for (_, key) in found {
+            keys.insert(key);
+        }
+        keys
+    }
+}
@@ -0,0 +1,221 @@
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::{header, HeaderValue, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+use dashmap::DashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant};
+
+use crate::api::error_response;
+use crate::auth::AccessControl;
+
+/// How long a client's bucket may sit idle before it is forgotten
+const IDLE_BUCKET_TTL: Duration = Duration::from_secs(300);
+
+/// Token bucket state for one client
+struct Bucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+/// Token-bucket rate limiter keyed by an arbitrary client id. Each client may
+/// burst up to `burst` requests and is refilled at `rate` requests per second.
+pub struct RateLimiter {
+    rate: f64,
+    burst: f64,
+    buckets: DashMap<String, Bucket>,
+}
+
+impl RateLimiter {
+    pub fn new(rate: f64, burst: u32) -> Self {
+        RateLimiter {
+            rate,
+            burst: f64::from(burst.max(1)),
+            buckets: DashMap::new(),
+        }
+    }
+
+    /// Takes one token from `client`'s bucket, or returns how long until one
+    /// is available
+    pub fn check(&self, client: &str) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut bucket = self.buckets.entry(client.to_string()).or_insert(Bucket {
+            tokens: self.burst,
+            refilled_at: now,
+        });
+        let elapsed = now.duration_since(bucket.refilled_at).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.rate).min(self.burst);
+        bucket.refilled_at = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else if self.rate > 0.0 {
+            Err(Duration::try_from_secs_f64((1.0 - bucket.tokens) / self.rate).unwrap_or(Duration::MAX))
+        } else {
+            Err(Duration::MAX)
+        }
+    }
+
+    /// Forgets clients that have been idle long enough to have a full bucket
+    pub fn purge_idle(&self) {
+        self.buckets
+            .retain(|_, bucket| bucket.refilled_at.elapsed() < IDLE_BUCKET_TTL);
+    }
+}
+
+/// Request-rate and concurrency quotas applied per client by the HTTP server
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimitConfig {
+    /// Sustained requests per second allowed for each client
+    pub requests_per_sec: f64,
+    /// Requests a client may make in a burst above the sustained rate
+    pub burst: u32,
+    /// Most requests a single client may have in flight at once
+    pub max_concurrent_per_client: Option<usize>,
+    /// Most requests in flight across all clients
+    pub max_concurrent: Option<usize>,
+}
+
+impl RateLimitConfig {
+    pub fn new(requests_per_sec: f64, burst: u32) -> Self {
+        RateLimitConfig {
+            requests_per_sec,
+            burst,
+            max_concurrent_per_client: None,
+            max_concurrent: None,
+        }
+    }
+}
+
+/// Shared state of the rate-limiting middleware
+pub struct ClientQuotas {
+    config: RateLimitConfig,
+    /// Users whose tokens identify a client; other requests count per IP
+    access: Option<Arc<AccessControl>>,
+    limiter: RateLimiter,
+    in_flight: DashMap<String, usize>,
+    total_in_flight: AtomicUsize,
+}
+
+impl ClientQuotas {
+    pub fn new(config: RateLimitConfig) -> Self {
+        ClientQuotas {
+            limiter: RateLimiter::new(config.requests_per_sec, config.burst),
+            config,
+            access: None,
+            in_flight: DashMap::new(),
+            total_in_flight: AtomicUsize::new(0),
+        }
+    }
+
+    /// Counts the requests of each of these users against their own quotas,
+    /// whichever address they come from
+    pub fn with_access(mut self, access: Arc<AccessControl>) -> Self {
+        self.access = Some(access);
+        self
+    }
+
+    /// Identifies the client by its user, if its bearer token is a known one,
+    /// else by IP address. Unknown tokens are ignored so that a client cannot
+    /// get fresh buckets by making tokens up.
+    fn client_id(&self, request: &Request) -> String {
+        let user = self.access.as_ref().and_then(|access| access.authenticate(request));
+        if let Some(user) = user {
+            return format!("user:{}", user.name);
+        }
+        match request.extensions().get::<ConnectInfo<SocketAddr>>() {
+            Some(ConnectInfo(addr)) => format!("ip:{}", addr.ip()),
+            None => "ip:unknown".to_string(),
+        }
+    }
+
+    /// Spawns a task that periodically forgets idle clients until the quotas are dropped
+    pub fn spawn_purger(quotas: &Arc<ClientQuotas>) {
+        let weak: Weak<ClientQuotas> = Arc::downgrade(quotas);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(IDLE_BUCKET_TTL / 5);
+            loop {
+                interval.tick().await;
+                match weak.upgrade() {
+                    Some(quotas) => quotas.limiter.purge_idle(),
+                    None => break,
+                }
+            }
+        });
+    }
+
+    /// Reserves an in-flight slot for `client`, if its quotas allow one
+    fn acquire(&self, client: &str) -> Option<InFlightSlot<'_>> {
+        let total = self.total_in_flight.fetch_add(1, Ordering::AcqRel) + 1;
+        let mut slot = InFlightSlot {
+            quotas: self,
+            client: None,
+        };
+        if self.config.max_concurrent.is_some_and(|max| total > max) {
+            return None;
+        }
+        let Some(max) = self.config.max_concurrent_per_client else {
+            return Some(slot);
+        };
+        let mut count = self.in_flight.entry(client.to_string()).or_insert(0);
+        if *count >= max {
+            return None;
+        }
+        *count += 1;
+        slot.client = Some(client.to_string());
+        Some(slot)
+    }
+}
+
+/// Releases an in-flight slot when the request finishes or is dropped
+struct InFlightSlot<'a> {
+    quotas: &'a ClientQuotas,
+    client: Option<String>,
+}
+
+impl Drop for InFlightSlot<'_> {
+    fn drop(&mut self) {
+        self.quotas.total_in_flight.fetch_sub(1, Ordering::AcqRel);
+        if let Some(client) = self.client.take() {
+            self.quotas
+                .in_flight
+                .remove_if_mut(&client, |_, count| {
+                    *count -= 1;
+                    *count == 0
+                });
+        }
+    }
+}
+
+fn too_many_requests(message: &str, retry_after: Duration) -> Response {
+    let mut response = error_response(StatusCode::TOO_MANY_REQUESTS, message.to_string());
+    let secs = retry_after.as_secs_f64().ceil().clamp(1.0, 86_400.0) as u64;
+    response
+        .headers_mut()
+        .insert(header::RETRY_AFTER, HeaderValue::from(secs));
+    response
+}
+
+/// Middleware enforcing per-client request rates and in-flight request
+/// quotas. Health probes are exempt so an orchestrator is never throttled.
+pub async fn rate_limit_layer(
+    State(quotas): State<Arc<ClientQuotas>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if request.uri().path().starts_with("/health") {
+        return next.run(request).await;
+    }
+    let client = quotas.client_id(&request);
+    if let Err(retry_after) = quotas.limiter.check(&client) {
+        return too_many_requests("Rate limit exceeded", retry_after);
+    }
+    let Some(_slot) = quotas.acquire(&client) else {
+        return too_many_requests("Too many concurrent requests", Duration::from_secs(1));
+    };
+    next.run(request).await
+}
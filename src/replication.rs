@@ -0,0 +1,457 @@
+//! Asynchronous cluster-to-cluster replication.
+//!
+//! A [`ReplicationBridge`] tails the change feed of a local cluster and ships
+//! batches of writes over TCP to a remote cluster running
+//! [`serve_replication`]. The receiving side resolves conflicts last-writer-wins
+//! on the source timestamps, which gives an active-passive disaster-recovery
+//! setup. CRDT values are merged instead, so two clusters replicating into
+//! each other converge on them (active-active).
+//!
+//! The wire protocol is newline-delimited JSON: the bridge sends one
+//! [`ReplicationBatch`] per line and the receiver answers each with `ok` or
+//! `error: <reason>`.
+
+use dashmap::mapref::entry::Entry;
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, PoisonError};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{info, warn};
+
+use crate::changes::{unix_millis, ChangeEvent, ChangeKind};
+use crate::crdt::CrdtValue;
+use crate::{KVCluster, Key, WriteOptions};
+
+/// Pause before reconnecting to an unreachable remote cluster
+const RECONNECT_BACKOFF: Duration = Duration::from_millis(500);
+
+/// How long the receiver remembers a key's last write timestamp for conflict
+/// resolution, including delete tombstones
+const LWW_RETENTION: Duration = Duration::from_secs(3600);
+
+#[derive(Debug, Clone)]
+pub struct BridgeConfig {
+    /// `host:port` of the remote cluster's replication listener
+    pub remote_addr: String,
+    /// Maximum number of events per batch
+    pub max_batch: usize,
+    /// How long to wait for a batch to fill before sending it
+    pub batch_interval: Duration,
+    /// Events buffered while the remote is unreachable; the oldest are dropped beyond this
+    pub max_pending: usize,
+}
+
+impl BridgeConfig {
+    pub fn new(remote_addr: impl Into<String>) -> Self {
+        BridgeConfig {
+            remote_addr: remote_addr.into(),
+            max_batch: 512,
+            batch_interval: Duration::from_millis(10),
+            max_pending: 100_000,
+        }
+    }
+}
+
+/// One line of the replication protocol
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReplicationBatch {
+    pub events: Vec<ChangeEvent>,
+}
+
+/// Live counters of a running bridge
+#[derive(Debug, Default)]
+pub struct BridgeMetrics {
+    remote_addr: String,
+    connected: AtomicBool,
+    events_sent: AtomicU64,
+    batches_sent: AtomicU64,
+    events_dropped: AtomicU64,
+    pending_events: AtomicU64,
+    oldest_pending_ms: AtomicU64,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct BridgeStats {
+    pub remote_addr: String,
+    pub connected: bool,
+    pub events_sent: u64,
+    pub batches_sent: u64,
+    /// Events lost because the bridge fell behind the change feed or its pending buffer overflowed
+    pub events_dropped: u64,
+    pub pending_events: u64,
+    /// Age of the oldest write not yet acknowledged by the remote cluster
+    pub lag_ms: u64,
+}
+
+impl BridgeMetrics {
+    pub fn snapshot(&self) -> BridgeStats {
+        let pending_events = self.pending_events.load(Ordering::Relaxed);
+        let lag_ms = if pending_events > 0 {
+            unix_millis().saturating_sub(self.oldest_pending_ms.load(Ordering::Relaxed))
+        } else {
+            0
+        };
+        BridgeStats {
+            remote_addr: self.remote_addr.clone(),
+            connected: self.connected.load(Ordering::Relaxed),
+            events_sent: self.events_sent.load(Ordering::Relaxed),
+            batches_sent: self.batches_sent.load(Ordering::Relaxed),
+            events_dropped: self.events_dropped.load(Ordering::Relaxed),
+            pending_events,
+            lag_ms,
+        }
+    }
+
+    fn record_pending(&self, pending: &VecDeque<ChangeEvent>) {
+        self.pending_events.store(pending.len() as u64, Ordering::Relaxed);
+        if let Some(oldest) = pending.front() {
+            self.oldest_pending_ms.store(oldest.timestamp_ms, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Live counters of a running receiver
+#[derive(Debug, Default)]
+pub struct ReceiverMetrics {
+    events_applied: AtomicU64,
+    events_superseded: AtomicU64,
+    events_failed: AtomicU64,
+    apply_lag_ms: AtomicU64,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ReceiverStats {
+    pub events_applied: u64,
+    /// Events ignored because a newer write to the same key was already applied
+    pub events_superseded: u64,
+    /// Events this cluster rejected, e.g. in read-only mode or over a quota
+    pub events_failed: u64,
+    /// Replication delay of the most recently applied write
+    pub apply_lag_ms: u64,
+}
+
+impl ReceiverMetrics {
+    pub fn snapshot(&self) -> ReceiverStats {
+        ReceiverStats {
+            events_applied: self.events_applied.load(Ordering::Relaxed),
+            events_superseded: self.events_superseded.load(Ordering::Relaxed),
+            events_failed: self.events_failed.load(Ordering::Relaxed),
+            apply_lag_ms: self.apply_lag_ms.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Replication endpoints attached to a cluster, reported in its stats
+#[derive(Default)]
+pub(crate) struct ReplicationStatus {
+    pub(crate) bridge: Option<Arc<BridgeMetrics>>,
+    pub(crate) receiver: Option<Arc<ReceiverMetrics>>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ReplicationStats {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bridge: Option<BridgeStats>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub receiver: Option<ReceiverStats>,
+}
+
+impl KVCluster {
+    /// Replication counters, if this cluster runs a bridge or a receiver
+    pub fn replication_stats(&self) -> Option<ReplicationStats> {
+        let status = self.state.replication.lock().unwrap_or_else(PoisonError::into_inner);
+        if status.bridge.is_none() && status.receiver.is_none() {
+            return None;
+        }
+        Some(ReplicationStats {
+            bridge: status.bridge.as_ref().map(|m| m.snapshot()),
+            receiver: status.receiver.as_ref().map(|m| m.snapshot()),
+        })
+    }
+}
+
+/// Ships a cluster's writes to a remote cluster
+pub struct ReplicationBridge;
+
+impl ReplicationBridge {
+    /// Starts tailing the cluster's change feed. Writes that were themselves
+    /// replicated in from another cluster are not forwarded.
+    pub fn start(cluster: &KVCluster, config: BridgeConfig) -> Arc<BridgeMetrics> {
+        let metrics = Arc::new(BridgeMetrics {
+            remote_addr: config.remote_addr.clone(),
+            ..Default::default()
+        });
+        cluster.state.replication.lock().unwrap_or_else(PoisonError::into_inner).bridge = Some(metrics.clone());
+
+        let rx = cluster.subscribe_changes();
+        tokio::spawn(run_bridge(rx, config, metrics.clone()));
+        metrics
+    }
+}
+
+type Connection = (BufReader<tokio::net::tcp::OwnedReadHalf>, tokio::net::tcp::OwnedWriteHalf);
+
+async fn run_bridge(
+    mut rx: tokio::sync::broadcast::Receiver<ChangeEvent>,
+    config: BridgeConfig,
+    metrics: Arc<BridgeMetrics>,
+) {
+    let mut pending: VecDeque<ChangeEvent> = VecDeque::new();
+    let mut conn: Option<Connection> = None;
+    let mut feed_closed = false;
+
+    loop {
+        // Block for the first event only when there is nothing left to send
+        if pending.is_empty() {
+            if feed_closed {
+                break;
+            }
+            match rx.recv().await {
+                Ok(event) => push_event(&mut pending, event),
+                Err(RecvError::Lagged(missed)) => {
+                    metrics.events_dropped.fetch_add(missed, Ordering::Relaxed);
+                    continue;
+                }
+                Err(RecvError::Closed) => break,
+            }
+        }
+        let fill_deadline = tokio::time::Instant::now() + config.batch_interval;
+        while !feed_closed && pending.len() < config.max_batch {
+            match tokio::time::timeout_at(fill_deadline, rx.recv()).await {
+                Ok(Ok(event)) => push_event(&mut pending, event),
+                Ok(Err(RecvError::Lagged(missed))) => {
+                    metrics.events_dropped.fetch_add(missed, Ordering::Relaxed);
+                }
+                Ok(Err(RecvError::Closed)) => feed_closed = true,
+                Err(_) => break,
+            }
+        }
+        if pending.len() > config.max_pending {
+            let overflow = pending.len() - config.max_pending;
+            pending.drain(..overflow);
+            metrics.events_dropped.fetch_add(overflow as u64, Ordering::Relaxed);
+        }
+        metrics.record_pending(&pending);
+        if pending.is_empty() {
+            continue;
+        }
+
+        if conn.is_none() {
+            match TcpStream::connect(&config.remote_addr).await {
+                Ok(stream) => {
+                    let _ = stream.set_nodelay(true);
+                    let (read, write) = stream.into_split();
+                    conn = Some((BufReader::new(read), write));
+                    metrics.connected.store(true, Ordering::Relaxed);
+                    info!(remote = %config.remote_addr, "replication bridge connected");
+                }
+                Err(e) => {
+                    warn!(remote = %config.remote_addr, "replication bridge cannot connect: {}", e);
+                    tokio::time::sleep(RECONNECT_BACKOFF).await;
+                    continue;
+                }
+            }
+        }
+
+        let batch_len = pending.len().min(config.max_batch);
+        let (reader, writer) = conn.as_mut().unwrap();
+        match send_batch(reader, writer, pending.range(..batch_len)).await {
+            Ok(()) => {
+                pending.drain(..batch_len);
+                metrics.events_sent.fetch_add(batch_len as u64, Ordering::Relaxed);
+                metrics.batches_sent.fetch_add(1, Ordering::Relaxed);
+                metrics.record_pending(&pending);
+            }
+            Err(e) => {
+                warn!(remote = %config.remote_addr, "replication batch failed, reconnecting: {}", e);
+                conn = None;
+                metrics.connected.store(false, Ordering::Relaxed);
+                tokio::time::sleep(RECONNECT_BACKOFF).await;
+            }
+        }
+    }
+}
+
+fn push_event(pending: &mut VecDeque<ChangeEvent>, event: ChangeEvent) {
+    if !event.replicated {
+        pending.push_back(event);
+    }
+}
+
+async fn send_batch<'a>(
+    reader: &mut BufReader<tokio::net::tcp::OwnedReadHalf>,
+    writer: &mut tokio::net::tcp::OwnedWriteHalf,
+    events: impl Iterator<Item = &'a ChangeEvent>,
+) -> std::io::Result<()> {
+    #[derive(Serialize)]
+    struct BatchRef<'a> {
+        events: Vec<&'a ChangeEvent>,
+    }
+    let mut line = serde_json::to_vec(&BatchRef {
+        events: events.collect(),
+    })?;
+    line.push(b'\n');
+    writer.write_all(&line).await?;
+
+    let mut ack = String::new();
+    if reader.read_line(&mut ack).await? == 0 {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::UnexpectedEof,
+            "remote closed the connection",
+        ));
+    }
+    match ack.trim_end() {
+        "ok" => Ok(()),
+        other => Err(std::io::Error::other(other.to_string())),
+    }
+}
+
+/// Applies replicated writes with last-writer-wins on the source timestamps
+struct LwwApplier {
+    cluster: KVCluster,
+    last_write: DashMap<Key, u64>,
+    metrics: Arc<ReceiverMetrics>,
+}
+
+impl LwwApplier {
+    async fn apply(&self, event: ChangeEvent) {
+        // CRDT states merge and converge in any order, so they skip LWW
+        if let ChangeKind::Set { value, expires_at_ms } = &event.kind {
+            if let Some(Ok(state)) = CrdtValue::decode(value) {
+                let ttl = expires_at_ms.map(|at| Duration::from_millis(at.saturating_sub(unix_millis())));
+                let options = WriteOptions::replicated();
+                match self.cluster.merge_crdt_with_options(event.key.as_bytes(), &state, ttl, options).await {
+                    Ok(_) => {
+                        self.metrics.events_applied.fetch_add(1, Ordering::Relaxed);
+                    }
+                    Err(e) => {
+                        warn!(key = %event.key, "cannot merge replicated CRDT: {}", e);
+                        self.metrics.events_failed.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+                return;
+            }
+        }
+
+        let previous = match self.last_write.entry(event.key.clone()) {
+            Entry::Occupied(existing) if *existing.get() > event.timestamp_ms => {
+                self.metrics.events_superseded.fetch_add(1, Ordering::Relaxed);
+                return;
+            }
+            Entry::Occupied(mut existing) => Some(existing.insert(event.timestamp_ms)),
+            Entry::Vacant(vacant) => {
+                vacant.insert(event.timestamp_ms);
+                None
+            }
+        };
+
+        let now = unix_millis();
+        let options = WriteOptions::replicated();
+        let key = event.key.clone();
+        let applied = match event.kind {
+            ChangeKind::Set { value, expires_at_ms } => match expires_at_ms {
+                Some(at) if at <= now => self.cluster.del_with_options(&event.key, options).await,
+                expires_at_ms => {
+                    let ttl = expires_at_ms.map(|at| Duration::from_millis(at - now));
+                    self.cluster.set_with_options(event.key, value, ttl, options).await
+                }
+            },
+            ChangeKind::Del => self.cluster.del_with_options(&event.key, options).await,
+            ChangeKind::Touch => self.cluster.touch_with_options(&event.key, options).await.map(|_| ()),
+        };
+        if let Err(e) = applied {
+            warn!(%key, "cannot apply replicated write: {}", e);
+            self.metrics.events_failed.fetch_add(1, Ordering::Relaxed);
+            self.forget_write(&key, event.timestamp_ms, previous);
+            return;
+        }
+        self.metrics.events_applied.fetch_add(1, Ordering::Relaxed);
+        self.metrics
+            .apply_lag_ms
+            .store(now.saturating_sub(event.timestamp_ms), Ordering::Relaxed);
+    }
+
+    /// Puts back the timestamp `key` had before a write stamped `timestamp`
+    /// that failed, so it does not shadow older events that may still apply.
+    /// A newer write accepted meanwhile is left alone.
+    fn forget_write(&self, key: &Key, timestamp: u64, previous: Option<u64>) {
+        if let Entry::Occupied(mut entry) = self.last_write.entry(key.clone()) {
+            if *entry.get() != timestamp {
+                return;
+            }
+            match previous {
+                Some(previous) => {
+                    entry.insert(previous);
+                }
+                None => {
+                    entry.remove();
+                }
+            }
+        }
+    }
+
+    fn prune(&self) {
+        let cutoff = unix_millis().saturating_sub(LWW_RETENTION.as_millis() as u64);
+        self.last_write.retain(|_, ts| *ts >= cutoff);
+    }
+}
+
+/// Accepts replication connections and applies incoming batches to `cluster`.
+/// Runs until the listener fails.
+pub async fn serve_replication(cluster: KVCluster, listener: TcpListener) -> std::io::Result<()> {
+    let metrics = Arc::new(ReceiverMetrics::default());
+    cluster.state.replication.lock().unwrap_or_else(PoisonError::into_inner).receiver = Some(metrics.clone());
+    let applier = Arc::new(LwwApplier {
+        cluster,
+        last_write: DashMap::new(),
+        metrics,
+    });
+
+    let pruner = Arc::downgrade(&applier);
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(LWW_RETENTION / 4);
+        loop {
+            interval.tick().await;
+            match pruner.upgrade() {
+                Some(applier) => applier.prune(),
+                None => break,
+            }
+        }
+    });
+
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        info!(%peer, "replication source connected");
+        let applier = applier.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_source(stream, &applier).await {
+                warn!(%peer, "replication source disconnected: {}", e);
+            }
+        });
+    }
+}
+
+async fn handle_source(stream: TcpStream, applier: &LwwApplier) -> std::io::Result<()> {
+    let (read, mut write) = stream.into_split();
+    let mut lines = BufReader::new(read).lines();
+    while let Some(line) = lines.next_line().await? {
+        match serde_json::from_str::<ReplicationBatch>(&line) {
+            Ok(batch) => {
+                for event in batch.events {
+                    applier.apply(event).await;
+                }
+                write.write_all(b"ok\n").await?;
+            }
+            Err(e) => {
+                write.write_all(format!("error: {}\n", e).as_bytes()).await?;
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, e));
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,209 @@
+//! Where node background tasks run, and how much time they spend running.
+//!
+//! By default each node's operation consumer and TTL sweeper share the
+//! runtime of the caller, so a node working through an expiry storm competes
+//! with request handling for worker threads. A dedicated runtime, shared by
+//! all nodes or one per node, keeps that work on threads of its own.
+
+use serde::Serialize;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Instant as WallInstant;
+use tokio::runtime::{Builder, Handle, Runtime};
+
+/// Where node background tasks run
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NodeRuntime {
+    /// On the runtime nodes are added from
+    #[default]
+    Shared,
+    /// On one runtime with this many worker threads, shared by every node
+    Dedicated { worker_threads: usize },
+    /// On a runtime of its own for each node, with this many worker threads
+    PerNode { worker_threads: usize },
+}
+
+impl std::str::FromStr for NodeRuntime {
+    type Err = String;
+
+    /// Parses `shared`, `dedicated[:threads]` or `per-node[:threads]`. The
+    /// thread count defaults to 1.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim().to_ascii_lowercase();
+        let (mode, threads) = s.split_once(':').unwrap_or((&s, "1"));
+        let worker_threads = match threads.parse() {
+            Ok(0) | Err(_) => return Err(format!("invalid node runtime '{}': thread count must be a positive number", s)),
+            Ok(threads) => threads,
+        };
+        match mode {
+            "shared" => Ok(NodeRuntime::Shared),
+            "dedicated" => Ok(NodeRuntime::Dedicated { worker_threads }),
+            "per-node" | "pernode" => Ok(NodeRuntime::PerNode { worker_threads }),
+            _ => Err(format!(
+                "invalid node runtime '{}': expected shared, dedicated[:threads] or per-node[:threads]",
+                s
+            )),
+        }
+    }
+}
+
+impl NodeRuntime {
+    fn label(self) -> &'static str {
+        match self {
+            NodeRuntime::Shared => "shared",
+            NodeRuntime::Dedicated { .. } => "dedicated",
+            NodeRuntime::PerNode { .. } => "per-node",
+        }
+    }
+}
+
+/// A runtime built for node tasks. It is shut down without waiting for its
+/// tasks, since a cluster may be dropped from inside another runtime, where
+/// blocking on them is not allowed.
+struct OwnedRuntime(Option<Runtime>);
+
+impl Drop for OwnedRuntime {
+    fn drop(&mut self) {
+        if let Some(runtime) = self.0.take() {
+            runtime.shutdown_background();
+        }
+    }
+}
+
+fn build_runtime(worker_threads: usize, thread_name: String) -> std::io::Result<Arc<OwnedRuntime>> {
+    let runtime = Builder::new_multi_thread()
+        .worker_threads(worker_threads)
+        .thread_name(thread_name)
+        .enable_all()
+        .build()?;
+    Ok(Arc::new(OwnedRuntime(Some(runtime))))
+}
+
+/// The runtime a node's tasks are spawned on
+#[derive(Clone)]
+pub(crate) struct TaskRuntime {
+    handle: Handle,
+    /// Keeps a built runtime alive for as long as a node uses it
+    _owned: Option<Arc<OwnedRuntime>>,
+    placement: NodeRuntime,
+}
+
+impl TaskRuntime {
+    pub(crate) fn spawn<F>(&self, task: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        self.handle.spawn(task);
+    }
+}
+
+/// Hands out task runtimes to nodes as they are added
+#[derive(Clone, Default)]
+pub(crate) struct RuntimePlacement {
+    config: NodeRuntime,
+    /// Runtime of every node under `NodeRuntime::Dedicated`
+    dedicated: Option<Arc<OwnedRuntime>>,
+}
+
+impl RuntimePlacement {
+    pub(crate) fn new(config: NodeRuntime) -> std::io::Result<Self> {
+        let dedicated = match config {
+            NodeRuntime::Dedicated { worker_threads } => Some(build_runtime(worker_threads, "volt-nodes".to_string())?),
+            _ => None,
+        };
+        Ok(RuntimePlacement { config, dedicated })
+    }
+
+    pub(crate) fn config(&self) -> NodeRuntime {
+        self.config
+    }
+
+    /// The runtime for the tasks of a new node. Under `Shared`, this must be
+    /// called from within a tokio runtime.
+    pub(crate) fn for_node(&self, node_id: &str) -> TaskRuntime {
+        let owned = match self.config {
+            NodeRuntime::Shared => None,
+            NodeRuntime::Dedicated { .. } => self.dedicated.clone(),
+            NodeRuntime::PerNode { worker_threads } => Some(
+                build_runtime(worker_threads, format!("volt-{}", node_id))
+                    .unwrap_or_else(|e| panic!("failed to start the runtime of node {}: {}", node_id, e)),
+            ),
+        };
+        let handle = match &owned {
+            Some(runtime) => runtime.0.as_ref().expect("runtime is only taken on drop").handle().clone(),
+            None => Handle::current(),
+        };
+        TaskRuntime {
+            handle,
+            _owned: owned,
+            placement: self.config,
+        }
+    }
+}
+
+/// Time a task has spent being polled, a close stand-in for its CPU time
+/// since node tasks do not block
+#[derive(Default)]
+pub(crate) struct TaskTime {
+    busy_ns: AtomicU64,
+    polls: AtomicU64,
+}
+
+impl TaskTime {
+    /// Runs `task`, adding the time spent in each of its polls
+    pub(crate) async fn run<F: Future>(&self, task: F) -> F::Output {
+        let mut task = std::pin::pin!(task);
+        std::future::poll_fn(|cx| {
+            let started = WallInstant::now();
+            let poll = task.as_mut().poll(cx);
+            self.busy_ns
+                .fetch_add(started.elapsed().as_nanos() as u64, Ordering::Relaxed);
+            self.polls.fetch_add(1, Ordering::Relaxed);
+            poll
+        })
+        .await
+    }
+
+    fn snapshot(&self) -> TaskTimeStats {
+        TaskTimeStats {
+            busy_us: self.busy_ns.load(Ordering::Relaxed) / 1_000,
+            polls: self.polls.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Time one node task has spent running
+#[derive(Serialize, Debug, Clone, Copy)]
+pub struct TaskTimeStats {
+    /// Total time spent polling the task, in microseconds
+    pub busy_us: u64,
+    pub polls: u64,
+}
+
+/// Running time of a node's background tasks
+#[derive(Default)]
+pub(crate) struct NodeTaskTimes {
+    pub(crate) ops: TaskTime,
+    pub(crate) sweeper: TaskTime,
+}
+
+/// Where a node's background tasks run and how long they have run
+#[derive(Serialize, Debug, Clone)]
+pub struct NodeTaskStats {
+    /// `shared`, `dedicated` or `per-node`, or `none` for a node that runs
+    /// no background tasks
+    pub runtime: &'static str,
+    pub ops: TaskTimeStats,
+    pub sweeper: TaskTimeStats,
+}
+
+impl NodeTaskTimes {
+    pub(crate) fn snapshot(&self, runtime: Option<&TaskRuntime>) -> NodeTaskStats {
+        NodeTaskStats {
+            runtime: runtime.map_or("none", |runtime| runtime.placement.label()),
+            ops: self.ops.snapshot(),
+            sweeper: self.sweeper.snapshot(),
+        }
+    }
+}
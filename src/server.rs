@@ -4,9 +4,18 @@ use tracing::{info, Level};
 use tracing_subscriber::FmtSubscriber;
 
 use crate::KVCluster;
-use crate::api::create_api_router;
+use crate::api::create_api_router_with_config;
+use crate::config::VoltConfig;
 
 pub async fn run_server(cluster: KVCluster, addr: SocketAddr) -> Result<(), Box<dyn std::error::Error>> {
+    run_server_with_config(cluster, addr, VoltConfig::default()).await
+}
+
+pub async fn run_server_with_config(
+    cluster: KVCluster,
+    addr: SocketAddr,
+    config: VoltConfig,
+) -> Result<(), Box<dyn std::error::Error>> {
     // Initialize tracing
     let subscriber = FmtSubscriber::builder()
         .with_max_level(Level::INFO)
@@ -14,16 +23,22 @@ pub async fn run_server(cluster: KVCluster, addr: SocketAddr) -> Result<(), Box<
     tracing::subscriber::set_global_default(subscriber)
         .expect("Failed to set tracing subscriber");
 
+    if let Some(push) = &config.metrics_push {
+        info!("Pushing metrics to {:?} every {:?}", push.target, push.interval);
+        cluster.start_metrics_push(push.target.sink(), push.prefix.clone(), push.interval);
+    }
+
     // Create shared state
     let shared_cluster = Arc::new(cluster);
     
     // Build the API router
-    let app = create_api_router(shared_cluster).await;
+    let app = create_api_router_with_config(shared_cluster, &config).await;
     
     // Start the server
     info!("Starting Volt server on {}", addr);
     let listener = tokio::net::TcpListener::bind(addr).await?;
-    axum::serve(listener, app).await?;
+    // Peer addresses identify clients for rate limiting
+    axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>()).await?;
     
     Ok(())
 } 
\ No newline at end of file
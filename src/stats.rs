@@ -0,0 +1,83 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::atomic::Ordering;
+
+use crate::consistency::ApplyLagStats;
+use crate::failover::NodeStatus;
+use crate::hedge::HedgeStats;
+use crate::interrupt::InterruptStats;
+use crate::quota::QuotaUsage;
+use crate::replication::ReplicationStats;
+use crate::runtime::NodeTaskStats;
+use crate::tracking::TrackingStats;
+use crate::{KVCluster, KVNode};
+
+/// Point-in-time counters for a single node
+#[derive(Serialize, Debug, Clone)]
+pub struct NodeStats {
+    pub id: String,
+    pub status: NodeStatus,
+    pub keys: usize,
+    pub keys_with_ttl: usize,
+    pub replica_backlog: usize,
+    pub task_restarts: u64,
+    /// Replica operations skipped because a later write to the same key superseded them
+    pub ops_coalesced: u64,
+    /// How far behind the primaries this node applies replica operations
+    #[serde(flatten)]
+    pub apply_lag: ApplyLagStats,
+    /// Where the node's background tasks run and the time they have taken
+    pub tasks: NodeTaskStats,
+}
+
+/// Point-in-time counters for the whole cluster
+#[derive(Serialize, Debug, Clone)]
+pub struct ClusterStats {
+    pub node_count: usize,
+    pub replication_factor: usize,
+    pub task_restarts: u64,
+    pub nodes: Vec<NodeStats>,
+    /// Reads through `mget_hedged` and how many fell back to replicas
+    pub hedging: HedgeStats,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub replication: Option<ReplicationStats>,
+    /// Usage of each namespace's key quota
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub namespace_quotas: BTreeMap<String, QuotaUsage>,
+    /// Clients tracking their reads for client-side caching
+    pub tracking: TrackingStats,
+    /// Writes whose caller stopped waiting before they completed
+    pub interrupted: InterruptStats,
+}
+
+fn node_stats(node: &KVNode, status: NodeStatus) -> NodeStats {
+    NodeStats {
+        id: node.id.clone(),
+        status,
+        keys: node.store.len(),
+        keys_with_ttl: node.expiries.len(),
+        replica_backlog: node.tx.max_capacity() - node.tx.capacity(),
+        task_restarts: node.task_restarts.load(Ordering::Relaxed),
+        ops_coalesced: node.ops_coalesced.load(Ordering::Relaxed),
+        apply_lag: node.apply_lag.snapshot(),
+        tasks: node.task_times.snapshot(node.runtime.as_ref()),
+    }
+}
+
+impl KVCluster {
+    /// Collects per-node counters. Key counts include replica copies.
+    pub fn stats(&self) -> ClusterStats {
+        let nodes: Vec<NodeStats> = self.nodes.iter().map(|n| node_stats(n, self.node_status(&n.id))).collect();
+        ClusterStats {
+            node_count: nodes.len(),
+            replication_factor: self.replication_factor,
+            task_restarts: nodes.iter().map(|n| n.task_restarts).sum(),
+            nodes,
+            hedging: self.state.hedges.snapshot(),
+            replication: self.replication_stats(),
+            namespace_quotas: self.quota_usage(),
+            tracking: self.tracking_stats(),
+            interrupted: self.state.interrupts.snapshot(),
+        }
+    }
+}
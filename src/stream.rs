@@ -0,0 +1,390 @@
+//! Streams: append-only logs of field maps stored under a single key.
+//!
+//! Entries get increasing ids of the form `<unix ms>-<seq>`. Readers either
+//! track their own position with `xread`, or share a consumer group whose
+//! offset advances as its members read with `xread_group`. Streams are
+//! capped in length and may also drop entries past a maximum age; a stream
+//! with a maximum age expires that long after its last append.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::changes::unix_millis;
+use crate::crdt::EncodedValue;
+use crate::{KVCluster, KVError, WriteOptions};
+
+/// Prefix marking a stored value as an encoded stream
+pub const STREAM_MAGIC: &[u8] = b"\0stream:";
+
+/// Length cap of streams created without one
+pub const DEFAULT_STREAM_MAX_LEN: usize = 10_000;
+
+/// Id of a stream entry. Ids order entries by append time.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct StreamId {
+    pub ms: u64,
+    pub seq: u64,
+}
+
+impl StreamId {
+    /// Sorts before every entry; reading from it returns the whole stream
+    pub const ZERO: StreamId = StreamId { ms: 0, seq: 0 };
+}
+
+impl fmt::Display for StreamId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.ms, self.seq)
+    }
+}
+
+impl FromStr for StreamId {
+    type Err = String;
+
+    /// Parses `<ms>-<seq>`, or `<ms>` for sequence 0
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (ms, seq) = s.trim().split_once('-').unwrap_or((s.trim(), "0"));
+        match (ms.parse(), seq.parse()) {
+            (Ok(ms), Ok(seq)) => Ok(StreamId { ms, seq }),
+            _ => Err(format!("invalid stream id '{}': expected <ms>-<seq>", s)),
+        }
+    }
+}
+
+impl Serialize for StreamId {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for StreamId {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// One appended record
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StreamEntry {
+    pub id: StreamId,
+    pub fields: BTreeMap<String, String>,
+}
+
+/// Trimming applied to a stream on every append
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StreamLimits {
+    /// Entries kept, at least 1; the oldest are dropped first
+    pub max_len: usize,
+    /// Age, by entry id, past which entries are dropped
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_age_ms: Option<u64>,
+}
+
+impl Default for StreamLimits {
+    fn default() -> Self {
+        StreamLimits {
+            max_len: DEFAULT_STREAM_MAX_LEN,
+            max_age_ms: None,
+        }
+    }
+}
+
+/// Stored state of a stream
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct Stream {
+    entries: VecDeque<StreamEntry>,
+    last_id: StreamId,
+    limits: StreamLimits,
+    /// Last id delivered to each consumer group
+    groups: BTreeMap<String, StreamId>,
+}
+
+impl EncodedValue for Stream {
+    const DESCRIPTION: &'static str = "a stream";
+
+    fn decode(bytes: &[u8]) -> Option<Result<Self, KVError>> {
+        bytes
+            .strip_prefix(STREAM_MAGIC)
+            .map(|state| serde_json::from_slice(state).map_err(KVError::from))
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut bytes = STREAM_MAGIC.to_vec();
+        // Serializing a stream cannot fail: all map keys are strings
+        serde_json::to_writer(&mut bytes, self).expect("stream state is serializable");
+        bytes
+    }
+}
+
+impl Stream {
+    fn append(&mut self, fields: BTreeMap<String, String>, now_ms: u64) -> StreamId {
+        // Ids keep increasing even if the clock steps back
+        let id = if now_ms > self.last_id.ms {
+            StreamId { ms: now_ms, seq: 0 }
+        } else {
+            StreamId {
+                ms: self.last_id.ms,
+                seq: self.last_id.seq + 1,
+            }
+        };
+        self.entries.push_back(StreamEntry { id, fields });
+        self.last_id = id;
+        self.trim(now_ms);
+        id
+    }
+
+    fn trim(&mut self, now_ms: u64) {
+        while self.entries.len() > self.limits.max_len.max(1) {
+            self.entries.pop_front();
+        }
+        if let Some(max_age) = self.limits.max_age_ms {
+            let oldest_kept = now_ms.saturating_sub(max_age);
+            while self.entries.front().is_some_and(|entry| entry.id.ms < oldest_kept) {
+                self.entries.pop_front();
+            }
+        }
+    }
+
+    /// Up to `count` entries with ids after `after`
+    fn read_after(&self, after: StreamId, count: usize) -> Vec<StreamEntry> {
+        let start = self.entries.partition_point(|entry| entry.id <= after);
+        self.entries.iter().skip(start).take(count).cloned().collect()
+    }
+}
+
+fn no_such_group(key: &str, group: &str) -> KVError {
+    KVError::KeyNotFound(format!("{} (consumer group '{}')", key, group))
+}
+
+impl KVCluster {
+    /// Appends an entry to the stream at `key`, creating the stream with the
+    /// default limits if needed, and returns the entry's id
+    pub async fn xadd(&self, key: &str, fields: BTreeMap<String, String>) -> Result<StreamId, KVError> {
+        self.xadd_with_options(key, fields, None, WriteOptions::default()).await
+    }
+
+    /// Appends an entry, replacing the stream's limits when `limits` is set.
+    /// A stream with a maximum age expires that long after its last append.
+    pub async fn xadd_with_options(
+        &self,
+        key: &str,
+        fields: BTreeMap<String, String>,
+        limits: Option<StreamLimits>,
+        options: WriteOptions,
+    ) -> Result<StreamId, KVError> {
+        let mut id = StreamId::ZERO;
+        self.update_encoded(key.as_bytes(), options, |current: Option<Stream>| {
+            let mut stream = current.unwrap_or_default();
+            if let Some(limits) = limits {
+                stream.limits = limits;
+            }
+            id = stream.append(fields, unix_millis());
+            let ttl = stream.limits.max_age_ms.map(Duration::from_millis);
+            Ok((stream, ttl))
+        })
+        .await?;
+        Ok(id)
+    }
+
+    async fn read_stream(&self, key: &str) -> Result<Option<Stream>, KVError> {
+        match self.get(key).await {
+            Some(bytes) => match Stream::decode(&bytes) {
+                Some(stream) => stream.map(Some),
+                None => Err(KVError::WrongType(format!("'{}' does not hold a stream", key))),
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// Up to `count` entries of the stream at `key` with ids after `after`.
+    /// A missing stream reads as empty.
+    pub async fn xread(&self, key: &str, after: StreamId, count: usize) -> Result<Vec<StreamEntry>, KVError> {
+        let Some(mut stream) = self.read_stream(key).await? else {
+            return Ok(Vec::new());
+        };
+        stream.trim(unix_millis());
+        Ok(stream.read_after(after, count))
+    }
+
+    /// Number of entries in the stream at `key`
+    pub async fn xlen(&self, key: &str) -> Result<usize, KVError> {
+        let Some(mut stream) = self.read_stream(key).await? else {
+            return Ok(0);
+        };
+        stream.trim(unix_millis());
+        Ok(stream.entries.len())
+    }
+
+    /// Creates a consumer group on the stream at `key`, or moves an existing
+    /// group, so its next read starts after `after`
+    pub async fn xgroup_create(&self, key: &str, group: &str, after: StreamId) -> Result<(), KVError> {
+        self.update_encoded(key.as_bytes(), WriteOptions::default(), |current: Option<Stream>| {
+            let mut stream = current.ok_or_else(|| KVError::KeyNotFound(key.to_string()))?;
+            stream.groups.insert(group.to_string(), after);
+            Ok((stream, None))
+        })
+        .await?;
+        Ok(())
+    }
+
+    /// Consumer groups of the stream at `key` with the last id each was
+    /// delivered
+    pub async fn xgroups(&self, key: &str) -> Result<BTreeMap<String, StreamId>, KVError> {
+        Ok(self.read_stream(key).await?.map(|stream| stream.groups).unwrap_or_default())
+    }
+
+    /// Delivers up to `count` entries the group has not seen yet and advances
+    /// its offset past them. Each entry goes to one member of the group;
+    /// there is no redelivery.
+    pub async fn xread_group(&self, key: &str, group: &str, count: usize) -> Result<Vec<StreamEntry>, KVError> {
+        let mut delivered = Vec::new();
+        self.update_encoded(key.as_bytes(), WriteOptions::default(), |current: Option<Stream>| {
+            let mut stream = current.ok_or_else(|| KVError::KeyNotFound(key.to_string()))?;
+            stream.trim(unix_millis());
+            let offset = *stream.groups.get(group).ok_or_else(|| no_such_group(key, group))?;
+            delivered = stream.read_after(offset, count);
+            if let Some(last) = delivered.last() {
+                stream.groups.insert(group.to_string(), last.id);
+            }
+            Ok((stream, None))
+        })
+        .await?;
+        Ok(delivered)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cluster() -> KVCluster {
+        let mut cluster = KVCluster::new(16, 3);
+        for i in 0..3 {
+            cluster.add_node(format!("node{}", i));
+        }
+        cluster
+    }
+
+    fn fields(n: usize) -> BTreeMap<String, String> {
+        BTreeMap::from([("n".to_string(), n.to_string())])
+    }
+
+    fn numbers(entries: &[StreamEntry]) -> Vec<String> {
+        entries.iter().map(|entry| entry.fields["n"].clone()).collect()
+    }
+
+    #[test]
+    fn ids_parse_and_print_as_ms_dash_seq() {
+        let id: StreamId = "1700000000000-3".parse().unwrap();
+        assert_eq!(id, StreamId { ms: 1_700_000_000_000, seq: 3 });
+        assert_eq!(id.to_string(), "1700000000000-3");
+        assert_eq!("42".parse::<StreamId>().unwrap(), StreamId { ms: 42, seq: 0 });
+        assert!("abc".parse::<StreamId>().is_err());
+        assert!("1-x".parse::<StreamId>().is_err());
+        assert!(StreamId { ms: 1, seq: 9 } < StreamId { ms: 2, seq: 0 });
+    }
+
+    #[test]
+    fn ids_keep_increasing_when_the_clock_stalls_or_steps_back() {
+        let mut stream = Stream::default();
+        let first = stream.append(fields(0), 100);
+        let same_ms = stream.append(fields(1), 100);
+        let stepped_back = stream.append(fields(2), 90);
+        let later = stream.append(fields(3), 200);
+        assert_eq!(first, StreamId { ms: 100, seq: 0 });
+        assert_eq!(same_ms, StreamId { ms: 100, seq: 1 });
+        assert_eq!(stepped_back, StreamId { ms: 100, seq: 2 });
+        assert_eq!(later, StreamId { ms: 200, seq: 0 });
+    }
+
+    #[test]
+    fn appends_trim_to_the_length_and_age_limits() {
+        let mut stream = Stream {
+            limits: StreamLimits {
+                max_len: 3,
+                max_age_ms: None,
+            },
+            ..Stream::default()
+        };
+        for n in 0..5 {
+            stream.append(fields(n), 100 + n as u64);
+        }
+        assert_eq!(numbers(&stream.read_after(StreamId::ZERO, 10)), ["2", "3", "4"]);
+
+        stream.limits.max_age_ms = Some(10);
+        stream.append(fields(5), 114);
+        assert_eq!(numbers(&stream.read_after(StreamId::ZERO, 10)), ["4", "5"]);
+
+        // A length cap of 0 still keeps the newest entry
+        stream.limits = StreamLimits {
+            max_len: 0,
+            max_age_ms: None,
+        };
+        stream.append(fields(6), 115);
+        assert_eq!(numbers(&stream.read_after(StreamId::ZERO, 10)), ["6"]);
+    }
+
+    #[tokio::test]
+    async fn reads_return_entries_after_an_id() {
+        let cluster = cluster();
+        assert!(cluster.xread("events", StreamId::ZERO, 10).await.unwrap().is_empty());
+        assert_eq!(cluster.xlen("events").await.unwrap(), 0);
+
+        let mut ids = Vec::new();
+        for n in 0..4 {
+            ids.push(cluster.xadd("events", fields(n)).await.unwrap());
+        }
+        assert!(ids.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(cluster.xlen("events").await.unwrap(), 4);
+
+        let all = cluster.xread("events", StreamId::ZERO, 10).await.unwrap();
+        assert_eq!(numbers(&all), ["0", "1", "2", "3"]);
+        let after = cluster.xread("events", ids[1], 1).await.unwrap();
+        assert_eq!(numbers(&after), ["2"]);
+        assert!(cluster.xread("events", ids[3], 10).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn group_members_share_one_offset() {
+        let cluster = cluster();
+        assert!(matches!(
+            cluster.xgroup_create("events", "workers", StreamId::ZERO).await,
+            Err(KVError::KeyNotFound(_))
+        ));
+
+        for n in 0..3 {
+            cluster.xadd("events", fields(n)).await.unwrap();
+        }
+        cluster.xgroup_create("events", "workers", StreamId::ZERO).await.unwrap();
+        assert!(matches!(
+            cluster.xread_group("events", "nobody", 10).await,
+            Err(KVError::KeyNotFound(_))
+        ));
+
+        let first = cluster.xread_group("events", "workers", 2).await.unwrap();
+        let second = cluster.xread_group("events", "workers", 2).await.unwrap();
+        assert_eq!(numbers(&first), ["0", "1"]);
+        assert_eq!(numbers(&second), ["2"]);
+        assert!(cluster.xread_group("events", "workers", 2).await.unwrap().is_empty());
+        assert_eq!(cluster.xgroups("events").await.unwrap()["workers"], second[0].id);
+
+        // Moving the group back redelivers from there
+        cluster.xgroup_create("events", "workers", first[0].id).await.unwrap();
+        let again = cluster.xread_group("events", "workers", 10).await.unwrap();
+        assert_eq!(numbers(&again), ["1", "2"]);
+    }
+
+    #[tokio::test]
+    async fn other_values_are_not_streams() {
+        let cluster = cluster();
+        cluster.set("plain", b"value".to_vec(), None).await.unwrap();
+        assert!(matches!(
+            cluster.xread("plain", StreamId::ZERO, 10).await,
+            Err(KVError::WrongType(_))
+        ));
+        assert!(cluster.xadd("plain", fields(0)).await.is_err());
+    }
+}
@@ -0,0 +1,74 @@
+use serde::Serialize;
+
+use crate::failover::NodeStatus;
+use crate::KVCluster;
+
+/// Hash function used to place keys and virtual nodes on the ring
+pub const RING_HASH_ALGORITHM: &str = "xxh32";
+
+/// Seed passed to the ring hash function
+pub const RING_HASH_SEED: u32 = 0;
+
+#[derive(Serialize, Debug, Clone)]
+pub struct TopologyNode {
+    pub id: String,
+    /// Address serving this node directly, if one was advertised. Clients
+    /// fall back to the server they fetched the topology from.
+    pub addr: Option<String>,
+    /// While a node is down, each of its keys is served by the next node
+    /// after it on the key's preference list that is up
+    pub status: NodeStatus,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct VirtualNode {
+    pub hash: u32,
+    pub node: String,
+}
+
+/// Snapshot of the hash ring, enough for a client to route keys itself.
+/// A key belongs to the first virtual node whose hash is >= the key's hash,
+/// wrapping around to the lowest hash.
+#[derive(Serialize, Debug, Clone)]
+pub struct RingTopology {
+    pub version: u64,
+    pub hash_algorithm: &'static str,
+    pub hash_seed: u32,
+    pub replication_factor: usize,
+    pub nodes: Vec<TopologyNode>,
+    pub vnodes: Vec<VirtualNode>,
+}
+
+impl KVCluster {
+    /// Version of the ring layout, bumped whenever nodes, their addresses or
+    /// their status change
+    pub fn ring_version(&self) -> u64 {
+        self.ring_version + self.state.failover.changes()
+    }
+
+    pub fn topology(&self) -> RingTopology {
+        RingTopology {
+            version: self.ring_version(),
+            hash_algorithm: RING_HASH_ALGORITHM,
+            hash_seed: RING_HASH_SEED,
+            replication_factor: self.replication_factor,
+            nodes: self
+                .nodes
+                .iter()
+                .map(|n| TopologyNode {
+                    id: n.id.clone(),
+                    addr: self.node_addrs.get(&n.id).cloned(),
+                    status: self.node_status(&n.id),
+                })
+                .collect(),
+            vnodes: self
+                .ring
+                .iter()
+                .map(|(hash, idx)| VirtualNode {
+                    hash: *hash,
+                    node: self.nodes[*idx].id.clone(),
+                })
+                .collect(),
+        }
+    }
+}
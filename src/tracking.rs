@@ -0,0 +1,194 @@
+//! Client tracking for client-side caching: the cluster remembers which
+//! connected clients read which keys, and tells them when those keys change
+//! or expire so they can drop their cached copies.
+//!
+//! A key is tracked for a client from its read until the next invalidation,
+//! after which the client must read it again to be told of later changes.
+//! A client whose invalidation queue fills up is disconnected rather than
+//! left with stale entries, and should clear its cache when that happens.
+
+use dashmap::DashMap;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::sync::mpsc;
+
+use crate::{KVCluster, Key};
+
+/// Invalidations queued for a client before it is disconnected
+const CLIENT_QUEUE_CAPACITY: usize = 4096;
+
+/// Keys tracked across all clients unless configured otherwise
+pub const DEFAULT_TRACKING_MAX_KEYS: usize = 1_000_000;
+
+/// Identifies a tracking client in the reads it makes
+pub type ClientId = u64;
+
+/// A message pushed to a tracking client
+#[derive(Serialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TrackingMessage {
+    /// First message on a connection, with the id to send along with reads
+    Hello { client_id: ClientId },
+    /// Keys that changed or expired since the client read them
+    Invalidate { keys: Vec<Key> },
+}
+
+/// Which clients read which keys
+pub(crate) struct Tracking {
+    next_id: AtomicU64,
+    clients: DashMap<ClientId, mpsc::Sender<Key>>,
+    readers: DashMap<Key, HashSet<ClientId>>,
+    max_keys: AtomicUsize,
+    invalidations: AtomicU64,
+    disconnects: AtomicU64,
+}
+
+impl Default for Tracking {
+    fn default() -> Self {
+        Tracking {
+            next_id: AtomicU64::new(1),
+            clients: DashMap::new(),
+            readers: DashMap::new(),
+            max_keys: AtomicUsize::new(DEFAULT_TRACKING_MAX_KEYS),
+            invalidations: AtomicU64::new(0),
+            disconnects: AtomicU64::new(0),
+        }
+    }
+}
+
+impl Tracking {
+    pub(crate) fn is_active(&self) -> bool {
+        !self.clients.is_empty()
+    }
+
+    /// Tracks `key` for `client`. Returns false if no such client is connected.
+    fn record(&self, client: ClientId, key: &[u8]) -> bool {
+        if !self.clients.contains_key(&client) {
+            return false;
+        }
+        if let Some(mut readers) = self.readers.get_mut(key) {
+            readers.insert(client);
+            return true;
+        }
+        // Past the limit, make room by invalidating some other key early
+        if self.readers.len() >= self.max_keys.load(Ordering::Relaxed) {
+            let victim = self.readers.iter().next().map(|entry| entry.key().clone());
+            if let Some(victim) = victim {
+                self.invalidate(victim.as_bytes());
+            }
+        }
+        self.readers.entry(Key::from(key)).or_default().insert(client);
+        true
+    }
+
+    /// Tells every client tracking `key` that it changed, and stops tracking it
+    pub(crate) fn invalidate(&self, key: &[u8]) {
+        if !self.is_active() {
+            return;
+        }
+        let Some((key, readers)) = self.readers.remove(key) else {
+            return;
+        };
+        for client in readers {
+            let sent = match self.clients.get(&client) {
+                Some(tx) => tx.try_send(key.clone()).is_ok(),
+                // Already gone
+                None => continue,
+            };
+            if sent {
+                self.invalidations.fetch_add(1, Ordering::Relaxed);
+            } else {
+                // Dropping the sender ends the client's connection
+                self.clients.remove(&client);
+                self.disconnects.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    fn snapshot(&self) -> TrackingStats {
+        TrackingStats {
+            clients: self.clients.len(),
+            tracked_keys: self.readers.len(),
+            invalidations: self.invalidations.load(Ordering::Relaxed),
+            disconnects: self.disconnects.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Client tracking counters
+#[derive(Serialize, Debug, Clone)]
+pub struct TrackingStats {
+    /// Connected tracking clients
+    pub clients: usize,
+    /// Keys read by at least one client since they last changed
+    pub tracked_keys: usize,
+    /// Invalidations queued for clients
+    pub invalidations: u64,
+    /// Clients disconnected because they fell behind on invalidations
+    pub disconnects: u64,
+}
+
+/// A connected tracking client. Dropping it stops tracking for the client.
+pub struct TrackedClient {
+    id: ClientId,
+    invalidations: mpsc::Receiver<Key>,
+    tracking: Arc<Tracking>,
+}
+
+impl TrackedClient {
+    pub fn id(&self) -> ClientId {
+        self.id
+    }
+
+    /// The next key to drop from the client's cache. Returns `None` once the
+    /// client has been disconnected for falling behind.
+    pub async fn recv(&mut self) -> Option<Key> {
+        self.invalidations.recv().await
+    }
+
+    /// Like `recv`, without waiting
+    pub fn try_recv(&mut self) -> Option<Key> {
+        self.invalidations.try_recv().ok()
+    }
+}
+
+impl Drop for TrackedClient {
+    fn drop(&mut self) {
+        self.tracking.clients.remove(&self.id);
+    }
+}
+
+impl KVCluster {
+    /// Connects a tracking client, which is told of changes to the keys
+    /// recorded for it with `track_read`
+    pub fn track_client(&self) -> TrackedClient {
+        let tracking = self.state.tracking.clone();
+        let id = tracking.next_id.fetch_add(1, Ordering::Relaxed);
+        let (tx, rx) = mpsc::channel(CLIENT_QUEUE_CAPACITY);
+        tracking.clients.insert(id, tx);
+        TrackedClient {
+            id,
+            invalidations: rx,
+            tracking,
+        }
+    }
+
+    /// Records that `client` is about to read `key`. Call it before the
+    /// read, so a write landing in between is still announced. Returns false
+    /// if the client is no longer connected.
+    pub fn track_read(&self, client: ClientId, key: impl AsRef<[u8]>) -> bool {
+        self.state.tracking.record(client, key.as_ref())
+    }
+
+    /// Caps how many keys are tracked across all clients. Past the cap,
+    /// tracking a new key invalidates an arbitrary tracked one.
+    pub fn set_tracking_max_keys(&self, max_keys: usize) {
+        self.state.tracking.max_keys.store(max_keys.max(1), Ordering::Relaxed);
+    }
+
+    pub fn tracking_stats(&self) -> TrackingStats {
+        self.state.tracking.snapshot()
+    }
+}
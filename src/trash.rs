@@ -0,0 +1,265 @@
+//! Soft delete: in namespaces configured for it, deleted keys are kept in a
+//! trash area for a retention window, during which they can be restored.
+//!
+//! Trashed entries still honour the TTL they were deleted with, so restoring
+//! a key never brings it back past the time it would have expired anyway.
+//! The trash holds values of up to a total size; past it, the entries due to
+//! be purged soonest make room.
+
+use priority_queue::PriorityQueue;
+use serde::Serialize;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, MutexGuard, PoisonError, RwLock};
+use std::time::Duration;
+use tokio::time::Instant;
+
+use crate::key::{key_name, namespace_of};
+use crate::{KVCluster, KVEntry, KVError, Key, WriteOptions};
+
+/// Total size of the values the trash holds unless configured otherwise
+pub const DEFAULT_TRASH_CAPACITY: usize = 64 * 1024 * 1024;
+
+/// A deleted entry awaiting restore or purge
+struct TrashedEntry {
+    /// Tells this deletion of the key from later ones
+    id: u64,
+    value: Vec<u8>,
+    /// Original expiry of the entry, if it had a TTL
+    expiry: Option<Instant>,
+    sliding: bool,
+    deleted_at: Instant,
+    purge_at: Instant,
+}
+
+#[derive(Default)]
+struct TrashContents {
+    entries: HashMap<Key, TrashedEntry>,
+    /// Keys by purge time, soonest first
+    purge_queue: PriorityQueue<Key, Reverse<Instant>>,
+    /// Total size of the trashed values
+    bytes: usize,
+    next_id: u64,
+}
+
+impl TrashContents {
+    /// Drops entries whose retention window or TTL has run out
+    fn prune(&mut self, now: Instant) {
+        while let Some((_, Reverse(purge_at))) = self.purge_queue.peek() {
+            if *purge_at > now {
+                break;
+            }
+            self.pop();
+        }
+    }
+
+    /// Drops the entry due to be purged soonest
+    fn pop(&mut self) {
+        if let Some((key, _)) = self.purge_queue.pop() {
+            if let Some(entry) = self.entries.remove(&key) {
+                self.bytes -= entry.value.len();
+            }
+        }
+    }
+
+    fn insert(&mut self, key: Key, mut entry: TrashedEntry, capacity: usize) {
+        self.remove(key.as_bytes());
+        if entry.value.len() > capacity {
+            return;
+        }
+        while self.bytes + entry.value.len() > capacity {
+            self.pop();
+        }
+        entry.id = self.next_id;
+        self.next_id += 1;
+        self.bytes += entry.value.len();
+        self.purge_queue.push(key.clone(), Reverse(entry.purge_at));
+        self.entries.insert(key, entry);
+    }
+
+    fn remove(&mut self, key: &[u8]) -> Option<TrashedEntry> {
+        self.purge_queue.remove(key);
+        let entry = self.entries.remove(key)?;
+        self.bytes -= entry.value.len();
+        Some(entry)
+    }
+}
+
+/// Soft-delete settings and the trash area of a cluster
+pub(crate) struct Trash {
+    /// Retention window of each namespace that soft-deletes
+    retention: RwLock<HashMap<String, Duration>>,
+    /// Most bytes of values the trash holds
+    capacity: AtomicUsize,
+    contents: Mutex<TrashContents>,
+}
+
+impl Default for Trash {
+    fn default() -> Self {
+        Trash {
+            retention: RwLock::default(),
+            capacity: AtomicUsize::new(DEFAULT_TRASH_CAPACITY),
+            contents: Mutex::default(),
+        }
+    }
+}
+
+impl Trash {
+    /// Locks the trash, dropping entries that are due for purge
+    fn contents(&self, now: Instant) -> MutexGuard<'_, TrashContents> {
+        let mut contents = self.contents.lock().unwrap_or_else(PoisonError::into_inner);
+        contents.prune(now);
+        contents
+    }
+
+    fn retention(&self, key: &[u8]) -> Option<Duration> {
+        let namespace = std::str::from_utf8(namespace_of(key)?).ok()?;
+        let retention = self.retention.read().unwrap_or_else(PoisonError::into_inner);
+        retention.get(namespace).copied()
+    }
+}
+
+/// A key in the trash
+#[derive(Serialize, Debug, Clone)]
+pub struct TrashedKey {
+    pub key: Key,
+    pub size: usize,
+    pub deleted_ms_ago: u64,
+    /// Time until the entry is purged, at the end of its retention window or
+    /// when its TTL would have run out
+    pub purge_in_ms: u64,
+}
+
+impl KVCluster {
+    /// Makes deletes of keys in `namespace` move them to the trash, where
+    /// they can be restored for `retention`. `None` turns soft delete off
+    /// for the namespace; keys already in the trash stay there.
+    pub fn set_soft_delete(&self, namespace: &str, retention: Option<Duration>) {
+        let mut namespaces = self
+            .state
+            .trash
+            .retention
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+        match retention {
+            Some(retention) => {
+                namespaces.insert(namespace.to_string(), retention);
+            }
+            None => {
+                namespaces.remove(namespace);
+            }
+        }
+    }
+
+    /// Caps the total size of the values in the trash. Past it, the entries
+    /// due to be purged soonest are dropped to make room, and a value larger
+    /// than the whole trash is not kept at all.
+    pub fn set_trash_capacity(&self, bytes: usize) {
+        self.state.trash.capacity.store(bytes, Ordering::Relaxed);
+        let mut contents = self.state.trash.contents(Instant::now());
+        while contents.bytes > bytes {
+            contents.pop();
+        }
+    }
+
+    pub fn trash_capacity(&self) -> usize {
+        self.state.trash.capacity.load(Ordering::Relaxed)
+    }
+
+    /// Namespaces that soft-delete, with their retention windows
+    pub fn soft_delete_namespaces(&self) -> BTreeMap<String, Duration> {
+        let namespaces = self.state.trash.retention.read().unwrap_or_else(PoisonError::into_inner);
+        namespaces.iter().map(|(namespace, retention)| (namespace.clone(), *retention)).collect()
+    }
+
+    /// Keeps a deleted entry in the trash if its namespace soft-deletes
+    pub(crate) fn move_to_trash(&self, key: &[u8], entry: KVEntry) {
+        let Some(retention) = self.state.trash.retention(key) else {
+            return;
+        };
+        let now = Instant::now();
+        if entry.is_expired(now) {
+            return;
+        }
+        let purge_at = entry.expiry.map_or(now + retention, |expiry| expiry.min(now + retention));
+        let capacity = self.trash_capacity();
+        let trashed = TrashedEntry {
+            id: 0,
+            value: entry.value.into(),
+            expiry: entry.expiry,
+            sliding: entry.ttl.is_some_and(|ttl| ttl.sliding),
+            deleted_at: now,
+            purge_at,
+        };
+        self.state.trash.contents(now).insert(Key::from(key), trashed, capacity);
+    }
+
+    /// Keys in the trash, sorted
+    pub fn trash(&self) -> Vec<TrashedKey> {
+        let now = Instant::now();
+        let contents = self.state.trash.contents(now);
+        let mut keys: Vec<TrashedKey> = contents
+            .entries
+            .iter()
+            .map(|(key, entry)| TrashedKey {
+                key: key.clone(),
+                size: entry.value.len(),
+                deleted_ms_ago: now.saturating_duration_since(entry.deleted_at).as_millis() as u64,
+                purge_in_ms: entry.purge_at.saturating_duration_since(now).as_millis() as u64,
+            })
+            .collect();
+        keys.sort_by(|a, b| a.key.cmp(&b.key));
+        keys
+    }
+
+    /// Puts a trashed key back with its value and remaining TTL. Fails with
+    /// `KeyNotFound` if the key is not in the trash, and with `KeyExists` if
+    /// it has been written again since it was deleted. The key stays in the
+    /// trash unless it is restored.
+    pub async fn restore(&self, key: impl AsRef<[u8]>) -> Result<(), KVError> {
+        self.restore_with_options(key, WriteOptions::default()).await
+    }
+
+    /// Restores a key with the same timeout and acknowledgement options as
+    /// `set_with_options`
+    pub async fn restore_with_options(&self, key: impl AsRef<[u8]>, options: WriteOptions) -> Result<(), KVError> {
+        let key = key.as_ref();
+        self.check_writable(&options)?;
+        let now = Instant::now();
+        let (id, value, expiry, sliding) = {
+            let contents = self.state.trash.contents(now);
+            let entry = contents
+                .entries
+                .get(key)
+                .ok_or_else(|| KVError::KeyNotFound(key_name(key)))?;
+            (entry.id, entry.value.clone(), entry.expiry, entry.sliding)
+        };
+        // A truncated copy would not be the key that was deleted
+        if let Some(limit) = self.value_limit().filter(|limit| value.len() > limit.max_bytes) {
+            return Err(limit.exceeded(value.len(), false));
+        }
+        let ttl = expiry.map(|expiry| expiry.saturating_duration_since(now));
+        let options = WriteOptions {
+            sliding_ttl: options.sliding_ttl || sliding,
+            ..options
+        };
+        self.store_value(Key::from(key), value, ttl, options, true).await?;
+        // Unless the key was deleted again meanwhile, which trashed a newer entry
+        let mut contents = self.state.trash.contents(Instant::now());
+        if contents.entries.get(key).is_some_and(|entry| entry.id == id) {
+            contents.remove(key);
+        }
+        Ok(())
+    }
+
+    /// Empties the trash, returning the number of keys dropped
+    pub fn purge(&self) -> usize {
+        let mut contents = self.state.trash.contents(Instant::now());
+        let purged = contents.entries.len();
+        contents.entries.clear();
+        contents.purge_queue.clear();
+        contents.bytes = 0;
+        purged
+    }
+}
@@ -0,0 +1,401 @@
+//! Typed values: values stored through a [`Codec`] rather than as raw bytes
+//! or JSON, so services sharing the cache agree on their encoding.
+//!
+//! A codec that names a schema stores it with each value, as
+//! [`TYPED_MAGIC`], the length of the schema id, the id, then the encoded
+//! value. Reads check the stored id against the one the reading codec
+//! expects, so a service does not silently decode data written with a
+//! schema it does not understand. Which ids may read which is up to the
+//! cluster's [`SchemaRegistry`]; without one, only the same id matches.
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::marker::PhantomData;
+use std::sync::{Arc, PoisonError, RwLock};
+use std::time::Duration;
+
+use crate::{KVCluster, KVError, Key, WriteOptions};
+
+/// Prefix of typed values stored with a schema id
+pub const TYPED_MAGIC: &[u8] = b"\0typed:";
+
+/// Longest schema id, so its length fits the single byte before it
+pub const MAX_SCHEMA_ID_LEN: usize = u8::MAX as usize;
+
+/// Converts values of one type to and from the bytes stored for them
+pub trait Codec<T>: Send + Sync {
+    fn encode(&self, value: &T) -> Result<Vec<u8>, KVError>;
+
+    fn decode(&self, bytes: &[u8]) -> Result<T, KVError>;
+
+    /// Schema stored with each value and expected on read; `None` stores
+    /// values without one and reads them unchecked
+    fn schema_id(&self) -> Option<&str> {
+        None
+    }
+}
+
+/// Encodes values as JSON with serde
+pub struct JsonCodec<T> {
+    schema_id: Option<String>,
+    _type: PhantomData<fn() -> T>,
+}
+
+impl<T> JsonCodec<T> {
+    pub fn new() -> Self {
+        JsonCodec {
+            schema_id: None,
+            _type: PhantomData,
+        }
+    }
+
+    pub fn with_schema_id(mut self, schema_id: impl Into<String>) -> Self {
+        self.schema_id = Some(schema_id.into());
+        self
+    }
+}
+
+impl<T> Default for JsonCodec<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Serialize + DeserializeOwned> Codec<T> for JsonCodec<T> {
+    fn encode(&self, value: &T) -> Result<Vec<u8>, KVError> {
+        Ok(serde_json::to_vec(value)?)
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<T, KVError> {
+        Ok(serde_json::from_slice(bytes)?)
+    }
+
+    fn schema_id(&self) -> Option<&str> {
+        self.schema_id.as_deref()
+    }
+}
+
+/// Encodes values as protobuf messages with prost
+#[cfg(feature = "protobuf")]
+pub struct ProtobufCodec<T> {
+    schema_id: Option<String>,
+    _type: PhantomData<fn() -> T>,
+}
+
+#[cfg(feature = "protobuf")]
+impl<T> ProtobufCodec<T> {
+    pub fn new() -> Self {
+        ProtobufCodec {
+            schema_id: None,
+            _type: PhantomData,
+        }
+    }
+
+    /// Stores `schema_id` with each value, e.g. the message's full name and
+    /// version such as `shop.Order.v2`
+    pub fn with_schema_id(mut self, schema_id: impl Into<String>) -> Self {
+        self.schema_id = Some(schema_id.into());
+        self
+    }
+}
+
+#[cfg(feature = "protobuf")]
+impl<T> Default for ProtobufCodec<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "protobuf")]
+impl<T: prost::Message + Default> Codec<T> for ProtobufCodec<T> {
+    fn encode(&self, value: &T) -> Result<Vec<u8>, KVError> {
+        Ok(value.encode_to_vec())
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<T, KVError> {
+        T::decode(bytes).map_err(|e| KVError::WrongType(format!("not a valid protobuf message: {}", e)))
+    }
+
+    fn schema_id(&self) -> Option<&str> {
+        self.schema_id.as_deref()
+    }
+}
+
+/// Decides which schema ids typed values may be written and read with
+pub trait SchemaRegistry: Send + Sync {
+    /// Checked before a value with `schema_id` is stored
+    fn validate(&self, schema_id: &str) -> Result<(), String> {
+        let _ = schema_id;
+        Ok(())
+    }
+
+    /// Whether a value written with the `writer` schema can be read by a
+    /// codec expecting `reader`
+    fn compatible(&self, writer: &str, reader: &str) -> bool {
+        writer == reader
+    }
+}
+
+/// A registry of known schema ids and the older schemas each can read
+#[derive(Debug, Default)]
+pub struct StaticSchemaRegistry {
+    schemas: HashSet<String>,
+    readable: HashSet<(String, String)>,
+}
+
+impl StaticSchemaRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register(mut self, schema_id: impl Into<String>) -> Self {
+        self.schemas.insert(schema_id.into());
+        self
+    }
+
+    /// Lets codecs expecting `reader` read values written with `writer`,
+    /// e.g. a new schema version reading values of the previous one
+    pub fn allow(mut self, writer: impl Into<String>, reader: impl Into<String>) -> Self {
+        self.readable.insert((writer.into(), reader.into()));
+        self
+    }
+}
+
+impl SchemaRegistry for StaticSchemaRegistry {
+    fn validate(&self, schema_id: &str) -> Result<(), String> {
+        if self.schemas.contains(schema_id) {
+            Ok(())
+        } else {
+            Err(format!("schema '{}' is not registered", schema_id))
+        }
+    }
+
+    fn compatible(&self, writer: &str, reader: &str) -> bool {
+        writer == reader || self.readable.contains(&(writer.to_string(), reader.to_string()))
+    }
+}
+
+/// The cluster's schema registry, if one is set
+#[derive(Default)]
+pub(crate) struct Schemas {
+    registry: RwLock<Option<Arc<dyn SchemaRegistry>>>,
+}
+
+impl Schemas {
+    fn registry(&self) -> Option<Arc<dyn SchemaRegistry>> {
+        self.registry.read().unwrap_or_else(PoisonError::into_inner).clone()
+    }
+}
+
+/// Splits a stored value into its schema id, if it has one, and the encoded value
+pub fn split_schema(bytes: &[u8]) -> (Option<&str>, &[u8]) {
+    let Some(rest) = bytes.strip_prefix(TYPED_MAGIC) else {
+        return (None, bytes);
+    };
+    let Some((&len, rest)) = rest.split_first() else {
+        return (None, bytes);
+    };
+    match rest.split_at_checked(len as usize) {
+        Some((schema_id, value)) => match std::str::from_utf8(schema_id) {
+            Ok(schema_id) => (Some(schema_id), value),
+            Err(_) => (None, bytes),
+        },
+        None => (None, bytes),
+    }
+}
+
+impl KVCluster {
+    /// Sets the registry deciding which schema ids typed values may use.
+    /// `None` accepts any id on write and only the same id on read.
+    pub fn set_schema_registry(&self, registry: Option<Arc<dyn SchemaRegistry>>) {
+        *self.state.schemas.registry.write().unwrap_or_else(PoisonError::into_inner) = registry;
+    }
+
+    /// Stores `value` encoded with `codec`, along with the codec's schema id
+    pub async fn set_typed<T>(
+        &self,
+        key: impl Into<Key>,
+        value: &T,
+        codec: &impl Codec<T>,
+        ttl: Option<Duration>,
+    ) -> Result<(), KVError> {
+        self.set_typed_with_options(key, value, codec, ttl, WriteOptions::default())
+            .await
+    }
+
+    pub async fn set_typed_with_options<T>(
+        &self,
+        key: impl Into<Key>,
+        value: &T,
+        codec: &impl Codec<T>,
+        ttl: Option<Duration>,
+        options: WriteOptions,
+    ) -> Result<(), KVError> {
+        let encoded = codec.encode(value)?;
+        let bytes = match codec.schema_id() {
+            Some(schema_id) => {
+                if schema_id.is_empty() || schema_id.len() > MAX_SCHEMA_ID_LEN {
+                    return Err(KVError::WrongType(format!(
+                        "schema id must be 1 to {} bytes long",
+                        MAX_SCHEMA_ID_LEN
+                    )));
+                }
+                if let Some(registry) = self.state.schemas.registry() {
+                    registry.validate(schema_id).map_err(KVError::WrongType)?;
+                }
+                let mut bytes = Vec::with_capacity(TYPED_MAGIC.len() + 1 + schema_id.len() + encoded.len());
+                bytes.extend_from_slice(TYPED_MAGIC);
+                bytes.push(schema_id.len() as u8);
+                bytes.extend_from_slice(schema_id.as_bytes());
+                bytes.extend_from_slice(&encoded);
+                bytes
+            }
+            None => encoded,
+        };
+        self.set_with_options(key, bytes, ttl, options).await
+    }
+
+    /// Retrieves a value stored with `set_typed`. Fails with
+    /// `SchemaMismatch` if the codec expects a schema id the stored value
+    /// was not written with, or one the registry does not let it read.
+    pub async fn get_typed<T>(&self, key: impl AsRef<[u8]>, codec: &impl Codec<T>) -> Result<Option<T>, KVError> {
+        let Some(bytes) = self.get(key).await else {
+            return Ok(None);
+        };
+        let (stored, value) = split_schema(&bytes);
+        if let Some(expected) = codec.schema_id() {
+            let readable = match (stored, self.state.schemas.registry()) {
+                (Some(stored), Some(registry)) => registry.compatible(stored, expected),
+                (Some(stored), None) => stored == expected,
+                (None, _) => false,
+            };
+            if !readable {
+                return Err(KVError::SchemaMismatch {
+                    expected: expected.to_string(),
+                    found: stored.map(String::from),
+                });
+            }
+        }
+        codec.decode(value).map(Some)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Order {
+        id: u32,
+        item: String,
+    }
+
+    fn order() -> Order {
+        Order {
+            id: 7,
+            item: "book".to_string(),
+        }
+    }
+
+    fn cluster() -> KVCluster {
+        let mut cluster = KVCluster::new(16, 3);
+        for i in 0..3 {
+            cluster.add_node(format!("node{}", i));
+        }
+        cluster
+    }
+
+    #[test]
+    fn schema_ids_are_split_from_stored_values() {
+        let mut bytes = TYPED_MAGIC.to_vec();
+        bytes.push(2);
+        bytes.extend_from_slice(b"v1{}");
+        assert_eq!(split_schema(&bytes), (Some("v1"), &b"{}"[..]));
+        assert_eq!(split_schema(b"{}"), (None, &b"{}"[..]));
+
+        // A length past the end of the value is not a schema id
+        let mut truncated = TYPED_MAGIC.to_vec();
+        truncated.push(10);
+        truncated.extend_from_slice(b"v1");
+        assert_eq!(split_schema(&truncated), (None, &truncated[..]));
+    }
+
+    #[tokio::test]
+    async fn values_round_trip_with_and_without_a_schema() {
+        let cluster = cluster();
+        let plain = JsonCodec::new();
+        cluster.set_typed("plain", &order(), &plain, None).await.unwrap();
+        assert_eq!(cluster.get("plain").await, Some(serde_json::to_vec(&order()).unwrap()));
+        assert_eq!(cluster.get_typed("plain", &plain).await.unwrap(), Some(order()));
+
+        let v1 = JsonCodec::new().with_schema_id("shop.Order.v1");
+        cluster.set_typed("order", &order(), &v1, None).await.unwrap();
+        assert_eq!(cluster.get_typed("order", &v1).await.unwrap(), Some(order()));
+        // A codec without a schema id reads any value unchecked
+        assert_eq!(cluster.get_typed("order", &plain).await.unwrap(), Some(order()));
+        assert_eq!(cluster.get_typed::<Order>("missing", &v1).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn reads_expecting_another_schema_are_refused() {
+        let cluster = cluster();
+        let v1 = JsonCodec::new().with_schema_id("shop.Order.v1");
+        let v2 = JsonCodec::new().with_schema_id("shop.Order.v2");
+        cluster.set_typed("order", &order(), &v1, None).await.unwrap();
+        cluster.set_typed("plain", &order(), &JsonCodec::new(), None).await.unwrap();
+
+        match cluster.get_typed::<Order>("order", &v2).await {
+            Err(KVError::SchemaMismatch { expected, found }) => {
+                assert_eq!(expected, "shop.Order.v2");
+                assert_eq!(found.as_deref(), Some("shop.Order.v1"));
+            }
+            other => panic!("expected a schema mismatch, got {:?}", other),
+        }
+        assert!(matches!(
+            cluster.get_typed::<Order>("plain", &v2).await,
+            Err(KVError::SchemaMismatch { found: None, .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn the_registry_decides_which_schemas_are_written_and_read() {
+        let cluster = cluster();
+        cluster.set_schema_registry(Some(Arc::new(
+            StaticSchemaRegistry::new()
+                .register("shop.Order.v1")
+                .register("shop.Order.v2")
+                .allow("shop.Order.v1", "shop.Order.v2"),
+        )));
+        let v1 = JsonCodec::new().with_schema_id("shop.Order.v1");
+        let v2 = JsonCodec::new().with_schema_id("shop.Order.v2");
+        let unknown = JsonCodec::new().with_schema_id("shop.Order.v3");
+
+        assert!(matches!(
+            cluster.set_typed("order", &order(), &unknown, None).await,
+            Err(KVError::WrongType(_))
+        ));
+
+        cluster.set_typed("old", &order(), &v1, None).await.unwrap();
+        assert_eq!(cluster.get_typed("old", &v2).await.unwrap(), Some(order()));
+        cluster.set_typed("new", &order(), &v2, None).await.unwrap();
+        assert!(matches!(
+            cluster.get_typed::<Order>("new", &v1).await,
+            Err(KVError::SchemaMismatch { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn schema_ids_must_fit_their_length_byte() {
+        let cluster = cluster();
+        let empty = JsonCodec::new().with_schema_id("");
+        let long = JsonCodec::new().with_schema_id("x".repeat(MAX_SCHEMA_ID_LEN + 1));
+        assert!(cluster.set_typed("order", &order(), &empty, None).await.is_err());
+        assert!(cluster.set_typed("order", &order(), &long, None).await.is_err());
+
+        let longest = JsonCodec::new().with_schema_id("x".repeat(MAX_SCHEMA_ID_LEN));
+        cluster.set_typed("order", &order(), &longest, None).await.unwrap();
+        assert_eq!(cluster.get_typed("order", &longest).await.unwrap(), Some(order()));
+    }
+}
@@ -0,0 +1,95 @@
+//! How values are held in store entries.
+//!
+//! Most values are a few dozen bytes, for which a heap allocation of their
+//! own costs more than the value: the allocator's header and rounding, and a
+//! pointer chase on every read. Values of up to [`INLINE_CAPACITY`] bytes are
+//! kept in the entry itself instead.
+//!
+//! Entries live in the store's hash table, so every slot pays for the inline
+//! buffer whether a value uses it or not. The capacity is therefore what fits
+//! in the 24 bytes a `Vec` took: a larger buffer saves more for short values
+//! than it wastes on the rest only when nearly all values are short.
+
+use std::fmt;
+use std::ops::Deref;
+
+/// Longest value kept inside its entry rather than in an allocation
+pub const INLINE_CAPACITY: usize = 22;
+
+/// A stored value, inline when short enough
+#[derive(Clone)]
+pub(crate) enum StoredValue {
+    Inline { len: u8, bytes: [u8; INLINE_CAPACITY] },
+    /// Boxed rather than a `Vec`, whose capacity field would not fit
+    Heap(Box<[u8]>),
+}
+
+// Inline values fill the enum exactly, with one byte each for tag and length
+const _: () = assert!(std::mem::size_of::<StoredValue>() == 24);
+
+impl StoredValue {
+    fn inline(value: &[u8]) -> Self {
+        let mut bytes = [0; INLINE_CAPACITY];
+        bytes[..value.len()].copy_from_slice(value);
+        StoredValue::Inline {
+            len: value.len() as u8,
+            bytes,
+        }
+    }
+}
+
+impl From<Vec<u8>> for StoredValue {
+    fn from(value: Vec<u8>) -> Self {
+        if value.len() <= INLINE_CAPACITY {
+            StoredValue::inline(&value)
+        } else {
+            StoredValue::Heap(value.into_boxed_slice())
+        }
+    }
+}
+
+impl From<StoredValue> for Vec<u8> {
+    fn from(value: StoredValue) -> Self {
+        match value {
+            StoredValue::Inline { .. } => value.to_vec(),
+            StoredValue::Heap(bytes) => bytes.into_vec(),
+        }
+    }
+}
+
+impl Deref for StoredValue {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            StoredValue::Inline { len, bytes } => &bytes[..*len as usize],
+            StoredValue::Heap(bytes) => bytes,
+        }
+    }
+}
+
+impl AsRef<[u8]> for StoredValue {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl PartialEq for StoredValue {
+    fn eq(&self, other: &Self) -> bool {
+        **self == **other
+    }
+}
+
+impl Eq for StoredValue {}
+
+impl PartialEq<&[u8]> for StoredValue {
+    fn eq(&self, other: &&[u8]) -> bool {
+        **self == **other
+    }
+}
+
+impl fmt::Debug for StoredValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
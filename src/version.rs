@@ -0,0 +1,154 @@
+//! Version handshake between servers and clients, so a client talking to a
+//! server of another release finds out what it can use up front instead of
+//! failing on the first request the server does not understand.
+//!
+//! The server reports its API revision, the oldest client revision it still
+//! serves, how it hashes keys onto the ring, and the features it was built
+//! with. A client uses the lower of the two revisions and only the features
+//! both sides have. Servers older than the handshake answer `/version` with
+//! 404 and are treated as revision 0 with no optional features.
+
+use serde::{Deserialize, Serialize};
+
+use crate::topology::{RING_HASH_ALGORITHM, RING_HASH_SEED};
+
+/// Revision of the HTTP API, bumped whenever a route or payload changes in
+/// a way a client has to know about
+pub const API_REVISION: u32 = 1;
+
+/// Oldest client API revision this server still serves
+pub const MIN_CLIENT_API_REVISION: u32 = 0;
+
+/// Optional features, each reported whether or not the server has it.
+/// Features a server does not know of read as absent.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(default)]
+pub struct Features {
+    /// Keys survive a restart
+    pub persistence: bool,
+    /// The Redis serialization protocol is served alongside HTTP
+    pub resp: bool,
+    /// Clients can subscribe to channels of published messages
+    pub pubsub: bool,
+    /// Quotas, sliding TTLs and soft deletes apply per key namespace
+    pub namespaces: bool,
+    /// Invalidations for client-side caches are pushed over `/tracking`
+    pub client_tracking: bool,
+    /// Faults can be injected through `/admin/chaos`
+    pub chaos: bool,
+}
+
+impl Features {
+    /// Features of this build
+    pub fn current() -> Self {
+        Features {
+            persistence: false,
+            resp: false,
+            pubsub: false,
+            namespaces: true,
+            client_tracking: true,
+            chaos: cfg!(feature = "chaos"),
+        }
+    }
+
+    /// Features both sides have
+    pub fn intersect(&self, other: &Features) -> Features {
+        Features {
+            persistence: self.persistence && other.persistence,
+            resp: self.resp && other.resp,
+            pubsub: self.pubsub && other.pubsub,
+            namespaces: self.namespaces && other.namespaces,
+            client_tracking: self.client_tracking && other.client_tracking,
+            chaos: self.chaos && other.chaos,
+        }
+    }
+}
+
+/// What `GET /version` reports
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct VersionInfo {
+    /// Release of the server, e.g. `0.1.0`
+    pub version: String,
+    pub api_revision: u32,
+    pub min_client_api_revision: u32,
+    pub ring_hash_algorithm: String,
+    pub ring_hash_seed: u32,
+    #[serde(default)]
+    pub features: Features,
+}
+
+impl VersionInfo {
+    /// Version of this build
+    pub fn current() -> Self {
+        VersionInfo {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            api_revision: API_REVISION,
+            min_client_api_revision: MIN_CLIENT_API_REVISION,
+            ring_hash_algorithm: RING_HASH_ALGORITHM.to_string(),
+            ring_hash_seed: RING_HASH_SEED,
+            features: Features::current(),
+        }
+    }
+}
+
+/// What a client and server agreed to use
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Negotiated {
+    /// Server release, or `None` for a server older than the handshake
+    pub server_version: Option<String>,
+    /// Lower of the client's and the server's API revisions
+    pub api_revision: u32,
+    pub features: Features,
+    /// Whether the client may route keys to nodes itself. Clients must go
+    /// through the server when it hashes keys differently than they do.
+    pub client_routing: bool,
+}
+
+impl Negotiated {
+    /// Whether both sides speak at least `revision` of the API
+    pub fn supports(&self, revision: u32) -> bool {
+        self.api_revision >= revision
+    }
+}
+
+/// Agrees on what a client built from this crate may use with a server that
+/// reported `server`, or `None` if the server predates the handshake. Fails
+/// if the server no longer serves this client's API revision.
+pub fn negotiate(server: Option<&VersionInfo>) -> Result<Negotiated, String> {
+    let Some(server) = server else {
+        return Ok(Negotiated {
+            server_version: None,
+            api_revision: 0,
+            features: Features::default(),
+            client_routing: false,
+        });
+    };
+    if API_REVISION < server.min_client_api_revision {
+        return Err(format!(
+            "server {} needs clients of API revision {} or later; this client is revision {}",
+            server.version, server.min_client_api_revision, API_REVISION
+        ));
+    }
+    Ok(Negotiated {
+        server_version: Some(server.version.clone()),
+        api_revision: API_REVISION.min(server.api_revision),
+        features: Features::current().intersect(&server.features),
+        client_routing: server.ring_hash_algorithm == RING_HASH_ALGORITHM && server.ring_hash_seed == RING_HASH_SEED,
+    })
+}
+
+/// Fetches `<base_url>/version` and negotiates with the server behind it
+pub async fn negotiate_with(client: &reqwest::Client, base_url: &str) -> Result<Negotiated, String> {
+    let url = format!("{}/version", base_url.trim_end_matches('/'));
+    let response = client.get(&url).send().await.map_err(|e| e.to_string())?;
+    if response.status() == reqwest::StatusCode::NOT_FOUND {
+        return negotiate(None);
+    }
+    let server: VersionInfo = response
+        .error_for_status()
+        .map_err(|e| e.to_string())?
+        .json()
+        .await
+        .map_err(|e| format!("invalid version from {}: {}", url, e))?;
+    negotiate(Some(&server))
+}
@@ -0,0 +1,214 @@
+//! Startup warm-up: preloading the cache before the cluster reports ready,
+//! so a fresh deploy does not send every request to the backing store at
+//! once.
+//!
+//! While a warm-up runs, `readiness` fails with a progress message. It
+//! succeeds again once every source is loaded or the warm-up times out.
+
+use axum::async_trait;
+use serde::Serialize;
+use std::io::{self, BufReader};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinSet;
+use tokio::time::Instant;
+use tracing::{info, warn};
+
+use crate::dump::{decrypt_dump, read_dump};
+use crate::encryption::KeyProvider;
+use crate::KVCluster;
+
+/// Backend fetches run at once during a warm-up
+const WARMUP_CONCURRENCY: usize = 32;
+
+/// The system of record behind the cache
+#[async_trait]
+pub trait Backend: Send + Sync {
+    /// Value of `key`, or `None` if the backend does not have it
+    async fn fetch(&self, key: &str) -> io::Result<Option<Vec<u8>>>;
+}
+
+/// A backend serving raw values over HTTP at `<base_url>/<key>`, with the
+/// key percent-encoded, and 404 for missing keys
+pub struct HttpBackend {
+    client: reqwest::Client,
+    base_url: String,
+}
+
+impl HttpBackend {
+    pub fn new(base_url: impl Into<String>) -> Self {
+        HttpBackend {
+            client: reqwest::Client::new(),
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl Backend for HttpBackend {
+    async fn fetch(&self, key: &str) -> io::Result<Option<Vec<u8>>> {
+        // The key is a single path segment, so `/`, `?` and `#` in it are
+        // escaped. Dot segments would be resolved away instead.
+        if key == "." || key == ".." {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "key is not a valid path segment"));
+        }
+        let mut url = reqwest::Url::parse(&self.base_url).map_err(io::Error::other)?;
+        url.path_segments_mut()
+            .map_err(|()| io::Error::new(io::ErrorKind::InvalidInput, "backend URL cannot take a path"))?
+            .pop_if_empty()
+            .push(key);
+        let response = self.client.get(url).send().await.map_err(io::Error::other)?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let body = response
+            .error_for_status()
+            .map_err(io::Error::other)?
+            .bytes()
+            .await
+            .map_err(io::Error::other)?;
+        Ok(Some(body.to_vec()))
+    }
+}
+
+/// Where a warm-up loads keys from
+pub enum WarmupSource {
+    /// An export dump file, as written by `volt-cli export`. Encrypted
+    /// dumps are decrypted with `keys`.
+    Snapshot {
+        path: PathBuf,
+        keys: Option<Arc<dyn KeyProvider>>,
+    },
+    /// A list of keys fetched one by one from a backend and stored with `ttl`
+    Backend {
+        backend: Arc<dyn Backend>,
+        keys: Vec<String>,
+        ttl: Option<Duration>,
+    },
+}
+
+/// Progress of the current or last warm-up
+#[derive(Default)]
+pub(crate) struct WarmupState {
+    running: AtomicBool,
+    loaded: AtomicU64,
+    failed: AtomicU64,
+}
+
+/// Outcome of a warm-up
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WarmupReport {
+    pub loaded: u64,
+    /// Keys that could not be fetched or stored
+    pub failed: u64,
+    /// Whether the warm-up stopped at its timeout before loading everything
+    pub timed_out: bool,
+}
+
+impl KVCluster {
+    /// Whether a warm-up is running, keeping the cluster from reporting ready
+    pub fn is_warming_up(&self) -> bool {
+        self.state.warmup.running.load(Ordering::Acquire)
+    }
+
+    /// Keys loaded so far by the current or last warm-up
+    pub fn warmup_loaded(&self) -> u64 {
+        self.state.warmup.loaded.load(Ordering::Relaxed)
+    }
+
+    /// Marks the cluster as warming up and loads `sources` in the
+    /// background, giving up after `timeout`. Readiness fails from this call
+    /// until the warm-up ends, so call it before the server starts listening.
+    pub fn start_warmup(&self, sources: Vec<WarmupSource>, timeout: Duration) -> tokio::task::JoinHandle<WarmupReport> {
+        let warmup = &self.state.warmup;
+        warmup.running.store(true, Ordering::Release);
+        warmup.loaded.store(0, Ordering::Relaxed);
+        warmup.failed.store(0, Ordering::Relaxed);
+        let cluster = self.clone();
+        tokio::spawn(async move {
+            let started = Instant::now();
+            let timed_out = tokio::time::timeout(timeout, cluster.load_sources(sources))
+                .await
+                .is_err();
+            let warmup = &cluster.state.warmup;
+            warmup.running.store(false, Ordering::Release);
+            let report = WarmupReport {
+                loaded: warmup.loaded.load(Ordering::Relaxed),
+                failed: warmup.failed.load(Ordering::Relaxed),
+                timed_out,
+            };
+            if timed_out {
+                warn!("Warm-up timed out after {:?}: {} keys loaded, {} failed", timeout, report.loaded, report.failed);
+            } else {
+                info!("Warm-up loaded {} keys in {:?}, {} failed", report.loaded, started.elapsed(), report.failed);
+            }
+            report
+        })
+    }
+
+    /// Loads `sources` and waits for the warm-up to end
+    pub async fn warm_up(&self, sources: Vec<WarmupSource>, timeout: Duration) -> WarmupReport {
+        // The task only panics if the store itself is broken
+        self.start_warmup(sources, timeout).await.expect("warm-up task panicked")
+    }
+
+    async fn load_sources(&self, sources: Vec<WarmupSource>) {
+        let warmup = &self.state.warmup;
+        for source in sources {
+            match source {
+                WarmupSource::Snapshot { path, keys } => match self.load_snapshot(path.clone(), keys).await {
+                    Ok(loaded) => {
+                        warmup.loaded.fetch_add(loaded as u64, Ordering::Relaxed);
+                    }
+                    Err(e) => {
+                        warn!("Cannot load warm-up snapshot {}: {}", path.display(), e);
+                        warmup.failed.fetch_add(1, Ordering::Relaxed);
+                    }
+                },
+                WarmupSource::Backend { backend, keys, ttl } => self.load_from_backend(backend, keys, ttl).await,
+            }
+        }
+    }
+
+    async fn load_snapshot(&self, path: PathBuf, keys: Option<Arc<dyn KeyProvider>>) -> io::Result<usize> {
+        // Reading and decrypting a large dump would hold up a runtime worker
+        let entries = tokio::task::spawn_blocking(move || {
+            let file = std::fs::File::open(path)?;
+            let mut entries = read_dump(BufReader::new(file))?;
+            decrypt_dump(&mut entries, keys.as_deref())?;
+            Ok::<_, io::Error>(entries)
+        })
+        .await
+        .map_err(io::Error::other)??;
+        self.import(entries).await.map_err(io::Error::other)
+    }
+
+    async fn load_from_backend(&self, backend: Arc<dyn Backend>, keys: Vec<String>, ttl: Option<Duration>) {
+        let mut tasks = JoinSet::new();
+        for key in keys {
+            if tasks.len() >= WARMUP_CONCURRENCY {
+                tasks.join_next().await;
+            }
+            let cluster = self.clone();
+            let backend = backend.clone();
+            tasks.spawn(async move {
+                let warmup = &cluster.state.warmup;
+                let loaded = match backend.fetch(&key).await {
+                    Ok(Some(value)) => cluster.set(key.clone(), value, ttl).await.map_err(|e| e.to_string()),
+                    Ok(None) => return,
+                    Err(e) => Err(e.to_string()),
+                };
+                match loaded {
+                    Ok(()) => warmup.loaded.fetch_add(1, Ordering::Relaxed),
+                    Err(e) => {
+                        warn!(key = %key, "Cannot warm up key: {}", e);
+                        warmup.failed.fetch_add(1, Ordering::Relaxed)
+                    }
+                };
+            });
+        }
+        while tasks.join_next().await.is_some() {}
+    }
+}
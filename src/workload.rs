@@ -0,0 +1,250 @@
+use rand::Rng;
+use serde::Serialize;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Skew used when a zipfian distribution is requested without one, as in YCSB
+pub const DEFAULT_ZIPFIAN_THETA: f64 = 0.99;
+
+/// How a workload picks which key to touch next
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KeyDistribution {
+    Uniform,
+    /// Key `i` is chosen with probability proportional to `1 / (i + 1)^theta`
+    Zipfian { theta: f64 },
+}
+
+impl FromStr for KeyDistribution {
+    type Err = String;
+
+    /// Parses `uniform`, `zipfian` or `zipfian:<theta>`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, param) = match s.split_once(':') {
+            Some((name, param)) => (name, Some(param)),
+            None => (s, None),
+        };
+        match (name.trim().to_ascii_lowercase().as_str(), param) {
+            ("uniform", None) => Ok(KeyDistribution::Uniform),
+            ("zipfian", None) => Ok(KeyDistribution::Zipfian {
+                theta: DEFAULT_ZIPFIAN_THETA,
+            }),
+            ("zipfian", Some(theta)) => match theta.trim().parse::<f64>() {
+                Ok(theta) if theta > 0.0 && theta != 1.0 => Ok(KeyDistribution::Zipfian { theta }),
+                _ => Err(format!("invalid zipfian theta '{}': expected a positive number other than 1", theta)),
+            },
+            _ => Err(format!("invalid key distribution '{}': expected uniform or zipfian[:theta]", s)),
+        }
+    }
+}
+
+/// Zipfian sampler over `0..items`, after Gray et al., "Quickly Generating
+/// Billion-Record Synthetic Databases" (the generator YCSB uses). Rank 0 is
+/// the hottest item.
+#[derive(Debug, Clone)]
+struct Zipfian {
+    items: u64,
+    theta: f64,
+    alpha: f64,
+    zetan: f64,
+    eta: f64,
+}
+
+fn zeta(n: u64, theta: f64) -> f64 {
+    (1..=n).map(|i| 1.0 / (i as f64).powf(theta)).sum()
+}
+
+impl Zipfian {
+    fn new(items: u64, theta: f64) -> Self {
+        let zetan = zeta(items, theta);
+        let zeta2 = zeta(2, theta);
+        Zipfian {
+            items,
+            theta,
+            alpha: 1.0 / (1.0 - theta),
+            zetan,
+            eta: (1.0 - (2.0 / items as f64).powf(1.0 - theta)) / (1.0 - zeta2 / zetan),
+        }
+    }
+
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> u64 {
+        let u: f64 = rng.gen();
+        let uz = u * self.zetan;
+        if uz < 1.0 {
+            return 0;
+        }
+        if uz < 1.0 + 0.5f64.powf(self.theta) {
+            return 1.min(self.items - 1);
+        }
+        let rank = self.items as f64 * (self.eta * u - self.eta + 1.0).powf(self.alpha);
+        (rank as u64).min(self.items - 1)
+    }
+}
+
+/// Picks key indices in `0..keys` following a distribution
+#[derive(Debug, Clone)]
+pub struct KeyChooser {
+    keys: u64,
+    zipfian: Option<Zipfian>,
+}
+
+impl KeyChooser {
+    /// Builds a chooser over `keys` keys. Zipfian setup is linear in `keys`.
+    pub fn new(keys: u64, distribution: KeyDistribution) -> Self {
+        let keys = keys.max(1);
+        let zipfian = match distribution {
+            KeyDistribution::Uniform => None,
+            KeyDistribution::Zipfian { theta } => Some(Zipfian::new(keys, theta)),
+        };
+        KeyChooser { keys, zipfian }
+    }
+
+    pub fn next_index<R: Rng + ?Sized>(&self, rng: &mut R) -> u64 {
+        match &self.zipfian {
+            Some(zipfian) => zipfian.sample(rng),
+            None => rng.gen_range(0..self.keys),
+        }
+    }
+}
+
+/// Latency percentiles of a set of operations
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct LatencySummary {
+    pub count: usize,
+    pub mean_us: f64,
+    pub p50_us: u64,
+    pub p95_us: u64,
+    pub p99_us: u64,
+    pub max_us: u64,
+}
+
+impl LatencySummary {
+    /// Summarizes latencies, sorting `samples` in place
+    pub fn from_samples(samples: &mut [Duration]) -> Self {
+        if samples.is_empty() {
+            return LatencySummary::default();
+        }
+        samples.sort_unstable();
+        let micros = |d: Duration| d.as_micros() as u64;
+        let percentile = |p: f64| {
+            let rank = ((samples.len() as f64 * p).ceil() as usize).clamp(1, samples.len());
+            micros(samples[rank - 1])
+        };
+        let total: Duration = samples.iter().sum();
+        LatencySummary {
+            count: samples.len(),
+            mean_us: total.as_secs_f64() * 1e6 / samples.len() as f64,
+            p50_us: percentile(0.50),
+            p95_us: percentile(0.95),
+            p99_us: percentile(0.99),
+            max_us: micros(samples[samples.len() - 1]),
+        }
+    }
+}
+
+/// Longest range read by a scan in the YCSB workloads; lengths are uniform
+/// in `1..=MAX_SCAN_LENGTH`
+pub const MAX_SCAN_LENGTH: usize = 100;
+
+/// An operation of a YCSB workload
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum YcsbOp {
+    Read,
+    Update,
+    /// Writes a record past the end of the loaded ones
+    Insert,
+    /// Reads a short range of records in key order
+    Scan,
+    /// Reads a record, then writes it back modified
+    ReadModifyWrite,
+}
+
+/// The YCSB core workloads
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum YcsbWorkload {
+    /// 50% reads, 50% updates
+    A,
+    /// 95% reads, 5% updates
+    B,
+    /// Reads only
+    C,
+    /// 95% reads, 5% inserts; reads favour the newest records
+    D,
+    /// 95% scans, 5% inserts
+    E,
+    /// 50% reads, 50% read-modify-writes
+    F,
+}
+
+impl YcsbWorkload {
+    pub const ALL: [YcsbWorkload; 6] = [
+        YcsbWorkload::A,
+        YcsbWorkload::B,
+        YcsbWorkload::C,
+        YcsbWorkload::D,
+        YcsbWorkload::E,
+        YcsbWorkload::F,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            YcsbWorkload::A => "a_update_heavy",
+            YcsbWorkload::B => "b_read_mostly",
+            YcsbWorkload::C => "c_read_only",
+            YcsbWorkload::D => "d_read_latest",
+            YcsbWorkload::E => "e_short_ranges",
+            YcsbWorkload::F => "f_read_modify_write",
+        }
+    }
+
+    /// Operation mix as (operation, percentage) pairs summing to 100
+    pub fn mix(self) -> &'static [(YcsbOp, u32)] {
+        match self {
+            YcsbWorkload::A => &[(YcsbOp::Read, 50), (YcsbOp::Update, 50)],
+            YcsbWorkload::B => &[(YcsbOp::Read, 95), (YcsbOp::Update, 5)],
+            YcsbWorkload::C => &[(YcsbOp::Read, 100)],
+            YcsbWorkload::D => &[(YcsbOp::Read, 95), (YcsbOp::Insert, 5)],
+            YcsbWorkload::E => &[(YcsbOp::Scan, 95), (YcsbOp::Insert, 5)],
+            YcsbWorkload::F => &[(YcsbOp::Read, 50), (YcsbOp::ReadModifyWrite, 50)],
+        }
+    }
+
+    /// Whether reads pick recently inserted records rather than follow the
+    /// key distribution over the whole keyspace
+    pub fn reads_latest(self) -> bool {
+        self == YcsbWorkload::D
+    }
+
+    pub fn next_op<R: Rng + ?Sized>(self, rng: &mut R) -> YcsbOp {
+        let mut roll = rng.gen_range(0..100);
+        for &(op, percent) in self.mix() {
+            if roll < percent {
+                return op;
+            }
+            roll -= percent;
+        }
+        YcsbOp::Read
+    }
+}
+
+impl FromStr for YcsbWorkload {
+    type Err = String;
+
+    /// Parses a workload letter, `a` to `f`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "a" => Ok(YcsbWorkload::A),
+            "b" => Ok(YcsbWorkload::B),
+            "c" => Ok(YcsbWorkload::C),
+            "d" => Ok(YcsbWorkload::D),
+            "e" => Ok(YcsbWorkload::E),
+            "f" => Ok(YcsbWorkload::F),
+            _ => Err(format!("invalid YCSB workload '{}': expected a letter from a to f", s)),
+        }
+    }
+}
+
+/// Key of the YCSB record with index `index`. Zero-padded so key order
+/// matches index order for scans.
+pub fn ycsb_key(index: u64) -> String {
+    format!("user{:010}", index)
+}
@@ -0,0 +1,354 @@
+//! Deterministic simulations of a cluster on tokio's paused clock.
+//!
+//! Each test runs on a single-threaded runtime whose clock only moves when
+//! every task is idle, so TTL expiry, replica lag and deadlines play out the
+//! same way on every run. Randomized tests draw from a seeded RNG; a failure
+//! message names the seed and step to replay.
+
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::time::{sleep, Instant};
+use volt::{AckMode, KVCluster, KVError, WriteOptions};
+
+fn cluster(nodes: usize, replication_factor: usize) -> KVCluster {
+    let mut cluster = KVCluster::new(16, replication_factor);
+    for i in 0..nodes {
+        cluster.add_node(format!("node{}", i));
+    }
+    cluster
+}
+
+fn stored_keys(cluster: &KVCluster) -> usize {
+    cluster.stats().nodes.iter().map(|n| n.keys).sum()
+}
+
+#[tokio::test(start_paused = true)]
+async fn ttl_expires_at_deadline_on_every_copy() {
+    let cluster = cluster(3, 3);
+    cluster
+        .set("session", b"abc".to_vec(), Some(Duration::from_secs(5)))
+        .await
+        .unwrap();
+
+    sleep(Duration::from_millis(4_999)).await;
+    assert_eq!(cluster.get("session").await, Some(b"abc".to_vec()));
+    assert_eq!(cluster.ttl("session").await, Some(Some(Duration::from_millis(1))));
+
+    sleep(Duration::from_millis(1)).await;
+    assert_eq!(cluster.get("session").await, None);
+
+    // Let the sweepers clear the replica copies too
+    sleep(Duration::from_millis(5)).await;
+    assert_eq!(stored_keys(&cluster), 0);
+}
+
+#[tokio::test(start_paused = true)]
+async fn overwrite_without_ttl_cancels_expiry() {
+    let cluster = cluster(3, 2);
+    cluster
+        .set("k", b"old".to_vec(), Some(Duration::from_millis(100)))
+        .await
+        .unwrap();
+    cluster.set("k", b"new".to_vec(), None).await.unwrap();
+
+    sleep(Duration::from_secs(1)).await;
+    assert_eq!(cluster.get("k").await, Some(b"new".to_vec()));
+    assert_eq!(cluster.ttl("k").await, Some(None));
+}
+
+#[tokio::test(start_paused = true)]
+async fn sliding_ttl_restarts_on_every_read() {
+    let cluster = cluster(3, 3);
+    let ttl = Some(Duration::from_secs(1));
+    cluster
+        .set_with_options("token", b"t".to_vec(), ttl, WriteOptions::with_sliding_ttl())
+        .await
+        .unwrap();
+    cluster.set_sliding_namespace("session", true);
+    cluster.set("session:1", b"s".to_vec(), ttl).await.unwrap();
+    cluster.set("fixed", b"f".to_vec(), ttl).await.unwrap();
+
+    for _ in 0..5 {
+        sleep(Duration::from_millis(800)).await;
+        assert!(cluster.get("token").await.is_some());
+        assert!(cluster.get("session:1").await.is_some());
+    }
+    assert_eq!(cluster.get("fixed").await, None);
+
+    // Replicas received the refreshed expiry, so they keep their copies
+    sleep(Duration::from_millis(500)).await;
+    assert_eq!(stored_keys(&cluster), 6);
+    assert_eq!(cluster.ttl("token").await, Some(Some(Duration::from_millis(500))));
+
+    sleep(Duration::from_millis(500)).await;
+    assert_eq!(cluster.get("token").await, None);
+    assert!(cluster.touch("session:1").await.is_ok_and(|found| !found));
+}
+
+#[tokio::test(start_paused = true)]
+async fn replica_acks_wait_out_the_coalescing_window() {
+    let cluster = cluster(3, 3);
+    cluster.set_write_coalescing(Some(Duration::from_millis(50)));
+    let all_acks = |timeout| WriteOptions::new(Some(timeout), Some(AckMode::All));
+
+    // Replicas hold writes for the window, so a shorter deadline cannot be met
+    let result = cluster
+        .set_with_options("k", b"v1".to_vec(), None, all_acks(Duration::from_millis(10)))
+        .await;
+    assert!(matches!(result, Err(KVError::Timeout)), "{:?}", result);
+
+    sleep(Duration::from_millis(100)).await;
+    let start = Instant::now();
+    cluster
+        .set_with_options("k", b"v2".to_vec(), None, all_acks(Duration::from_millis(100)))
+        .await
+        .unwrap();
+    let waited = start.elapsed();
+    assert!(waited >= Duration::from_millis(50) && waited < Duration::from_millis(60), "{:?}", waited);
+
+    // Acknowledging only the primary returns without waiting for replicas
+    let start = Instant::now();
+    cluster
+        .set_with_options("k", b"v3".to_vec(), None, WriteOptions::with_ack(AckMode::Primary))
+        .await
+        .unwrap();
+    assert_eq!(start.elapsed(), Duration::ZERO);
+}
+
+/// Expected state of one key in the reference model
+struct ModelEntry {
+    value: Vec<u8>,
+    expiry: Option<Instant>,
+}
+
+impl ModelEntry {
+    fn live(&self, now: Instant) -> bool {
+        self.expiry.is_none_or(|expiry| expiry > now)
+    }
+}
+
+/// Drives a cluster with a seeded mix of writes, deletes, reads and clock
+/// advances, checking every read against a single-map reference model
+async fn run_model_check(seed: u64, steps: usize) {
+    let cluster = cluster(4, 3);
+    let mut rng = SmallRng::seed_from_u64(seed);
+    let mut model: HashMap<String, ModelEntry> = HashMap::new();
+
+    for step in 0..steps {
+        let key = format!("key:{}", rng.gen_range(0..16));
+        match rng.gen_range(0..100) {
+            0..=44 => {
+                let value = format!("{}:{}", seed, step).into_bytes();
+                let ttl = rng
+                    .gen_bool(0.5)
+                    .then(|| Duration::from_millis(rng.gen_range(1..100)));
+                cluster.set(key.clone(), value.clone(), ttl).await.unwrap();
+                let expiry = ttl.map(|ttl| Instant::now() + ttl);
+                model.insert(key, ModelEntry { value, expiry });
+            }
+            45..=59 => {
+                cluster.del(&key).await.unwrap();
+                model.remove(&key);
+            }
+            60..=89 => {
+                let now = Instant::now();
+                let expected = model.get(&key).filter(|e| e.live(now));
+                assert_eq!(
+                    cluster.get(&key).await,
+                    expected.map(|e| e.value.clone()),
+                    "seed {} step {}: get {}",
+                    seed,
+                    step,
+                    key
+                );
+                assert_eq!(
+                    cluster.ttl(&key).await,
+                    expected.map(|e| e.expiry.map(|x| x - now)),
+                    "seed {} step {}: ttl {}",
+                    seed,
+                    step,
+                    key
+                );
+            }
+            _ => sleep(Duration::from_millis(rng.gen_range(0..30))).await,
+        }
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn seeded_operations_match_reference_model() {
+    for seed in 0..16 {
+        run_model_check(seed, 500).await;
+    }
+}
+
+/// Values each key may read back: that of its last acknowledged write, plus
+/// those of later writes that failed and may have been applied all the same.
+/// `None` stands for the key being absent.
+#[derive(Default)]
+struct AckedWrites(HashMap<String, Vec<Option<Vec<u8>>>>);
+
+impl AckedWrites {
+    fn record(&mut self, key: &str, value: Option<Vec<u8>>, result: Result<(), KVError>) {
+        let allowed = self.0.entry(key.to_string()).or_insert_with(|| vec![None]);
+        match result {
+            Ok(()) => *allowed = vec![value],
+            Err(_) => allowed.push(value),
+        }
+    }
+}
+
+/// Applies a seeded write or delete to `cluster`, recording it in `acked`
+async fn write_or_delete(cluster: &KVCluster, rng: &mut SmallRng, acked: &mut AckedWrites, tag: &str) {
+    let key = format!("key:{}", rng.gen_range(0..32));
+    if rng.gen_range(0..4) == 0 {
+        let result = cluster.del(&key).await;
+        acked.record(&key, None, result);
+    } else {
+        let value = format!("{}:{}", tag, rng.gen::<u32>()).into_bytes();
+        let result = cluster.set(key.clone(), value.clone(), None).await;
+        acked.record(&key, Some(value), result);
+    }
+}
+
+async fn assert_reads_back(cluster: &KVCluster, acked: &AckedWrites, context: &str) {
+    for (key, allowed) in &acked.0 {
+        let value = cluster.get(key).await;
+        assert!(allowed.contains(&value), "{}: {} reads {:?}", context, key, value);
+    }
+}
+
+/// Checks that no acknowledged write was lost: once replicas have settled,
+/// every key reads back, including with each node taken down in turn, so
+/// that each key is also read from a copy other than its primary's
+async fn assert_no_write_lost(cluster: &KVCluster, nodes: usize, acked: &AckedWrites, context: &str) {
+    sleep(Duration::from_millis(100)).await;
+    assert_reads_back(cluster, acked, context).await;
+    for i in 0..nodes {
+        let node = format!("node{}", i);
+        assert!(cluster.mark_node_down(&node));
+        assert_reads_back(cluster, acked, &format!("{}, {} down", context, node)).await;
+        cluster.mark_node_up(&node).await.unwrap();
+    }
+}
+
+/// Takes random nodes down and brings them back while writing, never more
+/// at once than leaves each key a copy
+async fn run_failover_check(seed: u64, steps: usize) {
+    let cluster = cluster(5, 3);
+    let mut rng = SmallRng::seed_from_u64(seed);
+    let mut acked = AckedWrites::default();
+    let mut down: Vec<String> = Vec::new();
+
+    for step in 0..steps {
+        match rng.gen_range(0..100) {
+            0..=69 => write_or_delete(&cluster, &mut rng, &mut acked, &seed.to_string()).await,
+            70..=79 if down.len() < 2 => {
+                let node = format!("node{}", rng.gen_range(0..5));
+                if !down.contains(&node) {
+                    assert!(cluster.mark_node_down(&node));
+                    down.push(node);
+                }
+            }
+            80..=89 if !down.is_empty() => {
+                let node = down.swap_remove(rng.gen_range(0..down.len()));
+                cluster.mark_node_up(&node).await.unwrap();
+            }
+            90..=94 => {
+                sleep(Duration::from_millis(5)).await;
+                let context = format!("seed {} step {}", seed, step);
+                assert_reads_back(&cluster, &acked, &context).await;
+            }
+            _ => sleep(Duration::from_millis(rng.gen_range(0..10))).await,
+        }
+    }
+    for node in down {
+        cluster.mark_node_up(&node).await.unwrap();
+    }
+    assert_no_write_lost(&cluster, 5, &acked, &format!("seed {}", seed)).await;
+}
+
+#[tokio::test(start_paused = true)]
+async fn failover_and_catch_up_keep_acknowledged_writes() {
+    for seed in 0..16 {
+        run_failover_check(seed, 400).await;
+    }
+}
+
+/// Adds a node to a cluster already holding keys. The node starts empty, so
+/// it joins the way a node returns from being down: it receives every key it
+/// now holds a copy of before it serves any of them.
+async fn join(cluster: &mut KVCluster, node_id: &str) {
+    cluster.add_node(node_id.to_string());
+    assert!(cluster.mark_node_down(node_id));
+    cluster.mark_node_up(node_id).await.unwrap();
+}
+
+#[tokio::test(start_paused = true)]
+async fn growing_the_cluster_keeps_acknowledged_writes() {
+    for seed in 0..16 {
+        let mut cluster = cluster(2, 2);
+        let mut rng = SmallRng::seed_from_u64(seed);
+        let mut acked = AckedWrites::default();
+        for nodes in 2..6 {
+            for _ in 0..100 {
+                write_or_delete(&cluster, &mut rng, &mut acked, &seed.to_string()).await;
+                if rng.gen_range(0..10) == 0 {
+                    sleep(Duration::from_millis(rng.gen_range(0..10))).await;
+                }
+            }
+            join(&mut cluster, &format!("node{}", nodes)).await;
+            let context = format!("seed {} after node{} joined", seed, nodes);
+            assert_reads_back(&cluster, &acked, &context).await;
+        }
+        assert_no_write_lost(&cluster, 6, &acked, &format!("seed {}", seed)).await;
+    }
+}
+
+/// Fails random nodes with the fault injector while writing, leaving it to
+/// the failure detector to take them out of service and to bring them back
+#[cfg(feature = "chaos")]
+async fn run_chaos_check(seed: u64, steps: usize) {
+    let cluster = cluster(4, 3);
+    let detector = cluster.start_failure_detector(Duration::from_millis(20));
+    cluster.faults().set_replica_delay(Some(Duration::from_millis(1)));
+    let mut rng = SmallRng::seed_from_u64(seed);
+    let mut acked = AckedWrites::default();
+    let mut failed: Option<String> = None;
+
+    for _ in 0..steps {
+        match rng.gen_range(0..100) {
+            0..=74 => write_or_delete(&cluster, &mut rng, &mut acked, &seed.to_string()).await,
+            75..=79 => match failed.take() {
+                // Replica operations a node drops while failed are only sent
+                // again once the detector has taken it out of service, so a
+                // failure must last until then
+                Some(node) if cluster.node_status(&node) == volt::failover::NodeStatus::Down => {
+                    cluster.faults().recover_node(&node)
+                }
+                Some(node) => failed = Some(node),
+                None => {
+                    let node = format!("node{}", rng.gen_range(0..4));
+                    cluster.faults().fail_node(&node);
+                    failed = Some(node);
+                }
+            },
+            _ => sleep(Duration::from_millis(rng.gen_range(0..40))).await,
+        }
+    }
+    cluster.faults().reset();
+    sleep(Duration::from_secs(1)).await;
+    assert!(cluster.degraded_nodes().is_empty(), "seed {}: {:?}", seed, cluster.degraded_nodes());
+    detector.abort();
+    assert_no_write_lost(&cluster, 4, &acked, &format!("seed {}", seed)).await;
+}
+
+#[cfg(feature = "chaos")]
+#[tokio::test(start_paused = true)]
+async fn failed_nodes_lose_no_acknowledged_writes() {
+    for seed in 0..8 {
+        run_chaos_check(seed, 400).await;
+    }
+}